@@ -0,0 +1,15 @@
+.global _start
+
+.data
+    counter: .word 5
+
+.text
+_start:
+    ADR r0, counter;
+    LDR r1, [r0];
+    PRINTR r1;
+    LDR r2, =_done;
+    BX r2;
+    PRINTR r0;
+_done:
+    PRINTR r2;
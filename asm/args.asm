@@ -0,0 +1,12 @@
+// Exits with the sum of the first 2 arguments as exit code:
+// cargo run -- run asm/args.asm --arg 3 --arg 4 --exit-code; echo $?
+.global _start
+
+.section .text
+
+_start:
+    ADD r0, r0, r1;
+    PRINTR r0;
+    EXIT;
+    // never reached
+    MOV r0, #0;
@@ -0,0 +1,34 @@
+/*
+ * Shows the bit manipulation instructions: counts the set bits of a value by testing every bit
+ * and extracts and inserts bit fields.
+ */
+.global _start
+
+.text
+_start:
+    MOV r0, #0xB6;            // 0b10110110
+    MOV r1, #0;               // the number of set bits
+    MOV r2, #0;               // the number of tested bits
+_loop:
+    TBZ r0, #0, _clear;
+    ADD r1, r1, #1;
+_clear:
+    UBFX r0, r0, #1, #31;     // shift right by one
+    ADD r2, r2, #1;
+    CBNZ r0, _loop;
+    PRINTR r1;                // 5
+
+    MOV r3, #0xB6;
+    CLZ r4, r3;               // 24 with 32 bit words
+    PRINTR r4;
+    RBIT r5, r3;
+    PRINTR r5;
+    SBFX r6, r3, #4, #4;      // 0b1011 sign extended: -5
+    PRINTR r6;
+    MOV r7, #0;
+    BFI r7, r3, #8, #4;       // 0b0110 placed at bit 8: 0x600
+    PRINTR r7;
+    TBNZ r3, #7, _done;
+    PRINTR r3;                // skipped
+_done:
+    NOP;
@@ -0,0 +1,69 @@
+// LDM and STM transfer a list of registers from and to consecutive words; they are decoded into a load
+// or store per register and, with writeback (`r0!`), an update of the base register. The lowest
+// register is always at the lowest address; the suffix determines where the block starts relative to
+// the base register: increment after (IA), increment before (IB), decrement after (DA) or decrement
+// before (DB).
+// cargo run -- test asm/conformance
+.global _start
+.data
+    src0: .word 1
+    src1: .word 2
+    src2: .word 3
+    src3: .word 4
+    src4: .word 5
+    src5: .word 6
+    src6: .word 7
+    src7: .word 8
+    dst0: .word 0
+    dst1: .word 0
+    dst2: .word 0
+    dst3: .word 0
+    dst4: .word 0
+    dst5: .word 0
+    dst6: .word 0
+    dst7: .word 0
+.text
+// the sum of r0 and r1; the callee saved registers are saved and restored like a compiler would
+_sum:
+    STMDB sp!, {r4, r5, lr};
+    MOV r4, r0;
+    MOV r5, r1;
+    ADD r0, r4, r5;
+    LDMIA sp!, {r4, r5, pc};
+_start:
+    // memcpy of 8 words, 4 per iteration
+    MOV r1, =src0;
+    MOV r2, =dst0;
+    MOV r3, #2;
+copy:
+    LDMIA r1!, {r4-r7};
+    STMIA r2!, {r4-r7};
+    SUB r3, r3, #1;
+    CBNZ r3, copy;
+    // the base registers point just past the arrays
+    MOV r3, =src0;
+    SUB r3, r1, r3;
+    MOV r12, =dst0;
+    SUB r12, r2, r12;
+    // without writeback the base register is kept
+    MOV r8, =src4;
+    LDMIB r8, {r4, r5};
+    LDMDA r8, {r6, r7};
+    LDMDB r8, {r9, r10};
+    MOV r0, #4;
+    MOV r1, #5;
+    BL _sum;
+
+.expect r0, 9
+.expect r4, 6
+.expect r5, 7
+.expect r6, 4
+.expect r7, 5
+.expect r9, 3
+.expect r10, 4
+.expect r3, 8
+.expect r12, 8
+.expect mem[dst0], 1
+.expect mem[dst3], 4
+.expect mem[dst7], 8
+.expect sp, 2048
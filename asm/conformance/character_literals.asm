@@ -0,0 +1,26 @@
+// Character literals, including the escapes \n, \r, \t, \0, \\, \' and \".
+// cargo run -- test asm/conformance
+.global _start
+.text
+_start:
+    MOV r0, #'A';
+    MOV r1, #'\n';
+    MOV r2, #'\r';
+    MOV r3, #'\t';
+    MOV r4, #'\0';
+    MOV r5, #'\\';
+    MOV r6, #'\'';
+    MOV r7, #'"';
+    MOV r8, #'\"';
+    MOV r9, #' ';
+
+.expect r0, 65
+.expect r1, 10
+.expect r2, 13
+.expect r3, 9
+.expect r4, 0
+.expect r5, 92
+.expect r6, 39
+.expect r7, 34
+.expect r8, 34
+.expect r9, 32
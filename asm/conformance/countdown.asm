@@ -0,0 +1,16 @@
+.global _start
+.data
+    counter: .word 3
+    result: .word 0
+.text
+_start:
+    MOV r1, =counter;
+    LDR r1, [r1];
+    MOV r2, #0;
+loop:
+    ADD r2, r2, r1;
+    SUB r1, r1, #1;
+    CBNZ r1, loop;
+    PRINTR r2;
+    MOV r3, =result;
+    STR r2, [r3];
@@ -0,0 +1,39 @@
+// Chains of flag setting compares and conditional branches. A CMP doesn't wait for the flags of an
+// older CMP, so the compares of independent branches can execute out of order.
+// cargo run -- test asm/conformance
+.global _start
+.text
+_start:
+    MOV r0, #5;
+    MOV r1, #7;
+    MOV r2, #0;
+    CMP r0, r1;
+    BGE skip1;
+    ADD r2, r2, #1;
+skip1:
+    CMP r1, r0;
+    BLE skip2;
+    ADD r2, r2, #2;
+skip2:
+    CMP r0, #5;
+    BNE skip3;
+    ADD r2, r2, #4;
+skip3:
+    // ADDS overwrites the flags of the CMP; ADCS reads its carry
+    CMP r0, r0;
+    ADDS r3, r0, r1;
+    ADCS r4, r0, r1;
+    // TST keeps the C flag of the SUBS
+    SUBS r5, r1, r0;
+    TST r5, #1;
+    BHS skip4;
+    ADD r2, r2, #8;
+skip4:
+    // the compares don't change the interrupt mask (bit 7) that is set after a reset
+    CMN r0, r1;
+
+.expect r2, 7
+.expect r3, 12
+.expect r4, 12
+.expect r5, 2
+.expect cpsr, 128
@@ -0,0 +1,27 @@
+.global _start
+.data
+    table: .word 0
+    table_end: .word 0
+    result: .word 0
+.text
+_double:
+    ADD r0, r0, r0;
+    BX lr;
+_square:
+    MUL r0, r0, r0;
+    BX lr;
+_start:
+    MOV r4, =table;
+    MOV r5, =table_end;
+    LDR r1, =_double;
+    STR r1, [r4];
+    LDR r1, =_square;
+    STR r1, [r5];
+    MOV r0, #3;
+    LDR r1, [r4];
+    BLX r1;
+    LDR r1, [r5];
+    BLR r1;
+    PRINTR r0;
+    MOV r1, =result;
+    STR r0, [r1];
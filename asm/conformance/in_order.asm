@@ -0,0 +1,31 @@
+// Runs on the classic 5-stage in-order pipeline (see in_order.expect.yaml). The loop has a load-use hazard
+// (the ADD waits a cycle for the loaded value), a multiply and a divide that hold the execute stage, and a
+// backward branch.
+// cargo run -- conformance asm/conformance
+.global _start
+.data
+    a0: .word 12
+    a1: .word 30
+    a2: .word 6
+    a3: .word 24
+    total: .word 0
+.text
+_start:
+    MOV r1, =a0;
+    MOV r2, #4;
+    MOV r3, #0;
+    MOV r5, #3;
+loop:
+    LDR r0, [r1], #1;
+    ADD r3, r3, r0;
+    MUL r4, r0, r5;
+    SDIV r4, r4, r5;
+    SUB r2, r2, #1;
+    CBNZ r2, loop;
+    MOV r6, =total;
+    STR r3, [r6];
+    PRINTR r3;
+
+.expect r3, 72
+.expect r4, 24
+.expect mem[total], 72
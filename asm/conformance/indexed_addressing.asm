@@ -0,0 +1,43 @@
+// Loads and stores with an immediate offset. A post-indexed access (`[r1], #1`) uses the address
+// register and then adds the offset to it; a pre-indexed access (`[r1, #1]!`) adds the offset first.
+// Offsets are in words.
+// cargo run -- test asm/conformance
+.global _start
+.data
+    a0: .word 1
+    a1: .word 2
+    a2: .word 3
+    a3: .word 4
+    copy0: .word 0
+    copy1: .word 0
+    copy2: .word 0
+    copy3: .word 0
+.text
+_start:
+    MOV r1, =a0;
+    MOV r2, =copy0;
+    MOV r3, #4;
+    MOV r4, #0;
+loop:
+    LDR r0, [r1], #1;
+    ADD r4, r4, r0;
+    STR r0, [r2], #1;
+    SUB r3, r3, #1;
+    CBNZ r3, loop;
+    // the address registers point just past the arrays
+    LDR r5, [r1, #-1];
+    MOV r6, =a0;
+    LDR r7, [r6, #2]!;
+    STR r4, [r6, #-1]!;
+    // r6 points to a1
+    LDR r8, [r6];
+    LDR r9, [r6, #3];
+
+.expect r4, 10
+.expect r5, 4
+.expect r7, 3
+.expect r8, 10
+.expect r9, 1
+.expect mem[copy0], 1
+.expect mem[copy3], 4
+.expect mem[a1], 10
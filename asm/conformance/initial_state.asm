@@ -0,0 +1,22 @@
+// The start state is prepared by directives instead of setup code.
+// cargo run -- test asm/conformance
+.global _start
+.data
+    values: .word 0
+    more: .word 0
+.text
+_start:
+    MOV r2, =values;
+    LDR r3, [r2];
+    ADD r0, r0, r3;
+    MOV r2, =more;
+    LDR r3, [r2];
+    ADD r0, r0, r3;
+    ADD r0, r0, r1;
+
+.reg r0, 100
+.reg r1, 20
+.mem values, 3
+.mem more, 4
+
+.expect r0, 127
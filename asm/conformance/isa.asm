@@ -0,0 +1,50 @@
+// Exercises the instructions that are written with their own mnemonic: SDIV, MVN, ADR, CMP and the
+// conditional branches. Every taken branch adds 1 to r7; a wrong branch ends up in fail.
+// cargo run -- test asm/conformance
+.global _start
+.data
+    value: .word 7
+.text
+_start:
+    MOV r0, #84;
+    MOV r1, #4;
+    SDIV r2, r0, r1;
+    MVN r3, r2;
+    MVN r4, r3;
+    ADR r5, value;
+    LDR r6, [r5];
+    MOV r7, #0;
+    CMP r2, #21;
+    BNE fail;
+    ADD r7, r7, #1;
+    CMP r6, #10;
+    BGE fail;
+    BLT less;
+    B fail;
+less:
+    ADD r7, r7, #1;
+    CMP r2, r4;
+    BEQ equal;
+    B fail;
+equal:
+    ADD r7, r7, #1;
+    CMP r6, #7;
+    BLE less_equal;
+    B fail;
+less_equal:
+    ADD r7, r7, #1;
+    CMP r2, r6;
+    BGT greater;
+    B fail;
+greater:
+    ADD r7, r7, #1;
+    B done;
+fail:
+    MOV r7, #100;
+done:
+    NOP;
+
+.expect r2, 21
+.expect r4, 21
+.expect r6, 7
+.expect r7, 5
@@ -0,0 +1,36 @@
+// PUSH and POP are decoded into a store or load per register and an update of the SP. The micro-ops
+// execute out of order, but the instruction retires as a whole: the loads of the POP get their values
+// from the stores of the PUSH that are still in the store buffer.
+// cargo run -- test asm/conformance
+.global _start
+.data
+    saved: .word 0
+    saved1: .word 0
+    saved2: .word 0
+    saved3: .word 0
+.text
+_swap:
+    PUSH {r4-r7, lr};
+    POP {r0-r3};
+    // the return address is still on the stack
+    POP {pc};
+_start:
+    MOV r4, #4;
+    MOV r5, #5;
+    MOV r6, #6;
+    MOV r7, #7;
+    BL _swap;
+    ADD r9, r0, r3;
+    MOV r8, =saved;
+    STR r9, [r8];
+    // a load per lane; the first lane is forwarded from the STR
+    VLD1 v0, [r8];
+    VADD v1, v0, v0;
+    VST1 v1, [r8];
+
+.expect r0, 4
+.expect r3, 7
+.expect r9, 11
+.expect mem[saved], 22
+.expect mem[saved1], 0
+.expect sp, 2048
@@ -0,0 +1,19 @@
+.global _start
+.data
+    result: .word 0
+.text
+_sum:
+    PUSH {r4-r5, lr};
+    MOV r4, #10;
+    MOV r5, #20;
+    ADD r0, r4, r5;
+    POP {r4-r5, pc};
+_start:
+    MOV r4, #1;
+    MOV r5, #2;
+    BL _sum;
+    ADD r0, r0, r4;
+    ADD r0, r0, r5;
+    PRINTR r0;
+    MOV r1, =result;
+    STR r0, [r1];
@@ -0,0 +1,34 @@
+// A file written for the GNU assembler: the code in front of the first section directive is in the
+// text section, the sections can be repeated in any order and .globl selects the entry point. .align 1
+// places the next variable at a doubleword (2 word) boundary, so a PUSH or POP of it is aligned.
+// cargo run -- test asm/conformance
+.globl main
+
+helper:
+    ADD r0, r0, #1;
+    BX lr;
+
+.data
+    first: .word 1
+    .align 1
+    pair: .word 2
+
+.text
+main:
+    MOV r0, =pair;
+    LDR r1, [r0];
+    BL helper;
+
+.section .data
+    last: .word 7
+
+.section .text
+    MOV r2, =last;
+    LDR r3, [r2];
+    // the address of pair is a multiple of 2
+    MOV r5, =pair;
+    AND r4, r5, #1;
+
+.expect r1, 2
+.expect r3, 7
+.expect r4, 0
@@ -0,0 +1,24 @@
+// A self-verifying test: the .expect directives are checked after the program exits.
+// cargo run -- test asm/conformance
+.global _start
+.data
+    total: .word 0
+    steps: .word 0
+.text
+_start:
+    MOV r0, #0;
+    MOV r1, #10;
+loop:
+    ADD r0, r0, r1;
+    SUB r1, r1, #1;
+    CBNZ r1, loop;
+    MOV r2, =total;
+    STR r0, [r2];
+    MOV r3, #10;
+    MOV r4, =steps;
+    STR r3, [r4];
+
+.expect r0, 55
+.expect r1, 0
+.expect mem[total], 55
+.expect mem[steps], 10
@@ -0,0 +1,37 @@
+// Adds and multiplies 4 lanes at a time; assumes the default 4 lanes of 32 bits.
+// cargo run -- test asm/conformance
+.global _start
+.data
+    va: .word 1
+    va1: .word 2
+    va2: .word 3
+    va3: .word 2147483647
+    vb: .word 10
+    vb1: .word 20
+    vb2: .word 30
+    vb3: .word 1
+    sum: .word 0
+    sum1: .word 0
+    sum2: .word 0
+    sum3: .word 0
+.text
+_start:
+    MOV r0, =va;
+    MOV r1, =vb;
+    MOV r2, =sum;
+    VLD1 v0, [r0];
+    VLD1 v1, [r1];
+    VADD v2, v0, v1;
+    VST1 v2, [r2];
+    VMUL v3, v0, v1;
+    VSUB v4, v3, v2;
+    // the lanes of the result are read back like any other words
+    LDR r3, [r2];
+
+.expect r3, 11
+.expect mem[sum+1], 22
+.expect mem[sum+2], 33
+// the lanes wrap at 32 bits
+.expect mem[sum+3], -2147483648
+.expect v3, 10
+.expect v4, -1
@@ -0,0 +1,27 @@
+// Stores on the wrong path of a mispredicted branch never reach the memory. The BTB learns that the
+// CBNZ is taken, so when the loop ends the frontend still fetches the next iteration; its stores are
+// issued (and can even execute), but they are squashed from the store buffer when the misprediction
+// is detected.
+// cargo run -- test asm/conformance
+.global _start
+.data
+    last: .word 100
+    count: .word 0
+.text
+_start:
+    MOV r6, =last;
+    MOV r7, =count;
+    MOV r3, #8;
+loop:
+    // on the wrong path r3 is 0
+    STR r3, [r6];
+    LDR r5, [r7];
+    ADD r5, r5, #1;
+    STR r5, [r7];
+    SUB r3, r3, #1;
+    CBNZ r3, loop;
+    LDR r0, [r6];
+
+.expect r0, 1
+.expect mem[last], 1
+.expect mem[count], 8
@@ -0,0 +1,25 @@
+// Measures the cycles and the retired instructions of a loop using the performance counters:
+// cargo run -- run asm/counters.asm
+.global _start
+
+.section .text
+
+_start:
+    MRS r4, CYCLES;
+    MRS r5, INSTRET;
+
+    MOV r0, #0;
+    MOV r1, #0;
+loop:
+    ADD r0, r0, r1;
+    ADD r1, r1, #1;
+    CMP r1, #100;
+    BLT loop;
+
+    MRS r6, CYCLES;
+    MRS r7, INSTRET;
+    // the cycles and instructions of the loop (including one MRS)
+    SUB r6, r6, r4;
+    SUB r7, r7, r5;
+    PRINTR r6;
+    PRINTR r7;
@@ -0,0 +1,24 @@
+.global _start
+
+.data
+    loop_count: .word 5
+
+.text
+_add_numbers:
+    ADD r2, r0, r1;
+    BX lr;
+
+_start:
+    MOV r0, #1;
+    MOV r1, #2;
+    MOV r3, =loop_count;
+    LDR r3, [r3];
+_loop:
+.deadline 20
+    BL _add_numbers;
+    MUL r0, r2, r1;
+    PRINTR r0;
+.end_deadline
+    SUB r3, r3, #1;
+.loop_bound 5
+    CBNZ r3, _loop;
@@ -0,0 +1,32 @@
+// Allocates an array on the heap and reads one word past its end:
+// cargo run -- run asm/heap.asm
+// With a redzone_size of 1 or more, the overflow faults and names the line of the MALLOC.
+.global _start
+
+.section .text
+
+_start:
+    // an array of 4 words
+    MOV r1, #4;
+    MALLOC r0, r1;
+    MOV r2, #0;
+fill:
+    ADD r3, r0, r2;
+    STR r2, [r3];
+    ADD r2, r2, #1;
+    CMP r2, r1;
+    BLT fill;
+
+    // off by one: reads the word after the array
+    ADD r3, r0, r1;
+    LDR r4, [r3];
+    PRINTR r4;
+
+    FREE r0;
+    BL make_pair;
+    EXIT;
+
+// the block is never freed; it is reported as a leak with the call that led to the MALLOC
+make_pair:
+    MALLOC r5, #2;
+    BX lr;
@@ -0,0 +1,18 @@
+// Sums the 16 words of a memory image at address 1024 and exits with the sum:
+// cargo run -- run asm/image_sum.asm --memory-image asm/image_sum.hex --image-offset 1024 --exit-code
+.global _start
+
+.section .text
+
+_start:
+    MOV r1, #1024;
+    MOV r2, #16;
+    MOV r0, #0;
+loop:
+    LDR r3, [r1];
+    ADD r0, r0, r3;
+    ADD r1, r1, #1;
+    SUB r2, r2, #1;
+    CBNZ r2, loop;
+    PRINTR r0;
+    EXIT;
@@ -0,0 +1,25 @@
+.global _start
+.irq_handler _timer
+
+.data
+    ticks: .word 0
+
+.text
+_timer:
+    MOV r5, =ticks;
+    LDR r6, [r5];
+    ADD r6, r6, #1;
+    STR r6, [r5];
+    PRINTR r6;
+    ERET;
+
+_start:
+    MOV r0, #200;
+    CPSIE;
+_loop:
+    SUB r0, r0, #1;
+    CBNZ r0, _loop;
+    CPSID;
+    MOV r5, =ticks;
+    LDR r6, [r5];
+    PRINTR r6;
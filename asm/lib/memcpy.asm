@@ -0,0 +1,13 @@
+// Copies r2 words from the address in r1 to the address in r0.
+.global memcpy
+
+memcpy:
+    CBZ r2, memcpy_done;
+    LDR r3, [r1];
+    STR r3, [r0];
+    ADD r0, r0, #1;
+    ADD r1, r1, #1;
+    SUB r2, r2, #1;
+    B memcpy;
+memcpy_done:
+    BX lr;
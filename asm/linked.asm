@@ -0,0 +1,22 @@
+// Calls memcpy from asm/lib/memcpy.asm:
+// cargo run -- run asm/linked.asm --lib asm/lib/memcpy.asm
+.global _start
+
+.section .data
+    src0: .word 7
+    src1: .word 8
+    src2: .word 9
+    dst0: .word 0
+    dst1: .word 0
+    dst2: .word 0
+.section .text
+
+_start:
+    LDR r0, =dst0;
+    LDR r1, =src0;
+    MOV r2, #3;
+    BL memcpy;
+
+    LDR r4, =dst2;
+    LDR r4, [r4];
+    PRINTR r4;
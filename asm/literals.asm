@@ -0,0 +1,20 @@
+/*
+ * Shows the comments and the immediate literals supported by the assembler.
+ */
+.global _start
+
+.data
+    mask: .word 0xFF          // hexadecimal
+    flags: .word 0b1010       // binary
+
+.text
+_start:
+    MOV r0, #-42;             // negative
+    MOV r1, #'A';             // character
+    MOV r4, #'\n';            // escaped character
+    LDR r2, =mask;
+    LDR r2, [r2];
+    AND r3, r1, r2;
+    PRINTR r0;
+    PRINTR r3;
+    PRINTR r4;
@@ -0,0 +1,22 @@
+// Message passing litmus test: the data is written before the flag that publishes it.
+// Run with 'memory_model: relaxed' and 'sb_drain_policy: eager'; the flag store can become visible
+// before the data store, which is reported under 'stores reordered'. With 'memory_model: tso' the
+// stores are written in program order and the count stays 0. See message_passing_dmb.asm for the fix.
+.global _start
+
+.data
+    data: .word 0
+    flag: .word 0
+
+.text
+
+_start:
+    MOV r0, =data;
+    MOV r1, =flag;
+    MOV r2, #42;
+    MOV r3, #1;
+    STR r2, [r0];
+    STR r3, [r1];
+    LDR r4, [r1];
+    LDR r5, [r0];
+    PRINTR r5;
@@ -0,0 +1,22 @@
+// Message passing litmus test with a barrier: the DMB waits till the data store is visible in memory
+// before the flag store can be written. With 'memory_model: relaxed' and 'sb_drain_policy: eager' the
+// 'stores reordered' count stays 0 for this pair of stores.
+.global _start
+
+.data
+    data: .word 0
+    flag: .word 0
+
+.text
+
+_start:
+    MOV r0, =data;
+    MOV r1, =flag;
+    MOV r2, #42;
+    MOV r3, #1;
+    STR r2, [r0];
+    DMB;
+    STR r3, [r1];
+    LDR r4, [r1];
+    LDR r5, [r0];
+    PRINTR r5;
@@ -0,0 +1,24 @@
+.global _start
+
+.text
+_start:
+    MOV r0, #3;
+    MOV r1, #0;
+1:
+    // counts down from 3; the local label 1 is declared again below
+    ADD r1, r1, r0;
+    SUB r0, r0, #1;
+    CBNZ r0, 1b;
+    PRINTR r1;
+
+    MOV r0, #2;
+1:
+    SUB r0, r0, #1;
+    CBZ r0, 2f;
+    B 1b;
+2:
+    // skips the PRINTR after the label
+    B skip+1;
+skip:
+    PRINTR r0;
+    PRINTR r1;
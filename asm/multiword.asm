@@ -0,0 +1,28 @@
+// Adds two 2-word numbers and compares words unsigned:
+// cargo run -- run asm/multiword.asm
+.global _start
+
+.section .text
+
+_start:
+    // a = r1:r0, b = r3:r2 (high:low)
+    MOV r0, #-1;
+    MOV r1, #1;
+    MOV r2, #1;
+    MOV r3, #2;
+
+    // r5:r4 = a + b; the carry of the low words goes into the high words
+    ADDS r4, r0, r2;
+    ADC r5, r1, r3;
+    PRINTR r4;
+    PRINTR r5;
+
+    // -1 is the largest unsigned word
+    CMP r0, r2;
+    BHI higher;
+    MOV r6, #0;
+    B end;
+higher:
+    MOV r6, #1;
+end:
+    PRINTR r6;
@@ -0,0 +1,22 @@
+// Reads a decimal number from the input and prints it doubled:
+// echo 21 | cargo run -- run asm/read_input.asm
+.global _start
+
+.section .text
+
+_start:
+    MOV r1, #0;
+next:
+    // -1 at the end of the input
+    READC r0;
+    SUB r0, r0, #'0';
+    // stops at the first byte that isn't a digit
+    CMP r0, #9;
+    BHI done;
+    MOV r2, #10;
+    MUL r1, r1, r2;
+    ADD r1, r1, r0;
+    B next;
+done:
+    ADD r1, r1, r1;
+    PRINTR r1;
@@ -0,0 +1,25 @@
+// Calls a function in Thumb code from ARM code; with fetch_bytes set (e.g. 8), the Thumb loop
+// fetches twice as many instructions per cycle:
+// cargo run -- run asm/thumb.asm
+.global _start
+
+.section .text
+
+_start:
+    MOV r0, #0;
+    MOV r1, #100;
+    // BL switches to Thumb code; the BX lr of the function switches back
+    BL sum;
+    PRINTR r0;
+    EXIT;
+
+.thumb
+// adds 1 up to r1 to r0; every instruction has a 2 byte encoding
+sum:
+    MOV r2, #1;
+loop:
+    ADD r0, r0, r2;
+    ADD r2, r2, #1;
+    CMP r2, r1;
+    BLE loop;
+    BX lr;
@@ -0,0 +1,66 @@
+// Benchmarks the cycle loop of the simulator: runs a nested loop of loads, stores, multiplies and
+// branches without the per-cycle sleep and prints the simulated cycles per second of the host. Run it
+// before and after a change to the pipeline plumbing; e.g. the instruction indices (InstrIndex) that
+// replaced the Rc<Instr> in every queue.
+//
+// cargo bench --bench cycle_loop
+
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use rust_cpu_emulator::cpu::{CPU, load_cpu_config};
+use rust_cpu_emulator::loader::loader::load_from_str;
+
+const RUNS: usize = 5;
+
+// 50.000 iterations of the inner loop; about 550.000 cycles with the cpu.yaml.
+const PROGRAM: &str = "
+.data
+    total: .word 0
+    value: .word 3
+.text
+_start:
+    MOV r6, =total;
+    MOV r7, =value;
+    MOV r1, #200;
+outer:
+    MOV r2, #250;
+inner:
+    LDR r3, [r7];
+    ADD r3, r3, r2;
+    MUL r4, r3, r1;
+    LDR r5, [r6];
+    ADD r5, r5, r4;
+    STR r5, [r6];
+    SUB r2, r2, #1;
+    CBNZ r2, inner;
+    SUB r1, r1, #1;
+    CBNZ r1, outer;
+";
+
+fn main() {
+    let mut config = load_cpu_config(concat!(env!("CARGO_MANIFEST_DIR"), "/cpu.yaml"))
+        .unwrap_or_else(|error| panic!("{}", error));
+    config.set_trace(false);
+    let program = Rc::new(load_from_str(config.clone(), PROGRAM).unwrap_or_else(|error| panic!("{}", error)));
+
+    let mut durations: Vec<Duration> = Vec::with_capacity(RUNS);
+    let mut cycles = 0;
+    for _ in 0..RUNS {
+        let mut cpu = CPU::new(&config);
+        cpu.set_throttled(false);
+        cpu.set_quiet(true);
+        cpu.set_stdout(Box::new(io::sink()));
+
+        let start = Instant::now();
+        cpu.run(&program).unwrap_or_else(|diagnosis| panic!("{}", diagnosis));
+        durations.push(start.elapsed());
+        cycles = cpu.perf_counters().cycle_cnt;
+    }
+
+    durations.sort();
+    let median = durations[RUNS / 2];
+    println!("cycle_loop: {} cycles, median {:?} ({:.0} cycles/s), best {:?} over {} runs",
+             cycles, median, cycles as f64 / median.as_secs_f64(), durations[0], RUNS);
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::cpu::{CPSR, CPUConfig, FP, LR, PC, SP};
+use crate::instructions::instructions::RegisterType;
+
+/// The register file as seen by a program: the configured number of general purpose registers and
+/// the registers with a special role.
+///
+/// The CPU keeps the FP, SP, LR and PC in fixed slots of its register file: the slots r11 and r13-r15.
+/// A program numbers its registers as configured (abi in cpu.yaml): the loader translates rN to the slot
+/// (see slot), and the slot of a special register that is numbered differently is used by the number
+/// the special register leaves free. E.g. with sp: 29, r29 is the SP and r13 is kept in slot 29. The CPSR
+/// is addressed by name, so its slot doesn't need a number.
+#[derive(Clone, Serialize, Debug)]
+pub struct Abi {
+    pub general_reg_count: u16,
+    pub fp: RegisterType,
+    pub sp: RegisterType,
+    pub lr: RegisterType,
+    pub pc: RegisterType,
+    pub cpsr: RegisterType,
+}
+
+impl Abi {
+    pub fn new(cpu_config: &CPUConfig) -> Abi {
+        let abi = &cpu_config.abi;
+        Abi { general_reg_count: cpu_config.general_reg_count, fp: abi.fp, sp: abi.sp, lr: abi.lr, pc: abi.pc, cpsr: CPSR }
+    }
+
+    /// True for r0 up to the last configured general purpose register.
+    pub fn is_general_register(&self, reg: RegisterType) -> bool {
+        reg < self.general_reg_count
+    }
+
+    /// The slot in the register file of rN.
+    pub(crate) fn slot(&self, number: RegisterType) -> RegisterType {
+        let specials = self.specials();
+        if let Some((_, slot)) = specials.iter().find(|(special, _)| *special == number) {
+            return *slot;
+        }
+        let (numbers, slots) = self.swapped();
+        match numbers.iter().position(|swapped| *swapped == number) {
+            Some(k) => slots[k],
+            None => number,
+        }
+    }
+
+    /// rN of a slot of a general purpose register; the inverse of slot.
+    pub(crate) fn number(&self, slot: RegisterType) -> RegisterType {
+        let specials = self.specials();
+        if let Some((number, _)) = specials.iter().find(|(_, special)| *special == slot) {
+            return *number;
+        }
+        let (numbers, slots) = self.swapped();
+        match slots.iter().position(|swapped| *swapped == slot) {
+            Some(k) => numbers[k],
+            None => slot,
+        }
+    }
+
+    /// The names of the general purpose registers that aren't kept in their own slot (e.g. R13 with
+    /// sp: 29), so an instruction is printed with the registers the program used; empty for the default ABI.
+    pub(crate) fn register_names(&self) -> HashMap<RegisterType, String> {
+        let (numbers, slots) = self.swapped();
+        numbers.iter().zip(slots.iter())
+            .map(|(number, slot)| (*slot, format!("R{}", number)))
+            .collect()
+    }
+
+    // (number, slot) of the special registers
+    fn specials(&self) -> [(RegisterType, RegisterType); 4] {
+        [(self.fp, FP), (self.sp, SP), (self.lr, LR), (self.pc, PC)]
+    }
+
+    // The numbers of the slots of the special registers that aren't special registers themselves, and the
+    // slots of the numbers of the special registers that they are kept in instead; both in ascending order.
+    fn swapped(&self) -> (Vec<RegisterType>, Vec<RegisterType>) {
+        let specials = self.specials();
+        let mut numbers: Vec<RegisterType> = specials.iter().map(|(_, slot)| *slot)
+            .filter(|slot| !specials.iter().any(|(number, _)| number == slot))
+            .collect();
+        let mut slots: Vec<RegisterType> = specials.iter().map(|(number, _)| *number)
+            .filter(|number| !specials.iter().any(|(_, slot)| slot == number))
+            .collect();
+        numbers.sort();
+        slots.sort();
+        (numbers, slots)
+    }
+}
+
+/// The registers a function needs to preserve for its caller according to the AAPCS.
+///
+/// R4-R10 and FP (R11) are callee saved. The SP is included since a function needs
+/// to return with the stack pointer it was called with.
+pub(crate) const CALLEE_SAVED_REGS: [RegisterType; 9] = [4, 5, 6, 7, 8, 9, 10, FP, SP];
+
+/// The registers that pass the first arguments of a function according to the AAPCS; further
+/// arguments are passed on the stack.
+pub(crate) const ARGUMENT_REGS: [RegisterType; 4] = [0, 1, 2, 3];
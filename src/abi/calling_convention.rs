@@ -0,0 +1,91 @@
+use std::fmt;
+
+use crate::abi::abi::CALLEE_SAVED_REGS;
+use crate::analysis::stack_usage::function_name;
+use crate::cpu::ArgRegFile;
+use crate::instructions::instructions::{Instr, Operand, Program, RegisterType, SourceLocation, WordType};
+
+/// A violation of the calling convention detected when a function returns.
+pub(crate) struct CallingConventionViolation {
+    pub(crate) function: String,
+    pub(crate) call_loc: Option<SourceLocation>,
+    pub(crate) return_loc: Option<SourceLocation>,
+    pub(crate) register: RegisterType,
+    pub(crate) expected: WordType,
+    pub(crate) actual: WordType,
+}
+
+impl fmt::Display for CallingConventionViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Calling convention violation: function '{}' clobbered {} (expected {}, found {})",
+               self.function, Operand::Register(self.register), self.expected, self.actual)?;
+
+        if let Some(loc) = self.call_loc {
+            write!(f, ", called at {}", loc)?;
+        }
+
+        if let Some(loc) = self.return_loc {
+            write!(f, ", returned at {}", loc)?;
+        }
+        Ok(())
+    }
+}
+
+struct CallFrame {
+    function: String,
+    call_loc: Option<SourceLocation>,
+    saved: [WordType; CALLEE_SAVED_REGS.len()],
+}
+
+/// Checks at retirement that functions called using BL or BLX preserve the callee saved registers.
+///
+/// On every call (BL or BLX) the callee saved registers are snapshotted and on the matching return (BX LR or POP {.., pc})
+/// they are compared with the current values. Since instructions retire in program order, the
+/// architectural registers are precise at those points.
+pub(crate) struct CallingConventionChecker {
+    frames: Vec<CallFrame>,
+}
+
+impl CallingConventionChecker {
+    pub(crate) fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+
+    pub(crate) fn on_retire(&mut self,
+                            instr: &Instr,
+                            next_pc: usize,
+                            arch_reg_file: &ArgRegFile,
+                            program: &Program) -> Vec<CallingConventionViolation> {
+        let mut violations = Vec::new();
+
+        if instr.is_call() {
+            let function = function_name(program, next_pc);
+
+            let mut saved = [0; CALLEE_SAVED_REGS.len()];
+            for (k, reg) in CALLEE_SAVED_REGS.iter().enumerate() {
+                saved[k] = arch_reg_file.get_value(*reg);
+            }
+
+            self.frames.push(CallFrame { function, call_loc: instr.loc, saved });
+        } else if instr.is_return() {
+            // A return without a matching call (e.g. returning from the entry point) can't be checked.
+            if let Some(frame) = self.frames.pop() {
+                for (k, reg) in CALLEE_SAVED_REGS.iter().enumerate() {
+                    let actual = arch_reg_file.get_value(*reg);
+                    if actual != frame.saved[k] {
+                        violations.push(CallingConventionViolation {
+                            function: frame.function.clone(),
+                            call_loc: frame.call_loc,
+                            return_loc: instr.loc,
+                            register: *reg,
+                            expected: frame.saved[k],
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+
+        violations
+    }
+}
@@ -0,0 +1,2 @@
+pub mod abi;
+pub mod calling_convention;
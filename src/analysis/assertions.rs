@@ -0,0 +1,83 @@
+use crate::cpu::ArgRegFile;
+use crate::instructions::instructions::{Instr, mnemonic, Operand, RegisterType, SourceLocation, WordType};
+
+/// The architectural state visible to an assertion when an instruction retires.
+///
+/// The registers already contain the values written by the retiring instruction.
+pub struct RetireEvent<'a> {
+    pub(crate) instr: &'a Instr,
+    pub(crate) pc: usize,
+    pub(crate) arch_reg_file: &'a ArgRegFile,
+}
+
+impl RetireEvent<'_> {
+    pub fn mnemonic(&self) -> &'static str {
+        mnemonic(self.instr.opcode)
+    }
+
+    /// The code address of the retiring instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn loc(&self) -> Option<SourceLocation> {
+        self.instr.loc
+    }
+
+    pub fn register(&self, reg: RegisterType) -> WordType {
+        self.arch_reg_file.get_value(reg)
+    }
+
+    /// Checks if the retiring instruction writes the given register.
+    pub fn writes_register(&self, reg: RegisterType) -> bool {
+        self.instr.sink[..self.instr.sink_cnt as usize].iter()
+            .any(|sink| matches!(sink, Operand::Register(r) if *r == reg))
+    }
+}
+
+/// An invariant that is checked every time an instruction retires.
+///
+/// A violation is reported as a fault on the offending instruction and stops the program.
+pub trait RetirementAssertion {
+    fn name(&self) -> &str;
+
+    /// Returns an error describing the violation when the invariant doesn't hold.
+    fn check(&self, event: &RetireEvent) -> Result<(), String>;
+}
+
+/// Asserts that a register is never written; e.g. a register reserved by a coding rule.
+pub struct RegisterNeverWritten(pub RegisterType);
+
+impl RetirementAssertion for RegisterNeverWritten {
+    fn name(&self) -> &str {
+        "register never written"
+    }
+
+    fn check(&self, event: &RetireEvent) -> Result<(), String> {
+        if event.writes_register(self.0) {
+            return Err(format!("{} is written", Operand::Register(self.0)));
+        }
+        Ok(())
+    }
+}
+
+/// Asserts that the value of a register is always a multiple of the alignment; e.g. the SP.
+pub struct RegisterAligned {
+    pub register: RegisterType,
+    pub alignment: WordType,
+}
+
+impl RetirementAssertion for RegisterAligned {
+    fn name(&self) -> &str {
+        "register aligned"
+    }
+
+    fn check(&self, event: &RetireEvent) -> Result<(), String> {
+        let value = event.register(self.register);
+        if value % self.alignment != 0 {
+            return Err(format!("{} has value {} which isn't aligned to {}",
+                               Operand::Register(self.register), value, self.alignment));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::analysis::stack_usage::function_name;
+use crate::instructions::instructions::{Instr, Opcode, Program};
+
+// The cycles and retired instructions attributed to a function, a call or an instruction.
+#[derive(Clone, Copy, Default)]
+struct Cost {
+    cycles: u64,
+    retired: u64,
+}
+
+impl Cost {
+    fn add(&mut self, other: Cost) {
+        self.cycles += other.cycles;
+        self.retired += other.retired;
+    }
+}
+
+#[derive(Clone, Default)]
+struct FunctionStats {
+    calls: u64,
+    // the instructions of the function itself
+    exclusive: Cost,
+    // including the functions it called; a recursive call only counts in the outermost frame
+    inclusive: Cost,
+}
+
+// The calls from a call site to a function.
+#[derive(Clone, Default)]
+struct CallStats {
+    calls: u64,
+    // the inclusive cost of the called function
+    cost: Cost,
+}
+
+#[derive(Clone)]
+struct CallFrame {
+    // the code address of the function
+    function: usize,
+    // the code address of the BL or BLX; None for the entry point
+    call_site: Option<usize>,
+    // the cost of the frame including its callees so far
+    cost: Cost,
+}
+
+/// Builds the dynamic call graph at retirement with the inclusive and exclusive cycles per function.
+///
+/// A call (BL or BLX) opens a frame for the function at its target and the matching return (BX LR or
+/// POP {.., pc}) closes it; the entry point is the root frame. Like the loop profiler, the cycles between
+/// two retirements are attributed to the later instruction, and so to the function of the innermost
+/// frame. Functions are named after their label (a function in a library too). The graph is reported at
+/// the end of the program and can be exported in the callgrind format (see to_callgrind) for KCachegrind.
+#[derive(Clone)]
+pub(crate) struct CallGraphProfiler {
+    program: Rc<Program>,
+    frames: Vec<CallFrame>,
+    // code address of the function -> stats
+    functions: BTreeMap<usize, FunctionStats>,
+    // (caller, call site, callee) -> stats
+    calls: BTreeMap<(usize, usize, usize), CallStats>,
+    // (function, code address) -> the cost of the instruction in the function
+    instructions: BTreeMap<(usize, usize), Cost>,
+    // the cycle the previous instruction retired
+    last_retire_cycle: u64,
+}
+
+impl CallGraphProfiler {
+    pub(crate) fn new(program: &Rc<Program>) -> Self {
+        Self {
+            program: Rc::clone(program),
+            frames: Vec::new(),
+            functions: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            instructions: BTreeMap::new(),
+            last_retire_cycle: 0,
+        }
+    }
+
+    pub(crate) fn on_retire(&mut self, instr: &Instr, pc: usize, next_pc: usize, cycle: u64) {
+        if self.frames.is_empty() {
+            self.open(self.program.entry_point, None);
+        }
+
+        let cost = Cost { cycles: cycle - self.last_retire_cycle, retired: 1 };
+        self.last_retire_cycle = cycle;
+
+        let frame = self.frames.last_mut().unwrap();
+        frame.cost.add(cost);
+        self.functions.get_mut(&frame.function).unwrap().exclusive.add(cost);
+        self.instructions.entry((frame.function, pc)).or_default().add(cost);
+
+        match instr.opcode {
+            _ if instr.is_call() => self.open(next_pc, Some(pc)),
+            // the root frame is only closed when the program exits
+            _ if instr.is_return() && self.frames.len() > 1 => self.close(),
+            Opcode::EXIT => self.unwind(),
+            _ => {}
+        }
+    }
+
+    fn open(&mut self, function: usize, call_site: Option<usize>) {
+        self.functions.entry(function).or_default().calls += 1;
+        self.frames.push(CallFrame { function, call_site, cost: Cost::default() });
+    }
+
+    fn close(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        if !self.frames.iter().any(|outer| outer.function == frame.function) {
+            self.functions.get_mut(&frame.function).unwrap().inclusive.add(frame.cost);
+        }
+
+        if let (Some(caller), Some(call_site)) = (self.frames.last_mut(), frame.call_site) {
+            caller.cost.add(frame.cost);
+            let call = self.calls.entry((caller.function, call_site, frame.function)).or_default();
+            call.calls += 1;
+            call.cost.add(frame.cost);
+        }
+    }
+
+    // Closes all frames; the frames of a program that ended with a fault are still open.
+    fn unwind(&mut self) {
+        while !self.frames.is_empty() {
+            self.close();
+        }
+    }
+
+    fn name(&self, function: usize) -> String {
+        function_name(&self.program, function)
+    }
+
+    fn line(&self, pc: usize) -> usize {
+        self.program.line_for(pc).unwrap_or(0)
+    }
+
+    /// The call graph in the callgrind format of Valgrind, so it can be visualized using KCachegrind. The
+    /// positions are the code address and the source line of the instructions of the file; the events
+    /// are the cycles and the retired instructions.
+    pub(crate) fn to_callgrind(&self, file: &str) -> String {
+        let mut profile = self.clone();
+        profile.unwind();
+
+        let total = profile.total();
+        let mut out = String::new();
+        writeln!(out, "# callgrind format").unwrap();
+        writeln!(out, "version: 1").unwrap();
+        writeln!(out, "creator: rust_cpu_emulator").unwrap();
+        writeln!(out, "cmd: {}", file).unwrap();
+        writeln!(out, "positions: instr line").unwrap();
+        writeln!(out, "events: Cycles Instructions").unwrap();
+        writeln!(out, "summary: {} {}", total.cycles, total.retired).unwrap();
+
+        for function in profile.functions.keys() {
+            writeln!(out).unwrap();
+            writeln!(out, "fl={}", file).unwrap();
+            writeln!(out, "fn={}", profile.name(*function)).unwrap();
+            for ((_, pc), cost) in profile.instructions.range((*function, 0)..=(*function, usize::MAX)) {
+                writeln!(out, "{} {} {} {}", pc, profile.line(*pc), cost.cycles, cost.retired).unwrap();
+            }
+            for ((_, call_site, callee), call) in profile.calls.range((*function, 0, 0)..=(*function, usize::MAX, usize::MAX)) {
+                writeln!(out, "cfl={}", file).unwrap();
+                writeln!(out, "cfn={}", profile.name(*callee)).unwrap();
+                writeln!(out, "calls={} {} {}", call.calls, callee, profile.line(*callee)).unwrap();
+                writeln!(out, "{} {} {} {}", call_site, profile.line(*call_site), call.cost.cycles, call.cost.retired).unwrap();
+            }
+        }
+        out
+    }
+
+    // The cost of all retired instructions.
+    fn total(&self) -> Cost {
+        let mut total = Cost::default();
+        for cost in self.instructions.values() {
+            total.add(*cost);
+        }
+        total
+    }
+}
+
+impl fmt::Display for CallGraphProfiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut profile = self.clone();
+        profile.unwind();
+
+        writeln!(f, "Call graph:")?;
+        let total = profile.total().cycles.max(1);
+        let percentage = |cycles: u64| 100.0 * cycles as f64 / total as f64;
+
+        // ties are broken on the code address, so the report is deterministic
+        let mut functions: Vec<(&usize, &FunctionStats)> = profile.functions.iter().collect();
+        functions.sort_by_key(|(function, stats)| (Reverse(stats.inclusive.cycles), **function));
+        for (function, stats) in functions {
+            writeln!(f, "  {:<20} calls={:<6} inclusive={} cycles ({:.1}%) exclusive={} cycles ({:.1}%) instructions={}",
+                     profile.name(*function), stats.calls, stats.inclusive.cycles, percentage(stats.inclusive.cycles),
+                     stats.exclusive.cycles, percentage(stats.exclusive.cycles), stats.inclusive.retired)?;
+            for ((_, call_site, callee), call) in profile.calls.range((*function, 0, 0)..=(*function, usize::MAX, usize::MAX)) {
+                let line = profile.program.line_for(*call_site).map(|line| format!(" (line {})", line)).unwrap_or_default();
+                writeln!(f, "    -> {} from {}{}: {} calls, {} cycles", profile.name(*callee), call_site, line, call.calls, call.cost.cycles)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+
+use crate::instructions::instructions::{Instr, Opcode, Operand};
+
+// The code address the instruction refers to, e.g. the target of a branch or the function of an ADR.
+fn code_operand(instr: &Instr) -> Option<usize> {
+    instr.source.iter()
+        .take(instr.source_cnt as usize)
+        .find_map(|operand| match operand {
+            Operand::Code(address) => Some(*address as usize),
+            _ => None,
+        })
+}
+
+// The code addresses that can execute after the instruction at the address. An indirect branch has
+// no known successors; its possible targets are the address taken code labels.
+fn successors(code: &[Instr], address: usize) -> Vec<usize> {
+    let instr = &code[address];
+    let next = address + 1;
+    match instr.opcode {
+        Opcode::EXIT | Opcode::ERET => Vec::new(),
+        _ if !instr.is_control => vec![next],
+        _ if instr.is_return() => Vec::new(),
+        Opcode::B => code_operand(instr).into_iter().collect(),
+        Opcode::BX => Vec::new(),
+        // an indirect call returns to the next instruction
+        Opcode::BLX => vec![next],
+        // a call returns to the next instruction and a conditional branch can fall through
+        _ => code_operand(instr).into_iter().chain(Some(next)).collect(),
+    }
+}
+
+/// Finds the instructions that can't be reached from any of the roots (the entry point, the interrupt
+/// handler and the code addresses taken using e.g. `LDR r0, =label`), following the control flow graph
+/// of the code. Returns the unreachable instructions as ranges of consecutive code addresses
+/// [start, end) within [first, code.len()).
+pub(crate) fn unreachable_code(code: &[Instr], first: usize, roots: &[usize]) -> Vec<(usize, usize)> {
+    let mut stack: Vec<usize> = roots.to_vec();
+    // an ADR of a code label takes its address as well
+    stack.extend(code.iter().filter(|instr| !instr.is_control).filter_map(code_operand));
+
+    let mut reachable = HashSet::new();
+    while let Some(address) = stack.pop() {
+        // the address just past the last instruction is the implicit EXIT
+        if address < code.len() && reachable.insert(address) {
+            stack.extend(successors(code, address));
+        }
+    }
+
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for address in first..=code.len() {
+        let unreachable = address < code.len() && !reachable.contains(&address);
+        match (unreachable, start) {
+            (true, None) => start = Some(address),
+            (false, Some(s)) => {
+                ranges.push((s, address));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use crate::instructions::instructions::{Deadline, Instr};
+
+/// A dynamic execution of a deadline region that exceeded its cycle budget.
+pub(crate) struct DeadlineMiss {
+    pub(crate) deadline: Deadline,
+    pub(crate) execution: u64,
+    pub(crate) cycles: u64,
+}
+
+impl fmt::Display for DeadlineMiss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Deadline miss: execution #{} of the region at {} took {} cycles; the budget is {} cycles",
+               self.execution, self.deadline.loc, self.cycles, self.deadline.budget)
+    }
+}
+
+#[derive(Default)]
+struct RegionState {
+    // the cycle at which the current execution entered the region
+    entered: Option<u64>,
+    // the depth of the functions called from the region
+    call_depth: u32,
+    executions: u64,
+    misses: u64,
+    worst: u64,
+}
+
+/// Measures at retirement how many cycles every execution of a deadline region takes.
+///
+/// An execution starts when the first instruction of the region retires and ends when an
+/// instruction outside of the region retires. Functions called from the region using BL or BLX are
+/// part of the execution till they return using BX LR or POP {.., pc}.
+pub(crate) struct DeadlineMonitor {
+    deadlines: Vec<Deadline>,
+    states: Vec<RegionState>,
+}
+
+impl DeadlineMonitor {
+    pub(crate) fn new(deadlines: &[Deadline]) -> Self {
+        Self {
+            deadlines: deadlines.to_vec(),
+            states: deadlines.iter().map(|_| RegionState::default()).collect(),
+        }
+    }
+
+    pub(crate) fn on_retire(&mut self, instr: &Instr, pc: usize, cycle: u64) -> Vec<DeadlineMiss> {
+        let mut misses = Vec::new();
+
+        for (deadline, state) in self.deadlines.iter().zip(self.states.iter_mut()) {
+            let inside = pc >= deadline.start && pc < deadline.end;
+
+            if state.entered.is_none() {
+                if pc != deadline.start {
+                    continue;
+                }
+                state.entered = Some(cycle);
+                state.call_depth = 0;
+            } else if state.call_depth == 0 && !inside {
+                let cycles = cycle - state.entered.take().unwrap();
+                state.executions += 1;
+                state.worst = state.worst.max(cycles);
+                if cycles > deadline.budget {
+                    state.misses += 1;
+                    misses.push(DeadlineMiss { deadline: *deadline, execution: state.executions, cycles });
+                }
+                continue;
+            }
+
+            if instr.is_call() {
+                state.call_depth += 1;
+            } else if instr.is_return() && state.call_depth > 0 {
+                state.call_depth -= 1;
+            }
+        }
+
+        misses
+    }
+}
+
+impl fmt::Display for DeadlineMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Deadlines:")?;
+        for (deadline, state) in self.deadlines.iter().zip(self.states.iter()) {
+            writeln!(f, "  region at {}: budget={} executions={} misses={} worst={}",
+                     deadline.loc, deadline.budget, state.executions, state.misses, state.worst)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+
+use crate::cpu::{EnergyConfig, PerfCounters};
+use crate::instructions::instructions::EU_TYPES;
+
+/// The energy consumed by a run according to the energy model, in picojoules.
+///
+/// The energy is derived from the performance counters, so instructions on a path that is flushed
+/// later on (e.g. after a misprediction) also consume energy when they were dispatched.
+#[derive(Clone, Debug)]
+pub struct EnergyReport {
+    // the dynamic energy of the execution units; indexed like EU_TYPES
+    pub execute: [f64; EU_TYPES.len()],
+    pub rename: f64,
+    pub mispredict: f64,
+    // the static energy of all cycles
+    pub leakage: f64,
+    pub retire_cnt: u64,
+}
+
+impl EnergyReport {
+    pub(crate) fn new(energy_config: &EnergyConfig, perf_counters: &PerfCounters) -> EnergyReport {
+        let mut execute = [0.0; EU_TYPES.len()];
+        for eu_type in EU_TYPES {
+            execute[eu_type as usize] = perf_counters.eu_dispatch_cnt[eu_type as usize] as f64 * energy_config.get(eu_type);
+        }
+
+        EnergyReport {
+            execute,
+            rename: perf_counters.rename_cnt as f64 * energy_config.rename,
+            mispredict: (perf_counters.btb_mispredict_cnt + perf_counters.ras_mispredict_cnt) as f64 * energy_config.mispredict,
+            leakage: perf_counters.cycle_cnt as f64 * energy_config.cycle,
+            retire_cnt: perf_counters.retire_cnt,
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.execute.iter().sum::<f64>() + self.rename + self.mispredict + self.leakage
+    }
+
+    // the energy per retired instruction; the energy of flushed instructions is attributed to the retired ones
+    pub fn per_instruction(&self) -> f64 {
+        self.total() / self.retire_cnt as f64
+    }
+}
+
+impl fmt::Display for EnergyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Energy: {:.1} pJ ({:.2} pJ per instruction)", self.total(), self.per_instruction())?;
+        for eu_type in EU_TYPES {
+            writeln!(f, "  {:<14} {:.1} pJ", format!("{}:", eu_type), self.execute[eu_type as usize])?;
+        }
+        writeln!(f, "  {:<14} {:.1} pJ", "Rename:", self.rename)?;
+        writeln!(f, "  {:<14} {:.1} pJ", "Mispredicts:", self.mispredict)?;
+        writeln!(f, "  {:<14} {:.1} pJ", "Leakage:", self.leakage)
+    }
+}
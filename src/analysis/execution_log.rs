@@ -0,0 +1,60 @@
+use std::fmt::Write;
+
+use crate::cpu::{CPSR, Flags, fp_value, FPSCR, is_fp_register, PC};
+use crate::instructions::instructions::{AliasedOperand, Instr, Operand, Program, RegisterType, WordType};
+
+// A register or memory word written by an instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LogTarget {
+    Register(RegisterType),
+    Memory(WordType),
+}
+
+// The registers and memory words the instruction writes; the store address and the number of words
+// are known once the instruction has executed. The PC is left out since every instruction changes it.
+pub(crate) fn log_targets(instr: &Instr, store_addr: Option<WordType>, transfer_cnt: u8) -> Vec<LogTarget> {
+    let mut targets: Vec<LogTarget> = instr.sink.iter()
+        .take(instr.sink_cnt as usize)
+        .filter_map(|sink| match sink {
+            Operand::Register(reg) if *reg != PC => Some(LogTarget::Register(*reg)),
+            _ => None,
+        })
+        .collect();
+    if let Some(addr) = store_addr {
+        targets.extend((0..transfer_cnt.max(1) as WordType).map(|k| LogTarget::Memory(addr + k)));
+    }
+    targets
+}
+
+// The line of the execution log for a retired instruction: the instruction with its source location and the
+// registers and memory words it changed, e.g. `r3: 5 -> 8`. The values are (target, before, after) and
+// are captured around the retirement, so only the architectural execution is logged.
+pub(crate) fn log_line(instr: &Instr, pc: usize, cycle: u64, values: &[(LogTarget, WordType, WordType)], program: &Program) -> String {
+    // the instruction prints its source location, e.g. `ADD r3, r3, r1 ; 9:5`
+    let mut line = format!("[{}] {}: {}", cycle, pc, instr.with_aliases(&program.register_aliases));
+
+    let changes: Vec<String> = values.iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(target, before, after)| match target {
+            LogTarget::Register(reg) => {
+                let register = AliasedOperand { operand: Operand::Register(*reg), aliases: Some(&program.register_aliases) };
+                format!("{}: {} -> {}", register, register_value(*reg, *before), register_value(*reg, *after))
+            }
+            LogTarget::Memory(addr) => format!("mem[{}]: {} -> {}", addr, before, after),
+        })
+        .collect();
+    if !changes.is_empty() {
+        write!(line, "  {}", changes.join(", ")).unwrap();
+    }
+    line
+}
+
+fn register_value(reg: RegisterType, value: WordType) -> String {
+    if is_fp_register(reg) {
+        fp_value(value).to_string()
+    } else if reg == CPSR || reg == FPSCR {
+        Flags::from_cpsr(value).to_string()
+    } else {
+        value.to_string()
+    }
+}
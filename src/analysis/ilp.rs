@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::PC;
+use crate::instructions::instructions::{Instr, Opcode, Operand, RegisterType, WordType};
+
+/// Computes the dataflow limit of the retired instruction stream.
+///
+/// The instructions are replayed on an ideal machine with infinite resources and perfect branch
+/// prediction: an instruction completes 'latency' cycles after the last of its register and memory
+/// dependencies completed. The dataflow limit IPC is the number of instructions divided by the
+/// completion time of the last instruction.
+pub(crate) struct IlpStudy {
+    // register -> the cycle its last value is available
+    registers: HashMap<RegisterType, u64>,
+    // memory address -> the cycle its last value is available
+    memory: HashMap<WordType, u64>,
+    instr_cnt: u64,
+    height: u64,
+}
+
+impl IlpStudy {
+    pub(crate) fn new() -> Self {
+        Self { registers: HashMap::new(), memory: HashMap::new(), instr_cnt: 0, height: 0 }
+    }
+
+    pub(crate) fn on_retire(&mut self,
+                            instr: &Instr,
+                            load_addr: Option<WordType>,
+                            store_addr: Option<WordType>,
+                            transfer_cnt: u8) {
+        if instr.opcode == Opcode::EXIT {
+            return;
+        }
+
+        // with perfect prediction, the PC is never a dependency
+        let mut ready = instr.source.iter()
+            .take(instr.source_cnt as usize)
+            .filter_map(|operand| match operand {
+                Operand::Register(reg) if *reg != PC => self.registers.get(reg).copied(),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        if let Some(addr) = load_addr {
+            for word in addr..addr + transfer_cnt as WordType {
+                ready = ready.max(self.memory.get(&word).copied().unwrap_or(0));
+            }
+        }
+
+        let complete = ready + instr.cycles as u64;
+
+        for sink in instr.sink.iter().take(instr.sink_cnt as usize) {
+            if let Operand::Register(reg) = sink {
+                if *reg != PC {
+                    self.registers.insert(*reg, complete);
+                }
+            }
+        }
+
+        if let Some(addr) = store_addr {
+            for word in addr..addr + transfer_cnt as WordType {
+                self.memory.insert(word, complete);
+            }
+        }
+
+        self.instr_cnt += 1;
+        self.height = self.height.max(complete);
+    }
+
+    pub(crate) fn ipc(&self) -> f32 {
+        self.instr_cnt as f32 / self.height.max(1) as f32
+    }
+}
+
+impl fmt::Display for IlpStudy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Dataflow limit: {} instructions in {} cycles [IPC={:.2}]", self.instr_cnt, self.height, self.ipc())
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+use crate::analysis::watchpoint::WatchLocation;
+use crate::instructions::instructions::WordType;
+
+// A retired instruction with the registers and memory words it wrote and their values before it retired.
+#[derive(Clone, Debug)]
+pub(crate) struct JournalEntry {
+    // the code address of the instruction
+    pub pc: usize,
+    pub before: Vec<(WatchLocation, WordType)>,
+}
+
+/// The journal of the architectural changes of the last retired instructions; so a debugger can step
+/// back (CPU::step_back). It holds at most depth instructions; the oldest entry is dropped first. The heap,
+/// the input, the output and the interrupt state can't be undone, so the journal starts over after an
+/// instruction or interrupt that changes them.
+pub(crate) struct Journal {
+    depth: usize,
+    entries: VecDeque<JournalEntry>,
+}
+
+impl Journal {
+    pub(crate) fn new(depth: u32) -> Self {
+        Journal { depth: depth as usize, entries: VecDeque::with_capacity(depth as usize) }
+    }
+
+    pub(crate) fn record(&mut self, entry: JournalEntry) {
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    // Removes the youngest entry; the instruction that retired last.
+    pub(crate) fn pop(&mut self) -> Option<JournalEntry> {
+        self.entries.pop_back()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // The number of instructions that can be stepped back.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
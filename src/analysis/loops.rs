@@ -0,0 +1,142 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::analysis::stall_attribution::StallCause;
+use crate::instructions::instructions::{Operand, Program};
+
+// The number of stall causes reported per loop.
+const CAUSE_LIMIT: usize = 3;
+
+// A loop found in the code and what was measured while executing it.
+struct LoopStats {
+    // the code address of the first instruction of the loop; the target of the backward branch
+    header: usize,
+    // the code address of the last backward branch to the header
+    latch: usize,
+    // the number of times the loop was entered from outside
+    entries: u64,
+    // the number of times the header retired; so the trip count over all entries
+    iterations: u64,
+    // the instructions retired inside the loop, including its inner loops
+    retired: u64,
+    // the cycles attributed to the instructions retired inside the loop
+    cycles: u64,
+    // the cycles instructions inside the loop waited per cause
+    stalls: HashMap<StallCause, u64>,
+}
+
+impl LoopStats {
+    fn contains(&self, pc: usize) -> bool {
+        self.header <= pc && pc <= self.latch
+    }
+}
+
+/// Detects the loops of a program when it is loaded and measures the trip counts, the IPC and the
+/// stalls per loop while it runs.
+///
+/// A loop is the code between the target of a backward branch (the header) and the branch (the latch);
+/// backward branches to the same header form a single loop. The cycles between two retirements are
+/// attributed to the loops of the later instruction, so an outer loop includes its inner loops.
+pub(crate) struct LoopProfiler {
+    program: Rc<Program>,
+    // ordered on header
+    loops: Vec<LoopStats>,
+    // the cycle the previous instruction retired
+    last_retire_cycle: u64,
+    // the code address of the previous retired instruction
+    prev_pc: Option<usize>,
+}
+
+impl LoopProfiler {
+    pub(crate) fn new(program: &Rc<Program>) -> Self {
+        // header -> latch
+        let mut headers = HashMap::<usize, usize>::new();
+        for (pc, instr) in program.code.iter().enumerate() {
+            if !instr.is_control || instr.is_call() {
+                continue;
+            }
+
+            let target = instr.source.iter()
+                .take(instr.source_cnt as usize)
+                .find_map(|operand| match operand {
+                    Operand::Code(address) => Some(*address as usize),
+                    _ => None,
+                });
+            if let Some(header) = target.filter(|header| *header <= pc) {
+                let latch = headers.entry(header).or_insert(pc);
+                *latch = (*latch).max(pc);
+            }
+        }
+
+        let mut loops: Vec<LoopStats> = headers.into_iter()
+            .map(|(header, latch)| LoopStats { header, latch, entries: 0, iterations: 0, retired: 0, cycles: 0, stalls: HashMap::new() })
+            .collect();
+        loops.sort_by_key(|l| l.header);
+
+        Self { program: Rc::clone(program), loops, last_retire_cycle: 0, prev_pc: None }
+    }
+
+    pub(crate) fn on_retire(&mut self, pc: usize, cycle: u64) {
+        let cycles = cycle - self.last_retire_cycle;
+        self.last_retire_cycle = cycle;
+        let prev_pc = self.prev_pc.replace(pc);
+
+        for l in self.loops.iter_mut().filter(|l| l.contains(pc)) {
+            l.retired += 1;
+            l.cycles += cycles;
+            if pc == l.header {
+                l.iterations += 1;
+                if !prev_pc.is_some_and(|prev_pc| l.contains(prev_pc)) {
+                    l.entries += 1;
+                }
+            }
+        }
+    }
+
+    // An instruction at the code address waited the number of cycles because of the cause.
+    pub(crate) fn on_stall(&mut self, pc: usize, cause: StallCause, cycles: u64) {
+        for l in self.loops.iter_mut().filter(|l| l.contains(pc)) {
+            *l.stalls.entry(cause).or_insert(0) += cycles;
+        }
+    }
+
+    fn describe(&self, l: &LoopStats) -> String {
+        let lines = match (self.program.line_for(l.header), self.program.line_for(l.latch)) {
+            (Some(first), Some(last)) => format!(" (lines {}-{})", first, last),
+            _ => String::new(),
+        };
+        let label = self.program.label_at(l.header).map(|label| format!(" '{}'", label)).unwrap_or_default();
+        format!("{}..{}{}{}", l.header, l.latch, label, lines)
+    }
+}
+
+impl fmt::Display for LoopProfiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Loops:")?;
+        if self.loops.is_empty() {
+            writeln!(f, "  no loops found")?;
+        }
+
+        for l in &self.loops {
+            if l.entries == 0 {
+                writeln!(f, "  {}: never entered", self.describe(l))?;
+                continue;
+            }
+
+            writeln!(f, "  {}: {} entries, {} iterations ({:.1} per entry), {} instructions in {} cycles (IPC {:.2})",
+                     self.describe(l), l.entries, l.iterations, l.iterations as f64 / l.entries as f64,
+                     l.retired, l.cycles, l.retired as f64 / l.cycles.max(1) as f64)?;
+
+            // ties are broken on the cause, so the report is deterministic
+            let mut causes: Vec<(&StallCause, &u64)> = l.stalls.iter().collect();
+            causes.sort_by_key(|(cause, cycles)| (Reverse(**cycles), format!("{:?}", cause)));
+            let total: u64 = l.stalls.values().sum();
+            for (cause, cycles) in causes.iter().take(CAUSE_LIMIT) {
+                writeln!(f, "    {}% of {} stall cycles {}", 100 * **cycles / total, total, cause.describe(&self.program))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,19 @@
+pub mod stack_usage;
+pub mod stack_guard;
+pub mod assertions;
+pub mod deadline;
+pub mod wcet;
+pub mod ilp;
+pub mod wrong_path;
+pub mod energy;
+pub mod watchdog;
+pub mod stall_attribution;
+pub mod loops;
+pub mod call_graph;
+pub mod sampling;
+pub mod oracle;
+pub mod watchpoint;
+pub mod journal;
+pub mod occupancy;
+pub mod execution_log;
+pub mod dead_code;
@@ -0,0 +1,70 @@
+use std::fmt;
+
+// The maximum number of rows of the histogram; larger structures are reported in buckets.
+const MAX_ROWS: usize = 16;
+// The width of the bar of the most frequent row.
+const BAR_WIDTH: u64 = 40;
+
+/// A histogram of the number of entries of a structure (e.g. the reservation stations) in use per cycle.
+pub(crate) struct OccupancyHistogram {
+    name: &'static str,
+    capacity: u16,
+    // the number of cycles per occupancy 0..=capacity
+    cycles: Vec<u64>,
+}
+
+impl OccupancyHistogram {
+    pub(crate) fn new(name: &'static str, capacity: u16) -> Self {
+        Self { name, capacity, cycles: vec![0; capacity as usize + 1] }
+    }
+
+    pub(crate) fn record(&mut self, used: u16, cycles: u64) {
+        self.cycles[used.min(self.capacity) as usize] += cycles;
+    }
+
+    fn total(&self) -> u64 {
+        self.cycles.iter().sum()
+    }
+
+    // the smallest occupancy of at least the given fraction of the cycles
+    fn percentile(&self, fraction: f64) -> usize {
+        let threshold = (fraction * self.total() as f64).ceil() as u64;
+        let mut seen = 0;
+        for (used, cycles) in self.cycles.iter().enumerate() {
+            seen += cycles;
+            if seen >= threshold.max(1) {
+                return used;
+            }
+        }
+        self.capacity as usize
+    }
+}
+
+impl fmt::Display for OccupancyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        writeln!(f, "{} occupancy ({} entries) over {} cycles:", self.name, self.capacity, total)?;
+        if total == 0 {
+            return Ok(());
+        }
+
+        let mean = self.cycles.iter().enumerate().map(|(used, cycles)| used as u64 * cycles).sum::<u64>() as f64 / total as f64;
+        let max = self.cycles.iter().rposition(|cycles| *cycles > 0).unwrap_or(0);
+        let full = self.cycles[self.capacity as usize];
+        writeln!(f, "  mean {:.1}, median {}, p90 {}, max {}; full {:.1}% of the cycles",
+                 mean, self.percentile(0.5), self.percentile(0.9), max, 100.0 * full as f64 / total as f64)?;
+
+        let bucket = self.cycles.len().div_ceil(MAX_ROWS);
+        let rows: Vec<(usize, usize, u64)> = self.cycles.chunks(bucket)
+            .enumerate()
+            .map(|(k, chunk)| (k * bucket, k * bucket + chunk.len() - 1, chunk.iter().sum()))
+            .collect();
+        let most = rows.iter().map(|(_, _, cycles)| *cycles).max().unwrap_or(0).max(1);
+        for (first, last, cycles) in rows {
+            let range = if first == last { first.to_string() } else { format!("{}-{}", first, last) };
+            writeln!(f, "  {:>7} {:>5.1}% {}", range, 100.0 * cycles as f64 / total as f64,
+                     "#".repeat((BAR_WIDTH * cycles / most) as usize))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::rc::Rc;
+
+use crate::cpu::{ARCH_REG_CNT, ArgRegFile, CPSR, CPUConfig, Flags, PC};
+use crate::instructions::instructions::{Instr, Opcode, Operand, Program, RegisterType, WordType};
+use crate::memory_subsystem::heap::Heap;
+use crate::memory_subsystem::memory_subsystem::MemorySubsystem;
+use crate::plugin::plugin::AccessKind;
+use crate::reference_interpreter::reference_interpreter::ReferenceInterpreter;
+
+// The architectural state of the reference interpreter between two retirements.
+struct OracleState {
+    registers: Vec<WordType>,
+    memory: Vec<WordType>,
+    heap: Heap,
+}
+
+/// Executes every retiring instruction on the reference interpreter as well and compares the
+/// architectural results (oracle mode); meant for changes to the out-of-order machinery.
+///
+/// The interpreter keeps its own copy of the architectural state, taken over from the CPU when the first
+/// instruction retires (so after the arguments, the memory images and any fast forwarding). The written
+/// registers, the code address of the next instruction and the stored words of an instruction must match
+/// the reference; the first divergence ends the program with the instruction, its source values and the
+/// expected and actual values. A stored word is compared with the word as seen by the program, since a
+/// committed store can still be in the store buffer.
+///
+/// The interpreter doesn't model interrupts, the counters or the input; after an MRS, READC or ERET and
+/// when an interrupt is taken, the registers are taken over from the CPU.
+pub(crate) struct ArchStateOracle {
+    cpu_config: CPUConfig,
+    program: Rc<Program>,
+    // None until the first instruction retires
+    state: Option<OracleState>,
+    checked_cnt: u64,
+}
+
+impl ArchStateOracle {
+    pub(crate) fn new(cpu_config: &CPUConfig, program: &Rc<Program>) -> Self {
+        Self { cpu_config: cpu_config.clone(), program: Rc::clone(program), state: None, checked_cnt: 0 }
+    }
+
+    pub(crate) fn checked_cnt(&self) -> u64 {
+        self.checked_cnt
+    }
+
+    // Takes the state over from the CPU, unless it already has been; pc is the code address of the next
+    // instruction to retire. Must be called before the instruction changes the state.
+    pub(crate) fn sync(&mut self, pc: usize, arch_reg_file: &ArgRegFile, memory_subsystem: &MemorySubsystem) {
+        if self.state.is_none() {
+            self.state = Some(OracleState {
+                registers: Vec::new(),
+                memory: memory_subsystem.architectural_memory(),
+                heap: memory_subsystem.heap.clone(),
+            });
+            self.take_registers(pc, arch_reg_file);
+        }
+    }
+
+    // Takes the registers over from the CPU, which continues at the code address; the memory is the same.
+    pub(crate) fn take_registers(&mut self, pc: usize, arch_reg_file: &ArgRegFile) {
+        if let Some(state) = &mut self.state {
+            state.registers = (0..ARCH_REG_CNT).map(|reg| arch_reg_file.get_value(reg)).collect();
+            state.registers[PC as usize] = pc as WordType;
+        }
+    }
+
+    /// Executes the instruction at the code address on the interpreter and compares the results with the
+    /// state of the CPU after the instruction retired. An error describes the divergence.
+    pub(crate) fn on_retire(&mut self, instr: &Instr, pc: usize, next_pc: usize,
+                            arch_reg_file: &ArgRegFile, memory_subsystem: &MemorySubsystem) -> Result<(), String> {
+        match instr.opcode {
+            Opcode::MRS | Opcode::READC => {
+                self.take_registers(next_pc, arch_reg_file);
+                return Ok(());
+            }
+            // the registers are taken over when the handler has returned
+            Opcode::ERET => return Ok(()),
+            _ => {}
+        }
+
+        let OracleState { registers, memory, heap } = self.state.take().expect("The oracle isn't synchronized");
+        let expected_pc = registers[PC as usize] as usize;
+        if expected_pc != pc {
+            return Err(format!("Divergence from the reference interpreter: retired code address {} instead of {}", pc, expected_pc));
+        }
+        let sources = source_values(instr, &registers);
+        let cpsr = registers[CPSR as usize];
+
+        let mut interpreter = ReferenceInterpreter::with_state(&self.cpu_config, &self.program, registers, memory, heap);
+        let step = interpreter.step()
+            .map_err(|fault| format!("Divergence from the reference interpreter: it ended with '{}'{}", fault, sources))?;
+        let (mut registers, memory, heap) = interpreter.into_state();
+
+        let mut mismatches = Vec::new();
+        match &step {
+            Some(step) => {
+                if step.next_pc != next_pc {
+                    mismatches.push(format!("the next code address is {} instead of {}", next_pc, step.next_pc));
+                }
+
+                let expected_stores: Vec<WordType> = step.accesses.iter()
+                    .filter(|(_, kind)| *kind == AccessKind::Store)
+                    .map(|(addr, _)| *addr)
+                    .collect();
+                for addr in expected_stores {
+                    let actual = memory_subsystem.architectural_word(addr);
+                    if actual != memory[addr as usize] {
+                        mismatches.push(format!("mem[{}] is {} instead of {}", addr, actual, memory[addr as usize]));
+                    }
+                }
+            }
+            // the end of the program
+            None if instr.opcode == Opcode::EXIT => {}
+            None => mismatches.push(format!("the program ended at code address {}", pc)),
+        }
+
+        for sink in instr.sink.iter().take(instr.sink_cnt as usize) {
+            let Operand::Register(reg) = *sink else { continue };
+            match reg {
+                // compared as the next code address
+                PC => continue,
+                // like on the CPU, a flag setter only writes the flags
+                CPSR if !matches!(instr.opcode, Opcode::CPSIE | Opcode::CPSID) => {
+                    registers[CPSR as usize] = Flags::from_cpsr(registers[CPSR as usize]).apply_to(cpsr);
+                }
+                _ => {}
+            }
+            let expected = registers[reg as usize];
+            let actual = arch_reg_file.get_value(reg);
+            if actual != expected {
+                mismatches.push(format!("{} is {} instead of {}", register_name(reg), actual, expected));
+            }
+        }
+
+        if !mismatches.is_empty() {
+            return Err(format!("Divergence from the reference interpreter: {}{}", mismatches.join(", "), sources));
+        }
+
+        registers[PC as usize] = next_pc as WordType;
+        self.state = Some(OracleState { registers, memory, heap });
+        self.checked_cnt += 1;
+        Ok(())
+    }
+}
+
+fn register_name(reg: RegisterType) -> String {
+    Operand::Register(reg).to_string()
+}
+
+// The values of the source registers before the instruction; e.g. "; the sources were R1=5, R2=7".
+fn source_values(instr: &Instr, registers: &[WordType]) -> String {
+    let values: Vec<String> = instr.source.iter().take(instr.source_cnt as usize)
+        .filter_map(|source| match *source {
+            Operand::Register(reg) if reg != PC => Some(format!("{}={}", register_name(reg), registers[reg as usize])),
+            _ => None,
+        })
+        .collect();
+    if values.is_empty() {
+        String::new()
+    } else {
+        format!("; the sources were {}", values.join(", "))
+    }
+}
@@ -0,0 +1,131 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::analysis::stack_usage::function_name;
+use crate::instructions::instructions::{Instr, InstrIndex, Opcode, Program};
+
+// The number of instructions reported in the flat profile.
+const HOT_INSTR_LIMIT: usize = 10;
+
+// A call that hasn't returned yet.
+struct Link {
+    // the value of the LR: the code address the call returns to
+    return_addr: usize,
+    // the code address of the called function
+    function: usize,
+}
+
+/// Samples the retiring instruction every `sampling_period` cycles; much cheaper than tracing every
+/// instruction, and for a long run good enough to find the hot loops.
+///
+/// Like the other profilers, a cycle in which nothing retires is attributed to the next instruction that
+/// retires. Every sample also records the call stack: the chain of link register values (the return
+/// addresses) of the calls that haven't returned yet, kept up to date at retirement. The flat profile per
+/// function and per instruction is reported at the end of the program and the stacks can be exported as
+/// collapsed stacks (see to_collapsed_stacks) for flamegraph.pl and compatible tools.
+pub(crate) struct SamplingProfiler {
+    program: Rc<Program>,
+    period: u64,
+    // the cycle of the next sample
+    next_sample: u64,
+    // the active calls, from the outermost to the innermost
+    links: Vec<Link>,
+    // code address -> samples
+    instructions: BTreeMap<usize, u64>,
+    // the functions from the entry point to the sampled function -> samples
+    stacks: BTreeMap<Vec<usize>, u64>,
+    sample_cnt: u64,
+}
+
+impl SamplingProfiler {
+    pub(crate) fn new(program: &Rc<Program>, period: u64) -> Self {
+        Self {
+            program: Rc::clone(program),
+            period,
+            next_sample: period,
+            links: Vec::new(),
+            instructions: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            sample_cnt: 0,
+        }
+    }
+
+    pub(crate) fn on_retire(&mut self, instr: &Instr, pc: usize, next_pc: usize, cycle: u64) {
+        while self.next_sample <= cycle {
+            self.sample(pc);
+            self.next_sample += self.period;
+        }
+
+        match instr.opcode {
+            _ if instr.is_call() => self.links.push(Link { return_addr: pc + 1, function: next_pc }),
+            // a return to an outer caller (e.g. after a tail call) also ends the calls in between
+            _ if instr.is_return() => {
+                if let Some(index) = self.links.iter().rposition(|link| link.return_addr == next_pc) {
+                    self.links.truncate(index);
+                }
+            }
+            Opcode::EXIT => self.links.clear(),
+            _ => {}
+        }
+    }
+
+    fn sample(&mut self, pc: usize) {
+        self.sample_cnt += 1;
+        *self.instructions.entry(pc).or_default() += 1;
+
+        let mut stack = vec![self.program.entry_point];
+        stack.extend(self.links.iter().map(|link| link.function));
+        *self.stacks.entry(stack).or_default() += 1;
+    }
+
+    /// The samples as collapsed stacks, the input format of flamegraph.pl: a line per call stack with
+    /// the functions from the entry point to the sampled function separated by ';' and the number of
+    /// samples.
+    pub(crate) fn to_collapsed_stacks(&self) -> String {
+        let mut out = String::new();
+        for (stack, samples) in &self.stacks {
+            let names: Vec<String> = stack.iter().map(|function| function_name(&self.program, *function)).collect();
+            writeln!(out, "{} {}", names.join(";"), samples).unwrap();
+        }
+        out
+    }
+}
+
+impl fmt::Display for SamplingProfiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Sampling profile: {} samples (every {} cycles)", self.sample_cnt, self.period)?;
+        let percentage = |samples: u64| 100.0 * samples as f64 / self.sample_cnt.max(1) as f64;
+
+        // self: sampled in the function itself; total: the function is on the stack (counted once per sample)
+        let mut functions = BTreeMap::<usize, (u64, u64)>::new();
+        for (stack, samples) in &self.stacks {
+            functions.entry(*stack.last().unwrap()).or_default().0 += samples;
+            let mut on_stack = stack.clone();
+            on_stack.sort();
+            on_stack.dedup();
+            for function in on_stack {
+                functions.entry(function).or_default().1 += samples;
+            }
+        }
+
+        // ties are broken on the code address, so the report is deterministic
+        let mut functions: Vec<(usize, (u64, u64))> = functions.into_iter().collect();
+        functions.sort_by_key(|(function, (self_samples, _))| (Reverse(*self_samples), *function));
+        for (function, (self_samples, total_samples)) in functions {
+            writeln!(f, "  {:<20} self={} ({:.1}%) total={} ({:.1}%)", function_name(&self.program, function),
+                     self_samples, percentage(self_samples), total_samples, percentage(total_samples))?;
+        }
+
+        let mut instructions: Vec<(&usize, &u64)> = self.instructions.iter().collect();
+        instructions.sort_by_key(|(pc, samples)| (Reverse(**samples), **pc));
+        writeln!(f, "  Hottest instructions:")?;
+        for (pc, samples) in instructions.into_iter().take(HOT_INSTR_LIMIT) {
+            // the instruction includes its source location
+            writeln!(f, "    {}: {} samples ({:.1}%) {}", pc, samples, percentage(*samples), self.program.instr(*pc as InstrIndex))?;
+        }
+        Ok(())
+    }
+}
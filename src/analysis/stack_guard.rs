@@ -0,0 +1,103 @@
+use crate::cpu::{ArgRegFile, SP};
+use crate::instructions::instructions::{Instr, Opcode, Operand, WordType};
+
+/// Checks the stack at retirement.
+///
+/// The stack occupies the addresses [limit, base); it grows down from the base. With the bounds
+/// check, the SP must stay within [limit, base] and every access through the SP must be inside the
+/// stack. With poisoning, every stack slot starts out poisoned and reading a slot that was never
+/// written is a violation.
+pub(crate) struct StackGuard {
+    base: WordType,
+    limit: WordType,
+    check_bounds: bool,
+    // per stack slot (indexed by address - limit) if it has been written; only used when poisoning.
+    initialized: Option<Vec<bool>>,
+}
+
+impl StackGuard {
+    pub(crate) fn new(base: WordType, capacity: u32, check_bounds: bool, poison: bool) -> Self {
+        Self {
+            base,
+            limit: base - capacity as WordType,
+            check_bounds,
+            initialized: if poison { Some(vec![false; capacity as usize]) } else { None },
+        }
+    }
+
+    /// Marks the words [addr, addr+cnt) as written; e.g. the arguments that are passed on the stack
+    /// before the program starts.
+    pub(crate) fn mark_initialized(&mut self, addr: WordType, cnt: usize) {
+        if let Some(initialized) = &mut self.initialized {
+            for word in addr..addr + cnt as WordType {
+                if self.limit <= word && word < self.base {
+                    initialized[(word - self.limit) as usize] = true;
+                }
+            }
+        }
+    }
+
+    /// Checks the retiring instruction; the registers already contain the values it has written.
+    /// Returns an error describing the violation.
+    pub(crate) fn on_retire(&mut self,
+                            instr: &Instr,
+                            load_addr: Option<WordType>,
+                            store_addr: Option<WordType>,
+                            transfer_cnt: u8,
+                            arch_reg_file: &ArgRegFile) -> Result<(), String> {
+        if self.check_bounds {
+            let writes_sp = instr.sink[..instr.sink_cnt as usize].iter()
+                .any(|sink| matches!(sink, Operand::Register(SP)));
+            if writes_sp {
+                let sp = arch_reg_file.get_value(SP);
+                if sp < self.limit {
+                    return Err(format!("Stack overflow: SP={} is below the stack limit {}", sp, self.limit));
+                }
+                if sp > self.base {
+                    return Err(format!("Stack underflow: SP={} is above the stack base {}", sp, self.base));
+                }
+            }
+
+            let sp_relative_addr = match instr.opcode {
+                Opcode::LDR if matches!(instr.source[0], Operand::Register(SP)) => load_addr,
+                Opcode::STR if matches!(instr.source[1], Operand::Register(SP)) => store_addr,
+                Opcode::VLD1 if matches!(instr.source[0], Operand::Register(SP)) => load_addr,
+                Opcode::VST1 if matches!(instr.source[0], Operand::Register(SP)) => store_addr,
+                Opcode::PUSH => store_addr,
+                Opcode::POP => load_addr,
+                Opcode::LDM(_) if matches!(instr.source[0], Operand::Register(SP)) => load_addr,
+                Opcode::STM(_) if matches!(instr.source[0], Operand::Register(SP)) => store_addr,
+                _ => None,
+            };
+            if let Some(addr) = sp_relative_addr {
+                let last_addr = addr + transfer_cnt as WordType - 1;
+                if addr < self.limit {
+                    return Err(format!("Stack overflow: access to address {} below the stack limit {}", addr, self.limit));
+                }
+                if last_addr >= self.base {
+                    return Err(format!("Stack underflow: access to address {} at or above the stack base {}", last_addr, self.base));
+                }
+            }
+        }
+
+        if let Some(initialized) = &mut self.initialized {
+            if let Some(addr) = store_addr {
+                for word in addr..addr + transfer_cnt as WordType {
+                    if self.limit <= word && word < self.base {
+                        initialized[(word - self.limit) as usize] = true;
+                    }
+                }
+            }
+
+            if let Some(addr) = load_addr {
+                for word in addr..addr + transfer_cnt as WordType {
+                    if self.limit <= word && word < self.base && !initialized[(word - self.limit) as usize] {
+                        return Err(format!("Read of uninitialized stack slot at address {}", word));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cpu::{ArgRegFile, SP};
+use crate::instructions::instructions::{Instr, Program, WordType};
+
+// The percentage of the stack capacity at which a warning is printed.
+const WARNING_THRESHOLD_PERCENTAGE: u64 = 90;
+
+struct StackFrame {
+    function: String,
+    entry_sp: WordType,
+}
+
+#[derive(Default)]
+pub(crate) struct FunctionStackUsage {
+    pub(crate) calls: u64,
+    // the maximum number of words used by the frame of the function itself (so excluding callees)
+    pub(crate) max_depth: u64,
+}
+
+/// Tracks the stack usage per call frame at retirement.
+///
+/// Every call (BL or BLX) opens a new frame and the matching return (BX LR or POP {.., pc}) closes it. The depth of a frame is the
+/// distance between the SP at the entry of the function and the current SP. Since the stack
+/// grows down, the stack top is the initial SP.
+pub(crate) struct StackUsageAnalyzer {
+    stack_top: WordType,
+    stack_capacity: u32,
+    frames: Vec<StackFrame>,
+    functions: BTreeMap<String, FunctionStackUsage>,
+    // the maximum number of words used by the whole program
+    max_depth: u64,
+    warned: bool,
+}
+
+impl StackUsageAnalyzer {
+    pub(crate) fn new(stack_top: WordType, stack_capacity: u32) -> Self {
+        Self {
+            stack_top,
+            stack_capacity,
+            frames: Vec::new(),
+            functions: BTreeMap::new(),
+            max_depth: 0,
+            warned: false,
+        }
+    }
+
+    pub(crate) fn on_retire(&mut self, instr: &Instr, next_pc: usize, arch_reg_file: &ArgRegFile, program: &Program) {
+        if self.frames.is_empty() {
+            let function = function_name(program, program.entry_point);
+            self.functions.entry(function.clone()).or_default().calls += 1;
+            self.frames.push(StackFrame { function, entry_sp: self.stack_top });
+        }
+
+        let sp = arch_reg_file.get_value(SP);
+
+        if instr.is_call() {
+            let function = function_name(program, next_pc);
+            self.functions.entry(function.clone()).or_default().calls += 1;
+            self.frames.push(StackFrame { function, entry_sp: sp });
+        } else if instr.is_return() && self.frames.len() > 1 {
+            // the root frame is never popped
+            self.frames.pop();
+        }
+
+        let frame = self.frames.last().unwrap();
+        let frame_depth = (frame.entry_sp - sp).max(0) as u64;
+        let usage = self.functions.get_mut(&frame.function).unwrap();
+        usage.max_depth = usage.max_depth.max(frame_depth);
+
+        let depth = (self.stack_top - sp).max(0) as u64;
+        if depth > self.max_depth {
+            self.max_depth = depth;
+
+            if !self.warned && depth * 100 >= self.stack_capacity as u64 * WARNING_THRESHOLD_PERCENTAGE {
+                self.warned = true;
+                println!("Warning: stack usage of {} words is approaching the stack capacity of {} words ({})",
+                         depth, self.stack_capacity, instr);
+            }
+        }
+    }
+}
+
+pub(crate) fn function_name(program: &Program, code_address: usize) -> String {
+    match program.label_at(code_address) {
+        Some(label) => String::from(label),
+        None => format!("[{}]", code_address),
+    }
+}
+
+impl fmt::Display for StackUsageAnalyzer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Stack usage (words):")?;
+        for (function, usage) in &self.functions {
+            writeln!(f, "  {:<20} max depth={:<6} calls={}", function, usage.max_depth, usage.calls)?;
+        }
+        write!(f, "  Program max depth={} of {}", self.max_depth, self.stack_capacity)
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::instructions::instructions::{EUType, Program};
+
+// the maximum number of static instructions in the report
+const REPORT_LIMIT: usize = 20;
+
+/// Why an instruction that hasn't retired yet isn't making progress in a cycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum StallCause {
+    // in the ROB, but there is no free reservation station
+    ReservationStation,
+    // an operand isn't available yet; it is produced by the instruction at the code address
+    Operand { producer_pc: usize, load: bool },
+    // ready, but all execution units of the type are busy (a structural hazard)
+    ExecutionUnit(EUType),
+    // a load that waits till the addresses of all older stores are known (conservative disambiguation)
+    StoreAddress,
+    // ready, but more instructions were ready than can be dispatched in a cycle
+    DispatchWidth,
+    // executed; waiting for the older instructions to retire
+    Retirement,
+    // ready, but the instruction only executes once all older instructions have retired (MALLOC, FREE, MRS, READC, DMB, DSB)
+    Serialization,
+    // ready, but an older instruction hasn't been dispatched yet (in-order pipeline)
+    ProgramOrder,
+    // ready, but an older instruction occupies the execute or the memory stage (in-order pipeline)
+    PipelineStage,
+    // a barrier (DMB, DSB) that waits till the older stores have been written from the store buffer to memory
+    StoreBufferDrain,
+    // ready, but an older barrier hasn't completed yet
+    Barrier,
+}
+
+impl StallCause {
+    pub(crate) fn describe(&self, program: &Program) -> String {
+        match self {
+            StallCause::ReservationStation => String::from("waiting for a reservation station"),
+            StallCause::Operand { producer_pc, load } => {
+                let producer = program.code.get(*producer_pc)
+                    .map(|instr| instr.with_aliases(&program.register_aliases).to_string())
+                    .unwrap_or_default();
+                let kind = if *load { "a load" } else { "an operand" };
+                format!("waiting on {} from {} [{}]", kind, producer_pc, producer)
+            }
+            StallCause::ExecutionUnit(eu_type) => format!("structural hazard on the {} unit", eu_type),
+            StallCause::StoreAddress => String::from("waiting for the addresses of older stores"),
+            StallCause::DispatchWidth => String::from("waiting for dispatch bandwidth"),
+            StallCause::Retirement => String::from("waiting for older instructions to retire"),
+            StallCause::Serialization => String::from("waiting to become the oldest instruction"),
+            StallCause::ProgramOrder => String::from("waiting for older instructions to start executing"),
+            StallCause::PipelineStage => String::from("waiting for the execute or memory stage"),
+            StallCause::StoreBufferDrain => String::from("waiting for the store buffer to drain"),
+            StallCause::Barrier => String::from("waiting for an older barrier"),
+        }
+    }
+}
+
+/// Attributes the cycles that instructions wait in the backend to the cause of the wait, per
+/// static instruction. Instructions on a path that is flushed later on are included; so the report
+/// also shows where a misprediction or replay wasted cycles.
+pub(crate) struct StallAttribution {
+    program: Rc<Program>,
+    // code address -> cause -> cycles
+    stalls: HashMap<usize, HashMap<StallCause, u64>>,
+}
+
+impl StallAttribution {
+    pub(crate) fn new(program: &Rc<Program>) -> Self {
+        Self { program: Rc::clone(program), stalls: HashMap::new() }
+    }
+
+    // An instruction at the code address waited the number of cycles because of the cause.
+    pub(crate) fn on_stall(&mut self, pc: usize, cause: StallCause, cycles: u64) {
+        *self.stalls.entry(pc).or_default().entry(cause).or_insert(0) += cycles;
+    }
+}
+
+// The cause with the most cycles; ties are broken on the cause, so the report is deterministic.
+pub(crate) fn dominant_cause(stalls: &HashMap<StallCause, u64>) -> Option<(StallCause, u64)> {
+    stalls.iter()
+        .max_by_key(|(cause, cycles)| (**cycles, format!("{:?}", cause)))
+        .map(|(cause, cycles)| (*cause, *cycles))
+}
+
+impl fmt::Display for StallAttribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut instructions: Vec<(usize, u64)> = self.stalls.iter()
+            .map(|(pc, causes)| (*pc, causes.values().sum()))
+            .collect();
+        instructions.sort_by_key(|(pc, cycles)| (std::cmp::Reverse(*cycles), *pc));
+
+        writeln!(f, "Stall cycles per instruction (dominant cause):")?;
+        for (pc, cycles) in instructions.iter().take(REPORT_LIMIT) {
+            let instr = self.program.code.get(*pc)
+                .map(|instr| instr.with_aliases(&self.program.register_aliases).to_string())
+                .unwrap_or_default();
+            let line = self.program.line_for(*pc).map(|line| format!(" (line {})", line)).unwrap_or_default();
+            let (cause, cause_cycles) = dominant_cause(&self.stalls[pc]).unwrap();
+            writeln!(f, "  {:>4}{} [{}]: {} cycles; {}% {}",
+                     pc, line, instr, cycles, 100 * cause_cycles / cycles, cause.describe(&self.program))?;
+        }
+        if instructions.len() > REPORT_LIMIT {
+            writeln!(f, "  ... {} more instructions", instructions.len() - REPORT_LIMIT)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+/// The number of occupied entries of a queue in the pipeline, e.g. '3/16'.
+#[derive(Clone, Debug)]
+pub struct Occupancy {
+    pub name: &'static str,
+    pub size: u16,
+    pub capacity: u16,
+}
+
+impl fmt::Display for Occupancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}/{}", self.name, self.size, self.capacity)
+    }
+}
+
+/// The oldest instruction in the reorder buffer; the instruction that blocks retirement.
+#[derive(Clone, Debug)]
+pub struct UnretiredInstr {
+    // the code address of the instruction
+    pub pc: usize,
+    // the instruction as written in assembly
+    pub text: String,
+    pub line: Option<usize>,
+    // what the instruction is waiting for
+    pub state: &'static str,
+    // the cycle the instruction was fetched in
+    pub fetch_cycle: u64,
+}
+
+/// Describes where the CPU is stuck when the program didn't complete within the max_cycles of the
+/// configuration; e.g. because of an infinite loop in the program.
+#[derive(Clone, Debug)]
+pub struct HangDiagnosis {
+    pub cycle_cnt: u64,
+    pub retire_cnt: u64,
+    // the code address the frontend fetches from next
+    pub pc: usize,
+    // the instruction at the pc; None when the pc is outside of the program
+    pub instr_at_pc: Option<String>,
+    pub line: Option<usize>,
+    // if the frontend waits for a control instruction to be executed
+    pub frontend_halted: bool,
+    // ordered from frontend to backend
+    pub queues: Vec<Occupancy>,
+    // None when the reorder buffer is empty
+    pub oldest_unretired: Option<UnretiredInstr>,
+}
+
+impl fmt::Display for HangDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Watchdog: the program didn't complete within {} cycles ({} instructions retired)",
+                 self.cycle_cnt, self.retire_cnt)?;
+
+        let line = self.line.map(|line| format!(" (line {})", line)).unwrap_or_default();
+        match &self.instr_at_pc {
+            Some(instr) => write!(f, "  PC: {} [{}]{}", self.pc, instr, line)?,
+            None => write!(f, "  PC: {} (outside of the program)", self.pc)?,
+        }
+        if self.frontend_halted {
+            write!(f, "; the frontend waits for a control instruction")?;
+        }
+        writeln!(f)?;
+
+        let queues: Vec<String> = self.queues.iter().map(|queue| queue.to_string()).collect();
+        writeln!(f, "  Occupancy: {}", queues.join(", "))?;
+
+        match &self.oldest_unretired {
+            Some(oldest) => {
+                let line = oldest.line.map(|line| format!(" (line {})", line)).unwrap_or_default();
+                writeln!(f, "  Oldest unretired: {} [{}]{}; {} (fetched in cycle {})",
+                         oldest.pc, oldest.text, line, oldest.state, oldest.fetch_cycle)
+            }
+            None => writeln!(f, "  Oldest unretired: none; the reorder buffer is empty"),
+        }
+    }
+}
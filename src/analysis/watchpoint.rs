@@ -0,0 +1,226 @@
+use std::fmt;
+
+use crate::cpu::{ArgRegFile, CPUConfig};
+use crate::conformance::expectation::parse_register;
+use crate::instructions::instructions::{Operand, Program, RegisterType, WordType};
+use crate::memory_subsystem::memory_subsystem::MemorySubsystem;
+
+// A register or memory word a watch expression reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum WatchLocation {
+    Register(RegisterType),
+    Memory(WordType),
+}
+
+impl WatchLocation {
+    pub(crate) fn value(self, arch_reg_file: &ArgRegFile, memory_subsystem: &MemorySubsystem) -> WordType {
+        match self {
+            WatchLocation::Register(reg) => arch_reg_file.get_value(reg),
+            WatchLocation::Memory(addr) => memory_subsystem.architectural_word(addr),
+        }
+    }
+}
+
+impl fmt::Display for WatchLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchLocation::Register(reg) => write!(f, "{}", Operand::Register(*reg)),
+            WatchLocation::Memory(addr) => write!(f, "mem[{}]", addr),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Term {
+    Location(WatchLocation),
+    Constant(WordType),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, a: WordType, b: WordType) -> bool {
+        match self {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+        }
+    }
+}
+
+// The operators in the order they are matched; so '<=' isn't read as '<'.
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq), ("!=", Comparison::Ne), ("<=", Comparison::Le),
+    (">=", Comparison::Ge), ("<", Comparison::Lt), (">", Comparison::Gt),
+];
+
+/// A watchpoint: a register or memory word, or a condition on them, checked when an instruction retires.
+///
+/// The expression is either a single location, e.g. `mem[count]`, which triggers when a retiring
+/// instruction changes its value, or comparisons joined by `&&`, e.g. `mem[count] > 100 && r3 == 0`, which
+/// trigger when a retiring instruction writes one of their locations and all of them hold afterwards. A
+/// location is a register (`r3`, `sp`, `lr`, `fp`, `pc`, `cpsr`) or a memory word: `mem[<address>]`,
+/// `mem[<variable>]`, `mem[<variable>+<offset>]` or just the variable. The values are signed words and
+/// the stores are seen as soon as they are committed.
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    expression: String,
+    // empty for a single location
+    comparisons: Vec<(Term, Comparison, Term)>,
+    // the locations the expression reads
+    locations: Vec<WatchLocation>,
+}
+
+impl Watchpoint {
+    /// Parses the expression; the variables are looked up in the program and the addresses are checked
+    /// against the memory of the config.
+    pub fn new(expression: &str, program: &Program, cpu_config: &CPUConfig) -> Result<Watchpoint, String> {
+        let parse_location = |text: &str| -> Result<Option<WatchLocation>, String> {
+            let text = text.trim();
+            if let Some(reg) = parse_register(text, &cpu_config.abi()) {
+                return Ok(Some(WatchLocation::Register(reg)));
+            }
+            let address = match text.strip_prefix("mem[").and_then(|rest| rest.strip_suffix(']')) {
+                Some(address) => address.trim(),
+                None if is_identifier(text) => text,
+                None => return Ok(None),
+            };
+            let addr = resolve_address(address, program)
+                .ok_or_else(|| format!("Unknown memory location '{}' in watch expression '{}'", address, expression))?;
+            if addr < 0 || addr >= cpu_config.memory_size as WordType {
+                return Err(format!("Address {} in watch expression '{}' is outside of memory of {} words", addr, expression, cpu_config.memory_size));
+            }
+            Ok(Some(WatchLocation::Memory(addr)))
+        };
+        let parse_term = |text: &str| -> Result<Term, String> {
+            if let Some(value) = parse_integer(text.trim()) {
+                return Ok(Term::Constant(value));
+            }
+            parse_location(text)?
+                .map(Term::Location)
+                .ok_or_else(|| format!("Invalid operand '{}' in watch expression '{}'", text.trim(), expression))
+        };
+
+        let mut watchpoint = Watchpoint { expression: String::from(expression.trim()), comparisons: Vec::new(), locations: Vec::new() };
+        for conjunct in expression.split("&&") {
+            let comparison = COMPARISONS.iter().find_map(|(operator, comparison)| {
+                conjunct.split_once(operator).map(|(a, b)| (a, *comparison, b))
+            });
+            match comparison {
+                Some((a, comparison, b)) => watchpoint.comparisons.push((parse_term(a)?, comparison, parse_term(b)?)),
+                None if !expression.contains("&&") => {
+                    let location = parse_location(conjunct)?
+                        .ok_or_else(|| format!("Watch expression '{}' is neither a location nor a comparison", expression))?;
+                    watchpoint.locations.push(location);
+                }
+                None => return Err(format!("'{}' in watch expression '{}' isn't a comparison", conjunct.trim(), expression)),
+            }
+        }
+
+        for (a, _, b) in &watchpoint.comparisons {
+            for term in [a, b] {
+                if let Term::Location(location) = term {
+                    if !watchpoint.locations.contains(location) {
+                        watchpoint.locations.push(*location);
+                    }
+                }
+            }
+        }
+        if watchpoint.locations.is_empty() {
+            return Err(format!("Watch expression '{}' doesn't read a register or memory", expression));
+        }
+        Ok(watchpoint)
+    }
+
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    // If the retiring instruction writes one of the locations.
+    pub(crate) fn is_written(&self, written: &[WatchLocation]) -> bool {
+        self.locations.iter().any(|location| written.contains(location))
+    }
+
+    pub(crate) fn values(&self, arch_reg_file: &ArgRegFile, memory_subsystem: &MemorySubsystem) -> Vec<WordType> {
+        self.locations.iter().map(|location| location.value(arch_reg_file, memory_subsystem)).collect()
+    }
+
+    // Checks the watchpoint after an instruction that wrote one of its locations retired; `before` are the
+    // values of the locations before the instruction.
+    pub(crate) fn check(&self, before: &[WordType], arch_reg_file: &ArgRegFile, memory_subsystem: &MemorySubsystem, pc: usize, cycle: u64) -> Option<WatchpointHit> {
+        let after = self.values(arch_reg_file, memory_subsystem);
+        let value = |term: Term| match term {
+            Term::Location(location) => location.value(arch_reg_file, memory_subsystem),
+            Term::Constant(value) => value,
+        };
+        let triggered = if self.comparisons.is_empty() {
+            before != after.as_slice()
+        } else {
+            self.comparisons.iter().all(|(a, comparison, b)| comparison.holds(value(*a), value(*b)))
+        };
+
+        triggered.then(|| WatchpointHit {
+            expression: self.expression.clone(),
+            pc,
+            cycle,
+            values: self.locations.iter().map(|location| location.to_string()).zip(after).collect(),
+        })
+    }
+}
+
+/// A watchpoint that triggered; the instruction at the code address has retired.
+#[derive(Clone, Debug)]
+pub struct WatchpointHit {
+    pub expression: String,
+    pub pc: usize,
+    pub cycle: u64,
+    // the locations of the expression with their values after the instruction
+    pub values: Vec<(String, WordType)>,
+}
+
+impl fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values: Vec<String> = self.values.iter().map(|(location, value)| format!("{}={}", location, value)).collect();
+        write!(f, "Watchpoint '{}' triggered at code address {} in cycle {}: {}", self.expression, self.pc, self.cycle, values.join(", "))
+    }
+}
+
+// A decimal or hexadecimal integer, optionally negative; e.g. -1 or 0x10.
+fn parse_integer(text: &str) -> Option<WordType> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => WordType::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// An address, a variable or a variable with an offset; e.g. 'buffer+2'.
+fn resolve_address(address: &str, program: &Program) -> Option<WordType> {
+    if let Some(addr) = parse_integer(address) {
+        return Some(addr);
+    }
+    let (name, offset) = match address.split_once('+') {
+        Some((name, offset)) => (name.trim(), parse_integer(offset.trim())?),
+        None => (address, 0),
+    };
+    program.symbols.offset_of(name).map(|offset_of| offset_of as WordType + offset)
+}
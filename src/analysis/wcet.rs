@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::analysis::stack_usage::function_name;
+use crate::instructions::instructions::{Instr, Opcode, Operand, Program};
+
+// The cycles an instruction spends outside of its execution unit: decode, issue, dispatch,
+// the CDB broadcast and retirement.
+const PIPELINE_OVERHEAD: u64 = 5;
+
+/// The static bound of a function; unbounded when the control flow can't be bounded.
+pub(crate) enum Bound {
+    Cycles(u64),
+    Unbounded(String),
+}
+
+struct Loop {
+    header: usize,
+    // the code address of the last backward branch to the header
+    latch: usize,
+    bound: u64,
+}
+
+// A loop that has been collapsed into its header.
+struct CollapsedLoop {
+    cycles: u64,
+    exits: Vec<usize>,
+}
+
+/// Computes a static worst case execution time bound for every function.
+///
+/// The bound is conservative under the following assumptions: instructions don't overlap, so every
+/// instruction takes the latency of its execution unit plus the pipeline overhead; there is no cache,
+/// so every memory access takes the load/store latency; there is no branch prediction; there are no
+/// load replays and no interrupts. Every loop needs a .loop_bound on its backward branch.
+struct StaticAnalysis<'a> {
+    program: &'a Program,
+    bounds: HashMap<usize, Result<u64, String>>,
+    call_stack: Vec<usize>,
+}
+
+impl<'a> StaticAnalysis<'a> {
+    fn loc(&self, address: usize) -> String {
+        match self.program.code.get(address).and_then(|instr| instr.loc) {
+            Some(loc) => loc.to_string(),
+            None => format!("[{}]", address),
+        }
+    }
+
+    fn target(instr: &Instr) -> Option<usize> {
+        instr.source.iter()
+            .take(instr.source_cnt as usize)
+            .find_map(|operand| match operand {
+                Operand::Code(address) => Some(*address as usize),
+                _ => None,
+            })
+    }
+
+    fn successors(&self, address: usize) -> Result<Vec<usize>, String> {
+        if address >= self.program.code.len() {
+            // the end of the program
+            return Ok(Vec::new());
+        }
+
+        let instr = &self.program.code[address];
+        let successors = match instr.opcode {
+            Opcode::B => vec![Self::target(instr).unwrap()],
+            Opcode::CBZ | Opcode::CBNZ | Opcode::TBZ | Opcode::TBNZ |
+            Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+            Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO =>
+                vec![Self::target(instr).unwrap(), address + 1],
+            _ if instr.is_return() => Vec::new(),
+            Opcode::BX => return Err(format!("indirect branch at {}", self.loc(address))),
+            Opcode::ERET | Opcode::EXIT => Vec::new(),
+            _ => vec![address + 1],
+        };
+        Ok(successors)
+    }
+
+    fn cost(&mut self, address: usize) -> Result<u64, String> {
+        if address >= self.program.code.len() {
+            // the implicit EXIT
+            return Ok(1 + PIPELINE_OVERHEAD);
+        }
+
+        let instr = self.program.code[address];
+        let mut cost = instr.cycles as u64 + PIPELINE_OVERHEAD;
+        match instr.opcode {
+            Opcode::BL => cost += self.function_bound(Self::target(&instr).unwrap())?,
+            Opcode::BLX => return Err(format!("indirect call at {}", self.loc(address))),
+            _ => {}
+        }
+        Ok(cost)
+    }
+
+    fn reachable(&self, entry: usize) -> Result<Vec<usize>, String> {
+        let mut visited = HashSet::new();
+        let mut stack = vec![entry];
+        while let Some(address) = stack.pop() {
+            if visited.insert(address) {
+                stack.extend(self.successors(address)?);
+            }
+        }
+        let mut reachable: Vec<usize> = visited.into_iter().collect();
+        reachable.sort();
+        Ok(reachable)
+    }
+
+    fn loops(&self, reachable: &[usize]) -> Result<Vec<Loop>, String> {
+        // header -> loop; multiple backward branches to the same header form a single loop.
+        let mut loops = BTreeMap::<usize, Loop>::new();
+        for &address in reachable {
+            for successor in self.successors(address)? {
+                if successor > address {
+                    continue;
+                }
+
+                let bound = match self.program.loop_bounds.get(&address) {
+                    Some(bound) => *bound,
+                    None => return Err(format!("loop at {} has no .loop_bound", self.loc(address))),
+                };
+
+                let entry = loops.entry(successor).or_insert(Loop { header: successor, latch: address, bound: 0 });
+                entry.latch = entry.latch.max(address);
+                entry.bound += bound;
+            }
+        }
+
+        // inner loops first
+        let mut loops: Vec<Loop> = loops.into_values().collect();
+        loops.sort_by_key(|l| l.latch - l.header);
+        Ok(loops)
+    }
+
+    // The longest path starting at 'address' that stays in the region. A path ends when it leaves the
+    // region or when it branches back to the header of the loop being analyzed.
+    fn longest_path(&mut self,
+                    address: usize,
+                    region: (usize, usize),
+                    header: Option<usize>,
+                    collapsed: &HashMap<usize, CollapsedLoop>,
+                    memo: &mut HashMap<usize, u64>,
+                    visiting: &mut HashSet<usize>) -> Result<u64, String> {
+        if let Some(cycles) = memo.get(&address) {
+            return Ok(*cycles);
+        }
+
+        if !visiting.insert(address) {
+            return Err(format!("irreducible control flow at {}", self.loc(address)));
+        }
+
+        let (cost, successors) = match collapsed.get(&address) {
+            Some(collapsed_loop) if Some(address) != header => (collapsed_loop.cycles, collapsed_loop.exits.clone()),
+            _ => (self.cost(address)?, self.successors(address)?),
+        };
+
+        let mut longest = 0;
+        for successor in successors {
+            if Some(successor) == header || successor < region.0 || successor > region.1 {
+                continue;
+            }
+            longest = longest.max(self.longest_path(successor, region, header, collapsed, memo, visiting)?);
+        }
+
+        visiting.remove(&address);
+        memo.insert(address, cost + longest);
+        Ok(cost + longest)
+    }
+
+    fn analyze_function(&mut self, entry: usize) -> Result<u64, String> {
+        let reachable = self.reachable(entry)?;
+        let mut collapsed = HashMap::<usize, CollapsedLoop>::new();
+
+        for l in self.loops(&reachable)? {
+            let region = (l.header, l.latch);
+            let body = self.longest_path(l.header, region, Some(l.header), &collapsed, &mut HashMap::new(), &mut HashSet::new())?;
+
+            let mut exits = Vec::new();
+            for &address in reachable.iter().filter(|a| **a >= l.header && **a <= l.latch) {
+                for successor in self.successors(address)? {
+                    if (successor < l.header || successor > l.latch) && !exits.contains(&successor) {
+                        exits.push(successor);
+                    }
+                }
+            }
+            collapsed.insert(l.header, CollapsedLoop { cycles: body * l.bound, exits });
+        }
+
+        self.longest_path(entry, (0, self.program.code.len()), None, &collapsed, &mut HashMap::new(), &mut HashSet::new())
+    }
+
+    fn function_bound(&mut self, entry: usize) -> Result<u64, String> {
+        if let Some(bound) = self.bounds.get(&entry) {
+            return bound.clone();
+        }
+
+        if self.call_stack.contains(&entry) {
+            return Err(format!("recursive call of {}", function_name(self.program, entry)));
+        }
+
+        self.call_stack.push(entry);
+        let bound = self.analyze_function(entry);
+        self.call_stack.pop();
+
+        self.bounds.insert(entry, bound.clone());
+        bound
+    }
+}
+
+struct CallFrame {
+    function: String,
+    start_cycle: u64,
+}
+
+/// Compares the static WCET bound of every function with the longest execution measured at retirement.
+///
+/// An execution of a function starts when the BL or BLX calling it retires and ends when the matching
+/// return (BX LR or POP {.., pc}) retires. The entry function ends when the program exits.
+pub(crate) struct WcetAnalyzer {
+    bounds: BTreeMap<String, Bound>,
+    measured: BTreeMap<String, u64>,
+    frames: Vec<CallFrame>,
+}
+
+impl WcetAnalyzer {
+    pub(crate) fn new(program: &Program) -> Self {
+        let mut analysis = StaticAnalysis { program, bounds: HashMap::new(), call_stack: Vec::new() };
+        let _ = analysis.function_bound(program.entry_point);
+
+        let bounds = analysis.bounds.into_iter()
+            .map(|(entry, bound)| {
+                let bound = match bound {
+                    Ok(cycles) => Bound::Cycles(cycles),
+                    Err(cause) => Bound::Unbounded(cause),
+                };
+                (function_name(program, entry), bound)
+            })
+            .collect();
+
+        Self { bounds, measured: BTreeMap::new(), frames: Vec::new() }
+    }
+
+    fn record(&mut self, frame: CallFrame, cycle: u64) {
+        let measured = self.measured.entry(frame.function).or_insert(0);
+        *measured = (*measured).max(cycle - frame.start_cycle);
+    }
+
+    pub(crate) fn on_retire(&mut self, instr: &Instr, next_pc: usize, program: &Program, cycle: u64) {
+        if self.frames.is_empty() {
+            self.frames.push(CallFrame { function: function_name(program, program.entry_point), start_cycle: cycle });
+        }
+
+        match instr.opcode {
+            _ if instr.is_call() => {
+                let function = function_name(program, next_pc);
+                self.frames.push(CallFrame { function, start_cycle: cycle });
+            }
+            // the root frame is never popped
+            _ if instr.is_return() && self.frames.len() > 1 => {
+                let frame = self.frames.pop().unwrap();
+                self.record(frame, cycle);
+            }
+            Opcode::EXIT => {
+                while let Some(frame) = self.frames.pop() {
+                    self.record(frame, cycle);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for WcetAnalyzer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "WCET (cycles):")?;
+        for (function, bound) in &self.bounds {
+            let measured = match self.measured.get(function) {
+                Some(cycles) => cycles.to_string(),
+                None => String::from("-"),
+            };
+            match bound {
+                Bound::Cycles(cycles) => writeln!(f, "  {:<20} bound={:<8} measured={}", function, cycles, measured)?,
+                Bound::Unbounded(cause) => writeln!(f, "  {:<20} unbounded ({}) measured={}", function, cause, measured)?,
+            }
+        }
+        Ok(())
+    }
+}
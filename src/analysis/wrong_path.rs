@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::instructions::instructions::{mnemonic, Instr, SourceLocation, WordType};
+
+/// A load that executed, but was flushed before it retired; so it ran on a wrong path.
+///
+/// The architectural state never sees the value, but the access itself happened.
+pub struct SquashedLoad<'a> {
+    pub(crate) instr: &'a Instr,
+    pub(crate) pc: usize,
+    pub(crate) addr: WordType,
+    pub(crate) value: WordType,
+    pub(crate) forwarded: bool,
+    pub(crate) cycle: u64,
+}
+
+impl SquashedLoad<'_> {
+    pub fn mnemonic(&self) -> &'static str {
+        mnemonic(self.instr.opcode)
+    }
+
+    /// The code address of the load.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn loc(&self) -> Option<SourceLocation> {
+        self.instr.loc
+    }
+
+    /// The memory address that was read.
+    pub fn addr(&self) -> WordType {
+        self.addr
+    }
+
+    /// The value that was transiently read.
+    pub fn value(&self) -> WordType {
+        self.value
+    }
+
+    /// True if the value came from the store buffer instead of memory.
+    pub fn forwarded(&self) -> bool {
+        self.forwarded
+    }
+
+    /// The cycle in which the load was flushed.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+}
+
+/// Gets notified of every load that is flushed after it executed; e.g. to quantify transient
+/// execution leakage.
+pub trait WrongPathObserver {
+    fn on_squashed_load(&mut self, load: &SquashedLoad);
+}
+
+/// Counts the squashed loads per memory address.
+pub(crate) struct WrongPathStats {
+    squashed_load_cnt: u64,
+    forwarded_cnt: u64,
+    // address -> number of squashed loads
+    addresses: BTreeMap<WordType, u64>,
+}
+
+impl WrongPathStats {
+    pub(crate) fn new() -> WrongPathStats {
+        WrongPathStats { squashed_load_cnt: 0, forwarded_cnt: 0, addresses: BTreeMap::new() }
+    }
+}
+
+impl WrongPathObserver for WrongPathStats {
+    fn on_squashed_load(&mut self, load: &SquashedLoad) {
+        self.squashed_load_cnt += 1;
+        if load.forwarded {
+            self.forwarded_cnt += 1;
+        }
+        *self.addresses.entry(load.addr).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for WrongPathStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Wrong path loads: {} ({} forwarded from the store buffer) to {} distinct address(es)",
+                 self.squashed_load_cnt, self.forwarded_cnt, self.addresses.len())?;
+        for (addr, count) in &self.addresses {
+            writeln!(f, "  address {}: {} load(s)", addr, count)?;
+        }
+        Ok(())
+    }
+}
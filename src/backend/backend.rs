@@ -1,19 +1,99 @@
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
+use crate::abi::calling_convention::CallingConventionChecker;
+use crate::analysis::assertions::{RetireEvent, RetirementAssertion};
+use crate::analysis::deadline::DeadlineMonitor;
+use crate::analysis::execution_log::{log_line, log_targets, LogTarget};
+use crate::analysis::journal::{Journal, JournalEntry};
+use crate::analysis::ilp::IlpStudy;
+use crate::analysis::call_graph::CallGraphProfiler;
+use crate::analysis::sampling::SamplingProfiler;
+use crate::analysis::oracle::ArchStateOracle;
+use crate::analysis::watchpoint::{WatchLocation, Watchpoint, WatchpointHit};
+use crate::analysis::loops::LoopProfiler;
+use crate::analysis::occupancy::OccupancyHistogram;
+use crate::analysis::stack_guard::StackGuard;
+use crate::analysis::stall_attribution::{dominant_cause, StallAttribution, StallCause};
+use crate::analysis::stack_usage::StackUsageAnalyzer;
+use crate::analysis::wcet::WcetAnalyzer;
+use crate::analysis::watchdog::{Occupancy, UnretiredInstr};
+use crate::analysis::wrong_path::{SquashedLoad, WrongPathObserver, WrongPathStats};
 use crate::backend::execution_unit::EUTable;
 use crate::backend::physical_register::PhysRegFile;
 use crate::backend::register_alias_table::RAT;
-use crate::backend::reorder_buffer::{ROB, ROBSlotState};
-use crate::backend::reservation_station::{RSState, RSTable};
-use crate::cpu::{ArgRegFile, CARRY_FLAG_BIT_POSITION, CPUConfig, NEGATIVE_FLAG_BIT_POSITION, OVERFLOW_FLAG_BIT_POSITION, PerfCounters, Trace, ZERO_FLAG_BIT_POSITION};
+use crate::backend::reorder_buffer::{ROB, ROBSlot, ROBSlotState};
+use crate::backend::reservation_station::{RS, RSState, RSTable};
+use crate::cpu::{add_sub, ARCH_REG_CNT, ArgRegFile, Bypass, CARRY_FLAG_BIT_POSITION, condition_holds, CoreContext, CPSR, CPUConfig, Flags, fp_result, FPSCR, fp_value, insert_bits, extract_bits, IRQ_MASK_BIT_POSITION, is_fp_register, is_single_register, leading_zeros, MemoryDisambiguation, NEGATIVE_FLAG_BIT_POSITION, OVERFLOW_FLAG_BIT_POSITION, PC, PerfCounters, PipelineMode, reverse_bits, RSSelectionPolicy, SBDrainPolicy, SideEffects, test_flags, Trace, vector_op, wrap_word, ZERO_FLAG_BIT_POSITION};
+use crate::frontend::btb::BranchTargetBuffer;
+use crate::frontend::ras::ReturnAddressStack;
 use crate::frontend::frontend::FrontendControl;
-use crate::instructions::instructions::{Instr, InstrQueue, Opcode, Operand, RegisterType, WordType};
+use crate::guest_io::streams::GuestStreams;
+use crate::interrupts::interrupt_controller::InterruptController;
+use crate::instructions::instructions::{AliasedOperand, counter_name, eu_type, Instr, InstrQueue, Opcode, Operand, overwrites_flags, Program, RegisterType, sets_flags, WordType};
+use crate::instructions::microcode::transfer_cnt;
 use crate::memory_subsystem::memory_subsystem::MemorySubsystem;
+use crate::plugin::plugin::{AccessKind, FlushCause, FlushEvent, InstrEvent, MemoryAccess, MispredictEvent, Plugins};
+use crate::report::pipeline_state::{ExecutingInstr, ExecutionUnitState, PipelineInstr, ROBEntry, StoreBufferSlot};
+use crate::random::random::{Jitter, Random};
 
 struct CDBBroadcast {
     phys_reg: RegisterType,
     value: WordType,
+    // if the value is forwarded in the cycle it is produced instead of read after the writeback
+    forwarded: bool,
+}
+
+// The micro-ops of a retiring instruction combined; the results and sinks are in the order of the sinks
+// of the instruction.
+struct RetiringInstr {
+    result: Vec<WordType>,
+    // the physical registers of the sinks
+    sink: Vec<Operand>,
+    load_addr: Option<WordType>,
+    store_addr: Option<WordType>,
+    transfer_cnt: u8,
+    replay: bool,
+    fault: Option<String>,
+    // the cycle the frontend was redirected when the micro-op that writes the PC executed
+    redirect_cycle: Option<u64>,
+    stalls: HashMap<StallCause, u64>,
+}
+
+impl RetiringInstr {
+    fn new(rob: &ROB, uop_slots: &[u16], uops: &[Instr]) -> Self {
+        let mut retiring = RetiringInstr {
+            result: Vec::new(),
+            sink: Vec::new(),
+            load_addr: None,
+            store_addr: None,
+            transfer_cnt: 0,
+            replay: false,
+            fault: None,
+            redirect_cycle: None,
+            stalls: HashMap::new(),
+        };
+        for (rob_slot_index, uop) in uop_slots.iter().zip(uops) {
+            let rob_slot = rob.get(*rob_slot_index);
+            retiring.result.extend(&rob_slot.result);
+            retiring.sink.extend(&rob_slot.sink[..uop.sink_cnt as usize]);
+            // the micro-ops access consecutive words, starting with the lowest address
+            retiring.load_addr = retiring.load_addr.or(rob_slot.load_addr);
+            retiring.store_addr = retiring.store_addr.or(rob_slot.store_addr);
+            retiring.transfer_cnt += rob_slot.transfer_cnt;
+            retiring.replay |= rob_slot.replay;
+            retiring.redirect_cycle = retiring.redirect_cycle.or(rob_slot.redirect_cycle);
+            if retiring.fault.is_none() {
+                retiring.fault = rob_slot.fault.clone();
+            }
+            for (cause, cycles) in &rob_slot.stalls {
+                *retiring.stalls.entry(*cause).or_insert(0) += cycles;
+            }
+        }
+        retiring
+    }
 }
 
 pub struct Backend {
@@ -30,189 +110,802 @@ pub struct Backend {
     retire_n_wide: u8,
     dispatch_n_wide: u8,
     issue_n_wide: u8,
+    // the classic 5-stage in-order pipeline: the instructions are dispatched in program order and pass a
+    // single execute stage and a single memory stage
+    in_order: bool,
+    // the first cycle in which the execute stage of the in-order pipeline is free
+    ex_free_cycle: u64,
+    // the first cycle in which the memory stage of the in-order pipeline is free
+    mem_free_cycle: u64,
+    rs_selection: RSSelectionPolicy,
+    // only set when the ready instructions are selected in a random order
+    rs_random: Option<Random>,
     cdb_broadcast_buffer: Vec<CDBBroadcast>,
+    // the results that aren't forwarded; they are written back and broadcast in the next cycle
+    writeback_buffer: Vec<CDBBroadcast>,
+    bypass: Bypass,
+    // only set when the latency jitter is enabled
+    eu_jitter: Option<Jitter>,
     stack_capacity: u32,
     pub(crate) exit: bool,
+    // the value of r0 when the EXIT retired; None while running or when the program ended with a fault
+    pub(crate) exit_code: Option<WordType>,
+    // retires at most 1 instruction per cycle; used when stepping a single instruction
+    pub(crate) single_retire: bool,
+    // the code addresses where retirement stops before the instruction retires
+    pub(crate) breakpoints: HashSet<usize>,
+    // the breakpoint retirement stopped at, with the fetch sequence number of the instruction
+    pub(crate) breakpoint_hit: Option<(usize, u64)>,
+    // the instruction that stopped at a breakpoint and may retire after resuming
+    resumed_seq: Option<u64>,
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    // the first watchpoint that triggered since resuming; retirement stops for the cycle after it triggers
+    pub(crate) watchpoint_hit: Option<WatchpointHit>,
     perf_counters: Rc<RefCell<PerfCounters>>,
+    program_option: Option<Rc<Program>>,
+    calling_convention_checker: Option<CallingConventionChecker>,
+    pub(crate) stack_usage: Option<StackUsageAnalyzer>,
+    // if every retired instruction is printed with the registers and memory words it changed
+    execution_log: bool,
+    // only present when the journal_depth isn't 0
+    pub(crate) journal: Option<Journal>,
+    stack_guard: Option<StackGuard>,
+    // the invariants checked when an instruction retires
+    pub(crate) assertions: Vec<Box<dyn RetirementAssertion>>,
+    interrupt_controller: Rc<RefCell<InterruptController>>,
+    btb: Rc<RefCell<BranchTargetBuffer>>,
+    ras: Rc<RefCell<ReturnAddressStack>>,
+    pub(crate) ilp_study: Option<IlpStudy>,
+    wcet_enabled: bool,
+    pub(crate) wcet: Option<WcetAnalyzer>,
+    stall_attribution_enabled: bool,
+    pub(crate) stall_attribution: Option<StallAttribution>,
+    loop_stats_enabled: bool,
+    pub(crate) loop_profiler: Option<LoopProfiler>,
+    call_graph_enabled: bool,
+    pub(crate) call_graph: Option<CallGraphProfiler>,
+    sampling_period: u64,
+    pub(crate) sampling: Option<SamplingProfiler>,
+    // the config the reference interpreter runs with; only present in oracle mode
+    oracle_config: Option<CPUConfig>,
+    pub(crate) oracle: Option<ArchStateOracle>,
+    rs_occupancy_enabled: bool,
+    pub(crate) rs_occupancy: Option<OccupancyHistogram>,
+    // only present when the program contains deadline regions
+    pub(crate) deadline_monitor: Option<DeadlineMonitor>,
+    memory_disambiguation: MemoryDisambiguation,
+    sb_drain_policy: SBDrainPolicy,
+    // the speculation epoch of the next issued instruction; every control instruction starts a new one
+    epoch: u64,
+    // the cycle of the last flush after a misprediction, till the first instruction of the correct path is issued
+    mispredict_flush_cycle: Option<u64>,
+    side_effects: SideEffects,
+    word_size: u8,
+    // the number of bits of a vector lane; lane arithmetic wraps at this width
+    lane_width: u8,
+    pub(crate) wrong_path_stats: Option<WrongPathStats>,
+    // notified of every load that is flushed after it executed
+    pub(crate) wrong_path_observers: Vec<Box<dyn WrongPathObserver>>,
+    // every line of output written by the program
+    pub(crate) output: Vec<String>,
+    // the input and output of the program; see READC and PRINTR
+    pub(crate) streams: GuestStreams,
+    plugins: Plugins,
 }
 
 impl Backend {
-    pub(crate) fn new(cpu_config: &CPUConfig,
-                      instr_queue: Rc<RefCell<InstrQueue>>,
-                      memory_subsystem: Rc<RefCell<MemorySubsystem>>,
-                      arch_reg_file: Rc<RefCell<ArgRegFile>>,
-                      frontend_control: Rc<RefCell<FrontendControl>>,
-                      perf_counters: Rc<RefCell<PerfCounters>>) -> Backend {
+    pub(crate) fn new(cpu_config: &CPUConfig, context: &CoreContext) -> Backend {
+        let CoreContext { instr_queue, memory_subsystem, arch_reg_file, frontend_control, perf_counters,
+            interrupt_controller, btb, ras, plugins } = context.clone();
         Backend {
+            interrupt_controller,
+            btb,
+            ras,
             trace: cpu_config.trace.clone(),
             instr_queue,
             memory_subsystem,
             arch_reg_file,
             rs_table: RSTable::new(cpu_config.rs_count),
             phys_reg_file: PhysRegFile::new(cpu_config.phys_reg_count),
-            rat: RAT::new(cpu_config.phys_reg_count),
+            rat: RAT::new(ARCH_REG_CNT),
             rob: ROB::new(cpu_config.rob_capacity),
-            eu_table: EUTable::new(cpu_config.eu_count),
-            retire_n_wide: cpu_config.retire_n_wide,
-            dispatch_n_wide: cpu_config.dispatch_n_wide,
-            issue_n_wide: cpu_config.issue_n_wide,
-            cdb_broadcast_buffer: Vec::with_capacity(cpu_config.eu_count as usize),
+            eu_table: EUTable::new(&cpu_config.execution_units),
+            retire_n_wide: cpu_config.n_wide(cpu_config.retire_n_wide),
+            dispatch_n_wide: cpu_config.n_wide(cpu_config.dispatch_n_wide),
+            issue_n_wide: cpu_config.n_wide(cpu_config.issue_n_wide),
+            in_order: cpu_config.pipeline_mode == PipelineMode::InOrder,
+            ex_free_cycle: 0,
+            mem_free_cycle: 0,
+            rs_selection: cpu_config.rs_selection.policy,
+            rs_random: if cpu_config.rs_selection.policy == RSSelectionPolicy::Random {
+                Some(Random::new(cpu_config.rs_selection.seed))
+            } else {
+                None
+            },
+            cdb_broadcast_buffer: Vec::with_capacity(cpu_config.execution_units.count() as usize),
+            writeback_buffer: Vec::with_capacity(cpu_config.execution_units.count() as usize),
+            bypass: cpu_config.bypass,
+            eu_jitter: if cpu_config.jitter.enabled {
+                Some(Jitter::new(cpu_config.jitter.seed, cpu_config.jitter.max_eu_delay))
+            } else {
+                None
+            },
             frontend_control,
-            stack_capacity: cpu_config.stack_capacity,
+            stack_capacity: cpu_config.stack_capacity(),
             exit: false,
+            exit_code: None,
+            single_retire: false,
+            breakpoints: HashSet::new(),
+            breakpoint_hit: None,
+            resumed_seq: None,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
             perf_counters,
+            program_option: None,
+            calling_convention_checker: if cpu_config.check_calling_convention {
+                Some(CallingConventionChecker::new())
+            } else {
+                None
+            },
+            memory_disambiguation: cpu_config.memory_disambiguation,
+            sb_drain_policy: cpu_config.sb_drain_policy,
+            epoch: 0,
+            mispredict_flush_cycle: None,
+            side_effects: cpu_config.side_effects,
+            word_size: cpu_config.word_size,
+            lane_width: cpu_config.vector.lane_width,
+            execution_log: cpu_config.execution_log,
+            journal: (cpu_config.journal_depth > 0).then(|| Journal::new(cpu_config.journal_depth)),
+            stack_usage: if cpu_config.stack_usage {
+                // on ARM the stack grows down; the SP starts at the stack base
+                Some(StackUsageAnalyzer::new(cpu_config.initial_sp() as WordType, cpu_config.stack_capacity()))
+            } else {
+                None
+            },
+            stack_guard: if cpu_config.stack_checks || cpu_config.stack_poison {
+                Some(StackGuard::new(cpu_config.initial_sp() as WordType,
+                                     cpu_config.stack_capacity(),
+                                     cpu_config.stack_checks,
+                                     cpu_config.stack_poison))
+            } else {
+                None
+            },
+            assertions: Vec::new(),
+            deadline_monitor: None,
+            ilp_study: if cpu_config.ilp_study {
+                Some(IlpStudy::new())
+            } else {
+                None
+            },
+            wcet_enabled: cpu_config.wcet,
+            wcet: None,
+            stall_attribution_enabled: cpu_config.stall_attribution,
+            stall_attribution: None,
+            loop_stats_enabled: cpu_config.loop_stats,
+            loop_profiler: None,
+            call_graph_enabled: cpu_config.call_graph,
+            call_graph: None,
+            sampling_period: cpu_config.sampling_period,
+            sampling: None,
+            oracle_config: cpu_config.oracle.then(|| cpu_config.clone()),
+            oracle: None,
+            rs_occupancy_enabled: cpu_config.rs_occupancy,
+            rs_occupancy: None,
+            wrong_path_stats: if cpu_config.wrong_path_loads {
+                Some(WrongPathStats::new())
+            } else {
+                None
+            },
+            wrong_path_observers: Vec::new(),
+            output: Vec::new(),
+            streams: GuestStreams::new(),
+            plugins,
+        }
+    }
+
+    pub(crate) fn init(&mut self, program: &Rc<Program>) {
+        self.program_option = Some(Rc::clone(program));
+        self.exit_code = None;
+        self.deadline_monitor = if program.deadlines.is_empty() {
+            None
+        } else {
+            Some(DeadlineMonitor::new(&program.deadlines))
+        };
+        self.stall_attribution = if self.stall_attribution_enabled {
+            Some(StallAttribution::new(program))
+        } else {
+            None
+        };
+        self.loop_profiler = if self.loop_stats_enabled {
+            Some(LoopProfiler::new(program))
+        } else {
+            None
+        };
+        self.call_graph = if self.call_graph_enabled {
+            Some(CallGraphProfiler::new(program))
+        } else {
+            None
+        };
+        self.sampling = if self.sampling_period > 0 {
+            Some(SamplingProfiler::new(program, self.sampling_period))
+        } else {
+            None
+        };
+        self.oracle = self.oracle_config.as_ref().map(|cpu_config| ArchStateOracle::new(cpu_config, program));
+        self.rs_occupancy = if self.rs_occupancy_enabled {
+            Some(OccupancyHistogram::new("RS", self.rs_table.capacity))
+        } else {
+            None
+        };
+        self.wcet = if self.wcet_enabled {
+            Some(WcetAnalyzer::new(program))
+        } else {
+            None
+        };
+    }
+
+    // Stack slots written before the pipeline starts; e.g. the arguments passed on the stack or the stores
+    // of the fast forwarded instructions. So they can be read.
+    pub(crate) fn mark_stack_written(&mut self, addr: WordType, cnt: usize) {
+        if let Some(stack_guard) = &mut self.stack_guard {
+            stack_guard.mark_initialized(addr, cnt);
         }
     }
 
+    // Continues after a breakpoint; the instruction at the breakpoint may retire.
+    pub(crate) fn resume(&mut self) {
+        if let Some((_, seq)) = self.breakpoint_hit.take() {
+            self.resumed_seq = Some(seq);
+        }
+        self.watchpoint_hit = None;
+    }
+
+    // Undoes at most cnt of the last retired instructions using the journal; the pipeline is flushed and the
+    // frontend continues at the oldest undone instruction. Returns the number of undone instructions.
+    pub(crate) fn step_back(&mut self, cnt: usize) -> usize {
+        let Some(journal) = &mut self.journal else {
+            return 0;
+        };
+        let mut entries = Vec::new();
+        while entries.len() < cnt {
+            match journal.pop() {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+        }
+        let Some(oldest) = entries.last() else {
+            return 0;
+        };
+
+        // the committed stores are written first, so the memory holds the architectural state to undo
+        self.memory_subsystem.borrow_mut().write_committed_stores();
+        self.flush(FlushCause::StepBack, oldest.pc, oldest.pc);
+
+        let mut arch_reg_file = self.arch_reg_file.borrow_mut();
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        // youngest first, so every location ends with its value from before the oldest undone instruction
+        for entry in &entries {
+            for (location, value) in entry.before.iter().rev() {
+                match location {
+                    WatchLocation::Register(reg) => arch_reg_file.set_value(*reg, *value),
+                    WatchLocation::Memory(addr) => memory_subsystem.memory[*addr as usize] = *value,
+                }
+            }
+        }
+        self.exit = false;
+        self.exit_code = None;
+        self.breakpoint_hit = None;
+        self.resumed_seq = None;
+        entries.len()
+    }
+
+    // The occupancy of the ROB, the reservation stations and the store buffer.
+    pub(crate) fn occupancy(&self) -> Vec<Occupancy> {
+        let sb = &self.memory_subsystem.borrow().sb;
+        vec![
+            Occupancy { name: "ROB", size: self.rob.size(), capacity: self.rob.capacity() },
+            Occupancy { name: "RS", size: self.rs_table.used(), capacity: self.rs_table.capacity },
+            Occupancy { name: "SB", size: sb.size(), capacity: sb.capacity() },
+        ]
+    }
+
+    // The oldest instruction that hasn't retired; None if the ROB is empty.
+    pub(crate) fn oldest_unretired(&self) -> Option<UnretiredInstr> {
+        let rob_slot = self.rob.head()?;
+        let program = self.program_option.as_ref().unwrap();
+        let instr = program.instr(rob_slot.instr_index?);
+
+        Some(UnretiredInstr {
+            pc: rob_slot.pc,
+            text: instr.with_aliases(&program.register_aliases).to_string(),
+            line: instr.loc.map(|loc| loc.line),
+            state: self.slot_state(rob_slot),
+            fetch_cycle: rob_slot.fetch_cycle,
+        })
+    }
+
+    fn slot_state(&self, rob_slot: &ROBSlot) -> &'static str {
+        match rob_slot.state {
+            ROBSlotState::ISSUED if self.rob.is_waiting_for_rs(rob_slot.index) => "waiting for a reservation station",
+            ROBSlotState::ISSUED => "waiting for its operands",
+            ROBSlotState::READY => "waiting to be dispatched",
+            ROBSlotState::DISPATCHED => "executing",
+            ROBSlotState::EXECUTED => "executed; waiting to retire",
+            ROBSlotState::UNUSED => "unused",
+        }
+    }
+
+    // The instructions waiting in a reservation station, the execution units, the store buffer and
+    // the reorder buffer.
+    pub(crate) fn pipeline_state(&self) -> (Vec<PipelineInstr>, Vec<ExecutionUnitState>, Vec<StoreBufferSlot>, Vec<ROBEntry>) {
+        let store_buffer = self.memory_subsystem.borrow().sb.slots();
+        let Some(program) = &self.program_option else {
+            return (Vec::new(), Vec::new(), store_buffer, Vec::new());
+        };
+        let describe = |rob_slot: &ROBSlot| PipelineInstr::new(program, rob_slot.instr_index.unwrap(), rob_slot.pc, rob_slot.seq);
+
+        let in_flight: Vec<&ROBSlot> = self.rob.in_flight().into_iter()
+            .map(|index| self.rob.get(index))
+            .filter(|rob_slot| rob_slot.instr_index.is_some())
+            .collect();
+
+        let issue_queue = in_flight.iter()
+            .filter(|rob_slot| rob_slot.state == ROBSlotState::ISSUED && !self.rob.is_waiting_for_rs(rob_slot.index))
+            .map(|rob_slot| describe(rob_slot))
+            .collect();
+
+        let execution_units = self.eu_table.units().iter()
+            .map(|eu| ExecutionUnitState {
+                index: eu.index,
+                eu_type: eu.eu_type.to_string(),
+                pipelined: eu.pipelined,
+                executing: eu.in_flight.iter()
+                    .map(|(rs_index, cycles_remaining)| ExecutingInstr {
+                        instr: describe(self.rob.get(self.rs_table.get(*rs_index).rob_slot_index)),
+                        cycles_remaining: *cycles_remaining,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let rob = in_flight.iter()
+            .map(|rob_slot| ROBEntry {
+                instr: describe(rob_slot),
+                state: String::from(self.slot_state(rob_slot)),
+                stall: rob_slot.dispatch_stall.map(|cause| cause.describe(program)),
+                fetch_cycle: rob_slot.fetch_cycle,
+            })
+            .collect();
+
+        (issue_queue, execution_units, store_buffer, rob)
+    }
+
     pub(crate) fn do_cycle(&mut self) {
-        self.cycle_retire();
+        self.write_back();
+        if let Some((cause, pc, target)) = self.cycle_retire() {
+            self.flush(cause, pc, target);
+            self.mispredict_flush_cycle = (cause == FlushCause::Mispredict).then(|| self.perf_counters.borrow().cycle_cnt);
+        }
         self.cycle_eu_table();
         self.cdb_broadcast();
         self.cycle_dispatch();
         self.cycle_issue();
+        self.record_cycles(1);
     }
 
-    fn cycle_eu_table(&mut self) {
-        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
-        let mut perf_monitors = self.perf_counters.borrow_mut();
+    // If the backend can't make progress before an instruction completes: nothing can retire, be
+    // dispatched or be issued, no result is waiting to be broadcast and no interrupt can be taken.
+    pub(crate) fn is_quiescent(&self) -> bool {
+        if !self.writeback_buffer.is_empty() || !self.cdb_broadcast_buffer.is_empty() || self.rs_table.has_ready() {
+            return false;
+        }
 
-        for eu_index in 0..self.eu_table.capacity {
-            let mut eu = self.eu_table.get_mut(eu_index);
-            if eu.cycles_remaining == 0 {
-                // eu is free, ignore it.
-                continue;
+        let program = self.program_option.as_ref().unwrap();
+        let irq_masked = self.arch_reg_file.borrow().get_value(CPSR) & (1 << IRQ_MASK_BIT_POSITION) != 0;
+        if program.irq_handler.is_some() && self.interrupt_controller.borrow().pending && !irq_masked {
+            return false;
+        }
+        if let Some(rob_slot) = self.rob.head() {
+            if self.rob.head_instr_has_executed(program.uops(rob_slot.instr_index.unwrap()).len()) {
+                return false;
             }
-            eu.cycles_remaining -= 1;
-            if eu.cycles_remaining > 0 {
-                // the execution unit isn't finished with its work
+        }
+
+        let instr_queue = self.instr_queue.borrow();
+        if !instr_queue.is_empty() && self.rob.has_space_for(program.uops(instr_queue.peek().index).len()) {
+            return false;
+        }
+        !(self.rob.has_issued() && self.rs_table.has_free())
+    }
+
+    // The number of cycles till the cycle an instruction completes; None when nothing executes.
+    pub(crate) fn cycles_till_completion(&self) -> Option<u64> {
+        self.eu_table.cycles_till_completion().map(u64::from)
+    }
+
+    // Advances the quiescent backend by the given number of cycles in which no instruction completes;
+    // with the same accounting as executing them one by one.
+    pub(crate) fn skip(&mut self, cycles: u64) {
+        self.eu_table.skip(cycles as u8);
+        self.record_cycles(cycles);
+    }
+
+    // Records the occupancy and the stalls of the given number of cycles in the current state.
+    fn record_cycles(&mut self, cycles: u64) {
+        if let Some(rs_occupancy) = &mut self.rs_occupancy {
+            rs_occupancy.record(self.rs_table.used(), cycles);
+        }
+        if self.stall_attribution.is_some() || self.loop_profiler.is_some() || self.trace.retire {
+            self.attribute_stalls(cycles);
+        }
+    }
+
+    // Attributes the cycles to the cause of the wait of every instruction in the ROB that is waiting.
+    fn attribute_stalls(&mut self, cycles: u64) {
+        let mut stalled = Vec::new();
+        let in_flight = self.rob.in_flight();
+        for (position, rob_slot_index) in in_flight.iter().enumerate() {
+            let rob_slot = self.rob.get(*rob_slot_index);
+            let cause = match rob_slot.state {
+                ROBSlotState::ISSUED if self.rob.is_waiting_for_rs(*rob_slot_index) => Some(StallCause::ReservationStation),
+                ROBSlotState::ISSUED => {
+                    let rs = self.rs_table.get(rob_slot.rs_index);
+                    rs.source.iter()
+                        .take(rs.source_cnt as usize)
+                        .find_map(|source| match source {
+                            Operand::Register(phys_reg) => Some(*phys_reg),
+                            _ => None,
+                        })
+                        .and_then(|phys_reg| self.producer_of(phys_reg, &in_flight[..position]))
+                }
+                ROBSlotState::READY | ROBSlotState::DISPATCHED => rob_slot.dispatch_stall,
+                ROBSlotState::EXECUTED => Some(StallCause::Retirement),
+                ROBSlotState::UNUSED => None,
+            };
+            if let Some(cause) = cause {
+                stalled.push((*rob_slot_index, cause));
+            }
+        }
+
+        for (rob_slot_index, cause) in stalled {
+            let rob_slot = self.rob.get_mut(rob_slot_index);
+            *rob_slot.stalls.entry(cause).or_insert(0) += cycles;
+            if let Some(stall_attribution) = &mut self.stall_attribution {
+                stall_attribution.on_stall(rob_slot.pc, cause, cycles);
+            }
+            if let Some(loop_profiler) = &mut self.loop_profiler {
+                loop_profiler.on_stall(rob_slot.pc, cause, cycles);
+            }
+        }
+    }
+
+    // The cause of waiting for the physical register: the older instruction that writes it.
+    fn producer_of(&self, phys_reg: RegisterType, older: &[u16]) -> Option<StallCause> {
+        older.iter().rev()
+            .map(|rob_slot_index| self.rob.get(*rob_slot_index))
+            .find_map(|rob_slot| {
+                let uop = &self.program_option.as_ref().unwrap().uops(rob_slot.instr_index?)[rob_slot.uop as usize];
+                rob_slot.sink.iter()
+                    .take(uop.sink_cnt as usize)
+                    .any(|sink| matches!(sink, Operand::Register(reg) if *reg == phys_reg))
+                    .then(|| StallCause::Operand { producer_pc: rob_slot.pc, load: uop.is_load() })
+            })
+    }
+
+    // Flushes all instructions that have not been retired and lets the frontend fetch again from the
+    // target. The pc is the code address of the instruction that caused the flush.
+    fn flush(&mut self, cause: FlushCause, pc: usize, target: usize) {
+        self.report_squashed_loads(&self.rob.in_flight());
+
+        let event = FlushEvent { cause, pc, target, squashed_cnt: self.rob.size(), cycle: self.perf_counters.borrow().cycle_cnt };
+        for plugin in self.plugins.borrow_mut().iter_mut() {
+            plugin.on_flush(&event);
+        }
+
+        self.rob.flush();
+        self.rs_table.flush();
+        self.eu_table.flush();
+        self.ex_free_cycle = 0;
+        self.mem_free_cycle = 0;
+        self.rat.flush();
+        self.phys_reg_file.flush();
+        self.cdb_broadcast_buffer.clear();
+        self.writeback_buffer.clear();
+        self.instr_queue.borrow_mut().flush();
+        self.memory_subsystem.borrow_mut().sb.flush();
+
+        let mut frontend_control = self.frontend_control.borrow_mut();
+        frontend_control.halted = false;
+        frontend_control.redirect = Some(target);
+    }
+
+    // Flushes the instructions that are younger than the mispredicted control instruction in the slot and lets
+    // the frontend fetch again from the target. The older instructions stay in flight; so do their stores in
+    // the store buffer, only the stores of the later speculation epochs are squashed.
+    fn flush_wrong_path(&mut self, rob_slot_index: u16, target: usize) {
+        let program = Rc::clone(self.program_option.as_ref().unwrap());
+        let (seq, pc, epoch) = {
+            let rob_slot = self.rob.get(rob_slot_index);
+            (rob_slot.seq, rob_slot.pc, rob_slot.epoch)
+        };
+        let cycle = self.perf_counters.borrow().cycle_cnt;
+        let squashed = self.rob.younger_than(seq);
+        self.report_squashed_loads(&squashed);
+
+        let event = FlushEvent { cause: FlushCause::Mispredict, pc, target, squashed_cnt: squashed.len() as u16, cycle };
+        for plugin in self.plugins.borrow_mut().iter_mut() {
+            plugin.on_flush(&event);
+        }
+
+        // the reservation stations and the physical registers of the squashed instructions
+        let mut squashed_rs = Vec::new();
+        let mut squashed_phys_regs = Vec::new();
+        for index in squashed {
+            if self.rob.is_waiting_for_rs(index) {
                 continue;
             }
+            let rob_slot = self.rob.get(index);
+            if rob_slot.state != ROBSlotState::EXECUTED {
+                squashed_rs.push(rob_slot.rs_index);
+            }
+            let uop = &program.uops(rob_slot.instr_index.unwrap())[rob_slot.uop as usize];
+            for sink in &rob_slot.sink[..uop.sink_cnt as usize] {
+                if let Operand::Register(phys_reg) = sink {
+                    squashed_phys_regs.push(*phys_reg);
+                }
+            }
+        }
+
+        self.eu_table.squash(&squashed_rs);
+        self.rs_table.squash(&squashed_rs);
+        for phys_reg in &squashed_phys_regs {
+            self.phys_reg_file.get_mut(*phys_reg).has_value = false;
+            self.phys_reg_file.deallocate(*phys_reg);
+        }
+        self.cdb_broadcast_buffer.retain(|req| !squashed_phys_regs.contains(&req.phys_reg));
+        self.writeback_buffer.retain(|req| !squashed_phys_regs.contains(&req.phys_reg));
+        self.rob.squash_younger(seq);
+
+        // the RAT maps every register to the youngest physical register of the instructions that stay
+        self.rat.flush();
+        for index in self.rob.in_flight() {
+            let rob_slot = self.rob.get(index);
+            let uop = &program.uops(rob_slot.instr_index.unwrap())[rob_slot.uop as usize];
+            for (instr_sink, rob_sink) in uop.sink.iter().zip(&rob_slot.sink).take(uop.sink_cnt as usize) {
+                if let (Operand::Register(arch_reg), Operand::Register(phys_reg)) = (instr_sink, rob_sink) {
+                    let rat_entry = self.rat.get_mut(*arch_reg);
+                    rat_entry.phys_reg = *phys_reg;
+                    rat_entry.valid = true;
+                }
+            }
+        }
+
+        self.instr_queue.borrow_mut().flush();
+        // the younger instructions were fetched from the wrong target; their stores must never drain to memory
+        let squashed_store_cnt = self.memory_subsystem.borrow_mut().sb.squash(epoch + 1);
+        self.perf_counters.borrow_mut().squashed_store_cnt += squashed_store_cnt;
+        self.mispredict_flush_cycle = Some(cycle);
+
+        let mut frontend_control = self.frontend_control.borrow_mut();
+        frontend_control.halted = false;
+        frontend_control.redirect = Some(target);
+    }
+
+    // Reports every load in the slots that has executed, but is about to be flushed.
+    fn report_squashed_loads(&mut self, rob_slot_indices: &[u16]) {
+        let mut perf_counters = self.perf_counters.borrow_mut();
+        let program = Rc::clone(self.program_option.as_ref().unwrap());
+        for rob_slot_index in rob_slot_indices.iter().copied() {
+            let rob_slot = self.rob.get_mut(rob_slot_index);
+            let addr = match rob_slot.load_addr {
+                Some(addr) if rob_slot.state == ROBSlotState::EXECUTED => addr,
+                _ => continue,
+            };
+
+            perf_counters.squashed_load_cnt += 1;
+            let load = SquashedLoad {
+                instr: program.instr(rob_slot.instr_index.unwrap()),
+                pc: rob_slot.pc,
+                addr,
+                value: rob_slot.result[0],
+                forwarded: rob_slot.load_forwarded_from.is_some(),
+                cycle: perf_counters.cycle_cnt,
+            };
+
+            if let Some(wrong_path_stats) = &mut self.wrong_path_stats {
+                wrong_path_stats.on_squashed_load(&load);
+            }
+            for observer in &mut self.wrong_path_observers {
+                observer.on_squashed_load(&load);
+            }
+        }
+    }
+
+    // Marks every executed load that is younger than the store with the given sequence number and
+    // that read the same address from an older source, for replay.
+    fn detect_load_conflicts(&mut self, store_seq: u64, addr: WordType) {
+        for rob_slot_index in self.rob.in_flight() {
+            let rob_slot = self.rob.get_mut(rob_slot_index);
+            let reads_addr = rob_slot.load_addr
+                .is_some_and(|load_addr| load_addr <= addr && addr < load_addr + rob_slot.transfer_cnt as WordType);
+            if reads_addr
+                && rob_slot.sb_tail > store_seq
+                && rob_slot.load_forwarded_from.is_none_or(|seq| seq < store_seq) {
+                rob_slot.replay = true;
+            }
+        }
+    }
 
+    fn cycle_eu_table(&mut self) {
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        let mut perf_monitors = self.perf_counters.borrow_mut();
+        let program = Rc::clone(self.program_option.as_ref().unwrap());
+        let aliases = &program.register_aliases;
+        // the stores that got their address this cycle
+        let mut resolved_stores = Vec::new();
+        // the control instructions that executed with another target than the frontend predicted, as
+        // (seq, rob slot, target)
+        let mut mispredicted = Vec::new();
+
+        for rs_index in self.eu_table.cycle() {
             // it is the last cycle; so lets give this Eu some real work
-            let rs_index = eu.rs_index;
             let mut rs = self.rs_table.get_mut(rs_index);
 
             let rob_index = rs.rob_slot_index;
             let mut rob_slot = self.rob.get_mut(rob_index);
 
-            let rc = <Option<Rc<Instr>> as Clone>::clone(&rob_slot.instr).unwrap();
-            let instr = Rc::clone(&rc);
-
-            if self.trace.execute {
-                println!("Executing {}", instr);
-            }
+            // the micro-op is executed; the plugins are notified of its instruction
+            let instr = &program.uops(rob_slot.instr_index.unwrap())[rob_slot.uop as usize];
+            let arch_instr = program.instr(rob_slot.instr_index.unwrap());
 
+            let word_size = self.word_size;
             match rs.opcode {
                 Opcode::NOP => {}
-                Opcode::ADD => rob_slot.result.push(rs.source[0].get_constant() + rs.source[1].get_constant()),
-                Opcode::SUB => rob_slot.result.push(rs.source[0].get_constant() - rs.source[1].get_constant()),
-                Opcode::MUL => rob_slot.result.push(rs.source[0].get_constant() * rs.source[1].get_constant()),
-                Opcode::SDIV => rob_slot.result.push(rs.source[0].get_constant() / rs.source[1].get_constant()),
-                Opcode::NEG => rob_slot.result.push(-rs.source[0].get_constant()),
+                Opcode::DMB | Opcode::DSB => {}
+                Opcode::ADD => rob_slot.result.push(wrap_word(rs.source[0].get_constant().wrapping_add(rs.source[1].get_constant()), word_size)),
+                Opcode::SUB => rob_slot.result.push(wrap_word(rs.source[0].get_constant().wrapping_sub(rs.source[1].get_constant()), word_size)),
+                Opcode::MUL => rob_slot.result.push(wrap_word(rs.source[0].get_constant().wrapping_mul(rs.source[1].get_constant()), word_size)),
+                Opcode::SDIV => rob_slot.result.push(wrap_word(rs.source[0].get_constant().wrapping_div(rs.source[1].get_constant()), word_size)),
+                Opcode::NEG => rob_slot.result.push(wrap_word(rs.source[0].get_constant().wrapping_neg(), word_size)),
                 Opcode::AND => rob_slot.result.push(rs.source[0].get_constant() & rs.source[1].get_constant()),
-                Opcode::MOV => rob_slot.result.push(rs.source[0].get_constant()),
-                Opcode::ADR => {}
+                Opcode::MOV => rob_slot.result.push(wrap_word(rs.source[0].get_constant(), word_size)),
+                Opcode::ADR => {
+                    let address = match rs.source[0] {
+                        Operand::Code(addr) | Operand::Memory(addr) => addr,
+                        _ => panic!("Illegal ADR source {:?}", rs.source[0]),
+                    };
+                    rob_slot.result.push(address);
+                }
                 Opcode::ORR => rob_slot.result.push(rs.source[0].get_constant() | rs.source[1].get_constant()),
                 Opcode::EOR => rob_slot.result.push(rs.source[0].get_constant() ^ rs.source[1].get_constant()),
                 Opcode::MVN => rob_slot.result.push(!rs.source[0].get_constant()),
-                Opcode::LDR => rob_slot.result.push(memory_subsystem.memory[rs.source[0].get_constant() as usize]),
-                Opcode::STR => rob_slot.result.push(rs.source[0].get_constant()),
-                Opcode::PRINTR => {
-                    println!("PRINTR {}={}", Operand::Register(instr.source[0].get_register()), rs.source[0].get_constant());
+                Opcode::CLZ => rob_slot.result.push(leading_zeros(rs.source[0].get_constant(), word_size)),
+                Opcode::RBIT => rob_slot.result.push(reverse_bits(rs.source[0].get_constant(), word_size)),
+                Opcode::UBFX | Opcode::SBFX => {
+                    let lsb = rs.source[1].get_constant() as u32;
+                    let width = rs.source[2].get_constant() as u32;
+                    let signed = rs.opcode == Opcode::SBFX;
+                    rob_slot.result.push(extract_bits(rs.source[0].get_constant(), lsb, width, signed, word_size));
                 }
-                Opcode::CMP => {
-                    let rn = rs.source[0].get_constant();
-                    let operand2 = rs.source[1].get_constant();
-                    let cprs_value = rs.source[2].get_constant();
-
-                    // Perform the comparison: rn - operand2
-                    let result = rn.wrapping_sub(operand2);
-
-                    // Update the CPSR flags based on the result
-                    let zero_flag = result == 0;
-                    let negative_flag = result < 0;
-                    let carry_flag = (rn as u64).wrapping_sub(operand2 as u64) > (rn as u64); // Checking for borrow
-                    let overflow_flag = ((rn ^ operand2) & (rn ^ result)) >> (std::mem::size_of::<i64>() * 8 - 1) != 0;
-
-                    let mut new_cprs_value = cprs_value;
-                    if zero_flag {
-                        new_cprs_value |= 1 << ZERO_FLAG_BIT_POSITION;
-                    } else {
-                        new_cprs_value &= !(1 << ZERO_FLAG_BIT_POSITION);
+                Opcode::MOVW => rob_slot.result.push(rs.source[0].get_constant()),
+                Opcode::MOVT => rob_slot.result.push(insert_bits(rs.source[0].get_constant(), rs.source[1].get_constant(), 16, 16, word_size)),
+                Opcode::MOVK => {
+                    let shift = rs.source[2].get_constant() as u32;
+                    rob_slot.result.push(insert_bits(rs.source[0].get_constant(), rs.source[1].get_constant(), shift, 16, word_size));
+                }
+                Opcode::BFI => {
+                    let lsb = rs.source[2].get_constant() as u32;
+                    let width = rs.source[3].get_constant() as u32;
+                    rob_slot.result.push(insert_bits(rs.source[0].get_constant(), rs.source[1].get_constant(), lsb, width, word_size));
+                }
+                Opcode::LDR => match instr.source[0] {
+                    // LDR r0, =label; the literal is loaded instead of a memory location.
+                    Operand::Immediate(literal) => rob_slot.result.push(wrap_word(literal, word_size)),
+                    _ => {
+                        let addr = access_addr(rs, 0);
+                        let value = if let Err(fault) = memory_subsystem.check_access(addr, AccessKind::Load) {
+                            // the fault is raised when the load retires
+                            rob_slot.fault = Some(fault);
+                            0
+                        } else {
+                            // store to load forwarding from the youngest older store to the same address.
+                            match memory_subsystem.sb.lookup(addr, rob_slot.sb_tail) {
+                                Some((seq, value)) => {
+                                    rob_slot.load_forwarded_from = Some(seq);
+                                    value
+                                }
+                                None => {
+                                    rob_slot.load_forwarded_from = None;
+                                    memory_subsystem.memory[addr as usize]
+                                }
+                            }
+                        };
+                        rob_slot.load_addr = Some(addr);
+                        rob_slot.transfer_cnt = 1;
+                        rob_slot.result.push(value);
                     }
-
-                    if negative_flag {
-                        new_cprs_value |= 1 << NEGATIVE_FLAG_BIT_POSITION;
-                    } else {
-                        new_cprs_value &= !(1 << NEGATIVE_FLAG_BIT_POSITION);
+                },
+                Opcode::STR => {
+                    let value = rs.source[0].get_constant();
+                    let addr = access_addr(rs, 1);
+                    if let Err(fault) = memory_subsystem.check_access(addr, AccessKind::Store) {
+                        // The store is completed so it doesn't block younger loads; but since it
+                        // faults, it never commits.
+                        rob_slot.fault = Some(fault);
                     }
-
-                    if carry_flag {
-                        new_cprs_value |= 1 << CARRY_FLAG_BIT_POSITION;
-                    } else {
-                        new_cprs_value &= !(1 << CARRY_FLAG_BIT_POSITION);
-                    }
-
-                    if overflow_flag {
-                        new_cprs_value |= 1 << OVERFLOW_FLAG_BIT_POSITION;
-                    } else {
-                        new_cprs_value &= !(1 << OVERFLOW_FLAG_BIT_POSITION);
+                    memory_subsystem.sb.store(rs.sb_pos, addr, value);
+                    rob_slot.store_addr = Some(addr);
+                    rob_slot.transfer_cnt = 1;
+                    resolved_stores.push((memory_subsystem.sb.seq(rs.sb_pos), addr));
+                }
+                // decoded into micro-ops; see microcode
+                Opcode::PUSH | Opcode::POP | Opcode::VST1 | Opcode::LDM(_) | Opcode::STM(_) => unreachable!("{} is executed as micro-ops", instr),
+                Opcode::VADD | Opcode::VSUB | Opcode::VMUL => {
+                    let lanes = rs.sink_cnt as usize;
+                    for k in 0..lanes {
+                        let result = vector_op(rs.opcode, rs.source[k].get_constant(), rs.source[lanes + k].get_constant());
+                        rob_slot.result.push(wrap_word(result, self.lane_width));
                     }
-
-                    // Update CPRS
-                    rob_slot.result.push(new_cprs_value as i64);
                 }
-                Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE => {
-                    let branch_target = rs.source[0].get_constant();
-                    let cpsr = rs.source[1].get_code_address();
-                    let pc = rs.source[2].get_constant();
-                    let pc_update = match rs.opcode {
-                        Opcode::BEQ => {
-                            if cpsr == 0 {
-                                branch_target
-                            } else {
-                                pc
-                            }
-                        }
-                        Opcode::BNE => {
-                            if cpsr != 0 {
-                                branch_target
-                            } else {
-                                pc
-                            }
-                        }
-                        Opcode::BLT => {
-                            if cpsr < 0 {
-                                branch_target
-                            } else {
-                                pc
-                            }
-                        }
-                        Opcode::BLE => {
-                            if cpsr <= 0 {
-                                branch_target
-                            } else {
-                                pc
+                Opcode::VLD1 => {
+                    // a single lane
+                    let addr = access_addr(rs, 0);
+                    let value = if let Err(fault) = memory_subsystem.check_access(addr, AccessKind::Load) {
+                        rob_slot.fault = Some(fault);
+                        0
+                    } else {
+                        match memory_subsystem.sb.lookup(addr, rob_slot.sb_tail) {
+                            Some((seq, value)) => {
+                                rob_slot.load_forwarded_from = Some(seq);
+                                value
                             }
-                        }
-                        Opcode::BGT => {
-                            if cpsr > 0 {
-                                branch_target
-                            } else {
-                                pc
+                            None => {
+                                rob_slot.load_forwarded_from = None;
+                                memory_subsystem.memory[addr as usize]
                             }
                         }
-                        Opcode::BGE => {
-                            if cpsr >= 0 {
-                                branch_target
-                            } else {
-                                pc
-                            }
-                        }
-                        _ => panic!("Unhandled opcode {:?}", rs.opcode),
                     };
-                    // Update pc
-                    rob_slot.result.push(pc_update as i64);
+                    rob_slot.load_addr = Some(addr);
+                    rob_slot.transfer_cnt = 1;
+                    rob_slot.result.push(wrap_word(value, self.lane_width));
+                }
+                Opcode::PRINTR => {
+                    let output = printr_output(instr.source[0].get_register(), rs.source[0].get_constant(), aliases);
+
+                    match self.side_effects {
+                        SideEffects::Execute => {
+                            self.streams.write_line(&output);
+                            self.output.push(output);
+                        }
+                        // An instruction that is flushed before it retires never writes its output.
+                        SideEffects::Retire => rob_slot.output = Some(output),
+                    }
+                }
+                Opcode::ADC | Opcode::SBC | Opcode::ADDS | Opcode::SUBS | Opcode::ADCS | Opcode::SBCS => {
+                    let cpsr = if overwrites_flags(rs.opcode) { 0 } else { rs.source[2].get_constant() };
+                    let (result, flags) = add_sub(rs.opcode, rs.source[0].get_constant(), rs.source[1].get_constant(), cpsr, word_size);
+                    rob_slot.result.push(result);
+                    if sets_flags(rs.opcode) {
+                        rob_slot.result.push(flags.apply_to(cpsr));
+                    }
+                }
+                Opcode::CMP | Opcode::CMN => {
+                    let (_, flags) = add_sub(rs.opcode, rs.source[0].get_constant(), rs.source[1].get_constant(), 0, word_size);
+                    rob_slot.result.push(flags.apply_to(0));
+                }
+                Opcode::TST => {
+                    let cpsr = rs.source[2].get_constant();
+                    let flags = test_flags(rs.source[0].get_constant(), rs.source[1].get_constant(), cpsr, word_size);
+                    rob_slot.result.push(flags.apply_to(cpsr));
+                }
+                Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+                Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => {
+                    let branch_target = rs.source[0].get_code_address();
+                    let flags = Flags::from_cpsr(rs.source[1].get_constant());
+                    let pc = rs.source[2].get_constant();
+
+                    // update the PC
+                    rob_slot.result.push(if condition_holds(rs.opcode, flags) { branch_target } else { pc });
                 }
                 Opcode::CBZ | Opcode::CBNZ => {
                     let reg_value = rs.source[0].get_constant();
@@ -239,6 +932,18 @@ impl Backend {
                     // update the PC
                     rob_slot.result.push(pc_update as i64);
                 }
+                Opcode::TBZ | Opcode::TBNZ => {
+                    let bit_set = (rs.source[0].get_constant() >> rs.source[1].get_constant()) & 1 == 1;
+                    let branch_target = rs.source[2].get_code_address();
+                    let pc = rs.source[3].get_constant();
+                    let taken = match instr.opcode {
+                        Opcode::TBZ => !bit_set,
+                        _ => bit_set,
+                    };
+
+                    // update the PC
+                    rob_slot.result.push(if taken { branch_target } else { pc });
+                }
                 Opcode::B => {
                     // update the PC
                     rob_slot.result.push(rs.source[0].get_code_address() as i64);
@@ -256,28 +961,155 @@ impl Backend {
                     // update the PC
                     rob_slot.result.push(target as i64);
                 }
+                Opcode::BLX => {
+                    let target = rs.source[0].get_constant();
+                    let pc = rs.source[1].get_constant();
+
+                    // update LR
+                    rob_slot.result.push(pc);
+                    // update the PC
+                    rob_slot.result.push(target);
+                }
                 Opcode::EXIT => {}
+                // the block is allocated when the MALLOC retires
+                Opcode::MALLOC => rob_slot.result.push(memory_subsystem.heap.next_addr(rs.source[0].get_constant())),
+                Opcode::FREE => {
+                    if let Err(fault) = memory_subsystem.heap.check_free(rs.source[0].get_constant()) {
+                        rob_slot.fault = Some(fault);
+                    }
+                }
+                Opcode::MRS => {
+                    let misses = |level| memory_subsystem.caches.as_ref().map_or(0, |caches| caches.misses(level));
+                    let value = match counter_name(rs.source[0].get_constant()) {
+                        Some("CYCLES") => perf_monitors.cycle_cnt,
+                        Some("INSTRET") => perf_monitors.retire_cnt,
+                        Some("L1_MISSES") => misses(0),
+                        Some("L2_MISSES") => misses(1),
+                        _ => unreachable!(),
+                    };
+                    rob_slot.result.push(value as WordType);
+                }
+                // the byte is consumed when the READC retires
+                Opcode::READC => rob_slot.result.push(self.streams.peek_byte().map_or(-1, |byte| byte as WordType)),
+                Opcode::FADD | Opcode::FSUB | Opcode::FMUL | Opcode::FDIV => {
+                    let single = is_single_register(instr.sink[0].get_register());
+                    let a = fp_value(rs.source[0].get_constant());
+                    let b = fp_value(rs.source[1].get_constant());
+                    let result = match rs.opcode {
+                        Opcode::FADD => a + b,
+                        Opcode::FSUB => a - b,
+                        Opcode::FMUL => a * b,
+                        _ => a / b,
+                    };
+                    rob_slot.result.push(fp_result(result, single));
+                }
+                Opcode::FMOV => {
+                    let single = is_single_register(instr.sink[0].get_register());
+                    let value = match instr.source[0] {
+                        // an immediate is an integer that is converted to floating point
+                        Operand::Immediate(value) => value as f64,
+                        _ => fp_value(rs.source[0].get_constant()),
+                    };
+                    rob_slot.result.push(fp_result(value, single));
+                }
+                Opcode::FCMP => {
+                    let a = fp_value(rs.source[0].get_constant());
+                    let b = fp_value(rs.source[1].get_constant());
+
+                    // The same NZCV encoding as the ARM VCMP: an unordered comparison (NaN) sets C and V.
+                    let flags = match a.partial_cmp(&b) {
+                        Some(Ordering::Equal) => (1 << ZERO_FLAG_BIT_POSITION) | (1 << CARRY_FLAG_BIT_POSITION),
+                        Some(Ordering::Less) => 1 << NEGATIVE_FLAG_BIT_POSITION,
+                        Some(Ordering::Greater) => 1 << CARRY_FLAG_BIT_POSITION,
+                        None => (1 << CARRY_FLAG_BIT_POSITION) | (1 << OVERFLOW_FLAG_BIT_POSITION),
+                    };
+                    rob_slot.result.push(flags);
+                }
+                Opcode::CPSIE => rob_slot.result.push(rs.source[0].get_constant() & !(1 << IRQ_MASK_BIT_POSITION)),
+                Opcode::CPSID => rob_slot.result.push(rs.source[0].get_constant() | (1 << IRQ_MASK_BIT_POSITION)),
+                // ERET is handled at retirement
+                Opcode::ERET => {}
+                Opcode::CUSTOM(custom) => {
+                    let handler = self.program_option.as_ref().unwrap().custom_instructions.get(custom);
+                    let sources: Vec<WordType> = rs.source.iter()
+                        .take(rs.source_cnt as usize)
+                        .map(|source| source.get_constant())
+                        .collect();
+                    let results = handler.execute(&sources);
+                    assert_eq!(results.len(), rs.sink_cnt as usize,
+                               "Custom instruction {} returned {} results, but has {} sinks",
+                               custom.mnemonic, results.len(), rs.sink_cnt);
+                    rob_slot.result.extend(results);
+                }
             }
 
-            let eu_index = eu.index;
-            self.eu_table.deallocate(eu_index);
+            // the PC register holds the code address; a result for it is a memory address (see cycle_issue)
+            for sink_index in 0..instr.sink_cnt as usize {
+                if matches!(instr.sink[sink_index], Operand::Register(PC)) {
+                    rob_slot.result[sink_index] = program.code_address(rob_slot.result[sink_index]) as WordType;
+                }
+            }
 
             for sink_index in 0..rs.sink_cnt {
                 let sink = rs.sink[sink_index as usize];
                 match sink {
                     Operand::Register(phys_reg) => {
-                        let phys_reg_entry = self.phys_reg_file.get_mut(phys_reg);
-                        phys_reg_entry.has_value = true;
                         let result = rob_slot.result[sink_index as usize];
-                        phys_reg_entry.value = result;
-                        self.cdb_broadcast_buffer.push(CDBBroadcast { phys_reg, value: result });
+                        if self.bypass.forwards(eu_type(instr.opcode)) {
+                            let phys_reg_entry = self.phys_reg_file.get_mut(phys_reg);
+                            phys_reg_entry.has_value = true;
+                            phys_reg_entry.value = result;
+                            self.cdb_broadcast_buffer.push(CDBBroadcast { phys_reg, value: result, forwarded: true });
+                        } else {
+                            self.writeback_buffer.push(CDBBroadcast { phys_reg, value: result, forwarded: false });
+                        }
                     }
                     Operand::Memory(addr) => {
                         let result = rob_slot.result[sink_index as usize];
                         // a store to memory
                         memory_subsystem.sb.store(rs.sb_pos, addr, result);
                     }
-                    Operand::Immediate(_) | Operand::Code(_) | Operand::Unused => panic!("Illegal sink {:?}", sink),
+                    Operand::Immediate(_) | Operand::Code(_) | Operand::RegisterList(_) | Operand::Unused => panic!("Illegal sink {:?}", sink),
+                }
+            }
+
+            let mut plugins = self.plugins.borrow_mut();
+            if !plugins.is_empty() {
+                let event = InstrEvent { instr: arch_instr, seq: rob_slot.seq, pc: rob_slot.pc, cycle: perf_monitors.cycle_cnt, results: &rob_slot.result };
+                for plugin in plugins.iter_mut() {
+                    plugin.on_execute(&event);
+                }
+
+                // a faulting access never happens
+                if rob_slot.fault.is_none() {
+                    let mut accesses = Vec::new();
+                    // a micro-op accesses a single word
+                    if let Some(addr) = rob_slot.load_addr {
+                        accesses.push((AccessKind::Load, addr, rob_slot.result[0]));
+                    }
+                    if let Some(addr) = rob_slot.store_addr {
+                        // a store micro-op is an STR, which stores its first source
+                        accesses.push((AccessKind::Store, addr, rs.source[0].get_constant()));
+                    }
+
+                    for (kind, addr, value) in accesses {
+                        let access = MemoryAccess { instr: arch_instr, pc: rob_slot.pc, kind, addr, value, cycle: perf_monitors.cycle_cnt };
+                        for plugin in plugins.iter_mut() {
+                            plugin.on_memory_access(&access);
+                        }
+                    }
+                }
+            }
+
+            // The branch is resolved: after a BTB hit with the wrong target, the younger instructions are on the
+            // wrong path.
+            if let Some(predicted_pc) = rob_slot.predicted_pc {
+                let target = (0..instr.sink_cnt as usize)
+                    .find(|sink_index| matches!(instr.sink[*sink_index], Operand::Register(PC)))
+                    .map(|sink_index| rob_slot.result[sink_index] as usize);
+                if let Some(target) = target.filter(|target| *target != predicted_pc) {
+                    rob_slot.redirect_cycle = Some(perf_monitors.cycle_cnt);
+                    mispredicted.push((rob_slot.seq, rob_index, target));
                 }
             }
 
@@ -287,10 +1119,38 @@ impl Backend {
             rob_slot.state = ROBSlotState::EXECUTED;
             perf_monitors.execute_cnt += 1;
         }
+
+        drop(memory_subsystem);
+        drop(perf_monitors);
+
+        // With conservative disambiguation a load never executes before an older store with an
+        // unknown address, so there can't be any conflicts.
+        if self.memory_disambiguation == MemoryDisambiguation::Speculative {
+            for (store_seq, addr) in resolved_stores {
+                self.detect_load_conflicts(store_seq, addr);
+            }
+        }
+
+        // the oldest misprediction squashes the younger ones
+        if let Some((_, rob_slot_index, target)) = mispredicted.into_iter().min_by_key(|(seq, _, _)| *seq) {
+            self.flush_wrong_path(rob_slot_index, target);
+        }
+    }
+
+    // Writes the results that weren't forwarded in the previous cycle to the register file; from now
+    // on the dependent instructions can read them.
+    fn write_back(&mut self) {
+        for req in self.writeback_buffer.drain(..) {
+            let phys_reg_entry = self.phys_reg_file.get_mut(req.phys_reg);
+            phys_reg_entry.has_value = true;
+            phys_reg_entry.value = req.value;
+            self.cdb_broadcast_buffer.push(req);
+        }
     }
 
     fn cdb_broadcast(&mut self) {
         let rs_table_capacity = self.rs_table.capacity;
+        let mut perf_monitors = self.perf_counters.borrow_mut();
 
         for req in &mut *self.cdb_broadcast_buffer {
             // Iterate over all RS and replace every matching physical register, by the value
@@ -313,12 +1173,19 @@ impl Backend {
                         if *phys_reg == req.phys_reg {
                             rs.source[source_index] = Operand::Immediate(req.value);
                             rs.source_ready_cnt += 1;
+                            if req.forwarded {
+                                perf_monitors.forwarded_operand_cnt += 1;
+                            }
                         }
                     }
                 }
 
                 if rs.source_cnt == rs.source_ready_cnt {
-                    rob_slot.state = ROBSlotState::DISPATCHED;
+                    if req.forwarded {
+                        perf_monitors.forwarding_saved_cycle_cnt += 1;
+                    }
+                    rob_slot.state = ROBSlotState::READY;
+                    rob_slot.dispatch_stall = Some(StallCause::DispatchWidth);
                     self.rs_table.enqueue_ready(rob_slot.rs_index);
                 }
             }
@@ -327,42 +1194,228 @@ impl Backend {
         self.cdb_broadcast_buffer.clear();
     }
 
-    fn cycle_retire(&mut self) {
+    // Retires instructions. Returns the cause, the code address of the causing instruction and the
+    // target to fetch from when the pipeline needs to be flushed; e.g. when a load needs to be replayed.
+    fn cycle_retire(&mut self) -> Option<(FlushCause, usize, usize)> {
         let mut arch_reg_file = self.arch_reg_file.borrow_mut();
         let mut perf_monitors = self.perf_counters.borrow_mut();
         let mut frontend_control = self.frontend_control.borrow_mut();
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        let mut interrupt_controller = self.interrupt_controller.borrow_mut();
+        let mut btb = self.btb.borrow_mut();
+        let mut ras = self.ras.borrow_mut();
+        let program = self.program_option.as_ref().unwrap();
+        let aliases = &program.register_aliases;
+
+        let retire_n_wide = if self.single_retire { 1 } else { self.retire_n_wide };
+        for _ in 0..retire_n_wide {
+            if let Some(handler) = program.irq_handler {
+                let cpsr = arch_reg_file.get_value(CPSR);
+                if interrupt_controller.pending && cpsr & (1 << IRQ_MASK_BIT_POSITION) == 0 {
+                    if let Some(pc) = self.rob.head_pc() {
+                        // The interrupt is taken before the oldest instruction retires; that
+                        // instruction and all younger ones are flushed and fetched again on return.
+                        interrupt_controller.enter(pc, cpsr);
+                        if let Some(oracle) = &mut self.oracle {
+                            oracle.sync(pc, &arch_reg_file, &memory_subsystem);
+                        }
+                        arch_reg_file.set_value(CPSR, cpsr | (1 << IRQ_MASK_BIT_POSITION));
+                        if let Some(oracle) = &mut self.oracle {
+                            oracle.take_registers(handler, &arch_reg_file);
+                        }
+                        perf_monitors.interrupt_cnt += 1;
+                        if let Some(journal) = &mut self.journal {
+                            journal.clear();
+                        }
+                        return Some((FlushCause::Interrupt, pc, handler));
+                    }
+                }
+            }
 
-        for _ in 0..self.retire_n_wide {
-            if !self.rob.head_has_executed() {
+            // the micro-ops of an instruction retire together
+            let uop_cnt = match self.rob.head() {
+                Some(rob_slot) => program.uops(rob_slot.instr_index.unwrap()).len(),
+                None => break,
+            };
+            if !self.rob.head_instr_has_executed(uop_cnt) {
                 break;
             }
 
-            let rob_slot_index = self.rob.next_executed();
-            let mut rob_slot = self.rob.get_mut(rob_slot_index);
+            if let Some(rob_slot) = self.rob.head() {
+                if self.breakpoints.contains(&rob_slot.pc) && self.resumed_seq != Some(rob_slot.seq) {
+                    // the architectural state is the state just before the instruction
+                    self.breakpoint_hit = Some((rob_slot.pc, rob_slot.seq));
+                    break;
+                }
+            }
+
+            if self.sb_drain_policy == SBDrainPolicy::Retire {
+                let mem_stores = self.rob.head()
+                    .and_then(|rob_slot| rob_slot.instr_index)
+                    .map_or(0, |index| program.instr(index).mem_stores);
+                if mem_stores > 0 && !memory_subsystem.sb.can_drain(mem_stores) {
+                    // the drain bandwidth of the cycle is used up
+                    perf_monitors.sb_drain_stall_cnt += 1;
+                    break;
+                }
+            }
+
+            let uop_slots: Vec<u16> = (0..uop_cnt).map(|_| self.rob.next_executed()).collect();
+            let instr_index = self.rob.get(uop_slots[0]).instr_index.unwrap();
+            let retiring = RetiringInstr::new(&self.rob, &uop_slots, program.uops(instr_index));
+            let rob_slot = self.rob.get_mut(uop_slots[0]);
 
-            let rc = <Option<Rc<Instr>> as Clone>::clone(&rob_slot.instr).unwrap();
-            let instr = Rc::clone(&rc);
+            let instr = program.instr(instr_index);
+
+            if retiring.replay {
+                // The load has read a stale value. The load and all younger instructions are
+                // flushed and the load is fetched again.
+                perf_monitors.load_replay_cnt += 1;
+                return Some((FlushCause::Replay, rob_slot.pc, rob_slot.pc));
+            }
+
+            // A multi-word access is checked as a whole. A heap block can be freed between the execution
+            // and the retirement of an access.
+            let access_addr = retiring.load_addr.or(retiring.store_addr);
+            let fault = access_addr.and_then(|addr| memory_subsystem.check_alignment(addr, retiring.transfer_cnt).err())
+                .or_else(|| retiring.fault.clone())
+                .or_else(|| access_addr.and_then(|addr| memory_subsystem.check_heap_access(addr, retiring.transfer_cnt).err()));
+            if let Some(fault) = fault {
+                print!("Fault: {} [{}]", fault, instr.with_aliases(aliases));
+                match instr.loc {
+                    Some(loc) => println!(" at {}", loc),
+                    None => println!(),
+                }
+                self.exit = true;
+                return None;
+            }
+
+            if instr.opcode == Opcode::ERET && !interrupt_controller.active {
+                println!("Fault: ERET outside of an interrupt handler [{}]", instr.with_aliases(aliases));
+                self.exit = true;
+                return None;
+            }
+
+            if let Some(oracle) = &mut self.oracle {
+                oracle.sync(rob_slot.pc, &arch_reg_file, &memory_subsystem);
+            }
+
+            // the watchpoints on a location the instruction writes, with the values before it retires
+            let watched_before: Vec<(usize, Vec<WordType>)> = if self.watchpoints.is_empty() {
+                Vec::new()
+            } else {
+                let written = written_locations(instr, retiring.store_addr, retiring.transfer_cnt);
+                self.watchpoints.iter().enumerate()
+                    .filter(|(_, watchpoint)| watchpoint.is_written(&written))
+                    .map(|(index, watchpoint)| (index, watchpoint.values(&arch_reg_file, &memory_subsystem)))
+                    .collect()
+            };
+
+            // the values of the registers and memory words the instruction writes, before it retires
+            let logged_before: Option<Vec<(LogTarget, WordType)>> = self.execution_log.then(|| {
+                log_targets(instr, retiring.store_addr, retiring.transfer_cnt).into_iter()
+                    .map(|target| (target, logged_value(target, &arch_reg_file, &memory_subsystem)))
+                    .collect()
+            });
+
+            if let Some(journal) = &mut self.journal {
+                if matches!(instr.opcode, Opcode::MALLOC | Opcode::FREE | Opcode::READC | Opcode::ERET) {
+                    // the heap, the input and the interrupt state can't be undone
+                    journal.clear();
+                } else {
+                    // the PC is left out; stepping back redirects the frontend
+                    let before = written_locations(instr, retiring.store_addr, retiring.transfer_cnt).into_iter()
+                        .filter(|location| *location != WatchLocation::Register(PC))
+                        .map(|location| (location, location.value(&arch_reg_file, &memory_subsystem)))
+                        .collect();
+                    journal.record(JournalEntry { pc: rob_slot.pc, before });
+                }
+            }
 
             if instr.opcode == Opcode::EXIT {
                 self.exit = true;
+                // like returning from main, the exit code is passed in r0
+                self.exit_code = Some(arch_reg_file.get_value(0));
             }
 
-            if self.trace.retire {
-                println!("Retiring {}", instr);
+            // The instruction is the oldest, so the architectural registers contain its sources. The
+            // heap is changed before the sinks are written since the MALLOC size can be in its sink.
+            match instr.opcode {
+                Opcode::MALLOC => {
+                    let size = match instr.source[0] {
+                        Operand::Register(arch_reg) => arch_reg_file.get_value(arch_reg),
+                        operand => operand.get_constant(),
+                    };
+                    memory_subsystem.malloc(size, instr.loc);
+                }
+                Opcode::FREE => memory_subsystem.free(arch_reg_file.get_value(instr.source[0].get_register()), instr.loc),
+                Opcode::READC => self.streams.consume_byte(),
+                _ => {}
             }
 
+            for _ in 0..instr.mem_stores {
+                memory_subsystem.sb.commit();
+            }
+            if instr.mem_stores > 0 && self.sb_drain_policy == SBDrainPolicy::Retire {
+                memory_subsystem.write_committed_stores();
+            }
+
+            // the code address of the next instruction on the correct path
+            let next_pc = (0..instr.sink_cnt as usize)
+                .find(|sink_index| matches!(instr.sink[*sink_index], Operand::Register(PC)))
+                .map_or(rob_slot.pc + 1, |sink_index| retiring.result[sink_index] as usize);
+            let mut mispredicted = None;
             if instr.is_control {
-                frontend_control.halted = false;
+                match rob_slot.predicted_pc {
+                    Some(predicted_pc) => {
+                        // the frontend already continued fetching from the predicted target
+                        if predicted_pc != next_pc {
+                            if rob_slot.ras_predicted {
+                                perf_monitors.ras_mispredict_cnt += 1;
+                            } else {
+                                perf_monitors.btb_mispredict_cnt += 1;
+                            }
+                            perf_monitors.redirect_cnt += 1;
+                            let redirect_cycle = retiring.redirect_cycle.unwrap_or(perf_monitors.cycle_cnt);
+                            perf_monitors.redirect_cycle_cnt += redirect_cycle - rob_slot.fetch_cycle;
+                            mispredicted = Some(next_pc);
+                        }
+                    }
+                    None => {
+                        frontend_control.halted = false;
+                        perf_monitors.redirect_cnt += 1;
+                        perf_monitors.redirect_cycle_cnt += perf_monitors.cycle_cnt - rob_slot.fetch_cycle;
+                    }
+                }
+                btb.update(rob_slot.pc, next_pc);
+                if instr.is_call() {
+                    ras.retire_call(rob_slot.pc + 1);
+                } else if instr.is_return() {
+                    ras.retire_return();
+                }
+
+                // the implicit EXIT is ARM code
+                if program.code.get(next_pc).is_some_and(|target| target.thumb) != instr.thumb {
+                    perf_monitors.mode_switch_cnt += 1;
+                }
             }
 
             perf_monitors.retire_cnt += 1;
 
+            if let Some(output) = rob_slot.output.take() {
+                self.streams.write_line(&output);
+                self.output.push(output);
+            }
+
             for sink_index in 0..instr.sink_cnt as usize {
                 let sink = instr.sink[sink_index];
                 if let Operand::Register(arch_reg) = sink {
+                    // after a BTB hit, the PC register (the fetch pointer) is owned by the frontend
+                    let write_arch_reg = !(arch_reg == PC && rob_slot.predicted_pc.is_some());
+
                     let rat_entry = self.rat.get_mut(arch_reg);
                     let rat_phys_reg = rat_entry.phys_reg;
-                    let rs_phys_reg = rob_slot.sink[sink_index].get_register();
+                    let rs_phys_reg = retiring.sink[sink_index].get_register();
 
                     // only when the physical register on the rat is the same as te physical register used for that
                     // instruction, the rat entry should be invalidated
@@ -372,43 +1425,338 @@ impl Backend {
 
                     self.phys_reg_file.get_mut(rs_phys_reg).has_value = false;
                     self.phys_reg_file.deallocate(rs_phys_reg);
-                    arch_reg_file.set_value(arch_reg, rob_slot.result[sink_index]);
+                    if arch_reg == CPSR && !matches!(instr.opcode, Opcode::CPSIE | Opcode::CPSID) {
+                        // a flag setter only writes the flags; the interrupt mask of its result can be stale since
+                        // e.g. a CMP doesn't read the CPSR
+                        let cpsr = arch_reg_file.get_value(CPSR);
+                        arch_reg_file.set_value(CPSR, Flags::from_cpsr(retiring.result[sink_index]).apply_to(cpsr));
+                    } else if write_arch_reg {
+                        arch_reg_file.set_value(arch_reg, retiring.result[sink_index]);
+                    }
+                }
+            }
+
+            if let Some(logged_before) = logged_before {
+                let values: Vec<(LogTarget, WordType, WordType)> = logged_before.into_iter()
+                    .map(|(target, before)| (target, before, logged_value(target, &arch_reg_file, &memory_subsystem)))
+                    .collect();
+                println!("{}", log_line(instr, rob_slot.pc, perf_monitors.cycle_cnt, &values, program));
+            }
+
+            if let Some(oracle) = &mut self.oracle {
+                if let Err(msg) = oracle.on_retire(instr, rob_slot.pc, next_pc, &arch_reg_file, &memory_subsystem) {
+                    print!("Fault: {} [{}]", msg, instr.with_aliases(aliases));
+                    match instr.loc {
+                        Some(loc) => println!(" at {}", loc),
+                        None => println!(),
+                    }
+                    self.exit = true;
+                    return None;
+                }
+            }
+
+            if let Some(stack_guard) = &mut self.stack_guard {
+                if let Err(msg) = stack_guard.on_retire(instr, retiring.load_addr, retiring.store_addr, retiring.transfer_cnt, &arch_reg_file) {
+                    print!("Fault: {} [{}]", msg, instr.with_aliases(aliases));
+                    match instr.loc {
+                        Some(loc) => println!(" at {}", loc),
+                        None => println!(),
+                    }
+                    self.exit = true;
+                    return None;
+                }
+            }
+
+            if let Some(checker) = &mut self.calling_convention_checker {
+                for violation in checker.on_retire(instr, next_pc, &arch_reg_file, program) {
+                    println!("{}", violation);
+                }
+            }
+
+            if let Some(stack_usage) = &mut self.stack_usage {
+                stack_usage.on_retire(instr, next_pc, &arch_reg_file, program);
+            }
+
+            if let Some(deadline_monitor) = &mut self.deadline_monitor {
+                for miss in deadline_monitor.on_retire(instr, rob_slot.pc, perf_monitors.cycle_cnt) {
+                    println!("{}", miss);
+                }
+            }
+
+            if let Some(ilp_study) = &mut self.ilp_study {
+                ilp_study.on_retire(instr, retiring.load_addr, retiring.store_addr, retiring.transfer_cnt);
+            }
+
+            if let Some(wcet) = &mut self.wcet {
+                wcet.on_retire(instr, next_pc, program, perf_monitors.cycle_cnt);
+            }
+
+            if let Some(loop_profiler) = &mut self.loop_profiler {
+                loop_profiler.on_retire(rob_slot.pc, perf_monitors.cycle_cnt);
+            }
+
+            if let Some(call_graph) = &mut self.call_graph {
+                call_graph.on_retire(instr, rob_slot.pc, next_pc, perf_monitors.cycle_cnt);
+            }
+
+            if let Some(sampling) = &mut self.sampling {
+                sampling.on_retire(instr, rob_slot.pc, next_pc, perf_monitors.cycle_cnt);
+            }
+
+            memory_subsystem.heap.on_retire(instr, rob_slot.pc, next_pc);
+
+            let mut watchpoint_triggered = false;
+            for (index, before) in watched_before {
+                if let Some(hit) = self.watchpoints[index].check(&before, &arch_reg_file, &memory_subsystem, rob_slot.pc, perf_monitors.cycle_cnt) {
+                    println!("{}", hit);
+                    self.watchpoint_hit.get_or_insert(hit);
+                    watchpoint_triggered = true;
+                }
+            }
+
+            if instr.opcode == Opcode::ERET {
+                let (pc, spsr) = interrupt_controller.exit();
+                arch_reg_file.set_value(CPSR, spsr);
+                if let Some(oracle) = &mut self.oracle {
+                    oracle.take_registers(pc, &arch_reg_file);
                 }
+                return Some((FlushCause::InterruptReturn, rob_slot.pc, pc));
+            }
+
+            let event = InstrEvent { instr, seq: rob_slot.seq, pc: rob_slot.pc, cycle: perf_monitors.cycle_cnt, results: &retiring.result };
+            for plugin in self.plugins.borrow_mut().iter_mut() {
+                plugin.on_retire(&event);
+            }
+
+            if self.trace.retire {
+                if let Some((cause, cycles)) = dominant_cause(&retiring.stalls) {
+                    let total: u64 = retiring.stalls.values().sum();
+                    println!("  waited {} cycles; mostly {} ({} cycles)", total, cause.describe(program), cycles);
+                }
+            }
+
+            let event = RetireEvent { instr, pc: rob_slot.pc, arch_reg_file: &arch_reg_file };
+            for assertion in &self.assertions {
+                if let Err(msg) = assertion.check(&event) {
+                    print!("Fault: Assertion '{}' violated: {} [{}]", assertion.name(), msg, instr.with_aliases(aliases));
+                    match instr.loc {
+                        Some(loc) => println!(" at {}", loc),
+                        None => println!(),
+                    }
+                    self.exit = true;
+                    return None;
+                }
+            }
+
+            if let Some(target) = mispredicted {
+                let event = MispredictEvent { instr, pc: rob_slot.pc, predicted_pc: rob_slot.predicted_pc.unwrap(), target, cycle: perf_monitors.cycle_cnt };
+                for plugin in self.plugins.borrow_mut().iter_mut() {
+                    plugin.on_mispredict(&event);
+                }
+
+                // The younger instructions were flushed when the instruction executed; unless it has no PC
+                // sink (e.g. an EXIT), then they are flushed now.
+                if retiring.redirect_cycle.is_none() {
+                    return Some((FlushCause::Mispredict, rob_slot.pc, target));
+                }
+            }
+
+            // so the host sees the state right after the instruction (see CPU::run_for)
+            if watchpoint_triggered {
+                break;
             }
         }
+
+        None
     }
 
-    fn cycle_dispatch(&mut self) {
-        let mut perf_monitors = self.perf_counters.borrow_mut();
+    // Orders the ready instructions in the reservation stations according to the selection policy;
+    // they are dispatched in this order.
+    fn select_ready(&mut self) {
+        if self.rs_selection == RSSelectionPolicy::ReadyOrder {
+            return;
+        }
 
-        for _ in 0..self.dispatch_n_wide {
-            if !self.rs_table.has_ready() || !self.eu_table.has_free() {
-                break;
+        let mut ready = self.rs_table.ready();
+        if ready.len() < 2 {
+            return;
+        }
+
+        match self.rs_selection {
+            RSSelectionPolicy::ReadyOrder => {}
+            RSSelectionPolicy::OldestFirst => {
+                ready.sort_by_key(|rs_index| self.rob.get(self.rs_table.get(*rs_index).rob_slot_index).seq);
             }
+            RSSelectionPolicy::Random => {
+                // Fisher-Yates shuffle
+                let random = self.rs_random.as_mut().unwrap();
+                for k in (1..ready.len()).rev() {
+                    ready.swap(k, random.below(k as u64 + 1) as usize);
+                }
+            }
+        }
+        self.rs_table.reorder_ready(ready);
+    }
 
+    fn cycle_dispatch(&mut self) {
+        self.select_ready();
+
+        let mut perf_monitors = self.perf_counters.borrow_mut();
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        let program = Rc::clone(self.program_option.as_ref().unwrap());
+
+        // the ready instructions that can't be dispatched this cycle
+        let mut deferred = Vec::new();
+        let mut dispatched = 0;
+        while dispatched < self.dispatch_n_wide && self.rs_table.has_ready() {
             let rs_index = self.rs_table.deque_ready();
             let rs = self.rs_table.get_mut(rs_index);
 
             let rob_slot_index = rs.rob_slot_index;
 
+            let is_oldest = self.rob.head().is_some_and(|head| head.index == rob_slot_index);
+            let is_oldest_undispatched = self.rob.oldest_undispatched() == Some(rob_slot_index);
+            // the barriers that are older than the instruction and that haven't executed yet
+            let older_barrier = self.rob.in_flight().into_iter()
+                .take_while(|index| *index != rob_slot_index)
+                .filter(|index| self.rob.get(*index).state != ROBSlotState::EXECUTED)
+                .filter_map(|index| self.rob.get(index).instr_index.map(|instr_index| program.instr(instr_index).opcode))
+                .filter(|opcode| matches!(opcode, Opcode::DMB | Opcode::DSB))
+                .max_by_key(|opcode| *opcode == Opcode::DSB);
             let rob_slot = self.rob.get_mut(rob_slot_index);
-            rob_slot.state = ROBSlotState::DISPATCHED;
 
-            let eu_index = self.eu_table.allocate();
+            let instr = &program.uops(rob_slot.instr_index.unwrap())[rob_slot.uop as usize];
 
-            let mut eu = self.eu_table.get_mut(eu_index);
+            if self.in_order && !is_oldest_undispatched {
+                rob_slot.dispatch_stall = Some(StallCause::ProgramOrder);
+                deferred.push(rs_index);
+                continue;
+            }
 
-            let rc = <Option<Rc<Instr>> as Clone>::clone(&rob_slot.instr).unwrap();
-            let instr = Rc::clone(&rc);
+            // The in-order pipeline has a single execute stage; a multi-cycle instruction holds it. A memory
+            // stage that is still busy (e.g. a load that misses the cache) stalls the execute stage as well.
+            let cycle = perf_monitors.cycle_cnt;
+            if self.in_order && (cycle < self.ex_free_cycle || cycle + 1 < self.mem_free_cycle) {
+                rob_slot.dispatch_stall = Some(StallCause::PipelineStage);
+                deferred.push(rs_index);
+                continue;
+            }
 
-            eu.rs_index = rs_index;
-            eu.cycles_remaining = instr.cycles;
+            if matches!(instr.opcode, Opcode::MALLOC | Opcode::FREE) && !is_oldest {
+                // The heap is changed when the instruction retires; so it may only execute when the
+                // older MALLOCs and FREEs have retired and it isn't on a wrong path.
+                rob_slot.dispatch_stall = Some(StallCause::Serialization);
+                deferred.push(rs_index);
+                continue;
+            }
+
+            if instr.opcode == Opcode::MRS && !is_oldest {
+                // The counters are read once all older instructions have retired; so the count
+                // includes them and none of the younger ones.
+                rob_slot.dispatch_stall = Some(StallCause::Serialization);
+                deferred.push(rs_index);
+                continue;
+            }
+
+            if instr.opcode == Opcode::READC && !is_oldest {
+                // The input is read in program order and not on a wrong path.
+                rob_slot.dispatch_stall = Some(StallCause::Serialization);
+                deferred.push(rs_index);
+                continue;
+            }
 
-            if self.trace.dispatch {
-                println!("Dispatched [{}]", instr);
+            if matches!(instr.opcode, Opcode::DMB | Opcode::DSB) {
+                if !is_oldest {
+                    rob_slot.dispatch_stall = Some(StallCause::Serialization);
+                    deferred.push(rs_index);
+                    continue;
+                }
+                if memory_subsystem.sb.has_committed() {
+                    // the barrier completes once the older stores are visible in memory
+                    rob_slot.dispatch_stall = Some(StallCause::StoreBufferDrain);
+                    deferred.push(rs_index);
+                    continue;
+                }
+            }
+
+            // A DMB orders the younger loads and stores after the older ones; a DSB holds back all
+            // younger instructions.
+            let held_back = match older_barrier {
+                Some(Opcode::DSB) => true,
+                Some(_) => instr.is_load() || instr.mem_stores > 0,
+                None => false,
+            };
+            if held_back {
+                rob_slot.dispatch_stall = Some(StallCause::Barrier);
+                deferred.push(rs_index);
+                continue;
+            }
+
+            if self.memory_disambiguation == MemoryDisambiguation::Conservative
+                && instr.is_load()
+                && memory_subsystem.sb.has_unresolved_before(rob_slot.sb_tail) {
+                // the load needs to wait till the addresses of all older stores are known.
+                rob_slot.dispatch_stall = Some(StallCause::StoreAddress);
+                deferred.push(rs_index);
+                continue;
+            }
+
+            let eu_type = eu_type(instr.opcode);
+            if !self.eu_table.has_free(eu_type) {
+                // structural hazard; all execution units of the required type are busy.
+                perf_monitors.structural_stall_cnt[eu_type as usize] += 1;
+                rob_slot.dispatch_stall = Some(StallCause::ExecutionUnit(eu_type));
+                deferred.push(rs_index);
+                continue;
+            }
+
+            rob_slot.state = ROBSlotState::DISPATCHED;
+            rob_slot.dispatch_stall = None;
+
+            let jitter = self.eu_jitter.as_mut().map_or(0, |jitter| jitter.delay());
+            // the operands are ready, so the address of a load is known
+            let cache_latency = if instr.is_load() {
+                memory_subsystem.load_latency(rob_slot.pc, access_addr(rs, 0), 1, perf_monitors.cycle_cnt)
+            } else {
+                0
+            };
+            // The first micro-op of a multi-word access accesses its lowest address; the penalty of the
+            // whole access is added to it.
+            let alignment_penalty = if rob_slot.uop == 0 && (instr.is_load() || instr.mem_stores > 0) {
+                let addr = if instr.is_load() { access_addr(rs, 0) } else { access_addr(rs, 1) };
+                memory_subsystem.alignment_penalty(addr, transfer_cnt(program.instr(rob_slot.instr_index.unwrap())))
+            } else {
+                0
+            };
+            if alignment_penalty > 0 {
+                perf_monitors.unaligned_access_cnt += 1;
+            }
+            let mut latency = instr.cycles.saturating_add(jitter).saturating_add(cache_latency).saturating_add(alignment_penalty);
+            if self.in_order {
+                // the execute stage is occupied for the latency of the instruction; a load or store then
+                // spends a cycle (plus the cache latency) in the memory stage, so an instruction that uses a
+                // loaded value waits a cycle longer than one that uses the result of an ALU instruction
+                let ex_latency = instr.cycles.saturating_add(jitter).saturating_add(alignment_penalty);
+                self.ex_free_cycle = cycle + ex_latency as u64;
+                if instr.is_load() || instr.mem_stores > 0 {
+                    latency = latency.saturating_add(1);
+                    self.mem_free_cycle = cycle + latency as u64;
+                }
+            }
+            self.eu_table.allocate(eu_type, rs_index, latency);
+            perf_monitors.eu_dispatch_cnt[eu_type as usize] += 1;
+
+            let event = InstrEvent { instr: program.instr(rob_slot.instr_index.unwrap()), seq: rob_slot.seq, pc: rob_slot.pc, cycle: perf_monitors.cycle_cnt, results: &[] };
+            for plugin in self.plugins.borrow_mut().iter_mut() {
+                plugin.on_dispatch(&event);
             }
 
             perf_monitors.dispatch_cnt += 1;
+            dispatched += 1;
+        }
+
+        for rs_index in deferred {
+            self.rs_table.enqueue_ready(rs_index);
         }
     }
 
@@ -418,28 +1766,65 @@ impl Backend {
         let mut instr_queue = self.instr_queue.borrow_mut();
         let arch_reg_file = self.arch_reg_file.borrow();
         let mut memory_subsystem = self.memory_subsystem.borrow_mut();
-
-        // try to put as many instructions into the rob
-        for _ in 0..self.issue_n_wide {
-            if instr_queue.is_empty() || !self.rob.has_space() {
+        let program = Rc::clone(self.program_option.as_ref().unwrap());
+
+        // try to put as many instructions into the rob; every micro-op gets its own slot and counts
+        // against the issue width.
+        let mut issued_uops = 0;
+        while issued_uops < self.issue_n_wide as usize && !instr_queue.is_empty() {
+            let fetched = instr_queue.peek();
+            let instr = program.instr(fetched.index);
+            let uop_cnt = program.uops(fetched.index).len();
+            let pc = fetched.pc;
+
+            // The micro-ops of an instruction enter the rob together. An instruction with more
+            // micro-ops than the issue width is issued in a cycle of its own.
+            if !self.rob.has_space_for(uop_cnt) || (issued_uops > 0 && issued_uops + uop_cnt > self.issue_n_wide as usize) {
                 break;
             }
 
-            let instr = instr_queue.peek();
+            // the decode stage of the in-order pipeline holds a single instruction till it is dispatched
+            if self.in_order && self.rob.oldest_undispatched().is_some() {
+                break;
+            }
 
             instr_queue.dequeue();
 
+            if let Some(flush_cycle) = self.mispredict_flush_cycle.take() {
+                perf_monitors.mispredict_refill_cnt += 1;
+                perf_monitors.mispredict_refill_cycle_cnt += perf_monitors.cycle_cnt - flush_cycle;
+            }
 
-            let rob_slot_index = self.rob.allocate();
-            let rob_slot = self.rob.get_mut(rob_slot_index);
-
-            if self.trace.issue {
-                println!("issue: Issued [{}]", instr);
+            let event = InstrEvent { instr, seq: fetched.seq, pc, cycle: perf_monitors.cycle_cnt, results: &[] };
+            for plugin in self.plugins.borrow_mut().iter_mut() {
+                plugin.on_issue(&event);
             }
 
-            rob_slot.state = ROBSlotState::ISSUED;
-            rob_slot.instr = Some(instr);
+            for uop in 0..uop_cnt {
+                let rob_slot_index = self.rob.allocate();
+                let rob_slot = self.rob.get_mut(rob_slot_index);
+
+                rob_slot.state = ROBSlotState::ISSUED;
+                rob_slot.instr_index = Some(fetched.index);
+                rob_slot.uop = uop as u8;
+                rob_slot.pc = pc;
+                rob_slot.predicted_pc = fetched.predicted_pc;
+                rob_slot.ras_predicted = fetched.ras_predicted;
+                rob_slot.redirect_cycle = None;
+                rob_slot.fetch_cycle = fetched.fetch_cycle;
+                rob_slot.seq = fetched.seq;
+                rob_slot.load_addr = None;
+                rob_slot.load_forwarded_from = None;
+                rob_slot.store_addr = None;
+                rob_slot.transfer_cnt = 0;
+                rob_slot.replay = false;
+                rob_slot.fault = None;
+                rob_slot.output = None;
+                rob_slot.dispatch_stall = None;
+                rob_slot.stalls.clear();
+            }
 
+            issued_uops += uop_cnt;
             perf_monitors.issue_cnt += 1;
         }
 
@@ -449,23 +1834,23 @@ impl Backend {
                 break;
             }
 
-            let rob_slot_index = self.rob.next_issued();
-            let mut rob_slot = self.rob.get_mut(rob_slot_index);
+            let rob_slot_index = self.rob.peek_issued();
+            let rob_slot = self.rob.get(rob_slot_index);
 
-            let rc = <Option<Rc<Instr>> as Clone>::clone(&rob_slot.instr).unwrap();
-            let instr = Rc::clone(&rc);
+            let instr = &program.uops(rob_slot.instr_index.unwrap())[rob_slot.uop as usize];
 
-            if instr.mem_stores > 0 && !memory_subsystem.sb.has_space() {
-                // we can't allocate a slot in the store buffer, we are done
+            if instr.mem_stores > 0 && !memory_subsystem.sb.has_space_for(instr.mem_stores as u16) {
+                // we can't allocate the slots in the store buffer, we are done; the instruction stays
+                // waiting for a reservation station.
+                perf_monitors.sb_full_stall_cnt += 1;
                 break;
             }
+            self.rob.next_issued();
+            let rob_slot = self.rob.get_mut(rob_slot_index);
 
             let rs_index = self.rs_table.allocate();
             let mut rs = self.rs_table.get_mut(rs_index);
 
-            if self.trace.issue {
-                println!("issue: Issued found RS [{}]", instr);
-            }
             rob_slot.state = ROBSlotState::ISSUED;
             rob_slot.result.clear();
             rob_slot.rs_index = rs_index;
@@ -475,6 +1860,24 @@ impl Backend {
             rs.opcode = instr.opcode;
             rs.state = RSState::BUSY;
 
+            // Stores allocate a slot in the sb at this point in program order, so every store
+            // before the sb tail is older than this instruction.
+            rob_slot.sb_tail = memory_subsystem.sb.tail();
+            rob_slot.epoch = self.epoch;
+            let instr_index = rob_slot.instr_index.unwrap();
+            if program.instr(instr_index).is_control && rob_slot.uop as usize + 1 == program.uops(instr_index).len() {
+                // the instructions after it depend on the prediction of the frontend
+                self.epoch += 1;
+            }
+            if instr.mem_stores > 0 {
+                // since the instructions are issued in program order, a slot is allocated in the
+                // sb in program order. And since sb will commit to the coherent cache
+                // (in this case directly to memory), the stores will become visible
+                // in program order.
+                // a store micro-op stores a single word.
+                rs.sb_pos = memory_subsystem.sb.allocate(rob_slot.epoch);
+            }
+
             rs.source_cnt = instr.source_cnt;
             rs.source_ready_cnt = 0;
 
@@ -482,6 +1885,12 @@ impl Backend {
                 let instr_source = &instr.source[source_index];
                 let rs_source = &rs.source[source_index];
                 match instr_source {
+                    Operand::Register(PC) => {
+                        // The PC reads as the memory address of the next instruction. It isn't read from the
+                        // PC register, since that is the fetch pointer of the frontend.
+                        rs.source[source_index] = Operand::Immediate(program.memory_address(rob_slot.pc + 1));
+                        rs.source_ready_cnt += 1;
+                    }
                     Operand::Register(arch_reg) => {
                         let rat_entry = self.rat.get(*arch_reg);
                         if rat_entry.valid {
@@ -501,11 +1910,16 @@ impl Backend {
                             rs.source_ready_cnt += 1;
                         }
                     }
-                    Operand::Memory(_) | Operand::Immediate(_) | Operand::Code(_) => {
+                    // a branch target or an ADR of a label is executed as its memory address
+                    Operand::Code(code_address) => {
+                        rs.source[source_index] = Operand::Code(program.memory_address(*code_address as usize));
+                        rs.source_ready_cnt += 1;
+                    }
+                    Operand::Memory(_) | Operand::Immediate(_) => {
                         rs.source[source_index] = *instr_source;
                         rs.source_ready_cnt += 1;
                     }
-                    Operand::Unused =>
+                    Operand::RegisterList(_) | Operand::Unused =>
                         panic!("Illegal source {:?}", rs_source)
                 }
             }
@@ -516,6 +1930,7 @@ impl Backend {
                 match instr_sink {
                     Operand::Register(arch_reg) => {
                         let phys_reg = self.phys_reg_file.allocate();
+                        perf_monitors.rename_cnt += 1;
                         // update the RAT entry to point to the newest phys_reg
                         let rat_entry = self.rat.get_mut(arch_reg);
                         rat_entry.phys_reg = phys_reg;
@@ -526,13 +1941,8 @@ impl Backend {
                     }
                     Operand::Memory(_) => {
                         rs.sink[sink_index] = instr_sink;
-                        // since the instructions are issued in program order, a slot is allocated in the
-                        // sb in program order. And since sb will commit to the coherent cache
-                        // (in this case directly to memory), the stores will become visible
-                        // in program order.
-                        rs.sb_pos = memory_subsystem.sb.allocate();
                     }
-                    Operand::Unused | Operand::Immediate(_) | Operand::Code(_) => {
+                    Operand::Unused | Operand::Immediate(_) | Operand::Code(_) | Operand::RegisterList(_) => {
                         panic!("Illegal sink {:?}", instr_sink)
                     }
                 }
@@ -540,9 +1950,51 @@ impl Backend {
             rob_slot.sink = rs.sink;
 
             if rs.source_ready_cnt == rs.source_cnt {
-                rob_slot.state = ROBSlotState::DISPATCHED;
+                rob_slot.state = ROBSlotState::READY;
+                rob_slot.dispatch_stall = Some(StallCause::DispatchWidth);
                 self.rs_table.enqueue_ready(rs_index);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// The address of the word accessed by a load or store micro-op: the address register at the source index
+// plus the immediate offset that follows it, if any.
+fn access_addr(rs: &RS, source_index: usize) -> WordType {
+    let offset = if rs.source_cnt as usize > source_index + 1 { rs.source[source_index + 1].get_constant() } else { 0 };
+    rs.source[source_index].get_constant() + offset
+}
+
+// The current value of a register or memory word of the execution log.
+// The registers and memory words the instruction writes.
+fn written_locations(instr: &Instr, store_addr: Option<WordType>, transfer_cnt: u8) -> Vec<WatchLocation> {
+    let mut written: Vec<WatchLocation> = instr.sink.iter().take(instr.sink_cnt as usize)
+        .filter_map(|sink| match sink {
+            Operand::Register(reg) => Some(WatchLocation::Register(*reg)),
+            _ => None,
+        })
+        .collect();
+    if let Some(addr) = store_addr {
+        written.extend((0..transfer_cnt as WordType).map(|k| WatchLocation::Memory(addr + k)));
+    }
+    written
+}
+
+fn logged_value(target: LogTarget, arch_reg_file: &ArgRegFile, memory_subsystem: &MemorySubsystem) -> WordType {
+    match target {
+        LogTarget::Register(reg) => arch_reg_file.get_value(reg),
+        LogTarget::Memory(addr) => memory_subsystem.architectural_word(addr),
+    }
+}
+
+// The line PRINTR writes for the value of the register.
+pub(crate) fn printr_output(arch_reg: RegisterType, value: WordType, aliases: &HashMap<RegisterType, String>) -> String {
+    let register = AliasedOperand { operand: Operand::Register(arch_reg), aliases: Some(aliases) };
+    if is_fp_register(arch_reg) {
+        format!("PRINTR {}={}", register, fp_value(value))
+    } else if arch_reg == CPSR || arch_reg == FPSCR {
+        format!("PRINTR {}={}", register, Flags::from_cpsr(value))
+    } else {
+        format!("PRINTR {}={}", register, value)
+    }
+}
@@ -1,50 +1,119 @@
+use std::collections::VecDeque;
+
+use crate::cpu::EUPoolConfig;
+use crate::instructions::instructions::{EU_TYPES, EUType};
+
 /// A single execution unit.
+///
+/// A pipelined EU accepts a new instruction every cycle, even when older instructions
+/// are still in flight. A non pipelined EU accepts a new instruction only when it is idle.
 pub struct EU {
     pub index: u8,
-    pub rs_index: u16,
-    pub cycles_remaining: u8,
+    pub eu_type: EUType,
+    pub pipelined: bool,
+    // the instructions in flight as (rs_index, cycles_remaining)
+    pub in_flight: VecDeque<(u16, u8)>,
+    // if an instruction was accepted in the current cycle
+    pub accepted: bool,
+}
+
+impl EU {
+    fn is_free(&self) -> bool {
+        !self.accepted && (self.pipelined || self.in_flight.is_empty())
+    }
 }
 
 /// The table containing all execution units of a CPU core.
 pub(crate) struct EUTable {
-    pub capacity: u8,
-    free_stack: Vec<u8>,
     array: Vec<EU>,
 }
 
 impl EUTable {
-    pub(crate) fn new(capacity: u8) -> EUTable {
-        let mut free_stack = Vec::with_capacity(capacity as usize);
-        let mut array = Vec::with_capacity(capacity as usize);
-        for i in 0..capacity {
-            array.push(EU { index: i, cycles_remaining: 0, rs_index: 0 });
-            free_stack.push(i);
+    pub(crate) fn new(config: &EUPoolConfig) -> EUTable {
+        let mut array = Vec::new();
+        for eu_type in EU_TYPES {
+            let eu_config = config.get(eu_type);
+            for _ in 0..eu_config.count {
+                array.push(EU {
+                    index: array.len() as u8,
+                    eu_type,
+                    pipelined: eu_config.pipelined,
+                    in_flight: VecDeque::new(),
+                    accepted: false,
+                });
+            }
         }
 
-        EUTable {
-            capacity,
-            array,
-            free_stack,
-        }
+        EUTable { array }
     }
 
-    pub(crate) fn has_free(&self) -> bool {
-        return !self.free_stack.is_empty();
+    pub(crate) fn units(&self) -> &[EU] {
+        &self.array
     }
 
-    pub(crate) fn get_mut(&mut self, eu_index: u8) -> &mut EU {
-        return self.array.get_mut(eu_index as usize).unwrap();
+    pub(crate) fn has_free(&self, eu_type: EUType) -> bool {
+        self.array.iter().any(|eu| eu.eu_type == eu_type && eu.is_free())
     }
 
-    pub(crate) fn allocate(&mut self) -> u8 {
-        if let Some(last_element) = self.free_stack.pop() {
-            return last_element;
+    /// Starts the execution of the instruction in the given reservation station on a free EU
+    /// of the given type.
+    pub(crate) fn allocate(&mut self, eu_type: EUType, rs_index: u16, cycles: u8) -> u8 {
+        if let Some(eu) = self.array.iter_mut().find(|eu| eu.eu_type == eu_type && eu.is_free()) {
+            eu.accepted = true;
+            eu.in_flight.push_back((rs_index, cycles));
+            eu.index
         } else {
-            panic!("No free PhysReg")
+            panic!("No free EU of type {}", eu_type)
+        }
+    }
+
+    /// Advances all execution units by a single cycle and returns the reservation stations
+    /// of the instructions that are in their last cycle.
+    pub(crate) fn cycle(&mut self) -> Vec<u16> {
+        let mut completed = Vec::new();
+        for eu in &mut self.array {
+            eu.accepted = false;
+            eu.in_flight.retain_mut(|(rs_index, cycles_remaining)| {
+                *cycles_remaining -= 1;
+                if *cycles_remaining > 0 {
+                    return true;
+                }
+                completed.push(*rs_index);
+                false
+            });
         }
+        completed
     }
 
-    pub(crate) fn deallocate(&mut self, eu_index: u8) {
-        self.free_stack.push(eu_index);
+    /// The number of cycles till the first of the instructions in flight is in its last cycle; None
+    /// when all execution units are idle.
+    pub(crate) fn cycles_till_completion(&self) -> Option<u8> {
+        self.array.iter()
+            .flat_map(|eu| eu.in_flight.iter().map(|(_, cycles_remaining)| *cycles_remaining))
+            .min()
+    }
+
+    /// Advances all execution units by the given number of cycles in which no instruction completes.
+    pub(crate) fn skip(&mut self, cycles: u8) {
+        for eu in &mut self.array {
+            eu.accepted = false;
+            for (_, cycles_remaining) in &mut eu.in_flight {
+                *cycles_remaining -= cycles;
+            }
+        }
+    }
+
+    /// Stops the execution of the instructions in the given reservation stations; they were squashed.
+    pub(crate) fn squash(&mut self, squashed: &[u16]) {
+        for eu in &mut self.array {
+            eu.in_flight.retain(|(rs_index, _)| !squashed.contains(rs_index));
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        for eu in &mut self.array {
+            eu.in_flight.clear();
+            eu.accepted = false;
+        }
     }
 }
@@ -52,6 +52,18 @@ impl PhysRegFile {
 
         self.free_stack.push(reg);
     }
+
+    // Frees all physical registers. Retired values live in the architectural registers, so only
+    // in flight instructions own a physical register.
+    pub(crate) fn flush(&mut self) {
+        self.free_stack.clear();
+        for i in 0..self.count {
+            let entry = &mut self.entries[i as usize];
+            entry.has_value = false;
+            entry.value = 0;
+            self.free_stack.push(self.count - 1 - i);
+        }
+    }
 }
 
 
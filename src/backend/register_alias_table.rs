@@ -26,4 +26,11 @@ impl RAT {
     pub fn get_mut(&mut self, arch_reg: RegisterType) -> &mut RATEntry {
         return self.table.get_mut(arch_reg as usize).unwrap();
     }
+
+    // Invalidates all entries; the values will be read from the architectural registers again.
+    pub fn flush(&mut self) {
+        for entry in &mut self.table {
+            entry.valid = false;
+        }
+    }
 }
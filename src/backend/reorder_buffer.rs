@@ -1,24 +1,65 @@
-use std::rc::Rc;
+use std::collections::HashMap;
 
-use crate::instructions::instructions::{Instr, MAX_SINK_COUNT, Operand, WordType};
+use crate::analysis::stall_attribution::StallCause;
+use crate::instructions::instructions::{InstrIndex, Operand, WordType};
+use crate::instructions::microcode::MAX_UOP_SINK_COUNT;
 
 #[derive(Clone, Copy, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ROBSlotState {
     UNUSED,
     ISSUED,
+    // the operands are ready; waiting in the reservation station to be dispatched
+    READY,
     DISPATCHED,
     EXECUTED,
 }
 
 pub struct ROBSlot {
-    pub instr: Option<Rc<Instr>>,
+    // the index of the instruction in the code of the program
+    pub instr_index: Option<InstrIndex>,
+    // the index of the micro-op within its instruction; every micro-op has its own slot
+    pub uop: u8,
     pub state: ROBSlotState,
     pub index: u16,
     //todo: not used
     pub rb_slot_index: Option<u16>,
     pub result: Vec<WordType>,
     pub rs_index: u16,
-    pub sink: [Operand; MAX_SINK_COUNT as usize],
+    pub sink: [Operand; MAX_UOP_SINK_COUNT as usize],
+    // the code address of the instruction
+    pub pc: usize,
+    // the code address the frontend continued fetching from after this control instruction (BTB hit)
+    pub predicted_pc: Option<usize>,
+    // the predicted_pc came from the return address stack
+    pub ras_predicted: bool,
+    // the cycle the frontend was redirected because the instruction executed with another target than predicted
+    pub redirect_cycle: Option<u64>,
+    pub fetch_cycle: u64,
+    // the fetch sequence number of the instruction
+    pub seq: u64,
+    // the tail of the store buffer when the instruction was issued; stores before it are older.
+    pub sb_tail: u64,
+    // the speculation epoch of the instruction: the number of control instructions issued before it.
+    pub epoch: u64,
+    // the address accessed by a load once it has executed.
+    pub load_addr: Option<WordType>,
+    // the sequence number of the store the load got its value from (None means memory).
+    pub load_forwarded_from: Option<u64>,
+    // the address written by a store once it has executed.
+    pub store_addr: Option<WordType>,
+    // the number of consecutive words read or written from load_addr/store_addr.
+    pub transfer_cnt: u8,
+    // set when an older store to the same address resolved after the load executed.
+    pub replay: bool,
+    // a fault detected during execution; raised when the instruction retires.
+    pub fault: Option<String>,
+    // the output of a side effecting instruction that is written when the instruction retires.
+    pub output: Option<String>,
+    // why a ready instruction wasn't dispatched yet; None once dispatched.
+    pub dispatch_stall: Option<StallCause>,
+    // the cycles the instruction waited per cause; only tracked for the stall attribution and the retire trace.
+    pub stalls: HashMap<StallCause, u64>,
 }
 
 pub(crate) struct ROB {
@@ -36,12 +77,30 @@ impl ROB {
         for k in 0..capacity {
             slots.push(ROBSlot {
                 index: k,
-                instr: None,
+                instr_index: None,
+                uop: 0,
                 state: ROBSlotState::UNUSED,
                 rb_slot_index: None,
-                result: Vec::with_capacity(MAX_SINK_COUNT as usize),
+                result: Vec::with_capacity(MAX_UOP_SINK_COUNT as usize),
                 rs_index: 0,
-                sink: [Operand::Unused, Operand::Unused],
+                sink: [Operand::Unused; MAX_UOP_SINK_COUNT as usize],
+                pc: 0,
+                predicted_pc: None,
+                ras_predicted: false,
+                redirect_cycle: None,
+                fetch_cycle: 0,
+                seq: 0,
+                sb_tail: 0,
+                epoch: 0,
+                load_addr: None,
+                load_forwarded_from: None,
+                store_addr: None,
+                transfer_cnt: 0,
+                replay: false,
+                fault: None,
+                output: None,
+                dispatch_stall: None,
+                stalls: HashMap::new(),
             });
         }
 
@@ -54,12 +113,16 @@ impl ROB {
         }
     }
 
+    pub(crate) fn get(&self, slot_index: u16) -> &ROBSlot {
+        &self.slots[slot_index as usize]
+    }
+
     pub(crate) fn get_mut(&mut self, slot_index: u16) -> &mut ROBSlot {
         &mut self.slots[slot_index as usize]
     }
 
     pub fn allocate(&mut self) -> u16 {
-        assert!(self.has_space(), "ROB: Can't allocate if no space.");
+        assert!(self.has_space_for(1), "ROB: Can't allocate if no space.");
 
         let index = (self.tail % self.capacity as u64) as u16;
         self.tail += 1;
@@ -71,6 +134,12 @@ impl ROB {
         return self.tail > self.issued;
     }
 
+    // The slot that next_issued returns next.
+    pub fn peek_issued(&self) -> u16 {
+        assert!(self.has_issued(), "ROB: can't peek issued since there are none");
+        (self.issued % self.capacity as u64) as u16
+    }
+
     pub fn next_issued(&mut self) -> u16 {
         assert!(self.has_issued(), "ROB: can't issue next since there are none");
         let index = (self.issued % self.capacity as u64) as u16;
@@ -90,6 +159,13 @@ impl ROB {
         return rob_slot.state == ROBSlotState::EXECUTED;
     }
 
+    // If the oldest instruction has executed all its micro-ops; an instruction retires as a whole.
+    pub fn head_instr_has_executed(&self, uop_cnt: usize) -> bool {
+        self.tail - self.head >= uop_cnt as u64
+            && (self.head..self.head + uop_cnt as u64)
+            .all(|k| self.slots[(k % self.capacity as u64) as usize].state == ROBSlotState::EXECUTED)
+    }
+
     pub fn next_executed(&mut self) -> u16 {
         assert!(self.head_has_executed(), "ROB: can't next_retire because there are no slots retired");
 
@@ -98,11 +174,87 @@ impl ROB {
         return index;
     }
 
+    // The pc of the oldest instruction that hasn't retired yet.
+    pub fn head_pc(&self) -> Option<usize> {
+        if self.tail == self.head {
+            return None;
+        }
+
+        let index = (self.head % self.capacity as u64) as usize;
+        Some(self.slots[index].pc)
+    }
+
     pub fn size(&self) -> u16 {
         return (self.tail - self.head) as u16;
     }
 
-    pub fn has_space(&self) -> bool {
-        return self.capacity > self.size();
+    pub fn capacity(&self) -> u16 {
+        self.capacity
+    }
+
+    pub fn has_space_for(&self, cnt: usize) -> bool {
+        self.capacity as usize >= self.size() as usize + cnt
+    }
+
+    // The oldest instruction that hasn't retired yet.
+    pub fn head(&self) -> Option<&ROBSlot> {
+        if self.tail == self.head {
+            return None;
+        }
+
+        let index = (self.head % self.capacity as u64) as usize;
+        Some(&self.slots[index])
+    }
+
+    // If the instruction in the slot is still waiting for a reservation station.
+    pub fn is_waiting_for_rs(&self, slot_index: u16) -> bool {
+        (self.issued..self.tail).any(|k| (k % self.capacity as u64) as u16 == slot_index)
+    }
+
+    // The indices of the slots that have been allocated but not yet retired; oldest first.
+    pub fn in_flight(&self) -> Vec<u16> {
+        (self.head..self.tail).map(|k| (k % self.capacity as u64) as u16).collect()
+    }
+
+    // The oldest instruction that hasn't been dispatched yet; waiting for its operands or ready.
+    pub fn oldest_undispatched(&self) -> Option<u16> {
+        self.in_flight().into_iter()
+            .find(|index| matches!(self.slots[*index as usize].state, ROBSlotState::ISSUED | ROBSlotState::READY))
+    }
+
+    // The slots of the instructions that were fetched after the instruction with the given sequence number;
+    // oldest first.
+    pub fn younger_than(&self, seq: u64) -> Vec<u16> {
+        self.in_flight().into_iter()
+            .filter(|index| self.slots[*index as usize].seq > seq)
+            .collect()
+    }
+
+    // Removes the instructions that were fetched after the instruction with the given sequence number; the
+    // older instructions stay.
+    pub fn squash_younger(&mut self, seq: u64) {
+        while self.tail > self.head {
+            let rob_slot = &mut self.slots[((self.tail - 1) % self.capacity as u64) as usize];
+            if rob_slot.seq <= seq {
+                break;
+            }
+            rob_slot.instr_index = None;
+            rob_slot.state = ROBSlotState::UNUSED;
+            rob_slot.result.clear();
+            self.tail -= 1;
+        }
+        self.issued = self.issued.min(self.tail);
+    }
+
+    // Removes all instructions that have not been retired.
+    pub fn flush(&mut self) {
+        for index in self.in_flight() {
+            let rob_slot = &mut self.slots[index as usize];
+            rob_slot.instr_index = None;
+            rob_slot.state = ROBSlotState::UNUSED;
+            rob_slot.result.clear();
+        }
+        self.tail = self.head;
+        self.issued = self.head;
     }
 }
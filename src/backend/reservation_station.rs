@@ -1,7 +1,8 @@
 use std::fmt;
 use std::fmt::Display;
 
-use crate::instructions::instructions::{MAX_SINK_COUNT, MAX_SOURCE_COUNT, mnemonic, Opcode, Operand};
+use crate::instructions::instructions::{mnemonic, Opcode, Operand};
+use crate::instructions::microcode::{MAX_UOP_SINK_COUNT, MAX_UOP_SOURCE_COUNT};
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum RSState {
@@ -15,10 +16,10 @@ pub struct RS {
     pub(crate) opcode: Opcode,
     pub(crate) state: RSState,
     pub(crate) source_cnt: u8,
-    pub(crate) source: [Operand; MAX_SOURCE_COUNT as usize],
+    pub(crate) source: [Operand; MAX_UOP_SOURCE_COUNT as usize],
     pub(crate) source_ready_cnt: u8,
     pub(crate) sink_cnt: u8,
-    pub(crate) sink: [Operand; MAX_SINK_COUNT as usize],
+    pub(crate) sink: [Operand; MAX_UOP_SINK_COUNT as usize],
 }
 
 impl RS {
@@ -27,10 +28,10 @@ impl RS {
             opcode: Opcode::NOP,
             state: RSState::FREE,
             source_cnt: 0,
-            source: [Operand::Unused, Operand::Unused, Operand::Unused],
+            source: [Operand::Unused; MAX_UOP_SOURCE_COUNT as usize],
             source_ready_cnt: 0,
             sink_cnt: 0,
-            sink: [Operand::Unused, Operand::Unused],
+            sink: [Operand::Unused; MAX_UOP_SINK_COUNT as usize],
             sb_pos: 0,
             rob_slot_index: 0,
         }
@@ -86,6 +87,10 @@ impl RSTable {
         }
     }
 
+    pub(crate) fn get(&self, rs_index: u16) -> &RS {
+        &self.array[rs_index as usize]
+    }
+
     pub(crate) fn get_mut(&mut self, rs_index: u16) -> &mut RS {
         return &mut self.array[rs_index as usize];
     }
@@ -110,6 +115,27 @@ impl RSTable {
         return rs_ready_index;
     }
 
+    // the ready reservation stations in the order they are dequeued
+    pub(crate) fn ready(&self) -> Vec<u16> {
+        (self.ready_queue_head..self.ready_queue_tail)
+            .map(|k| self.ready_queue[(k % self.capacity as u64) as usize])
+            .collect()
+    }
+
+    // Replaces the ready queue by the same reservation stations in a different order.
+    pub(crate) fn reorder_ready(&mut self, ready: Vec<u16>) {
+        assert_eq!(ready.len() as u64, self.ready_queue_tail - self.ready_queue_head, "RSTable: reordering changed the number of ready items");
+        self.ready_queue_head = self.ready_queue_tail;
+        for rs_index in ready {
+            self.enqueue_ready(rs_index);
+        }
+    }
+
+    // the number of reservation stations in use
+    pub(crate) fn used(&self) -> u16 {
+        self.capacity - self.free_stack.len() as u16
+    }
+
     pub(crate) fn has_free(&self) -> bool {
         return !self.free_stack.is_empty();
     }
@@ -125,5 +151,29 @@ impl RSTable {
     pub(crate) fn deallocate(&mut self, rs_index: u16) {
         self.free_stack.push(rs_index);
     }
+
+    // Frees the reservation stations of squashed instructions; they are taken out of the ready queue as well.
+    pub(crate) fn squash(&mut self, squashed: &[u16]) {
+        let ready = self.ready().into_iter()
+            .filter(|rs_index| !squashed.contains(rs_index))
+            .collect::<Vec<_>>();
+        self.ready_queue_head = self.ready_queue_tail;
+        for rs_index in ready {
+            self.enqueue_ready(rs_index);
+        }
+        for rs_index in squashed {
+            self.array[*rs_index as usize].state = RSState::FREE;
+            self.deallocate(*rs_index);
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        self.free_stack.clear();
+        for i in 0..self.capacity {
+            self.array[i as usize].state = RSState::FREE;
+            self.free_stack.push(i);
+        }
+        self.ready_queue_head = self.ready_queue_tail;
+    }
 }
 
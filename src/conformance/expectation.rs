@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::abi::abi::Abi;
+use crate::cpu::FPSCR;
+use crate::instructions::instructions::{ExpectTarget, get_register, Program, RegisterType, WordType};
+use crate::snapshot::snapshot::Snapshot;
+
+// the extension of the sidecar file next to a test; 'loop.asm' is checked against 'loop.expect.yaml'.
+const SIDECAR_EXTENSION: &str = "expect.yaml";
+
+/// The expected outcome of a conformance test. Everything is optional; only what is listed is checked.
+///
+/// ```yaml
+/// registers:
+///   r0: 55
+///   sp: 1024
+/// memory:
+///   result: 55
+///   array+2: 7
+/// flags: N z C v
+/// output:
+///   - PRINTR r0=55
+/// cycles:
+///   min: 10
+///   max: 200
+/// exit_code: 0
+/// config:
+///   pipeline_mode: in_order
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expectation {
+    // register name -> value
+    pub registers: BTreeMap<String, WordType>,
+    // variable name, optionally with an offset like 'array+2' -> value
+    pub memory: BTreeMap<String, WordType>,
+    // the condition flags of the CPSR like 'N z C v'; uppercase is set, lowercase is clear
+    pub flags: Option<String>,
+    // the complete output of the program, line by line
+    pub output: Option<Vec<String>>,
+    pub cycles: Option<CycleRange>,
+    // the value of r0 when the program exited
+    pub exit_code: Option<WordType>,
+    // the settings of the config the test runs on, changed like with CPUConfig::set; e.g. 'pipeline_mode'
+    pub config: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// An inclusive range for the number of cycles the program may take; the timing depends on the config.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CycleRange {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl Expectation {
+    pub fn load(path: &Path) -> Result<Expectation, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let expectation = serde_yaml::from_reader(file)?;
+        Ok(expectation)
+    }
+
+    /// The path of the sidecar file with the expectation of the given test.
+    pub fn sidecar_path(test_path: &Path) -> PathBuf {
+        test_path.with_extension(SIDECAR_EXTENSION)
+    }
+
+    /// Checks the outcome of a run and returns every violated expectation. An error means the
+    /// expectation itself is invalid, e.g. it refers to a variable the program doesn't have or to a
+    /// register the register file of the ABI doesn't have.
+    pub fn check(&self, abi: &Abi, snapshot: &Snapshot, output: &[String], cycles: u64, exit_code: Option<WordType>) -> Result<Vec<String>, String> {
+        let mut failures = Vec::new();
+
+        for (name, expected) in &self.registers {
+            let reg = parse_register(name, abi)
+                .ok_or_else(|| format!("Unknown register '{}'", name))?;
+            let actual = snapshot.registers[reg as usize];
+            if actual != *expected {
+                failures.push(format!("register {}: expected {}, actual {}", name, expected, actual));
+            }
+        }
+
+        for (location, expected) in &self.memory {
+            let addr = resolve_location(location, snapshot)?;
+            match snapshot.memory.get(addr as usize) {
+                Some(actual) if actual == expected => {}
+                Some(actual) => failures.push(format!("memory {} (address {}): expected {}, actual {}", location, addr, expected, actual)),
+                None => return Err(format!("Memory location '{}' is outside of memory", location)),
+            }
+        }
+
+        if let Some(expected_flags) = &self.flags {
+            let actual = snapshot.flags().to_string();
+            if expected_flags.split_whitespace().collect::<Vec<_>>() != actual.split_whitespace().collect::<Vec<_>>() {
+                failures.push(format!("flags: expected {}, actual {}", expected_flags, actual));
+            }
+        }
+
+        if let Some(expected_output) = &self.output {
+            if expected_output.as_slice() != output {
+                failures.push(format!("output: expected {:?}, actual {:?}", expected_output, output));
+            }
+        }
+
+        if let Some(cycle_range) = &self.cycles {
+            let too_few = cycle_range.min.is_some_and(|min| cycles < min);
+            let too_many = cycle_range.max.is_some_and(|max| cycles > max);
+            if too_few || too_many {
+                let bound = |bound: Option<u64>| bound.map_or(String::from("-"), |b| b.to_string());
+                failures.push(format!("cycles: expected [{}, {}], actual {}", bound(cycle_range.min), bound(cycle_range.max), cycles));
+            }
+        }
+
+        if let Some(expected_exit_code) = self.exit_code {
+            match exit_code {
+                Some(actual) if actual == expected_exit_code => {}
+                Some(actual) => failures.push(format!("exit code: expected {}, actual {}", expected_exit_code, actual)),
+                None => failures.push(format!("exit code: expected {}, but the program didn't exit", expected_exit_code)),
+            }
+        }
+
+        Ok(failures)
+    }
+}
+
+/// The outcome of a single .expect directive of a program.
+pub struct InlineOutcome {
+    // the line of the directive
+    pub line: usize,
+    // the directive, e.g. '.expect r0, 42'
+    pub directive: String,
+    // why the expectation doesn't hold; None when it holds
+    pub failure: Option<String>,
+}
+
+impl InlineOutcome {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Checks the .expect directives of the program against the state at the end of the program. They are
+/// only checked after an EXIT; when the program ended otherwise, e.g. with a fault, they all fail.
+pub fn check_inline(program: &Program, snapshot: &Snapshot, exited: bool) -> Vec<InlineOutcome> {
+    program.expectations.iter()
+        .map(|expectation| {
+            let actual = match expectation.target {
+                ExpectTarget::Register(reg) => snapshot.registers[reg as usize],
+                ExpectTarget::Memory(_, addr) => snapshot.memory[addr as usize],
+            };
+            let failure = if !exited {
+                Some(String::from("the program didn't exit"))
+            } else if actual != expectation.value {
+                Some(format!("expected {}, actual {}", expectation.value, actual))
+            } else {
+                None
+            };
+            InlineOutcome { line: expectation.loc.line, directive: expectation.to_string(), failure }
+        })
+        .collect()
+}
+
+pub(crate) fn parse_register(name: &str, abi: &Abi) -> Option<RegisterType> {
+    match name.to_lowercase().as_str() {
+        "cpsr" => Some(abi.cpsr),
+        "fpscr" => Some(FPSCR),
+        _ => {
+            let re = Regex::new(r"^(?i)(r\d+|sp|lr|pc|fp)$").unwrap();
+            if re.is_match(name) {
+                get_register(name, abi)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// Resolves 'variable' or 'variable+offset' to a memory address.
+fn resolve_location(location: &str, snapshot: &Snapshot) -> Result<u64, String> {
+    let (name, offset) = match location.split_once('+') {
+        Some((name, offset)) => {
+            let offset: u64 = offset.trim().parse()
+                .map_err(|_| format!("Invalid offset in memory location '{}'", location))?;
+            (name.trim(), offset)
+        }
+        None => (location.trim(), 0),
+    };
+
+    match snapshot.variables.get(name) {
+        Some(base) => Ok(base + offset),
+        None => Err(format!("Unknown variable '{}'", name)),
+    }
+}
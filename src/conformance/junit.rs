@@ -0,0 +1,59 @@
+use std::fmt::Write;
+
+use crate::conformance::runner::{Outcome, SuiteResult};
+
+/// Renders the results as JUnit XML; every config is a test suite and every test file a test case.
+pub fn to_junit_xml(suites: &[SuiteResult]) -> String {
+    let tests: usize = suites.iter().map(|suite| suite.results.len()).sum();
+    let failures: usize = suites.iter().map(|suite| suite.failure_cnt()).sum();
+    let errors: usize = suites.iter().map(|suite| suite.error_cnt()).sum();
+    let skipped: usize = suites.iter().map(|suite| suite.skipped_cnt()).sum();
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(xml, r#"<testsuites tests="{}" failures="{}" errors="{}" skipped="{}">"#,
+             tests, failures, errors, skipped).unwrap();
+
+    for suite in suites {
+        writeln!(xml, r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+                 escape(&suite.config_name), suite.results.len(), suite.failure_cnt(), suite.error_cnt(),
+                 suite.skipped_cnt(), suite.duration().as_secs_f64()).unwrap();
+
+        for result in &suite.results {
+            write!(xml, r#"    <testcase classname="{}" name="{}" time="{:.3}""#,
+                   escape(&suite.config_name), escape(&result.name), result.duration.as_secs_f64()).unwrap();
+            match &result.outcome {
+                Outcome::Passed => writeln!(xml, "/>").unwrap(),
+                Outcome::Failed(failures) => {
+                    writeln!(xml, ">").unwrap();
+                    writeln!(xml, r#"      <failure message="{} expectation(s) violated">{}</failure>"#,
+                             failures.len(), escape(&failures.join("\n"))).unwrap();
+                    writeln!(xml, "    </testcase>").unwrap();
+                }
+                Outcome::Error(error) => {
+                    writeln!(xml, ">").unwrap();
+                    writeln!(xml, r#"      <error message="{}"/>"#, escape(error)).unwrap();
+                    writeln!(xml, "    </testcase>").unwrap();
+                }
+                Outcome::Skipped => {
+                    writeln!(xml, ">").unwrap();
+                    writeln!(xml, r#"      <skipped message="no expectation file"/>"#).unwrap();
+                    writeln!(xml, "    </testcase>").unwrap();
+                }
+            }
+        }
+
+        writeln!(xml, "  </testsuite>").unwrap();
+    }
+
+    writeln!(xml, "</testsuites>").unwrap();
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
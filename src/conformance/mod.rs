@@ -0,0 +1,3 @@
+pub mod expectation;
+pub mod runner;
+pub mod junit;
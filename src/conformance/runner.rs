@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::conformance::expectation::{check_inline, Expectation, InlineOutcome};
+use crate::cpu::{CPU, CPUConfig};
+use crate::loader::loader::load;
+
+/// The outcome of a single test on a single config.
+pub enum Outcome {
+    Passed,
+    // the violated expectations
+    Failed(Vec<String>),
+    // the test couldn't be run, e.g. it doesn't assemble or its expectation is invalid
+    Error(String),
+    // there is no expectation for the test; neither a sidecar file nor .expect directives
+    Skipped,
+}
+
+pub struct TestResult {
+    // the path of the test relative to the corpus directory
+    pub name: String,
+    pub outcome: Outcome,
+    pub duration: Duration,
+    // the outcome of every .expect directive of the test
+    pub inline: Vec<InlineOutcome>,
+}
+
+/// The results of all tests in a corpus for one config.
+pub struct SuiteResult {
+    pub config_name: String,
+    pub results: Vec<TestResult>,
+}
+
+impl SuiteResult {
+    pub fn count(&self, matches: fn(&Outcome) -> bool) -> usize {
+        self.results.iter().filter(|result| matches(&result.outcome)).count()
+    }
+
+    pub fn failure_cnt(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Failed(_)))
+    }
+
+    pub fn error_cnt(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Error(_)))
+    }
+
+    pub fn skipped_cnt(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Skipped))
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.results.iter().map(|result| result.duration).sum()
+    }
+}
+
+/// Finds all '.asm' files in the directory tree; sorted so the order of the results is stable.
+pub fn find_tests(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut tests = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|extension| extension == "asm") {
+                tests.push(path);
+            }
+        }
+    }
+    tests.sort();
+    Ok(tests)
+}
+
+/// Runs every test in the corpus directory on the given config and checks it against its sidecar
+/// expectation file and its .expect directives.
+pub fn run_suite(dir: &Path, config_name: &str, cpu_config: &CPUConfig) -> io::Result<SuiteResult> {
+    let mut cpu_config = cpu_config.clone();
+    cpu_config.set_trace(false);
+
+    let mut results = Vec::new();
+    for test_path in find_tests(dir)? {
+        let name = test_path.strip_prefix(dir).unwrap_or(&test_path).display().to_string();
+        let start = Instant::now();
+        let (outcome, inline) = run_test(&test_path, &cpu_config);
+        results.push(TestResult { name, outcome, duration: start.elapsed(), inline });
+    }
+
+    Ok(SuiteResult { config_name: config_name.to_string(), results })
+}
+
+// Returns the outcome of the test and of its .expect directives.
+fn run_test(test_path: &Path, cpu_config: &CPUConfig) -> (Outcome, Vec<InlineOutcome>) {
+    let expectation_path = Expectation::sidecar_path(test_path);
+    // a file without a sidecar and without .expect directives isn't a test; it isn't even loaded
+    let has_directives = fs::read_to_string(test_path).is_ok_and(|source| source.contains(".expect"));
+    if !expectation_path.exists() && !has_directives {
+        return (Outcome::Skipped, Vec::new());
+    }
+
+    let expectation = if expectation_path.exists() {
+        match Expectation::load(&expectation_path) {
+            Ok(expectation) => Some(expectation),
+            Err(error) => return (Outcome::Error(format!("Failed to load {}. Cause: {}", expectation_path.display(), error)), Vec::new()),
+        }
+    } else {
+        None
+    };
+
+    let mut cpu_config = cpu_config.clone();
+    for (key, value) in expectation.iter().flat_map(|expectation| &expectation.config) {
+        if let Err(error) = cpu_config.set_value(key, value.clone()) {
+            return (Outcome::Error(format!("Invalid config in {}: {}", expectation_path.display(), error)), Vec::new());
+        }
+    }
+    let cpu_config = &cpu_config;
+
+    let program = match load(cpu_config.clone(), test_path.to_str().unwrap()) {
+        Ok(program) => Rc::new(program),
+        Err(error) => return (Outcome::Error(format!("Loading program failed. {}", error)), Vec::new()),
+    };
+    if expectation.is_none() && program.expectations.is_empty() {
+        return (Outcome::Skipped, Vec::new());
+    }
+
+    // only the failures are reported; the output is checked using cpu.output()
+    let mut cpu = CPU::new(cpu_config);
+    cpu.set_quiet(true);
+    // don't throttle the CPU to the configured frequency
+    cpu.set_throttled(false);
+    cpu.set_stdout(Box::new(io::sink()));
+    if let Err(diagnosis) = cpu.run(&program) {
+        return (Outcome::Error(diagnosis.to_string()), Vec::new());
+    }
+
+    let snapshot = cpu.snapshot(&program);
+    let inline = check_inline(&program, &snapshot, cpu.exit_code().is_some());
+    let mut failures: Vec<String> = inline.iter()
+        .filter_map(|outcome| outcome.failure.as_ref()
+            .map(|failure| format!("line {}: {}: {}", outcome.line, outcome.directive, failure)))
+        .collect();
+
+    if let Some(expectation) = expectation {
+        match expectation.check(&cpu_config.abi(), &snapshot, cpu.output(), cpu.perf_counters().cycle_cnt, cpu.exit_code()) {
+            Ok(sidecar_failures) => failures.extend(sidecar_failures),
+            Err(error) => return (Outcome::Error(format!("Invalid expectation {}: {}", expectation_path.display(), error)), inline),
+        }
+    }
+
+    if failures.is_empty() {
+        (Outcome::Passed, inline)
+    } else {
+        (Outcome::Failed(failures), inline)
+    }
+}
@@ -1,33 +1,464 @@
 use std::cell::RefCell;
 use std::error::Error;
-use std::fs::File;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
 use std::rc::Rc;
 use std::thread;
 use std::time::Duration;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::backend::backend::Backend;
+use crate::abi::abi::{Abi, ARGUMENT_REGS};
+use crate::analysis::assertions::RetirementAssertion;
+use crate::analysis::energy::EnergyReport;
+use crate::analysis::watchdog::HangDiagnosis;
+use crate::analysis::watchpoint::{Watchpoint, WatchpointHit};
+use crate::analysis::wrong_path::WrongPathObserver;
+use crate::frontend::btb::BranchTargetBuffer;
+use crate::frontend::ras::ReturnAddressStack;
+use crate::backend::backend::{Backend, printr_output};
 use crate::frontend::frontend::{Frontend, FrontendControl};
-use crate::instructions::instructions::{InstrQueue, Program, RegisterType, WordType};
+use crate::interrupts::interrupt_controller::InterruptController;
+use crate::interrupts::timer::Timer;
+use crate::instructions::instructions::{EU_TYPES, EUType, InstrQueue, Opcode, Program, RegisterType, WordType};
 use crate::memory_subsystem::memory_subsystem::MemorySubsystem;
+use crate::plugin::plugin::{AccessKind, Plugin, Plugins};
+use crate::reference_interpreter::reference_interpreter::ReferenceInterpreter;
+use crate::random::random::Random;
+use crate::report::pipeline_state::PipelineSnapshot;
+use crate::plugin::tracer::Tracer;
+use crate::snapshot::memory_image::MemoryImage;
+use crate::snapshot::snapshot::Snapshot;
 
-
-pub(crate) struct PerfCounters {
+/// The performance counters of the CPU.
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct PerfCounters {
     pub decode_cnt: u64,
     pub issue_cnt: u64,
     pub dispatch_cnt: u64,
     pub execute_cnt: u64,
     pub retire_cnt: u64,
     pub cycle_cnt: u64,
+    pub load_replay_cnt: u64,
+    pub interrupt_cnt: u64,
+    // the number of loads that executed, but were flushed before they retired
+    pub squashed_load_cnt: u64,
+    // the stores removed from the store buffer because they were issued after a mispredicted branch
+    pub squashed_store_cnt: u64,
+    // the number of fetched control instructions that were looked up in the BTB
+    pub btb_lookup_cnt: u64,
+    pub btb_hit_cnt: u64,
+    // the number of BTB hits with the wrong target
+    pub btb_mispredict_cnt: u64,
+    // the number of fetched returns that were looked up in the return address stack; a hit is a non-empty stack
+    pub ras_lookup_cnt: u64,
+    pub ras_hit_cnt: u64,
+    // the number of return address stack hits with the wrong target
+    pub ras_mispredict_cnt: u64,
+    // the number of times the frontend had to be redirected after a control instruction: either it
+    // waited for the instruction to retire, or the BTB predicted the wrong target.
+    pub redirect_cnt: u64,
+    // the total number of cycles between fetching those control instructions and the redirect
+    pub redirect_cycle_cnt: u64,
+    // the number of mispredictions after which an instruction of the correct path was issued
+    pub mispredict_refill_cnt: u64,
+    // the total number of cycles between those flushes and the issue of the first instruction of the correct path;
+    // grows with the depth of the frontend
+    pub mispredict_refill_cycle_cnt: u64,
+    // the number of times a ready instruction couldn't be dispatched because no EU of its type was free;
+    // indexed by ALU, LoadStore, Multiplier, Divider, FloatingPoint
+    pub structural_stall_cnt: [u64; EU_TYPES.len()],
+    // the number of instructions dispatched to an execution unit per type; indexed like structural_stall_cnt
+    pub eu_dispatch_cnt: [u64; EU_TYPES.len()],
+    // the number of physical registers allocated when renaming the sinks of issued instructions
+    pub rename_cnt: u64,
+    // the number of source operands that received their value through the bypass network
+    pub forwarded_operand_cnt: u64,
+    // the number of instructions that became ready a cycle earlier because their last operand was forwarded
+    pub forwarding_saved_cycle_cnt: u64,
+    // the number of cycles issue stalled because the store buffer was full
+    pub sb_full_stall_cnt: u64,
+    // the number of cycles a store couldn't retire because the drain bandwidth was used up
+    pub sb_drain_stall_cnt: u64,
+    // the number of committed stores merged into a younger store to the same address
+    pub sb_coalesced_cnt: u64,
+    // the number of stores written to memory before an older store (relaxed memory model)
+    pub sb_reordered_cnt: u64,
+    // the number of retired branches that switched between ARM and Thumb code
+    pub mode_switch_cnt: u64,
+    // the number of dispatched multi-word accesses that paid the unaligned access penalty
+    pub unaligned_access_cnt: u64,
+    // the number of instructions executed by the reference interpreter before the cycle accurate simulation
+    pub fast_forward_cnt: u64,
+    // the number of cycles (included in cycle_cnt) in which the whole pipeline waited and that were skipped at once
+    pub skipped_cycle_cnt: u64,
 }
 
 impl PerfCounters {
     pub fn new() -> Self {
-        Self { decode_cnt: 0, issue_cnt: 0, dispatch_cnt: 0, execute_cnt: 0, retire_cnt: 0, cycle_cnt: 0 }
+        Self::default()
+    }
+
+    pub fn ipc(&self) -> f32 {
+        self.retire_cnt as f32 / self.cycle_cnt as f32
+    }
+
+    // None before the first BTB lookup
+    pub fn btb_hit_rate(&self) -> Option<f32> {
+        if self.btb_lookup_cnt == 0 { None } else { Some(self.btb_hit_cnt as f32 / self.btb_lookup_cnt as f32) }
+    }
+
+    // None before the first return address stack lookup
+    pub fn ras_hit_rate(&self) -> Option<f32> {
+        if self.ras_lookup_cnt == 0 { None } else { Some(self.ras_hit_cnt as f32 / self.ras_lookup_cnt as f32) }
+    }
+
+    // the average number of cycles between fetching a control instruction and redirecting the frontend;
+    // None before the first redirect
+    pub fn redirect_latency(&self) -> Option<f32> {
+        if self.redirect_cnt == 0 { None } else { Some(self.redirect_cycle_cnt as f32 / self.redirect_cnt as f32) }
+    }
+
+    // the average number of cycles between the flush after a misprediction and the issue of the first instruction
+    // of the correct path; the effective penalty of the frontend. None before the first misprediction
+    pub fn mispredict_penalty(&self) -> Option<f32> {
+        if self.mispredict_refill_cnt == 0 { None } else { Some(self.mispredict_refill_cycle_cnt as f32 / self.mispredict_refill_cnt as f32) }
+    }
+}
+
+// A rate as a percentage; n/a when there was nothing to count.
+fn format_percentage(rate: Option<f32>) -> String {
+    rate.map_or(String::from("n/a"), |rate| format!("{:.0}%", 100.0 * rate))
+}
+
+// An average number of cycles; n/a when there was nothing to count.
+fn format_cycles(cycles: Option<f32>) -> String {
+    cycles.map_or(String::from("n/a"), |cycles| format!("{:.2} cycles", cycles))
+}
+
+impl fmt::Display for PerfCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Cycles:       {}", self.cycle_cnt)?;
+        writeln!(f, "Decoded:      {}", self.decode_cnt)?;
+        writeln!(f, "Issued:       {}", self.issue_cnt)?;
+        writeln!(f, "Dispatched:   {}", self.dispatch_cnt)?;
+        writeln!(f, "Executed:     {}", self.execute_cnt)?;
+        writeln!(f, "Retired:      {}", self.retire_cnt)?;
+        writeln!(f, "IPC:          {:.2}", self.ipc())?;
+        writeln!(f, "Load replays: {}", self.load_replay_cnt)?;
+        writeln!(f, "Interrupts:   {}", self.interrupt_cnt)?;
+        writeln!(f, "Squashed loads: {}", self.squashed_load_cnt)?;
+        writeln!(f, "Squashed stores: {}", self.squashed_store_cnt)?;
+        writeln!(f, "BTB hits:     {}/{} ({}), mispredicts: {}",
+                 self.btb_hit_cnt, self.btb_lookup_cnt, format_percentage(self.btb_hit_rate()), self.btb_mispredict_cnt)?;
+        writeln!(f, "RAS hits:     {}/{} ({}), mispredicts: {}",
+                 self.ras_hit_cnt, self.ras_lookup_cnt, format_percentage(self.ras_hit_rate()), self.ras_mispredict_cnt)?;
+        writeln!(f, "Redirects:    {} (average latency {})", self.redirect_cnt, format_cycles(self.redirect_latency()))?;
+        writeln!(f, "Mispredicts:  {} (average penalty {} from the flush to the issue of the correct path)",
+                 self.mispredict_refill_cnt, format_cycles(self.mispredict_penalty()))?;
+        writeln!(f, "Forwarding:   {} operands forwarded, {} cycles saved", self.forwarded_operand_cnt, self.forwarding_saved_cycle_cnt)?;
+        writeln!(f, "Store buffer: {} full stalls, {} drain stalls, {} stores coalesced, {} stores reordered",
+                 self.sb_full_stall_cnt, self.sb_drain_stall_cnt, self.sb_coalesced_cnt, self.sb_reordered_cnt)?;
+        writeln!(f, "Mode switches: {}", self.mode_switch_cnt)?;
+        writeln!(f, "Unaligned accesses: {}", self.unaligned_access_cnt)?;
+        writeln!(f, "Fast forwarded: {}", self.fast_forward_cnt)?;
+        writeln!(f, "Skipped idle cycles: {}", self.skipped_cycle_cnt)?;
+        write!(f, "Structural hazard stalls:")?;
+        for eu_type in EU_TYPES {
+            write!(f, " {}={}", eu_type, self.structural_stall_cnt[eu_type as usize])?;
+        }
+        Ok(())
+    }
+}
+
+/// The configuration of a single type of execution unit.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub(crate) struct EUConfig {
+    // the number of execution units of this type
+    pub count: u8,
+    // the number of cycles an instruction occupies the execution unit
+    pub latency: u8,
+    // if the execution unit can accept a new instruction every cycle
+    pub pipelined: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct EUPoolConfig {
+    pub alu: EUConfig,
+    pub load_store: EUConfig,
+    pub multiplier: EUConfig,
+    pub divider: EUConfig,
+    pub fpu: EUConfig,
+}
+
+impl Default for EUPoolConfig {
+    fn default() -> Self {
+        let eu = |count, latency, pipelined| EUConfig { count, latency, pipelined };
+        Self { alu: eu(4, 1, true), load_store: eu(2, 1, true), multiplier: eu(1, 3, true), divider: eu(1, 12, false), fpu: eu(2, 4, true) }
+    }
+}
+
+impl EUPoolConfig {
+    pub(crate) fn get(&self, eu_type: EUType) -> &EUConfig {
+        match eu_type {
+            EUType::ALU => &self.alu,
+            EUType::LoadStore => &self.load_store,
+            EUType::Multiplier => &self.multiplier,
+            EUType::Divider => &self.divider,
+            EUType::FloatingPoint => &self.fpu,
+        }
+    }
+
+    pub(crate) fn count(&self) -> u8 {
+        EU_TYPES.iter().map(|eu_type| self.get(*eu_type).count).sum()
+    }
+}
+
+/// The energy model; the energy per event in picojoules.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct EnergyConfig {
+    // if the energy should be accounted and reported at the end of the program
+    pub enabled: bool,
+    // per instruction dispatched to an execution unit of the type; also on a path that is flushed later on
+    pub alu: f64,
+    pub load_store: f64,
+    pub multiplier: f64,
+    pub divider: f64,
+    pub fpu: f64,
+    // per physical register allocated when renaming
+    pub rename: f64,
+    // per BTB or return address stack misprediction; the flush and the redirect of the frontend
+    pub mispredict: f64,
+    // the static (leakage) energy per cycle
+    pub cycle: f64,
+}
+
+impl Default for EnergyConfig {
+    fn default() -> Self {
+        Self { enabled: false, alu: 1.0, load_store: 4.0, multiplier: 3.0, divider: 10.0, fpu: 5.0, rename: 0.5, mispredict: 15.0, cycle: 2.0 }
+    }
+}
+
+/// Randomly varies the latencies to shake out ordering bugs in the out of order machinery and to test
+/// the synchronization code of a program. The same seed always gives the same latencies.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct JitterConfig {
+    // if the latencies should vary randomly
+    pub enabled: bool,
+    // the seed of the random generator; a run is replayed by using the same seed
+    pub seed: u64,
+    // the maximum number of cycles added to the latency of an instruction in an execution unit
+    pub max_eu_delay: u8,
+    // the maximum number of cycles a committed store waits before it is written to memory
+    pub max_store_delay: u8,
+}
+
+impl Default for JitterConfig {
+    fn default() -> Self {
+        Self { enabled: false, seed: 0, max_eu_delay: 2, max_store_delay: 2 }
+    }
+}
+
+/// Where the code, the variables, the heap and the stack are placed in memory. The bases can be moved by a
+/// random number of words (address space layout randomization), so a program that relies on absolute
+/// addresses or reads past a variable behaves differently per seed.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct MemoryLayoutConfig {
+    // the address of the first instruction
+    pub code_base: u32,
+    // the first address of the variables; 0 places them just after the code
+    pub data_base: u32,
+    // the first address of the heap; 0 places it just after the variables
+    pub heap_base: u32,
+    // if the bases should be moved by a random number of words
+    pub randomize: bool,
+    // the seed of the random generator; the same seed always gives the same layout
+    pub seed: u64,
+    // the maximum number of words a base is moved; the code, the variables and the heap move up, the stack moves down
+    pub max_shift: u32,
+}
+
+impl Default for MemoryLayoutConfig {
+    fn default() -> Self {
+        Self { code_base: 0, data_base: 0, heap_base: 0, randomize: false, seed: 0, max_shift: 64 }
+    }
+}
+
+/// The number of words the bases of the memory layout are moved by; all 0 without randomization.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct LayoutShifts {
+    pub code: u32,
+    pub data: u32,
+    pub heap: u32,
+    pub stack: u32,
+}
+
+impl MemoryLayoutConfig {
+    pub(crate) fn shifts(&self) -> LayoutShifts {
+        if !self.randomize {
+            return LayoutShifts::default();
+        }
+        let mut random = Random::new(self.seed);
+        let mut shift = || random.below(self.max_shift as u64 + 1) as u32;
+        let data = shift();
+        let heap = shift();
+        // the SP stays at a doubleword boundary, like the stack alignment of the AAPCS
+        let stack = shift() & !1;
+        // drawn last, so a seed moves the other bases like before the code could move
+        let code = shift();
+        LayoutShifts { code, data, heap, stack }
+    }
+
+    // The largest number of words a base can be moved.
+    fn max_shift(&self) -> u32 {
+        if self.randomize { self.max_shift } else { 0 }
+    }
+}
+
+/// Executes the start of a program on the reference interpreter before the cycle accurate simulation
+/// takes over; e.g. to skip the initialization of a long running program.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct FastForwardConfig {
+    // the number of instructions the reference interpreter executes; 0 disables the fast forward
+    pub instructions: u64,
+    // if the loads and stores of the fast forwarded instructions should warm up the caches
+    pub warm_caches: bool,
+    // if the control instructions of the fast forwarded instructions should warm up the BTB and the return address stack
+    pub warm_btb: bool,
+}
+
+impl Default for FastForwardConfig {
+    fn default() -> Self {
+        Self { instructions: 0, warm_caches: true, warm_btb: true }
+    }
+}
+
+/// The vector (SIMD) extension: VADD, VSUB and VMUL operate on every lane of the vector registers and
+/// VLD1 and VST1 load and store the lanes from and to consecutive words.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct VectorConfig {
+    // the number of lanes of a vector register; at most MAX_VECTOR_LANES
+    pub lanes: u8,
+    // the number of bits of a lane (8, 16, 32 or 64; at most the word_size); lane arithmetic wraps at this width
+    pub lane_width: u8,
+}
+
+impl Default for VectorConfig {
+    fn default() -> Self {
+        Self { lanes: 4, lane_width: 32 }
+    }
+}
+
+/// The numbers of the general purpose registers with a special role; e.g. sp: 29 makes r29 the stack pointer.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct AbiConfig {
+    // the frame pointer
+    pub fp: RegisterType,
+    // the stack pointer
+    pub sp: RegisterType,
+    // the link register; BL writes the return address to it
+    pub lr: RegisterType,
+    // the program counter
+    pub pc: RegisterType,
+}
+
+impl Default for AbiConfig {
+    fn default() -> Self {
+        Self { fp: FP, sp: SP, lr: LR, pc: PC }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WritePolicy {
+    // a store only marks the line dirty; the line is written to the next level when it is evicted.
+    // A store that misses first reads the line (write allocate).
+    WriteBack,
+    // every store is also written to the next level; a store that misses doesn't allocate the line.
+    WriteThrough,
+}
+
+/// A level of the data cache hierarchy; the sizes are in words.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub(crate) struct CacheConfig {
+    pub size: u32,
+    pub line_size: u32,
+    // the number of lines per set
+    pub associativity: u32,
+    // the cycles added to the latency of a load that accesses this level
+    pub latency: u8,
+    pub write_policy: WritePolicy,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PrefetchPolicy {
+    None,
+    // a miss (or the first hit on a prefetched line) prefetches the next lines
+    NextLine,
+    // a load that accesses memory with a constant stride prefetches the next addresses of the stride
+    Stride,
+}
+
+/// The hardware prefetcher that fills the L1.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct PrefetcherConfig {
+    pub policy: PrefetchPolicy,
+    // the number of lines (next line) or strides (stride) that are prefetched ahead
+    pub degree: u8,
+    // the number of loads the stride prefetcher tracks; a load is found on its code address
+    pub table_size: u16,
+}
+
+impl Default for PrefetcherConfig {
+    fn default() -> Self {
+        Self { policy: PrefetchPolicy::None, degree: 2, table_size: 16 }
+    }
+}
+
+/// The data caches. The memory itself holds the values, so the caches only model the latency of the
+/// loads and the traffic between the levels.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct CachesConfig {
+    pub enabled: bool,
+    // the L1 followed by the optional unified L2 (and further levels)
+    pub levels: Vec<CacheConfig>,
+    // the cycles added to the latency of a load that misses in every level
+    pub memory_latency: u8,
+    pub prefetcher: PrefetcherConfig,
+}
+
+impl Default for CachesConfig {
+    fn default() -> Self {
+        let level = |size, line_size, associativity, latency| CacheConfig { size, line_size, associativity, latency, write_policy: WritePolicy::WriteBack };
+        Self { enabled: false, levels: vec![level(64, 4, 2, 1), level(512, 8, 4, 6)], memory_latency: 30, prefetcher: PrefetcherConfig::default() }
     }
 }
 
-#[derive(Clone, Deserialize, Debug)]
+impl EnergyConfig {
+    pub(crate) fn get(&self, eu_type: EUType) -> f64 {
+        match eu_type {
+            EUType::ALU => self.alu,
+            EUType::LoadStore => self.load_store,
+            EUType::Multiplier => self.multiplier,
+            EUType::Divider => self.divider,
+            EUType::FloatingPoint => self.fpu,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
 pub(crate) struct Trace {
     pub decode: bool,
     pub issue: bool,
@@ -37,30 +468,200 @@ pub(crate) struct Trace {
     pub cycle: bool,
 }
 
-#[derive(Clone, Deserialize, Debug)]
-pub(crate) struct CPUConfig {
+impl Default for Trace {
+    fn default() -> Self {
+        Self { decode: false, issue: false, dispatch: false, execute: false, retire: false, cycle: true }
+    }
+}
+
+impl Trace {
+    // If any stage of the pipeline is traced; the cycle summary is printed by the CPU itself.
+    pub(crate) fn traces_instructions(&self) -> bool {
+        self.decode || self.issue || self.dispatch || self.execute || self.retire
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MemoryDisambiguation {
+    // A load only executes when the addresses of all older stores are known.
+    Conservative,
+    // A load executes before older stores with unknown addresses. If such a store turns out to
+    // write to the same address, the load and all younger instructions are flushed and replayed.
+    Speculative,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Bypass {
+    // Every result is forwarded to the waiting instructions in the cycle it is produced.
+    Full,
+    // Only the results of the ALU, multiplier, divider and FPU are forwarded; a loaded value is
+    // first written back to the register file and read in the next cycle.
+    Execute,
+    // No result is forwarded; every result is written back and read in the next cycle.
+    None,
+}
+
+impl Bypass {
+    // if the results of the given type of execution unit are forwarded
+    pub(crate) fn forwards(&self, eu_type: EUType) -> bool {
+        match self {
+            Bypass::Full => true,
+            Bypass::Execute => eu_type != EUType::LoadStore,
+            Bypass::None => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AlignmentPolicy {
+    // unaligned accesses are as fast as aligned ones
+    Allow,
+    // an unaligned access takes unaligned_penalty extra cycles
+    Penalty,
+    // an unaligned access faults when it retires
+    Fault,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MemoryModel {
+    // total store order: the committed stores are written to memory in program order
+    Tso,
+    // a committed store can be written to memory before an older committed store to another address;
+    // the youngest store that may go first, goes first. Only a barrier (DMB, DSB) orders the stores.
+    Relaxed,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SBDrainPolicy {
+    // Committed stores are written to memory in the background, lfb_count per cycle; so the store
+    // buffer can fill up with committed stores.
+    Eager,
+    // A store is written to memory when it retires. When the lfb_count writes of the cycle are used
+    // up, the store waits with retiring till the next cycle.
+    Retire,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SideEffects {
+    // Side effecting instructions like PRINTR act when they execute; also on a path that is
+    // later flushed (e.g. a replayed load or an interrupt).
+    Execute,
+    // Side effecting instructions act when they retire; so only committed instructions act.
+    Retire,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PipelineMode {
+    // A classic 5-stage pipeline: fetch, decode, execute, memory and writeback. A single instruction per
+    // cycle passes every stage and the instructions pass them in program order. A multi-cycle instruction
+    // holds the execute stage, a load or store spends a cycle (plus the cache latency) in the memory stage.
+    InOrder,
+    // Instructions are dispatched to the execution units as soon as their operands are ready.
+    OutOfOrder,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RSSelectionPolicy {
+    // the ready instructions are dispatched in the order their operands became ready
+    ReadyOrder,
+    // the oldest ready instructions (in program order) are dispatched first
+    OldestFirst,
+    // the ready instructions are dispatched in a random order
+    Random,
+}
+
+/// How the scheduler picks the instructions to dispatch when more instructions in the reservation
+/// stations are ready than the dispatch width or the free execution units allow.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub(crate) struct RSSelectionConfig {
+    pub policy: RSSelectionPolicy,
+    // the seed of the random generator of the random policy; the same seed gives the same order
+    pub seed: u64,
+}
+
+impl Default for RSSelectionConfig {
+    fn default() -> Self {
+        Self { policy: RSSelectionPolicy::ReadyOrder, seed: 0 }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    // the least significant byte of a word is stored at the lowest address
+    Little,
+    // the most significant byte of a word is stored at the lowest address
+    Big,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct CPUConfig {
+    // in order or out of order execution; the in-order pipeline ignores the n_wide settings
+    pub(crate) pipeline_mode: PipelineMode,
     // the number of physical registers
     pub(crate) phys_reg_count: u16,
+    // the number of general purpose registers (r0, r1, ...); between 16 and 31
+    pub(crate) general_reg_count: u16,
+    // the general purpose registers that are the FP, SP, LR and PC
+    pub(crate) abi: AbiConfig,
     // the number of instructions the frontend can fetch/decode per clock cycle.
     pub(crate) frontend_n_wide: u8,
     // the size of the instruction queue between frontend and backend
     pub(crate) instr_queue_capacity: u16,
+    // the number of fetched instructions that can wait to be decoded; 0 fetches directly into the InstrQueue
+    pub(crate) fetch_queue_capacity: u16,
+    // the number of bytes of instructions the frontend can fetch per cycle; 0 doesn't limit the fetch by size
+    pub(crate) fetch_bytes: u16,
+    // the number of cycles an instruction spends in the fetch stages before it can be decoded
+    pub(crate) fetch_stages: u8,
+    // the number of cycles an instruction spends in the decode stages before it enters the InstrQueue
+    pub(crate) decode_stages: u8,
+    // the number of entries in the branch target buffer; 0 disables the BTB
+    pub(crate) btb_size: u16,
+    // the number of entries in the return address stack; 0 disables it and returns are predicted by the BTB
+    pub(crate) ras_depth: u16,
     // the frequency of the CPU in Hz.
     pub(crate) frequency_hz: u64,
     // the number of reservation stations
     pub(crate) rs_count: u16,
-    // the size of the memory in machine words
+    // how the ready instructions in the reservation stations are selected for dispatch
+    pub(crate) rs_selection: RSSelectionConfig,
+    // the size of the memory in machine words; the code occupies the memory from address 0, followed by the variables
     pub(crate) memory_size: u32,
+    // where the variables, the heap and the stack are placed; optionally randomized
+    pub(crate) memory_layout: MemoryLayoutConfig,
+    // the data caches between the load/store unit (and store buffer) and the memory
+    pub(crate) caches: CachesConfig,
+    // how a multi-word access (PUSH, POP, LDM, STM, VLD1, VST1) that doesn't start at a doubleword (2 word) boundary is handled
+    pub(crate) alignment_policy: AlignmentPolicy,
+    // the extra cycles of an unaligned access with the penalty alignment policy
+    pub(crate) unaligned_penalty: u8,
     // the capacity of the store buffer
     pub(crate) sb_capacity: u16,
+    // when committed stores are written from the store buffer to memory
+    pub(crate) sb_drain_policy: SBDrainPolicy,
+    // if a committed store is merged into a younger committed store to the same address
+    pub(crate) sb_coalescing: bool,
+    // the order in which the committed stores become visible in memory
+    pub(crate) memory_model: MemoryModel,
     // the number of line fill buffers; currently there are no line fill buffer
     // it is just a limit of the number of stores that can commit to memory
-    // per clock cycle (there is also no cache)
+    // per clock cycle (there is also no cache); so the drain bandwidth of the store buffer
     pub(crate) lfb_count: u8,
     // the capacity of the reorder buffer
     pub(crate) rob_capacity: u16,
-    // the number of execution units
-    pub(crate) eu_count: u8,
+    // the execution units per type
+    pub(crate) execution_units: EUPoolConfig,
     // if processing of a single instruction should be traced (printed)
     pub(crate) trace: Trace,
     // the number of instructions that can retire per clock cycle
@@ -69,17 +670,419 @@ pub(crate) struct CPUConfig {
     pub(crate) dispatch_n_wide: u8,
     // the number of instructions that can be issued to  the rob or finding reservation stations, every clock cycle.
     pub(crate) issue_n_wide: u8,
-    // The size of the stack
+    // the address just above the stack; the initial SP. The stack grows down from here. 0 places the stack at
+    // the end of the memory; see stack_base()
+    pub(crate) stack_base: u32,
+    // The size of the stack; limited to half of the memory below the stack_base, see stack_capacity()
     pub(crate) stack_capacity: u32,
+    // if the SP and the accesses through the SP should be checked against the stack bounds
+    pub(crate) stack_checks: bool,
+    // if reading a stack slot that was never written should fault
+    pub(crate) stack_poison: bool,
+    // if the callee saved registers should be checked when returning from a function called with BL
+    pub(crate) check_calling_convention: bool,
+    // if the stack usage per function should be tracked and reported at the end of the program
+    pub(crate) stack_usage: bool,
+    // if every retired instruction should be printed with the registers and memory words it changed
+    pub(crate) execution_log: bool,
+    // the number of retired instructions a debugger can step back (CPU::step_back); 0 disables the journal
+    pub(crate) journal_depth: u32,
+    // the memory disambiguation policy for loads that are executed before older stores
+    pub(crate) memory_disambiguation: MemoryDisambiguation,
+    // when side effecting instructions like PRINTR act
+    pub(crate) side_effects: SideEffects,
+    // which results are forwarded to dependent instructions without going through the register file
+    pub(crate) bypass: Bypass,
+    // the number of poisoned words placed around every variable and heap block; 0 disables the redzones.
+    pub(crate) redzone_size: u8,
+    // the number of cycles between two timer interrupts; 0 disables the timer
+    pub(crate) timer_period: u64,
+    // if the static WCET bound per function should be computed and compared with the measured times
+    pub(crate) wcet: bool,
+    // if the dataflow limit IPC (infinite resources, perfect prediction) should be computed
+    pub(crate) ilp_study: bool,
+    // if the cycles instructions wait should be attributed to a cause and reported per instruction
+    pub(crate) stall_attribution: bool,
+    // if the loops should be detected and the trip counts, IPC and stalls reported per loop
+    pub(crate) loop_stats: bool,
+    // if the dynamic call graph with the inclusive and exclusive cycles per function should be reported
+    pub(crate) call_graph: bool,
+    // the cycles between two samples of the sampling profiler; 0 disables it
+    pub(crate) sampling_period: u64,
+    // if every retiring instruction should be checked against the reference interpreter
+    pub(crate) oracle: bool,
+    // if a histogram of the number of reservation stations in use per cycle should be reported
+    pub(crate) rs_occupancy: bool,
+    // if the addresses read by loads on a wrong path (flushed after they executed) should be reported
+    pub(crate) wrong_path_loads: bool,
+    // the energy per event; when enabled the energy is reported at the end of the program
+    pub(crate) energy: EnergyConfig,
+    // randomly varies the latencies; e.g. for robustness testing
+    pub(crate) jitter: JitterConfig,
+    // if immediates that can't be encoded in an ARM instruction are rejected when loading a program; see
+    // immediate_forms
+    pub(crate) strict_immediates: bool,
+    // the number of cycles after which the program is stopped with a hang diagnosis; 0 disables the watchdog
+    pub(crate) max_cycles: u64,
+    // if the cycles in which the whole pipeline waits for a long latency operation are skipped at once
+    pub(crate) skip_idle_cycles: bool,
+    // executes the first instructions on the reference interpreter (functional warmup) before the cycle accurate simulation
+    pub(crate) fast_forward: FastForwardConfig,
+    // the number of bits of a word (32 or 64); integer arithmetic wraps at this width
+    pub(crate) word_size: u8,
+    // the order of the bytes in a word in memory
+    pub(crate) endianness: Endianness,
+    // the number of lanes of the vector registers and their width
+    pub(crate) vector: VectorConfig,
+}
+
+/// The defaults of the settings that are missing from a config file. A setting added to the simulator
+/// defaults to the behaviour from before it existed, e.g. no BTB and no watchdog; so an older config file
+/// still loads and simulates the same CPU.
+impl Default for CPUConfig {
+    fn default() -> Self {
+        Self {
+            pipeline_mode: PipelineMode::OutOfOrder,
+            phys_reg_count: 64,
+            general_reg_count: 31,
+            abi: AbiConfig::default(),
+            frontend_n_wide: 4,
+            instr_queue_capacity: 32,
+            fetch_queue_capacity: 0,
+            fetch_bytes: 0,
+            fetch_stages: 1,
+            decode_stages: 1,
+            btb_size: 0,
+            ras_depth: 0,
+            frequency_hz: 10,
+            rs_count: 16,
+            rs_selection: RSSelectionConfig::default(),
+            memory_size: 2048,
+            memory_layout: MemoryLayoutConfig::default(),
+            caches: CachesConfig::default(),
+            alignment_policy: AlignmentPolicy::Allow,
+            unaligned_penalty: 2,
+            sb_capacity: 16,
+            sb_drain_policy: SBDrainPolicy::Eager,
+            sb_coalescing: false,
+            memory_model: MemoryModel::Tso,
+            lfb_count: 4,
+            rob_capacity: 64,
+            execution_units: EUPoolConfig::default(),
+            trace: Trace::default(),
+            retire_n_wide: 4,
+            dispatch_n_wide: 4,
+            issue_n_wide: 4,
+            stack_base: 0,
+            stack_capacity: 64,
+            stack_checks: false,
+            stack_poison: false,
+            check_calling_convention: false,
+            stack_usage: false,
+            execution_log: false,
+            journal_depth: 0,
+            memory_disambiguation: MemoryDisambiguation::Speculative,
+            side_effects: SideEffects::Execute,
+            bypass: Bypass::Full,
+            redzone_size: 0,
+            timer_period: 0,
+            wcet: false,
+            ilp_study: false,
+            stall_attribution: false,
+            loop_stats: false,
+            call_graph: false,
+            sampling_period: 0,
+            oracle: false,
+            rs_occupancy: false,
+            wrong_path_loads: false,
+            energy: EnergyConfig::default(),
+            jitter: JitterConfig::default(),
+            strict_immediates: false,
+            max_cycles: 0,
+            skip_idle_cycles: true,
+            fast_forward: FastForwardConfig::default(),
+            word_size: 32,
+            endianness: Endianness::Little,
+            vector: VectorConfig::default(),
+        }
+    }
+}
+
+impl CPUConfig {
+    // The number of instructions a stage handles per cycle; an in-order pipeline is scalar.
+    pub(crate) fn n_wide(&self, configured: u8) -> u8 {
+        match self.pipeline_mode {
+            PipelineMode::InOrder => 1,
+            PipelineMode::OutOfOrder => configured,
+        }
+    }
+
+    /// The register file a program can use.
+    pub fn abi(&self) -> Abi {
+        Abi::new(self)
+    }
+
+    // The address of the first instruction: the code_base, moved up when the memory layout is randomized.
+    pub(crate) fn code_base(&self) -> u32 {
+        self.memory_layout.code_base + self.memory_layout.shifts().code
+    }
+
+    // The initial SP: the stack_base, moved down when the memory layout is randomized. The stack occupies
+    // the stack_capacity words below it.
+    pub(crate) fn initial_sp(&self) -> u32 {
+        self.stack_base() - self.memory_layout.shifts().stack
+    }
+
+    // The address just above the stack; the end of the memory when the stack_base isn't set.
+    pub(crate) fn stack_base(&self) -> u32 {
+        if self.stack_base == 0 { self.memory_size } else { self.stack_base }
+    }
+
+    // The size of the stack; at most half of the memory below the stack_base, so the code, the variables and
+    // the heap keep the other half. A config file from before the stack was bounded has a capacity larger than
+    // the memory.
+    pub(crate) fn stack_capacity(&self) -> u32 {
+        self.stack_capacity.min(self.stack_base() / 2)
+    }
+
+    /// Loads the configuration from a YAML or TOML file; the format is determined by the extension.
+    pub fn from_file(file_path: &str) -> Result<CPUConfig, Box<dyn Error>> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|error| format!("Can't read config file '{}': {}", file_path, error))?;
+
+        let config: CPUConfig = match Path::new(file_path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)
+                .map_err(|error| format!("Invalid config file '{}': {}", file_path, error))?,
+            _ => serde_yaml::from_str(&content)
+                .map_err(|error| format!("Invalid config file '{}': {}", file_path, error))?,
+        };
+
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(format!("Invalid config file '{}':\n  {}", file_path, problems.join("\n  ")).into());
+        }
+        Ok(config)
+    }
+
+    /// Changes a single setting, written like in the config file; e.g. set("rob_capacity", "64") or
+    /// set("execution_units.alu.count", "2"). Nested settings are separated by a dot. The changed
+    /// configuration is validated; when it is invalid, the configuration is left unchanged.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let value = serde_yaml::from_str(value)
+            .map_err(|error| format!("Invalid value '{}' for setting '{}': {}", value, key, error))?;
+        self.set_value(key, value)
+    }
+
+    /// Changes a single setting to a value that is already parsed; e.g. read from a YAML file.
+    pub fn set_value(&mut self, key: &str, value: serde_yaml::Value) -> Result<(), Box<dyn Error>> {
+        let shown = serde_yaml::to_string(&value)?;
+        let shown = shown.trim_start_matches("---").trim();
+        let mut root = serde_yaml::to_value(&*self)?;
+        let mut setting = &mut root;
+        for name in key.split('.') {
+            setting = match setting {
+                serde_yaml::Value::Mapping(mapping) => mapping.get_mut(&serde_yaml::Value::String(String::from(name))),
+                _ => None,
+            }.ok_or_else(|| format!("Unknown setting '{}'", key))?;
+        }
+        *setting = value;
+
+        let config: CPUConfig = serde_yaml::from_value(root)
+            .map_err(|error| format!("Invalid value '{}' for setting '{}': {}", shown, key, error))?;
+        let problems = config.validate();
+        if !problems.is_empty() {
+            return Err(format!("Invalid value '{}' for setting '{}':\n  {}", shown, key, problems.join("\n  ")).into());
+        }
+        *self = config;
+        Ok(())
+    }
+
+    /// Enables or disables the tracing of all pipeline stages.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = Trace {
+            decode: enabled,
+            issue: enabled,
+            dispatch: enabled,
+            execute: enabled,
+            retire: enabled,
+            cycle: enabled,
+        };
+    }
+
+    /// Returns the problems that make the configuration impossible to simulate, each with the
+    /// reason; empty when the configuration is valid. Configurations loaded with from_file or
+    /// changed with set are always validated.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let non_zero = [
+            ("phys_reg_count", self.phys_reg_count as u64, "every result is renamed to a physical register"),
+            ("frontend_n_wide", self.frontend_n_wide as u64, "the frontend couldn't fetch any instruction"),
+            ("instr_queue_capacity", self.instr_queue_capacity as u64, "fetched instructions wait in the instruction queue"),
+            ("frequency_hz", self.frequency_hz, "the clock would never tick"),
+            ("rs_count", self.rs_count as u64, "every instruction waits in a reservation station for its operands"),
+            ("memory_size", self.memory_size as u64, "the memory holds the variables and the stack"),
+            ("sb_capacity", self.sb_capacity as u64, "every store waits in the store buffer until it is written to memory"),
+            ("lfb_count", self.lfb_count as u64, "every cache miss needs a line fill buffer"),
+            ("rob_capacity", self.rob_capacity as u64, "every instruction waits in the ROB until it retires"),
+            ("retire_n_wide", self.retire_n_wide as u64, "no instruction could retire"),
+            ("dispatch_n_wide", self.dispatch_n_wide as u64, "no instruction could be dispatched"),
+            ("issue_n_wide", self.issue_n_wide as u64, "no instruction could be issued"),
+        ];
+        for (name, value, reason) in non_zero {
+            if value == 0 {
+                problems.push(format!("'{}' must be larger than 0; {}", name, reason));
+            }
+        }
+
+        if self.issue_n_wide as u16 > self.rob_capacity {
+            problems.push(format!("'issue_n_wide' {} is larger than 'rob_capacity' {}; every issued micro-op needs a slot in the ROB",
+                                  self.issue_n_wide, self.rob_capacity));
+        }
+
+        for eu_type in EU_TYPES {
+            let eu_config = self.execution_units.get(eu_type);
+            if eu_config.count == 0 {
+                problems.push(format!("'execution_units' needs at least 1 {} unit", eu_type));
+            }
+            if eu_config.latency == 0 {
+                problems.push(format!("'execution_units' latency of the {} unit must be larger than 0", eu_type));
+            }
+        }
+
+        if self.general_reg_count <= PC || self.general_reg_count > GENERAL_ARG_REG_CNT {
+            problems.push(format!("'general_reg_count' {} must be between {} and {}",
+                                  self.general_reg_count, PC + 1, GENERAL_ARG_REG_CNT));
+        }
+
+        let special_regs = [("fp", self.abi.fp), ("sp", self.abi.sp), ("lr", self.abi.lr), ("pc", self.abi.pc)];
+        for (k, (name, reg)) in special_regs.iter().enumerate() {
+            if *reg >= self.general_reg_count {
+                problems.push(format!("'abi' {} r{} isn't one of the {} general purpose registers", name, reg, self.general_reg_count));
+            }
+            if let Some((other, _)) = special_regs[..k].iter().find(|(_, other_reg)| other_reg == reg) {
+                problems.push(format!("'abi' {} and {} are both r{}", other, name, reg));
+            }
+        }
+
+        if self.fetch_stages == 0 || self.decode_stages == 0 {
+            problems.push(String::from("'fetch_stages' and 'decode_stages' must be at least 1"));
+        }
+
+        if self.fetch_bytes > 0 && self.fetch_bytes < 4 {
+            problems.push(format!("'fetch_bytes' {} can't hold a 4 byte instruction; use 0 or at least 4", self.fetch_bytes));
+        }
+
+        if self.stack_base > self.memory_size {
+            problems.push(format!("'stack_base' {} is outside of 'memory_size' of {} words",
+                                  self.stack_base, self.memory_size));
+        }
+
+        let layout = &self.memory_layout;
+        let max_shift = layout.max_shift();
+        if self.stack_capacity() + max_shift > self.stack_base() {
+            problems.push(format!("'memory_layout' max_shift of {} words moves the stack of {} words below address 0",
+                                  max_shift, self.stack_capacity()));
+        }
+        // the lowest address the stack can grow to
+        let stack_limit = self.stack_base().saturating_sub(self.stack_capacity() + max_shift);
+        let moved = if max_shift > 0 { format!(" moved up by up to {} words", max_shift) } else { String::new() };
+        for (name, base) in [("code_base", layout.code_base), ("data_base", layout.data_base), ("heap_base", layout.heap_base)] {
+            if base + max_shift > stack_limit {
+                problems.push(format!("'memory_layout' {} {}{} isn't below the stack at {}", name, base, moved, stack_limit));
+            }
+        }
+        if layout.heap_base > 0 && layout.heap_base < layout.data_base {
+            problems.push(format!("'memory_layout' heap_base {} is below data_base {}", layout.heap_base, layout.data_base));
+        }
+
+        let energy_costs = [
+            ("alu", self.energy.alu),
+            ("load_store", self.energy.load_store),
+            ("multiplier", self.energy.multiplier),
+            ("divider", self.energy.divider),
+            ("fpu", self.energy.fpu),
+            ("rename", self.energy.rename),
+            ("mispredict", self.energy.mispredict),
+            ("cycle", self.energy.cycle),
+        ];
+        for (name, cost) in energy_costs {
+            if !cost.is_finite() || cost < 0.0 {
+                problems.push(format!("'energy' cost '{}' must be a non negative number", name));
+            }
+        }
+
+        if self.word_size != 32 && self.word_size != 64 {
+            problems.push(format!("'word_size' {} must be 32 or 64", self.word_size));
+        }
+
+        if self.vector.lanes == 0 || self.vector.lanes as u16 > MAX_VECTOR_LANES {
+            problems.push(format!("'vector' lanes {} must be between 1 and {}", self.vector.lanes, MAX_VECTOR_LANES));
+        }
+        if ![8, 16, 32, 64].contains(&self.vector.lane_width) || self.vector.lane_width > self.word_size {
+            problems.push(format!("'vector' lane_width {} must be 8, 16, 32 or 64 and at most the word_size of {}",
+                                  self.vector.lane_width, self.word_size));
+        }
+
+        if self.caches.enabled && self.caches.levels.is_empty() {
+            problems.push(String::from("'caches' needs at least 1 level when enabled"));
+        }
+        if self.caches.prefetcher.policy != PrefetchPolicy::None && self.caches.prefetcher.degree == 0 {
+            problems.push(String::from("'caches' prefetcher degree must be larger than 0"));
+        }
+        if self.caches.prefetcher.policy == PrefetchPolicy::Stride && self.caches.prefetcher.table_size == 0 {
+            problems.push(String::from("'caches' prefetcher table_size must be larger than 0 for the stride prefetcher"));
+        }
+        for (index, level) in self.caches.levels.iter().enumerate() {
+            if level.line_size == 0 || level.associativity == 0 || level.size == 0
+                || level.size % (level.line_size * level.associativity) != 0 {
+                problems.push(format!("'caches' L{} size of {} words must be a non zero multiple of line_size * associativity",
+                                      index + 1, level.size));
+            }
+            if level.line_size != 0 && !level.line_size.is_power_of_two() {
+                problems.push(format!("'caches' L{} line_size {} must be a power of 2 so the lines don't straddle the lines of the next level",
+                                      index + 1, level.line_size));
+            }
+        }
+
+        problems
+    }
 }
 
 pub fn load_cpu_config(file_path: &str) -> Result<CPUConfig, Box<dyn Error>> {
-    let file = File::open(file_path)?;
-    let config = serde_yaml::from_reader(file)?;
-    Ok(config)
+    CPUConfig::from_file(file_path)
+}
+
+/// Why run_for returned.
+#[derive(Clone, Debug)]
+pub enum RunOutcome {
+    /// The program exited; with the exit code (None when it ended with a fault).
+    Exited(Option<WordType>),
+    /// The instruction at the code address is the next to retire.
+    Breakpoint(usize),
+    /// A watchpoint triggered; the instruction that triggered it has retired.
+    Watchpoint(WatchpointHit),
+    /// All cycles of the budget were executed; the program is still running.
+    BudgetExhausted,
+    /// The program didn't complete within the max_cycles of the configuration.
+    Hang(HangDiagnosis),
+}
+
+// The state the frontend and the backend share; every part is owned by the CPU.
+#[derive(Clone)]
+pub(crate) struct CoreContext {
+    pub(crate) instr_queue: Rc<RefCell<InstrQueue>>,
+    pub(crate) memory_subsystem: Rc<RefCell<MemorySubsystem>>,
+    pub(crate) arch_reg_file: Rc<RefCell<ArgRegFile>>,
+    pub(crate) frontend_control: Rc<RefCell<FrontendControl>>,
+    pub(crate) perf_counters: Rc<RefCell<PerfCounters>>,
+    pub(crate) interrupt_controller: Rc<RefCell<InterruptController>>,
+    pub(crate) btb: Rc<RefCell<BranchTargetBuffer>>,
+    pub(crate) ras: Rc<RefCell<ReturnAddressStack>>,
+    pub(crate) plugins: Plugins,
 }
 
-pub(crate) struct CPU {
+pub struct CPU {
     backend: Backend,
     frontend: Frontend,
     memory_subsystem: Rc<RefCell<MemorySubsystem>>,
@@ -87,10 +1090,40 @@ pub(crate) struct CPU {
     cycle_period: Duration,
     trace: Trace,
     perf_counters: Rc<RefCell<PerfCounters>>,
+    interrupt_controller: Rc<RefCell<InterruptController>>,
+    timer: Timer,
+    // only set when the energy model is enabled
+    energy_config: Option<EnergyConfig>,
+    // if run shouldn't print the cycle period and the summary at the end of the program
+    quiet: bool,
+    // if every cycle sleeps for the cycle_period
+    throttled: bool,
+    plugins: Plugins,
+    max_cycles: u64,
+    skip_idle_cycles: bool,
+    // only set when the latency jitter is enabled
+    jitter_seed: Option<u64>,
+    // the program that is loaded
+    program_option: Option<Rc<Program>>,
+    // the arguments the program is started with
+    arguments: Vec<WordType>,
+    // written to memory when the program is loaded; in the order they were added
+    memory_images: Vec<MemoryImage>,
+    stack_base: WordType,
+    stack_capacity: u32,
+    btb: Rc<RefCell<BranchTargetBuffer>>,
+    ras: Rc<RefCell<ReturnAddressStack>>,
+    // only set when fast forwarding; the reference interpreter is configured like the CPU
+    fast_forward_config: Option<CPUConfig>,
 }
 
 impl CPU {
-    pub(crate) fn new(cpu_config: &CPUConfig) -> CPU {
+    /// Creates a CPU for the configuration; panics with the problems when the configuration is
+    /// invalid, see CPUConfig::validate.
+    pub fn new(cpu_config: &CPUConfig) -> CPU {
+        let problems = cpu_config.validate();
+        assert!(problems.is_empty(), "Invalid config:\n  {}", problems.join("\n  "));
+
         let instr_queue = Rc::new(RefCell::new(InstrQueue::new(cpu_config.instr_queue_capacity)));
 
         let perf_counters = Rc::new(RefCell::new(PerfCounters::new()));
@@ -99,34 +1132,40 @@ impl CPU {
             MemorySubsystem::new(cpu_config)));
 
         let arch_reg_file = Rc::new(RefCell::new(
-            ArgRegFile::new(GENERAL_ARG_REG_CNT + SPECIAL_ARG_REG_CNT)));
+            ArgRegFile::new(ARCH_REG_CNT)));
 
         // on ARM the stack grows down (from larger address to smaller address)
-        arch_reg_file.borrow_mut().set_value(SP, cpu_config.memory_size as WordType);
+        arch_reg_file.borrow_mut().set_value(SP, cpu_config.initial_sp() as WordType);
+        // like on ARM, interrupts are disabled after a reset
+        arch_reg_file.borrow_mut().set_value(CPSR, 1 << IRQ_MASK_BIT_POSITION);
 
-        let frontend_control = Rc::new(RefCell::new(
-            FrontendControl { halted: false }));
+        let interrupt_controller = Rc::new(RefCell::new(InterruptController::new()));
 
-        let backend = Backend::new(
-            cpu_config,
-            Rc::clone(&instr_queue),
-            Rc::clone(&memory_subsystem),
-            Rc::clone(&arch_reg_file),
-            Rc::clone(&frontend_control),
-            Rc::clone(&perf_counters),
-        );
+        let btb = Rc::new(RefCell::new(BranchTargetBuffer::new(cpu_config.btb_size)));
+        let ras = Rc::new(RefCell::new(ReturnAddressStack::new(cpu_config.ras_depth)));
 
-        let frontend = Frontend::new(
-            cpu_config,
-            Rc::clone(&instr_queue),
-            Rc::clone(&frontend_control),
-            Rc::clone(&perf_counters),
-            Rc::clone(&arch_reg_file),
-        );
+        let frontend_control = Rc::new(RefCell::new(
+            FrontendControl { halted: false, redirect: None }));
 
+        let plugins: Plugins = Rc::new(RefCell::new(Vec::new()));
+        if cpu_config.trace.traces_instructions() {
+            // the tracing is an observer of the pipeline like any other plugin
+            plugins.borrow_mut().push(Box::new(Tracer::new(&cpu_config.trace)));
+        }
 
-        let x = Duration::from_micros(1_000_000 / cpu_config.frequency_hz);
-        println!("Duration: {:?}", x);
+        let context = CoreContext {
+            instr_queue,
+            memory_subsystem: Rc::clone(&memory_subsystem),
+            arch_reg_file: Rc::clone(&arch_reg_file),
+            frontend_control,
+            perf_counters: Rc::clone(&perf_counters),
+            interrupt_controller: Rc::clone(&interrupt_controller),
+            btb: Rc::clone(&btb),
+            ras: Rc::clone(&ras),
+            plugins: Rc::clone(&plugins),
+        };
+        let backend = Backend::new(cpu_config, &context);
+        let frontend = Frontend::new(cpu_config, &context);
 
         CPU {
             backend,
@@ -136,36 +1175,636 @@ impl CPU {
             cycle_period: Duration::from_micros(1_000_000 / cpu_config.frequency_hz),
             trace: cpu_config.trace.clone(),
             perf_counters: Rc::clone(&perf_counters),
+            interrupt_controller,
+            timer: Timer::new(cpu_config.timer_period),
+            quiet: false,
+            throttled: true,
+            energy_config: if cpu_config.energy.enabled { Some(cpu_config.energy.clone()) } else { None },
+            jitter_seed: if cpu_config.jitter.enabled { Some(cpu_config.jitter.seed) } else { None },
+            plugins,
+            max_cycles: cpu_config.max_cycles,
+            skip_idle_cycles: cpu_config.skip_idle_cycles,
+            program_option: None,
+            arguments: Vec::new(),
+            memory_images: Vec::new(),
+            stack_base: cpu_config.initial_sp() as WordType,
+            stack_capacity: cpu_config.stack_capacity(),
+            btb,
+            ras,
+            fast_forward_config: if cpu_config.fast_forward.instructions > 0 { Some(cpu_config.clone()) } else { None },
         }
     }
 
-    pub(crate) fn run(&mut self, program: &Rc<Program>) {
+    pub fn perf_counters(&self) -> PerfCounters {
+        self.perf_counters.borrow().clone()
+    }
+
+    /// The part of the accesses to the cache level (0 is the L1) that missed so far; None when the
+    /// caches are disabled, the level doesn't exist or it wasn't accessed.
+    pub fn cache_miss_rate(&self, level: usize) -> Option<f64> {
+        self.memory_subsystem.borrow().caches.as_ref().and_then(|caches| caches.miss_rate(level))
+    }
+
+    /// The energy consumed so far; None when the energy model is disabled.
+    pub fn energy(&self) -> Option<EnergyReport> {
+        self.energy_config.as_ref()
+            .map(|energy_config| EnergyReport::new(energy_config, &self.perf_counters.borrow()))
+    }
+
+    /// Registers an observer that is notified of every load that is flushed after it executed.
+    pub fn add_wrong_path_observer(&mut self, observer: Box<dyn WrongPathObserver>) {
+        self.backend.wrong_path_observers.push(observer);
+    }
+
+    /// Registers a plugin that is notified of the decode, issue, execute, memory access and retire
+    /// of every instruction. Plugins are notified in the order they are registered.
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.borrow_mut().push(plugin);
+    }
+
+    /// Registers an invariant that is checked every time an instruction retires.
+    pub fn add_assertion(&mut self, assertion: Box<dyn RetirementAssertion>) {
+        self.backend.assertions.push(assertion);
+    }
+
+    /// The condition flags of the CPSR.
+    pub fn flags(&self) -> Flags {
+        Flags::from_cpsr(self.arch_reg_file.borrow().get_value(CPSR))
+    }
+
+    /// Sets the condition flags of the CPSR; e.g. to set up a test before the program runs.
+    pub fn set_flags(&mut self, flags: Flags) {
+        let mut arch_reg_file = self.arch_reg_file.borrow_mut();
+        let cpsr = arch_reg_file.get_value(CPSR);
+        arch_reg_file.set_value(CPSR, flags.apply_to(cpsr));
+    }
+
+    /// Takes a snapshot of the architectural state: the registers and the memory including the
+    /// committed stores that are still in the store buffer.
+    pub fn snapshot(&self, program: &Program) -> Snapshot {
+        let arch_reg_file = self.arch_reg_file.borrow();
+        let registers = (0..ARCH_REG_CNT)
+            .map(|reg| arch_reg_file.get_value(reg))
+            .collect();
+
+        Snapshot {
+            registers,
+            memory: self.memory_subsystem.borrow().architectural_memory(),
+            variables: program.symbols.variables()
+                .map(|(name, offset)| (String::from(name), offset))
+                .collect(),
+        }
+    }
+
+    /// What is in every part of the pipeline in the current cycle; e.g. for a GUI that polls it
+    /// after every step_cycle.
+    pub fn pipeline_state(&self) -> PipelineSnapshot {
+        let (fetch, decode) = self.frontend.pipeline_state();
+        let (issue_queue, execution_units, store_buffer, rob) = self.backend.pipeline_state();
+        PipelineSnapshot {
+            cycle: self.perf_counters.borrow().cycle_cnt,
+            pc: self.arch_reg_file.borrow().get_value(PC) as usize,
+            frontend_halted: self.frontend.is_halted(),
+            fetch,
+            decode,
+            issue_queue,
+            execution_units,
+            store_buffer,
+            rob,
+        }
+    }
+
+    /// The lines of output the program has written so far, e.g. using PRINTR.
+    pub fn output(&self) -> &[String] {
+        &self.backend.output
+    }
+
+    /// Sets the stream the program reads its input from using READC; the stdin of the host by default.
+    /// E.g. `cpu.set_stdin(input_buffer("y\n"))` feeds a scripted answer to an interactive program.
+    pub fn set_stdin(&mut self, input: Box<dyn Read>) {
+        self.backend.streams.set_input(input);
+    }
+
+    /// Sets the stream the program writes its output to, e.g. using PRINTR; the stdout of the host
+    /// by default. An OutputBuffer captures the output in memory.
+    pub fn set_stdout(&mut self, output: Box<dyn Write>) {
+        self.backend.streams.set_output(output);
+    }
+
+    /// Stops run from printing the cycle period and the summary at the end of the program, e.g. when
+    /// a test runner only reports the failures. The output of the program goes to set_stdout.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Stops every cycle from sleeping for the cycle period of the frequency_hz, so the simulation runs as
+    /// fast as the host allows; e.g. to benchmark the simulator itself (see benches/cycle_loop.rs).
+    pub fn set_throttled(&mut self, throttled: bool) {
+        self.throttled = throttled;
+    }
+
+    /// Sets the arguments the program is started with; they are applied when the program is loaded.
+    /// Like the AAPCS, the first 4 arguments are passed in r0..r3 and the remaining arguments on the
+    /// stack; the SP points to the 5th argument. Fails when the arguments don't fit on the stack.
+    pub fn set_arguments(&mut self, arguments: &[WordType]) -> Result<(), String> {
+        let stack_arg_cnt = arguments.len().saturating_sub(ARGUMENT_REGS.len());
+        if stack_arg_cnt > self.stack_capacity as usize {
+            return Err(format!("{} arguments are passed on the stack, but the stack capacity is {} words",
+                               stack_arg_cnt, self.stack_capacity));
+        }
+        self.arguments = arguments.to_vec();
+        Ok(())
+    }
+
+    /// Adds an image that is written to memory when the program is loaded, after the variables are
+    /// initialized; so it overwrites the variables it overlaps. Images are written in the order they
+    /// are added. Fails when the image doesn't fit in memory.
+    pub fn add_memory_image(&mut self, image: MemoryImage) -> Result<(), String> {
+        let memory_size = self.memory_subsystem.borrow().memory.len() as u64;
+        if image.offset + image.words.len() as u64 > memory_size {
+            return Err(format!("The memory image of {} words at offset {} doesn't fit in the memory of {} words",
+                               image.words.len(), image.offset, memory_size));
+        }
+        self.memory_images.push(image);
+        Ok(())
+    }
+
+    /// Reads a memory image file and adds it at the offset; see add_memory_image. A file with the `.bin`
+    /// extension is raw binary in the configured word size and endianness, any other file hex text.
+    pub fn load_memory_image(&mut self, path: &str, offset: u64) -> Result<(), Box<dyn Error>> {
+        let image = self.memory_subsystem.borrow().load_image(path, offset)?;
+        self.add_memory_image(image)?;
+        Ok(())
+    }
+
+    /// Writes the call graph measured so far in the callgrind format, so it can be visualized using
+    /// KCachegrind; `file` is the name of the program file. Fails when the call graph isn't enabled
+    /// (see `call_graph` in the config).
+    pub fn write_callgrind(&self, path: &str, file: &str) -> Result<(), Box<dyn Error>> {
+        let call_graph = self.backend.call_graph.as_ref()
+            .ok_or("The call graph isn't enabled; set call_graph in the config")?;
+        fs::write(path, call_graph.to_callgrind(file))?;
+        Ok(())
+    }
+
+    /// Writes the call stacks sampled so far as collapsed stacks, e.g. for flamegraph.pl. Fails when the
+    /// sampling profiler isn't enabled (see `sampling_period` in the config).
+    pub fn write_collapsed_stacks(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let sampling = self.backend.sampling.as_ref()
+            .ok_or("The sampling profiler isn't enabled; set sampling_period in the config")?;
+        fs::write(path, sampling.to_collapsed_stacks())?;
+        Ok(())
+    }
+
+    /// Writes the memory as seen by the program (including the committed stores in the store buffer)
+    /// to a file; the format follows from the extension like for load_memory_image.
+    pub fn dump_memory(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.memory_subsystem.borrow().dump(path)
+    }
+
+    /// The exit code of the program; the value of r0 when the program exited. None while the
+    /// program is running or when it ended with a fault.
+    pub fn exit_code(&self) -> Option<WordType> {
+        self.backend.exit_code
+    }
+
+    /// Runs the program till it exits and returns its exit code; see exit_code. Fails with a
+    /// diagnosis of where the CPU is stuck when the program doesn't exit within the max_cycles.
+    pub fn run_until_exit(&mut self, program: &Rc<Program>) -> Result<Option<WordType>, Box<HangDiagnosis>> {
+        self.run(program)?;
+        Ok(self.exit_code())
+    }
+
+    /// Runs the program till it exits. Fails with a diagnosis of where the CPU is stuck when the
+    /// program doesn't exit within the max_cycles of the configuration.
+    pub fn run(&mut self, program: &Rc<Program>) -> Result<(), Box<HangDiagnosis>> {
+        if !self.quiet {
+            println!("Duration: {:?}", self.cycle_period);
+        }
+        self.load(program);
+
+        while !self.is_exited() {
+            self.check_watchdog(program)?;
+            self.advance(self.watchdog_budget());
+        }
+
+        if !self.quiet {
+            self.report();
+        }
+        Ok(())
+    }
+
+    /// Loads the program, so it can be executed using step_cycle or step_instruction.
+    pub fn load(&mut self, program: &Rc<Program>) {
         self.frontend.init(program);
+        self.backend.init(program);
 
         self.memory_subsystem.borrow_mut().init(program);
+        for image in &self.memory_images {
+            self.memory_subsystem.borrow_mut().write_image(image);
+        }
+        self.init_arguments();
+        self.init_state(program);
+        self.program_option = Some(Rc::clone(program));
+
+        for plugin in self.plugins.borrow_mut().iter_mut() {
+            plugin.on_load(program);
+        }
+
+        if let Some(cpu_config) = self.fast_forward_config.take() {
+            self.fast_forward(program, &cpu_config);
+            self.fast_forward_config = Some(cpu_config);
+        }
+    }
+
+    // Executes the first instructions of the program on the reference interpreter and hands the
+    // architectural state over to the pipeline, which continues at the PC the interpreter ended at.
+    // Stops early at an instruction the interpreter can't execute.
+    fn fast_forward(&mut self, program: &Rc<Program>, cpu_config: &CPUConfig) {
+        let fast_forward = &cpu_config.fast_forward;
+        let mut interpreter = ReferenceInterpreter::new(cpu_config, program, fast_forward.instructions);
+        let registers = (0..ARCH_REG_CNT).map(|reg| self.arch_reg_file.borrow().get_value(reg)).collect();
+        interpreter.set_state(registers, self.memory_subsystem.borrow().memory.clone());
+
+        let mut fast_forward_cnt = 0;
+        let mut ended = false;
+        while fast_forward_cnt < fast_forward.instructions {
+            let instr = match interpreter.next_instr() {
+                // the counters, the interrupts and the input only exist in the pipeline
+                Some(instr) if matches!(instr.opcode, Opcode::MRS | Opcode::ERET | Opcode::READC) => break,
+                instr => instr,
+            };
+            let step = match interpreter.step() {
+                Ok(Some(step)) => step,
+                Ok(None) => {
+                    ended = true;
+                    break;
+                }
+                Err(fault) => {
+                    println!("{} while fast forwarding", fault);
+                    self.backend.exit = true;
+                    break;
+                }
+            };
+            fast_forward_cnt += 1;
+            let instr = instr.unwrap();
+
+            if instr.opcode == Opcode::PRINTR {
+                let arch_reg = instr.source[0].get_register();
+                let output = printr_output(arch_reg, interpreter.register(arch_reg), &program.register_aliases);
+                self.backend.streams.write_line(&output);
+                self.backend.output.push(output);
+            }
+            for (addr, kind) in &step.accesses {
+                if *kind == AccessKind::Store {
+                    self.backend.mark_stack_written(*addr, 1);
+                }
+                if fast_forward.warm_caches {
+                    self.memory_subsystem.borrow_mut().warm_up(step.pc, *addr, *kind);
+                }
+            }
+            if fast_forward.warm_btb && instr.is_control {
+                self.btb.borrow_mut().update(step.pc, step.next_pc);
+                let mut ras = self.ras.borrow_mut();
+                if instr.is_call() {
+                    ras.retire_call(step.pc + 1);
+                } else if instr.is_return() {
+                    ras.retire_return();
+                }
+                ras.recover();
+            }
+        }
 
-        while !self.backend.exit {
-            self.perf_counters.borrow_mut().cycle_cnt += 1;
-
-            if self.trace.cycle {
-                let perf_counters = self.perf_counters.borrow_mut();
-                println!("[Cycles:{}][Decoded={}][Issued={}][Dispatched={}][Executed={}][Retired={}][IPC={:.2}]",
-                         perf_counters.cycle_cnt,
-                         perf_counters.decode_cnt,
-                         perf_counters.issue_cnt,
-                         perf_counters.dispatch_cnt,
-                         perf_counters.execute_cnt,
-                         perf_counters.retire_cnt,
-                         perf_counters.retire_cnt as f32 / perf_counters.cycle_cnt as f32
-                );
-            }
-            self.memory_subsystem.borrow_mut().do_cycle();
-            self.backend.do_cycle();
-            self.frontend.do_cycle();
+        let (registers, memory, heap) = interpreter.into_state();
+        let mut arch_reg_file = self.arch_reg_file.borrow_mut();
+        for (reg, value) in registers.into_iter().enumerate() {
+            arch_reg_file.set_value(reg as RegisterType, value);
+        }
+        if ended {
+            // like returning from main, the exit code is passed in r0
+            self.backend.exit = true;
+            self.backend.exit_code = Some(arch_reg_file.get_value(0));
+        }
+
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        memory_subsystem.memory = memory;
+        memory_subsystem.restore_heap(heap);
+        if let Some(caches) = &mut memory_subsystem.caches {
+            caches.clear_stats();
+        }
+        self.perf_counters.borrow_mut().fast_forward_cnt = fast_forward_cnt;
+    }
+
+    // Passes the arguments in the argument registers and on the stack; so the program starts as if
+    // it was called.
+    fn init_arguments(&mut self) {
+        let mut arch_reg_file = self.arch_reg_file.borrow_mut();
+        for (reg, value) in ARGUMENT_REGS.iter().zip(&self.arguments) {
+            arch_reg_file.set_value(*reg, *value);
+        }
+
+        let stack_args = self.arguments.get(ARGUMENT_REGS.len()..).unwrap_or(&[]);
+        let sp = self.stack_base - stack_args.len() as WordType;
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        for (k, value) in stack_args.iter().enumerate() {
+            memory_subsystem.memory[sp as usize + k] = *value;
+        }
+        arch_reg_file.set_value(SP, sp);
+        self.backend.mark_stack_written(sp, stack_args.len());
+    }
+
+    // Sets the registers and memory words of the .reg and .mem directives; they take precedence over
+    // the arguments.
+    fn init_state(&mut self, program: &Program) {
+        let mut arch_reg_file = self.arch_reg_file.borrow_mut();
+        for (reg, value) in &program.initial_state.registers {
+            arch_reg_file.set_value(*reg, *value);
+        }
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        for (addr, value) in &program.initial_state.memory {
+            memory_subsystem.memory[*addr as usize] = *value;
+        }
+    }
+
+    /// If the loaded program has exited.
+    pub fn is_exited(&self) -> bool {
+        self.backend.exit
+    }
+
+    /// Stops retirement before the instruction at the code address retires; see run_for.
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.backend.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) {
+        self.backend.breakpoints.remove(&pc);
+    }
+
+    /// Reports every retiring instruction that triggers the watchpoint and stops run_for after it.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.backend.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the watchpoints with the expression.
+    pub fn remove_watchpoint(&mut self, expression: &str) {
+        self.backend.watchpoints.retain(|watchpoint| watchpoint.expression() != expression);
+    }
+
+    /// Executes at most the given number of cycles of the loaded program; so a host with an event
+    /// loop (a GUI, a game or an async server) can run the program in slices. It returns early when
+    /// the program exits, an instruction reaches a breakpoint or an instruction triggers a watchpoint; the
+    /// next call continues from there.
+    pub fn run_for(&mut self, cycles: u64) -> RunOutcome {
+        let program = Rc::clone(self.program_option.as_ref().expect("No program loaded"));
+
+        self.backend.resume();
+        let mut remaining = cycles;
+        while remaining > 0 {
+            if self.is_exited() {
+                break;
+            }
+            if let Err(diagnosis) = self.check_watchdog(&program) {
+                return RunOutcome::Hang(*diagnosis);
+            }
+            remaining -= self.advance(remaining.min(self.watchdog_budget()));
+            if let Some((pc, _)) = self.backend.breakpoint_hit {
+                return RunOutcome::Breakpoint(pc);
+            }
+            if let Some(hit) = self.backend.watchpoint_hit.take() {
+                return RunOutcome::Watchpoint(hit);
+            }
+        }
+
+        if self.is_exited() {
+            RunOutcome::Exited(self.exit_code())
+        } else {
+            RunOutcome::BudgetExhausted
+        }
+    }
+
+    /// Executes a single clock cycle of the loaded program; any number of instructions can retire.
+    pub fn step_cycle(&mut self) {
+        self.perf_counters.borrow_mut().cycle_cnt += 1;
+        self.trace_cycle();
+        let (coalesced_cnt, reordered_cnt) = self.memory_subsystem.borrow_mut().do_cycle();
+        self.perf_counters.borrow_mut().sb_coalesced_cnt += coalesced_cnt;
+        self.perf_counters.borrow_mut().sb_reordered_cnt += reordered_cnt;
+        self.timer.do_cycle(&mut self.interrupt_controller.borrow_mut());
+        self.backend.do_cycle();
+        self.frontend.do_cycle();
+        if self.throttled {
             thread::sleep(self.cycle_period);
         }
+    }
+
+    fn trace_cycle(&self) {
+        if self.trace.cycle {
+            let perf_counters = self.perf_counters.borrow();
+            println!("[Cycles:{}][Decoded={}][Issued={}][Dispatched={}][Executed={}][Retired={}][IPC={:.2}][LoadReplays={}]",
+                     perf_counters.cycle_cnt,
+                     perf_counters.decode_cnt,
+                     perf_counters.issue_cnt,
+                     perf_counters.dispatch_cnt,
+                     perf_counters.execute_cnt,
+                     perf_counters.retire_cnt,
+                     perf_counters.ipc(),
+                     perf_counters.load_replay_cnt
+            );
+        }
+    }
+
+    // Executes the next cycle; or when the whole pipeline waits, skips the cycles till the next event
+    // (an instruction that completes or a timer IRQ) at once. At most the budget of cycles is
+    // executed; returns the number of cycles executed.
+    fn advance(&mut self, budget: u64) -> u64 {
+        let idle_cycles = self.idle_cycles().min(budget);
+        if idle_cycles == 0 {
+            self.step_cycle();
+            return 1;
+        }
+
+        if self.trace.cycle {
+            for _ in 0..idle_cycles {
+                self.perf_counters.borrow_mut().cycle_cnt += 1;
+                self.trace_cycle();
+            }
+        } else {
+            self.perf_counters.borrow_mut().cycle_cnt += idle_cycles;
+        }
+        self.perf_counters.borrow_mut().skipped_cycle_cnt += idle_cycles;
+        self.timer.skip(idle_cycles);
+        self.backend.skip(idle_cycles);
+        if self.throttled {
+            thread::sleep(self.cycle_period.saturating_mul(u32::try_from(idle_cycles).unwrap_or(u32::MAX)));
+        }
+        idle_cycles
+    }
+
+    // The number of cycles before the next event in which nothing but the countdowns of the execution
+    // units and the timer change; 0 when the pipeline can make progress in the next cycle.
+    fn idle_cycles(&self) -> u64 {
+        if !self.skip_idle_cycles
+            || self.memory_subsystem.borrow().sb.has_committed()
+            || !self.frontend.is_quiescent()
+            || !self.backend.is_quiescent() {
+            return 0;
+        }
+
+        // without a next event, the pipeline is stuck; it is left to the watchdog
+        let next_event = match (self.backend.cycles_till_completion(), self.timer.cycles_till_irq()) {
+            (Some(completion), Some(irq)) => completion.min(irq),
+            (Some(cycles), None) | (None, Some(cycles)) => cycles,
+            (None, None) => return 0,
+        };
+        next_event - 1
+    }
+
+    // The number of cycles till the watchdog stops the program.
+    fn watchdog_budget(&self) -> u64 {
+        if self.max_cycles == 0 {
+            u64::MAX
+        } else {
+            self.max_cycles.saturating_sub(self.perf_counters.borrow().cycle_cnt)
+        }
+    }
+
+    /// Executes cycles of the loaded program till exactly one more instruction has retired; so the
+    /// architectural state advances by one instruction. While stepping, at most one instruction
+    /// retires per cycle. Returns false when the program has exited, or has stopped at a breakpoint,
+    /// before an instruction retired; the next step continues after the breakpoint.
+    pub fn step_instruction(&mut self) -> Result<bool, Box<HangDiagnosis>> {
+        let program = Rc::clone(self.program_option.as_ref().expect("No program loaded"));
+        let retire_cnt = self.perf_counters.borrow().retire_cnt;
+
+        self.backend.resume();
+        self.backend.single_retire = true;
+        while !self.is_exited() && self.backend.breakpoint_hit.is_none() && self.perf_counters.borrow().retire_cnt == retire_cnt {
+            if let Err(diagnosis) = self.check_watchdog(&program) {
+                self.backend.single_retire = false;
+                return Err(diagnosis);
+            }
+            self.advance(self.watchdog_budget());
+        }
+        self.backend.single_retire = false;
+
+        Ok(self.perf_counters.borrow().retire_cnt > retire_cnt)
+    }
+
+    /// Steps back the given number of retired instructions: the registers and memory words they wrote get
+    /// their values from before and the program continues at the oldest of them. The journal_depth of the
+    /// config bounds how far back it can go; the cycles, the counters and the output aren't taken back.
+    /// Returns the number of instructions stepped back; fewer than asked when the journal holds fewer.
+    pub fn step_back(&mut self, cnt: usize) -> usize {
+        self.backend.step_back(cnt)
+    }
+
+    /// The number of retired instructions step_back can undo.
+    pub fn journal_len(&self) -> usize {
+        self.backend.journal.as_ref().map_or(0, |journal| journal.len())
+    }
+
+    fn check_watchdog(&self, program: &Program) -> Result<(), Box<HangDiagnosis>> {
+        if self.max_cycles > 0 && self.perf_counters.borrow().cycle_cnt >= self.max_cycles {
+            return Err(Box::new(self.diagnose_hang(program)));
+        }
+        Ok(())
+    }
+
+    // Prints the summary and the enabled reports at the end of the program.
+    fn report(&self) {
+        println!("Program complete! Flags: {}", self.flags());
+
+        let perf_counters = self.perf_counters.borrow();
+        let stalls: Vec<String> = EU_TYPES.iter()
+            .map(|eu_type| format!("{}={}", eu_type, perf_counters.structural_stall_cnt[*eu_type as usize]))
+            .collect();
+        println!("Structural hazard stalls: {}", stalls.join(" "));
+
+        if let Some(stack_usage) = &self.backend.stack_usage {
+            println!("{}", stack_usage);
+        }
+
+        if let Some(ilp_study) = &self.backend.ilp_study {
+            println!("Real IPC={:.2}; {} ({:.0}% of the limit is reached)",
+                     perf_counters.ipc(), ilp_study, 100.0 * perf_counters.ipc() / ilp_study.ipc());
+        }
+
+        if let Some(wcet) = &self.backend.wcet {
+            print!("{}", wcet);
+        }
+
+        if let Some(stall_attribution) = &self.backend.stall_attribution {
+            print!("{}", stall_attribution);
+        }
+
+        if let Some(loop_profiler) = &self.backend.loop_profiler {
+            print!("{}", loop_profiler);
+        }
+
+        if let Some(call_graph) = &self.backend.call_graph {
+            print!("{}", call_graph);
+        }
+
+        if let Some(sampling) = &self.backend.sampling {
+            print!("{}", sampling);
+        }
+
+        if let Some(oracle) = &self.backend.oracle {
+            println!("Oracle: {} instructions matched the reference interpreter", oracle.checked_cnt());
+        }
+
+        if let Some(caches) = &self.memory_subsystem.borrow().caches {
+            print!("{}", caches);
+        }
+
+        if let Some(rs_occupancy) = &self.backend.rs_occupancy {
+            print!("{}", rs_occupancy);
+        }
+
+        if let Some(deadline_monitor) = &self.backend.deadline_monitor {
+            print!("{}", deadline_monitor);
+        }
+
+        if let Some(wrong_path_stats) = &self.backend.wrong_path_stats {
+            print!("{}", wrong_path_stats);
+        }
+
+        let memory_subsystem = self.memory_subsystem.borrow();
+        if !memory_subsystem.heap.is_empty() {
+            print!("{}", memory_subsystem.heap);
+        }
+
+        if let Some(energy_config) = &self.energy_config {
+            print!("{}", EnergyReport::new(energy_config, &perf_counters));
+        }
+
+        if let Some(exit_code) = self.exit_code() {
+            println!("Exit code: {}", exit_code);
+        }
+
+        if let Some(seed) = self.jitter_seed {
+            println!("Latency jitter seed: {}; use the same seed to replay the run", seed);
+        }
+    }
 
-        println!("Program complete!");
+    fn diagnose_hang(&self, program: &Program) -> HangDiagnosis {
+        let perf_counters = self.perf_counters.borrow();
+        let pc = self.arch_reg_file.borrow().get_value(PC) as usize;
+
+        let mut queues = self.frontend.occupancy();
+        queues.extend(self.backend.occupancy());
+
+        HangDiagnosis {
+            cycle_cnt: perf_counters.cycle_cnt,
+            retire_cnt: perf_counters.retire_cnt,
+            pc,
+            instr_at_pc: program.code.get(pc)
+                .map(|instr| instr.with_aliases(&program.register_aliases).to_string()),
+            line: program.line_for(pc),
+            frontend_halted: self.frontend.is_halted(),
+            queues,
+            oldest_unretired: self.backend.oldest_unretired(),
+        }
     }
 }
 
@@ -176,11 +1815,208 @@ pub const SP: u16 = 13;
 pub const LR: u16 = 14;
 pub const PC: u16 = 15;
 pub const CPSR: u16 = GENERAL_ARG_REG_CNT;
+// the floating point registers; S0-S31 (single precision) and D0-D31 (double precision) are separate registers.
+pub const FP_REG_CNT: u16 = 32;
+pub const S_REG_BASE: u16 = CPSR + 1;
+pub const D_REG_BASE: u16 = S_REG_BASE + FP_REG_CNT;
+// the floating point status register; FCMP sets its NZCV flags.
+pub const FPSCR: u16 = D_REG_BASE + FP_REG_CNT;
+// the vector registers V0-V7. Every lane is a separate register, so the lanes are renamed and forwarded
+// like scalar registers; a vector register is its lane 0 and only the first 'vector.lanes' lanes are used.
+pub const V_REG_CNT: u16 = 8;
+pub const MAX_VECTOR_LANES: u16 = 8;
+pub const V_REG_BASE: u16 = FPSCR + 1;
+pub const ARCH_REG_CNT: u16 = V_REG_BASE + V_REG_CNT * MAX_VECTOR_LANES;
+
+pub(crate) fn is_fp_register(reg: RegisterType) -> bool {
+    (S_REG_BASE..FPSCR).contains(&reg)
+}
+
+pub(crate) fn is_vector_register(reg: RegisterType) -> bool {
+    (V_REG_BASE..ARCH_REG_CNT).contains(&reg)
+}
+
+// The vector register (0-7) and the lane of a vector register lane.
+pub(crate) fn vector_lane(reg: RegisterType) -> (u16, u16) {
+    ((reg - V_REG_BASE) / MAX_VECTOR_LANES, (reg - V_REG_BASE) % MAX_VECTOR_LANES)
+}
+
+pub(crate) fn is_single_register(reg: RegisterType) -> bool {
+    (S_REG_BASE..D_REG_BASE).contains(&reg)
+}
+
+// Floating point registers contain the bits of an f64; also for the single precision registers.
+pub(crate) fn fp_value(bits: WordType) -> f64 {
+    f64::from_bits(bits as u64)
+}
+
+// Single precision results are rounded to f32 before they are written.
+pub(crate) fn fp_result(value: f64, single: bool) -> WordType {
+    let value = if single { value as f32 as f64 } else { value };
+    value.to_bits() as WordType
+}
+
+// Wraps an integer to a word of word_size bits: the bits above the word are replaced by the sign bit.
+// Registers always contain the sign extended value, so comparisons work for every word size.
+pub(crate) fn wrap_word(value: WordType, word_size: u8) -> WordType {
+    let shift = WordType::BITS - word_size as u32;
+    (value << shift) >> shift
+}
+
+// The result of a lane of VADD, VSUB or VMUL before it is wrapped to the lane width.
+pub(crate) fn vector_op(opcode: Opcode, a: WordType, b: WordType) -> WordType {
+    match opcode {
+        Opcode::VADD => a.wrapping_add(b),
+        Opcode::VSUB => a.wrapping_sub(b),
+        Opcode::VMUL => a.wrapping_mul(b),
+        _ => unreachable!(),
+    }
+}
+
+// The unsigned value of a (sign extended) word; e.g. to compute the carry.
+pub(crate) fn unsigned_word(value: WordType, word_size: u8) -> u64 {
+    (value as u64) & (u64::MAX >> (u64::BITS - word_size as u32))
+}
+
+// Adds two words and a carry like the ALU of ARM (AddWithCarry) and returns the result with the NZCV
+// flags. The carry is set when the unsigned sum doesn't fit in a word; the overflow when the signed sum
+// doesn't fit.
+pub(crate) fn add_with_carry(a: WordType, b: WordType, carry_in: bool, word_size: u8) -> (WordType, Flags) {
+    let unsigned_sum = unsigned_word(a, word_size) as u128 + unsigned_word(b, word_size) as u128 + carry_in as u128;
+    let signed_sum = wrap_word(a, word_size) as i128 + wrap_word(b, word_size) as i128 + carry_in as i128;
+    let result = wrap_word(unsigned_sum as WordType, word_size);
+    let flags = Flags {
+        n: result < 0,
+        z: result == 0,
+        c: unsigned_word(result, word_size) as u128 != unsigned_sum,
+        v: result as i128 != signed_sum,
+    };
+    (result, flags)
+}
+
+// The result and the flags of an addition or subtraction (ADC, SBC, ADDS, SUBS, ADCS, SBCS, CMP or CMN).
+// Like on ARM, rn - operand2 is computed as rn + !operand2 + 1; so after a subtraction the carry is
+// set when there is no borrow. The cpsr provides the carry of ADC and SBC.
+pub(crate) fn add_sub(opcode: Opcode, rn: WordType, operand2: WordType, cpsr: WordType, word_size: u8) -> (WordType, Flags) {
+    let carry = Flags::from_cpsr(cpsr).c;
+    match opcode {
+        Opcode::ADDS | Opcode::CMN => add_with_carry(rn, operand2, false, word_size),
+        Opcode::SUBS | Opcode::CMP => add_with_carry(rn, !operand2, true, word_size),
+        Opcode::ADC | Opcode::ADCS => add_with_carry(rn, operand2, carry, word_size),
+        Opcode::SBC | Opcode::SBCS => add_with_carry(rn, !operand2, carry, word_size),
+        _ => panic!("{:?} isn't an addition or subtraction", opcode),
+    }
+}
+
+// The flags of a TST: N and Z of rn AND operand2; C and V are left untouched.
+pub(crate) fn test_flags(rn: WordType, operand2: WordType, cpsr: WordType, word_size: u8) -> Flags {
+    let result = wrap_word(rn & operand2, word_size);
+    Flags { n: result < 0, z: result == 0, ..Flags::from_cpsr(cpsr) }
+}
+
+// If the condition of a conditional branch holds for the flags; e.g. BHI branches if the first
+// operand of the CMP was unsigned higher than the second.
+pub(crate) fn condition_holds(opcode: Opcode, flags: Flags) -> bool {
+    match opcode {
+        Opcode::BEQ => flags.z,
+        Opcode::BNE => !flags.z,
+        Opcode::BLT => flags.n != flags.v,
+        Opcode::BLE => flags.z || flags.n != flags.v,
+        Opcode::BGT => !flags.z && flags.n == flags.v,
+        Opcode::BGE => flags.n == flags.v,
+        Opcode::BHI => flags.c && !flags.z,
+        Opcode::BLS => !flags.c || flags.z,
+        Opcode::BHS => flags.c,
+        Opcode::BLO => !flags.c,
+        _ => panic!("{:?} isn't a conditional branch", opcode),
+    }
+}
+
+// The number of leading zero bits of a word (CLZ).
+pub(crate) fn leading_zeros(value: WordType, word_size: u8) -> WordType {
+    (unsigned_word(value, word_size).leading_zeros() - (u64::BITS - word_size as u32)) as WordType
+}
+
+// Reverses the bits of a word (RBIT).
+pub(crate) fn reverse_bits(value: WordType, word_size: u8) -> WordType {
+    let reversed = unsigned_word(value, word_size).reverse_bits() >> (u64::BITS - word_size as u32);
+    wrap_word(reversed as WordType, word_size)
+}
+
+// Extracts the field of width bits starting at bit lsb (UBFX, SBFX); a signed field is sign extended.
+pub(crate) fn extract_bits(value: WordType, lsb: u32, width: u32, signed: bool, word_size: u8) -> WordType {
+    let field = value << (WordType::BITS - lsb - width);
+    let extracted = if signed {
+        field >> (WordType::BITS - width)
+    } else {
+        ((field as u64) >> (u64::BITS - width)) as WordType
+    };
+    wrap_word(extracted, word_size)
+}
+
+// Replaces the field of width bits starting at bit lsb by the low bits of the source (BFI).
+pub(crate) fn insert_bits(value: WordType, source: WordType, lsb: u32, width: u32, word_size: u8) -> WordType {
+    let mask = (u64::MAX >> (u64::BITS - width)) << lsb;
+    let inserted = ((value as u64) & !mask) | (((source as u64) << lsb) & mask);
+    wrap_word(inserted as WordType, word_size)
+}
 
 pub const ZERO_FLAG_BIT_POSITION: u8 = 30;
 pub const NEGATIVE_FLAG_BIT_POSITION: u8 = 31;
 pub const CARRY_FLAG_BIT_POSITION: u8 = 29;
 pub const OVERFLOW_FLAG_BIT_POSITION: u8 = 28;
+// when set, interrupts are disabled
+pub const IRQ_MASK_BIT_POSITION: u8 = 7;
+
+/// A typed view on the NZCV condition flags of the CPSR (or the FPSCR).
+///
+/// Displayed like "N z C v": a set flag is uppercase, a clear flag lowercase.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub n: bool,
+    pub z: bool,
+    pub c: bool,
+    pub v: bool,
+}
+
+impl Flags {
+    pub fn from_cpsr(cpsr: WordType) -> Flags {
+        let flag = |bit_position: u8| cpsr & (1 << bit_position) != 0;
+        Flags {
+            n: flag(NEGATIVE_FLAG_BIT_POSITION),
+            z: flag(ZERO_FLAG_BIT_POSITION),
+            c: flag(CARRY_FLAG_BIT_POSITION),
+            v: flag(OVERFLOW_FLAG_BIT_POSITION),
+        }
+    }
+
+    /// Returns the CPSR with its flags replaced by these flags; the other bits are left untouched.
+    pub fn apply_to(&self, cpsr: WordType) -> WordType {
+        let flags = [
+            (NEGATIVE_FLAG_BIT_POSITION, self.n),
+            (ZERO_FLAG_BIT_POSITION, self.z),
+            (CARRY_FLAG_BIT_POSITION, self.c),
+            (OVERFLOW_FLAG_BIT_POSITION, self.v),
+        ];
+
+        let mut cpsr = cpsr;
+        for (bit_position, set) in flags {
+            if set {
+                cpsr |= 1 << bit_position;
+            } else {
+                cpsr &= !(1 << bit_position);
+            }
+        }
+        cpsr
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |set: bool, name: char| if set { name } else { name.to_ascii_lowercase() };
+        write!(f, "{} {} {} {}", flag(self.n, 'N'), flag(self.z, 'Z'), flag(self.c, 'C'), flag(self.v, 'V'))
+    }
+}
 
 struct ArgRegEntry {
     pub(crate) value: WordType,
@@ -209,3 +2045,69 @@ impl ArgRegFile {
         arch_reg.value = value;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The cpu.yaml of the first version of the simulator; the settings added since then are missing.
+    const FIRST_CONFIG: &str = "
+phys_reg_count: 64
+frontend_n_wide: 4
+instr_queue_capacity: 32
+frequency_hz: 10
+rs_count: 16
+memory_size: 128
+sb_capacity: 16
+lfb_count: 4
+rob_capacity: 64
+eu_count: 8
+trace:
+  decode: false
+  issue: false
+  dispatch: false
+  execute: false
+  retire: false
+  cycle: true
+retire_n_wide: 4
+dispatch_n_wide: 4
+issue_n_wide: 4
+stack_capacity: 256
+";
+
+    #[test]
+    fn missing_settings_are_defaulted() {
+        let config: CPUConfig = serde_yaml::from_str(FIRST_CONFIG).unwrap();
+        assert!(config.validate().is_empty(), "{:?}", config.validate());
+        assert_eq!(config.pipeline_mode, PipelineMode::OutOfOrder);
+        assert_eq!(config.btb_size, 0);
+        assert_eq!(config.max_cycles, 0);
+        // the SP starts at the end of the memory, like it did before the stack_base existed
+        assert_eq!(config.initial_sp(), 128);
+        assert_eq!(config.stack_capacity(), 64);
+    }
+
+    #[test]
+    fn empty_config_is_valid() {
+        let config: CPUConfig = serde_yaml::from_str("{}").unwrap();
+        assert!(config.validate().is_empty(), "{:?}", config.validate());
+        assert!(CPUConfig::default().validate().is_empty());
+    }
+
+    #[test]
+    fn nested_settings_are_defaulted() {
+        let config: CPUConfig = serde_yaml::from_str("caches:\n  enabled: true\nexecution_units:\n  alu:\n    count: 1\n    latency: 1\n    pipelined: true\n").unwrap();
+        assert!(config.caches.enabled);
+        assert_eq!(config.caches.levels.len(), 2);
+        assert_eq!(config.execution_units.alu.count, 1);
+        assert_eq!(config.execution_units.divider.latency, 12);
+    }
+
+    #[test]
+    fn impossible_settings_are_explained() {
+        let config = CPUConfig { sb_capacity: 0, issue_n_wide: 128, ..CPUConfig::default() };
+        let problems = config.validate();
+        assert!(problems.iter().any(|problem| problem.starts_with("'sb_capacity' must be larger than 0")), "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.starts_with("'issue_n_wide' 128 is larger than 'rob_capacity' 64")), "{:?}", problems);
+    }
+}
@@ -0,0 +1,46 @@
+/// A direct mapped branch target buffer.
+///
+/// Every taken control instruction records its target when it retires. When the frontend fetches a
+/// control instruction that hits in the BTB, it continues fetching from the recorded target instead
+/// of waiting for the instruction to retire. A control instruction that isn't taken is removed, so
+/// a hit always predicts taken.
+pub(crate) struct BranchTargetBuffer {
+    // (code address of the control instruction, target)
+    entries: Vec<Option<(usize, usize)>>,
+}
+
+impl BranchTargetBuffer {
+    // A BTB without entries never hits; so the frontend always waits for control instructions.
+    pub(crate) fn new(size: u16) -> BranchTargetBuffer {
+        BranchTargetBuffer { entries: vec![None; size as usize] }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    pub(crate) fn lookup(&self, pc: usize) -> Option<usize> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        match self.entries[pc % self.entries.len()] {
+            Some((entry_pc, target)) if entry_pc == pc => Some(target),
+            _ => None,
+        }
+    }
+
+    // Records the outcome of a retired control instruction.
+    pub(crate) fn update(&mut self, pc: usize, next_pc: usize) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let index = pc % self.entries.len();
+        if next_pc != pc + 1 {
+            self.entries[index] = Some((pc, next_pc));
+        } else if matches!(self.entries[index], Some((entry_pc, _)) if entry_pc == pc) {
+            self.entries[index] = None;
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::rc::Rc;
 
-use crate::cpu::{ArgRegFile, CPUConfig, PC, PerfCounters, Trace};
-use crate::instructions::instructions::{EXIT, InstrQueue, Opcode, Program, WordType};
+use crate::analysis::watchdog::Occupancy;
+use crate::cpu::{ArgRegFile, CoreContext, CPUConfig, PC, PerfCounters};
+use crate::frontend::btb::BranchTargetBuffer;
+use crate::frontend::ras::ReturnAddressStack;
+use crate::instructions::instructions::{encoded_size, FetchedInstr, InstrIndex, InstrQueue, Opcode, Program, WordType};
+use crate::plugin::plugin::{InstrEvent, Plugins};
+use crate::report::pipeline_state::PipelineInstr;
 
 pub(crate) struct FrontendControl {
     pub(crate) halted: bool,
+    // set by the backend after a pipeline flush; the code address to continue fetching from.
+    pub(crate) redirect: Option<usize>,
 }
 
 pub(crate) struct Frontend {
@@ -13,28 +21,49 @@ pub(crate) struct Frontend {
     n_wide: u8,
     frontend_control: Rc<RefCell<FrontendControl>>,
     program_option: Option<Rc<Program>>,
-    trace: Trace,
     exit: bool,
     perf_counters: Rc<RefCell<PerfCounters>>,
     arch_reg_file: Rc<RefCell<ArgRegFile>>,
+    btb: Rc<RefCell<BranchTargetBuffer>>,
+    ras: Rc<RefCell<ReturnAddressStack>>,
+    // the fetched instructions that still need to be decoded
+    fetch_queue: VecDeque<FetchedInstr>,
+    // 0 means that instructions are fetched directly into the InstrQueue
+    fetch_queue_capacity: u16,
+    // the number of bytes fetched per cycle; 0 means only n_wide limits the fetch
+    fetch_bytes: u16,
+    // the cycles an instruction spends in the fetch stages and in the decode stages
+    fetch_stages: u8,
+    decode_stages: u8,
+    // the decoded instructions that are still in the decode stages (or, without a fetch queue, in the fetch
+    // stages), with the cycle they enter the InstrQueue
+    decode_pipe: VecDeque<(u64, FetchedInstr)>,
+    // the sequence number of the next fetched instruction
+    fetch_seq: u64,
+    plugins: Plugins,
 }
 
 impl Frontend {
-    pub(crate) fn new(cpu_config: &CPUConfig,
-                      instr_queue: Rc<RefCell<InstrQueue>>,
-                      frontend_control: Rc<RefCell<FrontendControl>>,
-                      perf_counters: Rc<RefCell<PerfCounters>>,
-                      arch_reg_file: Rc<RefCell<ArgRegFile>>,
-    ) -> Frontend {
+    pub(crate) fn new(cpu_config: &CPUConfig, context: &CoreContext) -> Frontend {
+        let CoreContext { instr_queue, arch_reg_file, frontend_control, perf_counters, btb, ras, plugins, .. } = context.clone();
         Frontend {
             instr_queue,
-            n_wide: cpu_config.frontend_n_wide,
+            n_wide: cpu_config.n_wide(cpu_config.frontend_n_wide),
             program_option: None,
-            trace: cpu_config.trace.clone(),
             frontend_control,
             exit: false,
             perf_counters,
             arch_reg_file,
+            btb,
+            ras,
+            fetch_queue: VecDeque::new(),
+            fetch_queue_capacity: cpu_config.fetch_queue_capacity,
+            fetch_bytes: cpu_config.fetch_bytes,
+            fetch_stages: cpu_config.fetch_stages,
+            decode_stages: cpu_config.decode_stages,
+            decode_pipe: VecDeque::new(),
+            fetch_seq: 0,
+            plugins,
         }
     }
 
@@ -43,6 +72,77 @@ impl Frontend {
         self.arch_reg_file.borrow_mut().set_value(PC, program.entry_point as WordType);
     }
 
+    // If fetching waits till a control instruction without a BTB prediction is executed.
+    pub(crate) fn is_halted(&self) -> bool {
+        self.frontend_control.borrow().halted
+    }
+
+    // The occupancy of the fetch queue (if there is one) and the InstrQueue.
+    pub(crate) fn occupancy(&self) -> Vec<Occupancy> {
+        let mut occupancy = Vec::new();
+        if self.fetch_queue_capacity > 0 {
+            occupancy.push(Occupancy { name: "fetch queue", size: self.fetch_queue.len() as u16, capacity: self.fetch_queue_capacity });
+        }
+        let instr_queue = self.instr_queue.borrow();
+        occupancy.push(Occupancy { name: "instr queue", size: instr_queue.size(), capacity: instr_queue.capacity() });
+        occupancy
+    }
+
+    // The instructions waiting to be decoded and the decoded instructions waiting to be issued.
+    pub(crate) fn pipeline_state(&self) -> (Vec<PipelineInstr>, Vec<PipelineInstr>) {
+        let Some(program) = &self.program_option else {
+            return (Vec::new(), Vec::new());
+        };
+        let describe = |fetched: &FetchedInstr| PipelineInstr::new(program, fetched.index, fetched.pc, fetched.seq);
+        let fetch = self.fetch_queue.iter().chain(self.decode_pipe.iter().map(|(_, fetched)| fetched)).map(describe).collect();
+        let decode = self.instr_queue.borrow().entries().iter().map(describe).collect();
+        (fetch, decode)
+    }
+
+    // If the frontend can't make progress till the backend does: nothing can be decoded or fetched.
+    pub(crate) fn is_quiescent(&self) -> bool {
+        if self.program_option.is_none() {
+            return true;
+        }
+
+        let instr_queue = self.instr_queue.borrow();
+        let frontend_control = self.frontend_control.borrow();
+        if frontend_control.redirect.is_some() || !self.decode_pipe.is_empty()
+            || (!self.fetch_queue.is_empty() && !self.is_decode_target_full(&instr_queue)) {
+            return false;
+        }
+        frontend_control.halted || self.exit || self.is_fetch_target_full(&instr_queue)
+    }
+
+    // Without a fetch queue, the instructions are fetched directly into the InstrQueue. Every fetch stage
+    // after the first holds the instructions of a cycle.
+    fn is_fetch_target_full(&self, instr_queue: &InstrQueue) -> bool {
+        if self.fetch_queue_capacity == 0 {
+            self.is_decode_target_full(instr_queue)
+        } else {
+            self.fetch_queue.len() >= self.fetch_queue_capacity as usize + (self.fetch_stages as usize - 1) * self.n_wide as usize
+        }
+    }
+
+    // If no instruction can be decoded: the InstrQueue and the decode stages after the first are full.
+    fn is_decode_target_full(&self, instr_queue: &InstrQueue) -> bool {
+        let extra_stages = if self.fetch_queue_capacity == 0 {
+            self.fetch_stages as usize + self.decode_stages as usize - 2
+        } else {
+            self.decode_stages as usize - 1
+        };
+        instr_queue.size() as usize + self.decode_pipe.len() >= instr_queue.capacity() as usize + extra_stages * self.n_wide as usize
+    }
+
+    // Passes a decoded instruction on to the InstrQueue; it leaves the last decode stage in the cycle `ready`.
+    fn pass_decoded(decode_pipe: &mut VecDeque<(u64, FetchedInstr)>, instr_queue: &mut InstrQueue, fetched: FetchedInstr, ready: u64, cycle: u64) {
+        if ready <= cycle && decode_pipe.is_empty() && !instr_queue.is_full() {
+            instr_queue.enqueue(fetched);
+        } else {
+            decode_pipe.push_back((ready, fetched));
+        }
+    }
+
     pub(crate) fn do_cycle(&mut self) {
         match &self.program_option {
             None => return,
@@ -51,30 +151,71 @@ impl Frontend {
                 let mut frontend_control = self.frontend_control.borrow_mut();
                 let mut perf_counters = self.perf_counters.borrow_mut();
                 let mut arch_reg_file = self.arch_reg_file.borrow_mut();
+                let btb = self.btb.borrow();
+                let mut ras = self.ras.borrow_mut();
+
+                let cycle = perf_counters.cycle_cnt;
+                if let Some(pc) = frontend_control.redirect.take() {
+                    self.exit = false;
+                    self.fetch_queue.clear();
+                    self.decode_pipe.clear();
+                    ras.recover();
+                    arch_reg_file.set_value(PC, pc as WordType);
+                }
+
+                // the instructions that leave the last decode stage
+                while let Some((ready, _)) = self.decode_pipe.front() {
+                    if *ready > cycle || instr_queue.is_full() {
+                        break;
+                    }
+                    instr_queue.enqueue(self.decode_pipe.pop_front().unwrap().1);
+                }
+
+                // decode: move the fetched instructions that left the fetch stages to the InstrQueue.
+                for _ in 0..self.n_wide {
+                    match self.fetch_queue.front() {
+                        Some(fetched) if fetched.fetch_cycle + self.fetch_stages as u64 <= cycle => {}
+                        _ => break,
+                    }
+                    if self.is_decode_target_full(&instr_queue) {
+                        break;
+                    }
+
+                    let fetched = self.fetch_queue.pop_front().unwrap();
+                    Self::notify_decode(&self.plugins, program, &fetched, cycle);
+                    Self::pass_decoded(&mut self.decode_pipe, &mut instr_queue, fetched, cycle + self.decode_stages as u64 - 1, cycle);
+                    perf_counters.decode_cnt += 1;
+                }
 
                 if frontend_control.halted {
                     return;
                 }
 
+                // the bytes fetched this cycle; Thumb code fits more instructions in the fetch width
+                let mut fetched_bytes = 0;
                 for _ in 0..self.n_wide {
                     if self.exit {
                         return;
                     }
 
-                    if instr_queue.is_full() {
+                    if self.is_fetch_target_full(&instr_queue) {
                         break;
                     }
 
                     let pc_value = arch_reg_file.get_value(PC) as usize;
-                    let instr = if program.code.len() == pc_value {
-                        // at the end of the program
-                         Rc::new(EXIT)
-                    }else{
-                        program.get_instr(pc_value)
-                    };
+                    // at the end of the program, this is the implicit EXIT
+                    let index = pc_value as InstrIndex;
+                    let instr = program.instr(index);
+
+                    let size = encoded_size(instr) as u16;
+                    if self.fetch_bytes > 0 && fetched_bytes + size > self.fetch_bytes {
+                        break;
+                    }
+                    fetched_bytes += size;
 
-                    if self.trace.decode {
-                        println!("Frontend: ip_next_fetch: {} decoded {}", pc_value, instr);
+                    let event = InstrEvent { instr, seq: self.fetch_seq, pc: pc_value, cycle: perf_counters.cycle_cnt, results: &[] };
+                    for plugin in self.plugins.borrow_mut().iter_mut() {
+                        plugin.on_fetch(&event);
                     }
 
                     if instr.opcode == Opcode::EXIT {
@@ -82,20 +223,60 @@ impl Frontend {
                     }
 
                     let is_control = instr.is_control;
+                    let mut ras_predicted = false;
+                    let predicted_pc = if !is_control {
+                        None
+                    } else if instr.is_return() && ras.is_enabled() {
+                        perf_counters.ras_lookup_cnt += 1;
+                        let predicted_pc = ras.pop();
+                        if predicted_pc.is_some() {
+                            perf_counters.ras_hit_cnt += 1;
+                            ras_predicted = true;
+                        }
+                        predicted_pc
+                    } else {
+                        perf_counters.btb_lookup_cnt += 1;
+                        let predicted_pc = btb.lookup(pc_value);
+                        if predicted_pc.is_some() {
+                            perf_counters.btb_hit_cnt += 1;
+                        }
+                        predicted_pc
+                    };
+                    if instr.is_call() {
+                        ras.push(pc_value + 1);
+                    }
 
-                    // todo: what about cloning?
-                    instr_queue.enqueue(instr);
+                    let fetched = FetchedInstr { index, pc: pc_value, predicted_pc, ras_predicted, fetch_cycle: perf_counters.cycle_cnt, seq: self.fetch_seq };
+                    self.fetch_seq += 1;
+                    if self.fetch_queue_capacity == 0 {
+                        Self::notify_decode(&self.plugins, program, &fetched, perf_counters.cycle_cnt);
+                        let ready = cycle + self.fetch_stages as u64 + self.decode_stages as u64 - 2;
+                        Self::pass_decoded(&mut self.decode_pipe, &mut instr_queue, fetched, ready, cycle);
+                        perf_counters.decode_cnt += 1;
+                    } else {
+                        self.fetch_queue.push_back(fetched);
+                    }
 
-                    // move the PC to the next instruction.
-                    arch_reg_file.set_value(PC, (pc_value + 1) as WordType);
-                    perf_counters.decode_cnt += 1;
+                    // move the PC to the next instruction; or the predicted target.
+                    let next_pc = predicted_pc.unwrap_or(pc_value + 1);
+                    arch_reg_file.set_value(PC, next_pc as WordType);
 
                     if is_control {
-                        frontend_control.halted = true;
+                        if predicted_pc.is_none() {
+                            frontend_control.halted = true;
+                        }
+                        // a control instruction ends the fetch block
                         return;
                     }
                 }
             }
         }
     }
+
+    fn notify_decode(plugins: &Plugins, program: &Program, fetched: &FetchedInstr, cycle: u64) {
+        let event = InstrEvent { instr: program.instr(fetched.index), seq: fetched.seq, pc: fetched.pc, cycle, results: &[] };
+        for plugin in plugins.borrow_mut().iter_mut() {
+            plugin.on_decode(&event);
+        }
+    }
 }
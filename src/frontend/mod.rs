@@ -1 +1,3 @@
-pub mod frontend;
\ No newline at end of file
+pub mod frontend;
+pub mod btb;
+pub mod ras;
@@ -0,0 +1,60 @@
+/// A return address stack.
+///
+/// When the frontend fetches a call (BL, BLX) it pushes the return address; when it fetches a return
+/// (BX LR or a POP of the PC) it pops the predicted target. Unlike the BTB, this predicts the right
+/// target for a function that is called from different places.
+///
+/// The frontend updates the stack speculatively, so calls and returns on a wrong path corrupt it. A
+/// second stack is updated when calls and returns retire; after a pipeline flush the speculative stack
+/// is restored from it. When a stack is full, a push overwrites the oldest entry.
+pub(crate) struct ReturnAddressStack {
+    depth: u16,
+    speculative: Vec<usize>,
+    retired: Vec<usize>,
+}
+
+impl ReturnAddressStack {
+    // A stack with depth 0 never predicts; returns are then predicted by the BTB.
+    pub(crate) fn new(depth: u16) -> ReturnAddressStack {
+        ReturnAddressStack { depth, speculative: Vec::new(), retired: Vec::new() }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    // A call was fetched.
+    pub(crate) fn push(&mut self, return_addr: usize) {
+        Self::push_on(&mut self.speculative, self.depth, return_addr);
+    }
+
+    // A return was fetched; None when the stack is empty.
+    pub(crate) fn pop(&mut self) -> Option<usize> {
+        self.speculative.pop()
+    }
+
+    // A call retired.
+    pub(crate) fn retire_call(&mut self, return_addr: usize) {
+        Self::push_on(&mut self.retired, self.depth, return_addr);
+    }
+
+    // A return retired.
+    pub(crate) fn retire_return(&mut self) {
+        self.retired.pop();
+    }
+
+    // The instructions that haven't retired were flushed.
+    pub(crate) fn recover(&mut self) {
+        self.speculative.clone_from(&self.retired);
+    }
+
+    fn push_on(stack: &mut Vec<usize>, depth: u16, return_addr: usize) {
+        if depth == 0 {
+            return;
+        }
+        if stack.len() == depth as usize {
+            stack.remove(0);
+        }
+        stack.push(return_addr);
+    }
+}
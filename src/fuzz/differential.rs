@@ -0,0 +1,65 @@
+use std::rc::Rc;
+
+use crate::cpu::{CPU, CPUConfig};
+use crate::fuzz::program_generator::ProgramGenerator;
+use crate::instructions::instructions::Program;
+use crate::reference_interpreter::reference_interpreter::ReferenceInterpreter;
+use crate::testgen::generator::{TestGenerator, TestGenOptions};
+
+// the generated programs only branch forward, so they never execute more instructions than this.
+const MAX_STEPS: u64 = 1_000_000;
+
+/// A generated program for which the CPU and the reference interpreter disagree.
+pub struct Mismatch {
+    pub seed: u64,
+    pub program: Rc<Program>,
+    pub reason: String,
+}
+
+/// Generates a program from the seed, runs it on both the pipelined CPU and the reference
+/// interpreter and compares the final architectural state.
+pub fn run_differential(cpu_config: &CPUConfig, seed: u64, length: usize) -> Result<(), Mismatch> {
+    let mut cpu_config = cpu_config.clone();
+    cpu_config.set_trace(false);
+
+    let program = Rc::new(ProgramGenerator::new(&cpu_config, seed).generate(length));
+    compare(&cpu_config, seed, program, MAX_STEPS)
+}
+
+/// Like run_differential, but for a stress test program of the TestGenerator; so with loops,
+/// PUSH/POP pairs and heap accesses.
+pub fn run_stress_differential(cpu_config: &CPUConfig, seed: u64, options: &TestGenOptions) -> Result<(), Mismatch> {
+    let mut cpu_config = cpu_config.clone();
+    cpu_config.set_trace(false);
+
+    let generator = TestGenerator::new(&cpu_config, seed, options.clone());
+    let max_steps = generator.max_steps();
+    let program = Rc::new(generator.generate());
+    compare(&cpu_config, seed, program, max_steps)
+}
+
+fn compare(cpu_config: &CPUConfig, seed: u64, program: Rc<Program>, max_steps: u64) -> Result<(), Mismatch> {
+    let reference_result = ReferenceInterpreter::new(cpu_config, &program, max_steps).run();
+    let expected = match reference_result {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            let reason = format!("The reference interpreter failed: {}", error);
+            return Err(Mismatch { seed, program, reason });
+        }
+    };
+
+    let mut cpu = CPU::new(cpu_config);
+    // don't throttle the CPU to the configured frequency
+    cpu.set_throttled(false);
+    if let Err(diagnosis) = cpu.run(&program) {
+        return Err(Mismatch { seed, program, reason: diagnosis.to_string() });
+    }
+    let actual = cpu.snapshot(&program);
+
+    let diff = expected.diff(&actual);
+    if diff.is_empty() {
+        return Ok(());
+    }
+
+    Err(Mismatch { seed, program, reason: diff.to_string() })
+}
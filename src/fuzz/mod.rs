@@ -0,0 +1,2 @@
+pub mod program_generator;
+pub mod differential;
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::cpu::CPUConfig;
+use crate::instructions::custom::CustomInstructions;
+use crate::instructions::instructions::{apply_indexing, create_instr, Data, eu_type, Indexing, InitialState, Instr, Opcode, Operand, Program, RegisterType, SourceLocation, WordType};
+use crate::instructions::microcode::decode_micro_ops;
+use crate::instructions::symbol_table::SymbolTable;
+use crate::random::random::Random;
+
+// the registers the generated instructions operate on
+const WORK_REG_CNT: u64 = 6;
+// contains the address of the variable that is loaded or stored
+const ADDR_REG: RegisterType = 6;
+// contains MASK; every arithmetic result is masked so that values can't overflow
+const MASK_REG: RegisterType = 7;
+const MASK: WordType = 0xFFFF;
+const VARIABLE_CNT: usize = 4;
+// the maximum number of random instructions a forward branch skips
+const MAX_BRANCH_SKIP: u64 = 4;
+// the maximum number of instructions emitted per random instruction
+const MAX_INSTRS_PER_STEP: usize = 2;
+
+/// Generates random, but deterministic, programs for differential testing.
+///
+/// The programs use arithmetic, loads and stores to a few variables, compares and forward branches.
+/// Since branches only go forward, every generated program terminates.
+pub struct ProgramGenerator<'a> {
+    cpu_config: &'a CPUConfig,
+    random: Random,
+    code: Vec<Instr>,
+    // the code address of the first instruction emitted per random instruction; a branch only targets
+    // these, so it can't separate the address load of an access from the access itself
+    steps: Vec<usize>,
+    // (code address of the branch, index in steps of the target)
+    branches: Vec<(usize, usize)>,
+}
+
+impl<'a> ProgramGenerator<'a> {
+    pub fn new(cpu_config: &'a CPUConfig, seed: u64) -> ProgramGenerator<'a> {
+        ProgramGenerator {
+            cpu_config,
+            random: Random::new(seed),
+            code: Vec::new(),
+            steps: Vec::new(),
+            branches: Vec::new(),
+        }
+    }
+
+    /// Generates a program with the given number of random instructions (besides the setup code).
+    pub fn generate(mut self, length: usize) -> Program {
+        // the variables are laid out like the loader does, so the redzones are respected. The code
+        // occupies the memory from the code base, so the variables are placed after the largest possible code.
+        let redzone_size = self.cpu_config.redzone_size as u64;
+        let mut data_items = HashMap::new();
+        let mut variables = Vec::new();
+        let mut heap_limit = self.cpu_config.code_base() as u64 + (2 + WORK_REG_CNT as usize + MAX_INSTRS_PER_STEP * length) as u64;
+        for k in 0..VARIABLE_CNT {
+            heap_limit += redzone_size;
+            let data = Data { value: self.random.below(100) as WordType, offset: heap_limit };
+            variables.push(data.offset as WordType);
+            data_items.insert(format!("v{}", k), Rc::new(data));
+            heap_limit += 1;
+        }
+
+        self.emit(Opcode::MOV, vec![Operand::Register(MASK_REG), Operand::Immediate(MASK)]);
+        // the address register always points to a variable, also when a branch skips an address load
+        self.emit(Opcode::LDR, vec![Operand::Register(ADDR_REG), Operand::Immediate(variables[0])]);
+        for reg in 0..WORK_REG_CNT {
+            let value = self.random.below(100) as WordType;
+            self.emit(Opcode::MOV, vec![Operand::Register(reg as RegisterType), Operand::Immediate(value)]);
+        }
+
+        for _ in 0..length {
+            self.steps.push(self.code.len());
+            self.generate_instr(&variables);
+        }
+
+        // branches that skip past the end jump to the end of the program
+        let end = self.code.len();
+        for (branch, step) in std::mem::take(&mut self.branches) {
+            let target = self.steps.get(step).copied().unwrap_or(end);
+            self.patch_branch(branch, target);
+        }
+
+        let symbols = SymbolTable::new(&HashMap::new(), &data_items, &self.code);
+        let micro_ops = decode_micro_ops(&self.code, &self.cpu_config.execution_units);
+        Program {
+            data_items,
+            code: self.code,
+            entry_point: 0,
+            register_aliases: self.cpu_config.abi().register_names(),
+            code_base: self.cpu_config.code_base() as WordType,
+            abi: self.cpu_config.abi(),
+            symbols,
+            custom_instructions: CustomInstructions::new(),
+            deadlines: Vec::new(),
+            irq_handler: None,
+            loop_bounds: HashMap::new(),
+            expectations: Vec::new(),
+            initial_state: InitialState::default(),
+            warnings: Vec::new(),
+            micro_ops,
+        }
+    }
+
+    fn generate_instr(&mut self, variables: &[WordType]) {
+        let rd = self.work_reg();
+        let rn = self.work_reg();
+        match self.random.below(8) {
+            0 | 1 => {
+                let opcode = [Opcode::ADD, Opcode::SUB, Opcode::MUL, Opcode::AND, Opcode::ORR, Opcode::EOR][self.random.below(6) as usize];
+                let operand2 = if opcode != Opcode::MUL && self.random.below(2) == 0 {
+                    Operand::Immediate(self.random.below(100) as WordType)
+                } else {
+                    self.work_reg()
+                };
+                self.emit(opcode, vec![rd, rn, operand2]);
+                self.emit(Opcode::AND, vec![rd, rd, Operand::Register(MASK_REG)]);
+            }
+            2 => {
+                let opcode = if self.random.below(2) == 0 { Opcode::NEG } else { Opcode::MVN };
+                self.emit(opcode, vec![rd, rn]);
+                self.emit(Opcode::AND, vec![rd, rd, Operand::Register(MASK_REG)]);
+            }
+            3 => {
+                let source = if self.random.below(2) == 0 {
+                    Operand::Immediate(self.random.below(100) as WordType)
+                } else {
+                    rn
+                };
+                self.emit(Opcode::MOV, vec![rd, source]);
+            }
+            4 | 5 => {
+                let variable = variables[self.random.below(variables.len() as u64) as usize];
+                self.emit(Opcode::LDR, vec![Operand::Register(ADDR_REG), Operand::Immediate(variable)]);
+                // a post-indexed access also moves the address register; it is reloaded before the next access
+                let offset = (self.random.below(2) == 0).then(|| Operand::Immediate(self.random.below(4) as WordType));
+                if self.random.below(2) == 0 {
+                    self.emit_access(Opcode::LDR, vec![rd, Operand::Register(ADDR_REG)], offset);
+                } else {
+                    self.emit_access(Opcode::STR, vec![rn, Operand::Register(ADDR_REG)], offset);
+                }
+            }
+            6 => {
+                self.emit(Opcode::CMP, vec![rd, rn]);
+            }
+            _ => {
+                let branch = self.code.len();
+                match self.random.below(3) {
+                    0 => self.emit(Opcode::B, vec![Operand::Code(0)]),
+                    1 => self.emit(Opcode::CBZ, vec![rn, Operand::Code(0)]),
+                    _ => self.emit(Opcode::CBNZ, vec![rn, Operand::Code(0)]),
+                }
+                let skip = self.random.below(MAX_BRANCH_SKIP + 1) as usize;
+                self.branches.push((branch, self.steps.len() + skip));
+            }
+        }
+    }
+
+    fn work_reg(&mut self) -> Operand {
+        Operand::Register(self.random.below(WORK_REG_CNT) as RegisterType)
+    }
+
+    fn emit(&mut self, opcode: Opcode, operands: Vec<Operand>) {
+        let loc = SourceLocation { line: self.code.len() + 1, column: 1 };
+        let mut instr = create_instr(opcode, &operands, loc)
+            .unwrap_or_else(|error| panic!("Generated an invalid instruction: {}", error));
+        instr.cycles = self.cpu_config.execution_units.get(eu_type(opcode)).latency;
+        self.code.push(instr);
+    }
+
+    // A load or store; with an offset it is post-indexed, e.g. `LDR r0, [r10], #1`.
+    fn emit_access(&mut self, opcode: Opcode, mut operands: Vec<Operand>, offset: Option<Operand>) {
+        let Some(offset) = offset else {
+            return self.emit(opcode, operands);
+        };
+        operands.push(offset);
+        self.emit(opcode, operands);
+        let instr = self.code.last_mut().unwrap();
+        apply_indexing(instr, Indexing::PostIndexed)
+            .unwrap_or_else(|error| panic!("Generated an invalid instruction: {}", error));
+    }
+
+    fn patch_branch(&mut self, branch: usize, target: usize) {
+        let instr = &mut self.code[branch];
+        for source in instr.source.iter_mut() {
+            if let Operand::Code(_) = source {
+                *source = Operand::Code(target as WordType);
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::io::{Cursor, Read, Write};
+use std::rc::Rc;
+
+/// The streams the program reads its input from (READC) and writes its output to (PRINTR).
+///
+/// By default these are the stdin and stdout of the host. A test can capture the output in an
+/// OutputBuffer and an interactive program can be fed scripted input; see CPU::set_stdin and
+/// CPU::set_stdout.
+pub(crate) struct GuestStreams {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    // the next byte of the input once it has been read, but not consumed; None at the end of the input
+    lookahead: Option<Option<u8>>,
+}
+
+impl GuestStreams {
+    pub(crate) fn new() -> GuestStreams {
+        GuestStreams { input: stdin(), output: stdout(), lookahead: None }
+    }
+
+    pub(crate) fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = input;
+        self.lookahead = None;
+    }
+
+    pub(crate) fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // Writes a line of output; like println, a failing stream is fatal.
+    pub(crate) fn write_line(&mut self, line: &str) {
+        writeln!(self.output, "{}", line)
+            .and_then(|_| self.output.flush())
+            .unwrap_or_else(|error| panic!("Failed to write the output of the program: {}", error));
+    }
+
+    // The next byte of the input without consuming it, so an instruction that is flushed after it
+    // executed doesn't lose input; None at the end of the input.
+    pub(crate) fn peek_byte(&mut self) -> Option<u8> {
+        if self.lookahead.is_none() {
+            let mut byte = [0u8; 1];
+            let next = match self.input.read(&mut byte) {
+                Ok(1) => Some(byte[0]),
+                Ok(_) => None,
+                Err(error) => panic!("Failed to read the input of the program: {}", error),
+            };
+            self.lookahead = Some(next);
+        }
+        self.lookahead.unwrap()
+    }
+
+    // Consumes the byte returned by peek_byte; the end of the input is never consumed.
+    pub(crate) fn consume_byte(&mut self) {
+        if let Some(Some(_)) = self.lookahead {
+            self.lookahead = None;
+        }
+    }
+}
+
+/// The stdin of the host; the default input of the program.
+pub fn stdin() -> Box<dyn Read> {
+    Box::new(std::io::stdin())
+}
+
+/// The stdout of the host; the default output of the program.
+pub fn stdout() -> Box<dyn Write> {
+    Box::new(std::io::stdout())
+}
+
+/// Scripted input; the program reads the bytes of the text and then the end of the input.
+pub fn input_buffer(text: &str) -> Box<dyn Read> {
+    Box::new(Cursor::new(text.as_bytes().to_vec()))
+}
+
+/// Captures the output of the program in memory. The buffer is shared by its clones, so a clone
+/// can be passed to CPU::set_stdout and the output read from the original.
+#[derive(Clone, Default)]
+pub struct OutputBuffer {
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    /// The output written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.borrow()).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
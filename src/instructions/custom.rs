@@ -0,0 +1,136 @@
+use std::rc::Rc;
+
+use crate::instructions::instructions::{get_opcode, Indexing, Instr, is_control, MAX_SINK_COUNT, MAX_SOURCE_COUNT, Opcode, Operand, SourceLocation, validate_operand, validate_operand_count, WordType};
+use crate::instructions::instructions::Operand::{Immediate, Register, Unused};
+use crate::instructions::microcode::{MAX_UOP_SINK_COUNT, MAX_UOP_SOURCE_COUNT};
+
+/// The operands of a custom instruction.
+///
+/// The sinks are the first operands of the instruction and are always registers. The sources
+/// follow the sinks; for every source the acceptable operand types are listed. E.g. a multiply
+/// accumulate `MADD r0, r1, #2` has 1 sink and sources `[[Register(0)], [Register(0), Immediate(0)]]`.
+pub struct CustomInstructionSignature {
+    pub sink_cnt: u8,
+    pub sources: Vec<Vec<Operand>>,
+}
+
+/// A handler for an instruction that isn't part of the instruction set of the CPU.
+///
+/// This makes it possible to prototype an ISA extension without modifying the Opcode enum. The
+/// loader parses the instruction using the signature and the backend calls execute when the
+/// instruction is executed by an execution unit.
+pub trait CustomInstructionHandler {
+    /// The mnemonic of the instruction. It should not clash with an existing mnemonic.
+    fn mnemonic(&self) -> &'static str;
+
+    fn signature(&self) -> CustomInstructionSignature;
+
+    /// The number of cycles the instruction occupies an execution unit.
+    fn cycles(&self) -> u8 {
+        1
+    }
+
+    /// Executes the instruction. The sources contain the values of the source operands and
+    /// the result should contain a value for every sink.
+    fn execute(&self, sources: &[WordType]) -> Vec<WordType>;
+}
+
+/// Identifies a custom instruction; the id is the index of the handler in the CustomInstructions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CustomOpcode {
+    pub(crate) id: u16,
+    pub(crate) mnemonic: &'static str,
+}
+
+/// The registered custom instructions.
+#[derive(Clone, Default)]
+pub struct CustomInstructions {
+    handlers: Vec<Rc<dyn CustomInstructionHandler>>,
+}
+
+impl CustomInstructions {
+    pub fn new() -> Self {
+        Self { handlers: Vec::new() }
+    }
+
+    pub fn register(&mut self, handler: Rc<dyn CustomInstructionHandler>) -> Result<(), String> {
+        let mnemonic = handler.mnemonic();
+        if get_opcode(mnemonic).is_some() || self.find(mnemonic).is_some() {
+            return Err(format!("There already exists an instruction with mnemonic '{}'", mnemonic));
+        }
+
+        // a custom instruction is scheduled as a single micro-op
+        let signature = handler.signature();
+        if signature.sink_cnt > MAX_UOP_SINK_COUNT {
+            return Err(format!("Custom instruction '{}' has {} sinks, but at most {} are supported",
+                               mnemonic, signature.sink_cnt, MAX_UOP_SINK_COUNT));
+        }
+
+        if signature.sources.len() > MAX_UOP_SOURCE_COUNT as usize {
+            return Err(format!("Custom instruction '{}' has {} sources, but at most {} are supported",
+                               mnemonic, signature.sources.len(), MAX_UOP_SOURCE_COUNT));
+        }
+
+        // the assembly grammar doesn't support more than 3 operands.
+        if signature.sink_cnt as usize + signature.sources.len() > 3 {
+            return Err(format!("Custom instruction '{}' has more than 3 operands", mnemonic));
+        }
+
+        for acceptable_types in &signature.sources {
+            if acceptable_types.is_empty() || !acceptable_types.iter().all(|t| matches!(t, Register(_) | Immediate(_))) {
+                return Err(format!("Custom instruction '{}' can only have Register and Immediate sources", mnemonic));
+            }
+        }
+
+        self.handlers.push(handler);
+        Ok(())
+    }
+
+    pub(crate) fn find(&self, mnemonic: &str) -> Option<Opcode> {
+        self.handlers.iter()
+            .position(|handler| handler.mnemonic().eq_ignore_ascii_case(mnemonic))
+            .map(|id| Opcode::CUSTOM(CustomOpcode { id: id as u16, mnemonic: self.handlers[id].mnemonic() }))
+    }
+
+    pub(crate) fn get(&self, opcode: CustomOpcode) -> &Rc<dyn CustomInstructionHandler> {
+        &self.handlers[opcode.id as usize]
+    }
+
+    pub(crate) fn create_instr(&self,
+                               opcode: CustomOpcode,
+                               operands: &[Operand],
+                               loc: SourceLocation) -> Result<Instr, String> {
+        let handler = self.get(opcode);
+        let signature = handler.signature();
+
+        let mut instr = Instr {
+            cycles: handler.cycles(),
+            opcode: Opcode::CUSTOM(opcode),
+            source_cnt: 0,
+            source: [Unused; MAX_SOURCE_COUNT as usize],
+            sink_cnt: 0,
+            sink: [Unused; MAX_SINK_COUNT as usize],
+            loc: Some(loc),
+            mem_stores: 0,
+            is_control: false,
+            thumb: false,
+            indexing: Indexing::Offset,
+        };
+
+        let sink_cnt = signature.sink_cnt as usize;
+        validate_operand_count(sink_cnt + signature.sources.len(), operands, instr.opcode)?;
+
+        instr.sink_cnt = signature.sink_cnt;
+        for k in 0..sink_cnt {
+            instr.sink[k] = validate_operand(k, operands, instr.opcode, &[Register(0)])?;
+        }
+
+        instr.source_cnt = signature.sources.len() as u8;
+        for (k, acceptable_types) in signature.sources.iter().enumerate() {
+            instr.source[k] = validate_operand(sink_cnt + k, operands, instr.opcode, acceptable_types)?;
+        }
+
+        instr.is_control = is_control(&instr);
+        Ok(instr)
+    }
+}
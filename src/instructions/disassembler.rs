@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::cpu::{CPSR, D_REG_BASE, FP, FPSCR, is_fp_register, is_single_register, is_vector_register, LR, PC, S_REG_BASE, SP, vector_lane};
+use crate::instructions::instructions::{address_operand, counter_name, Indexing, Instr, mnemonic, Opcode, Operand, Program, register_list, RegisterType, WordType};
+
+/// Converts a program back into assembly text that the loader can parse again.
+///
+/// Code addresses that are used as a target but don't have a label, get a generated label
+/// of the form 'L<address>'. Register aliases are not emitted; the plain registers are used.
+struct Disassembler<'a> {
+    program: &'a Program,
+    // code address -> label
+    labels: BTreeMap<usize, String>,
+    // memory offset -> variable
+    variables: BTreeMap<u64, String>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn new(program: &'a Program) -> Self {
+        let mut labels = BTreeMap::new();
+        for (name, address) in program.symbols.labels() {
+            // if there are multiple labels for the same address, the first by name is used.
+            labels.entry(address).or_insert_with(|| String::from(name));
+        }
+
+        let variables = program.symbols.variables()
+            .map(|(name, offset)| (offset, String::from(name)))
+            .collect();
+
+        let mut disassembler = Disassembler { program, labels, variables };
+        disassembler.generate_labels();
+        disassembler
+    }
+
+    // Adds labels for the entry point and every branch target without a label.
+    fn generate_labels(&mut self) {
+        let mut targets = vec![self.program.entry_point];
+        targets.extend(self.program.irq_handler);
+        for instr in &self.program.code {
+            for operand in instr.source.iter().take(instr.source_cnt as usize) {
+                match operand {
+                    Operand::Code(address) => targets.push(*address as usize),
+                    // a literal load of an address that isn't a variable
+                    Operand::Immediate(value) if instr.opcode == Opcode::LDR && instr.source_cnt == 1
+                        && !self.variables.contains_key(&(*value as u64)) && self.program.code_address(*value) <= self.program.code.len() => {
+                        targets.push(self.program.code_address(*value));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for target in targets {
+            self.labels.entry(target).or_insert_with(|| format!("L{}", target));
+        }
+    }
+
+    fn register(&self, reg: RegisterType) -> String {
+        match reg {
+            FP => String::from("fp"),
+            SP => String::from("sp"),
+            LR => String::from("lr"),
+            PC => String::from("pc"),
+            CPSR => String::from("cpsr"),
+            FPSCR => String::from("fpscr"),
+            _ if is_single_register(reg) => format!("s{}", reg - S_REG_BASE),
+            _ if is_fp_register(reg) => format!("d{}", reg - D_REG_BASE),
+            _ if is_vector_register(reg) => format!("v{}", vector_lane(reg).0),
+            _ => format!("r{}", self.program.abi.number(reg)),
+        }
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Register(reg) => self.register(*reg),
+            Operand::Immediate(value) => format!("#{}", value),
+            Operand::Memory(offset) => self.variables.get(&(*offset as u64)).cloned()
+                .unwrap_or_else(|| format!("{}", offset)),
+            Operand::Code(address) => self.labels[&(*address as usize)].clone(),
+            Operand::RegisterList(_) => {
+                let registers: Vec<String> = register_list(*operand).into_iter().map(|reg| self.register(reg)).collect();
+                format!("{{{}}}", registers.join(", "))
+            }
+            Operand::Unused => String::new(),
+        }
+    }
+
+    // The operand of a literal load; the loader resolves a label before a variable.
+    fn literal(&self, value: WordType) -> String {
+        if let Some(label) = self.labels.get(&self.program.code_address(value)) {
+            return format!("={}", label);
+        }
+        match self.variables.get(&(value as u64)) {
+            Some(name) => format!("={}", name),
+            None => format!("#{}", value),
+        }
+    }
+
+    // The operand of a MRS; the name of the counter unless a symbol with that name shadows it.
+    fn counter(&self, operand: Operand) -> String {
+        let index = operand.get_constant();
+        match counter_name(index) {
+            Some(name) if !self.labels.values().chain(self.variables.values()).any(|symbol| symbol == name) => String::from(name),
+            _ => format!("#{}", index),
+        }
+    }
+
+    // Only the explicit operands are emitted; e.g. the LR and PC of a BL are implicit.
+    fn instr(&self, instr: &Instr) -> String {
+        let op = |operand: Operand| self.operand(&operand);
+        let operands = match instr.opcode {
+            Opcode::ADD |
+            Opcode::SUB |
+            Opcode::MUL |
+            Opcode::SDIV |
+            Opcode::AND |
+            Opcode::ORR |
+            Opcode::EOR |
+            Opcode::ADC |
+            Opcode::SBC |
+            Opcode::ADDS |
+            Opcode::SUBS |
+            Opcode::ADCS |
+            Opcode::SBCS |
+            Opcode::FADD |
+            Opcode::FSUB |
+            Opcode::FMUL |
+            Opcode::FDIV => format!("{}, {}, {}", op(instr.sink[0]), op(instr.source[0]), op(instr.source[1])),
+            Opcode::LDR => match instr.source[0] {
+                Operand::Immediate(value) => format!("{}, {}", op(instr.sink[0]), self.literal(value)),
+                _ => {
+                    let offset = (instr.source_cnt > 1).then(|| op(instr.source[1]));
+                    format!("{}, {}", op(instr.sink[0]), address_operand(instr.indexing, &op(instr.source[0]), offset.as_deref()))
+                }
+            },
+            Opcode::STR => {
+                let offset = (instr.source_cnt > 2).then(|| op(instr.source[2]));
+                format!("{}, {}", op(instr.source[0]), address_operand(instr.indexing, &op(instr.source[1]), offset.as_deref()))
+            }
+            Opcode::MOV |
+            Opcode::FMOV |
+            Opcode::ADR |
+            Opcode::NEG |
+            Opcode::MVN |
+            Opcode::MALLOC => format!("{}, {}", op(instr.sink[0]), op(instr.source[0])),
+            Opcode::MRS => format!("{}, {}", op(instr.sink[0]), self.counter(instr.source[0])),
+            Opcode::READC => op(instr.sink[0]),
+            Opcode::NOP |
+            Opcode::EXIT |
+            Opcode::CPSIE |
+            Opcode::CPSID |
+            Opcode::ERET |
+            Opcode::DMB |
+            Opcode::DSB => String::new(),
+            Opcode::PRINTR |
+            Opcode::FREE |
+            Opcode::B |
+            Opcode::BX |
+            Opcode::BL |
+            Opcode::BLX |
+            Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+            Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => op(instr.source[0]),
+            Opcode::TBZ |
+            Opcode::TBNZ => format!("{}, {}, {}", op(instr.source[0]), op(instr.source[1]), op(instr.source[2])),
+            Opcode::CLZ |
+            Opcode::RBIT => format!("{}, {}", op(instr.sink[0]), op(instr.source[0])),
+            Opcode::UBFX |
+            Opcode::SBFX => format!("{}, {}, {}, {}", op(instr.sink[0]), op(instr.source[0]), op(instr.source[1]), op(instr.source[2])),
+            Opcode::BFI => format!("{}, {}, {}, {}", op(instr.sink[0]), op(instr.source[1]), op(instr.source[2]), op(instr.source[3])),
+            Opcode::MOVW => format!("{}, {}", op(instr.sink[0]), op(instr.source[0])),
+            Opcode::MOVT => format!("{}, {}", op(instr.sink[0]), op(instr.source[1])),
+            Opcode::MOVK => format!("{}, {}, {}", op(instr.sink[0]), op(instr.source[1]), op(instr.source[2])),
+            Opcode::CBZ |
+            Opcode::CBNZ |
+            Opcode::CMP |
+            Opcode::CMN |
+            Opcode::TST |
+            Opcode::FCMP => format!("{}, {}", op(instr.source[0]), op(instr.source[1])),
+            Opcode::PUSH => {
+                let registers: Vec<String> = instr.source[1..instr.source_cnt as usize].iter().map(|reg| op(*reg)).collect();
+                format!("{{{}}}", registers.join(", "))
+            }
+            Opcode::POP => {
+                // the last sink is the SP
+                let registers: Vec<String> = instr.sink[..instr.sink_cnt as usize - 1].iter().map(|reg| op(*reg)).collect();
+                format!("{{{}}}", registers.join(", "))
+            }
+            Opcode::LDM(_) | Opcode::STM(_) => {
+                let writeback = if instr.indexing != Indexing::Offset { "!" } else { "" };
+                let registers: Vec<String> = instr.block_registers().iter().map(|reg| op(*reg)).collect();
+                format!("{}{}, {{{}}}", op(instr.source[0]), writeback, registers.join(", "))
+            }
+            // a vector register is its lane 0
+            Opcode::VADD |
+            Opcode::VSUB |
+            Opcode::VMUL => format!("{}, {}, {}", op(instr.sink[0]), op(instr.source[0]), op(instr.source[instr.sink_cnt as usize])),
+            Opcode::VLD1 => format!("{}, [{}]", op(instr.sink[0]), op(instr.source[0])),
+            Opcode::VST1 => format!("{}, [{}]", op(instr.source[1]), op(instr.source[0])),
+            Opcode::CUSTOM(_) => {
+                let sinks = instr.sink.iter().take(instr.sink_cnt as usize);
+                let sources = instr.source.iter().take(instr.source_cnt as usize);
+                sinks.chain(sources).map(|operand| op(*operand)).collect::<Vec<String>>().join(", ")
+            }
+        };
+
+        if operands.is_empty() {
+            format!("{};", mnemonic(instr.opcode))
+        } else {
+            format!("{} {};", mnemonic(instr.opcode), operands)
+        }
+    }
+
+    fn deadline_end(&self, out: &mut String, address: usize) {
+        if self.program.deadlines.iter().any(|deadline| deadline.end == address) {
+            writeln!(out, ".end_deadline").unwrap();
+        }
+    }
+
+    fn disassemble(&self) -> String {
+        let mut out = String::new();
+
+        let entry_label = &self.labels[&self.program.entry_point];
+        writeln!(out, ".global {}", entry_label).unwrap();
+        if let Some(handler) = self.program.irq_handler {
+            writeln!(out, ".irq_handler {}", self.labels[&handler]).unwrap();
+        }
+
+        if !self.variables.is_empty() {
+            let values: HashMap<&String, WordType> = self.program.data_items.iter()
+                .map(|(name, data)| (name, data.value))
+                .collect();
+
+            writeln!(out, "\n.data").unwrap();
+            for name in self.variables.values() {
+                writeln!(out, "    {}: .word {}", name, values[name]).unwrap();
+            }
+        }
+
+        writeln!(out, "\n.text").unwrap();
+        // the code starts as ARM code
+        let mut thumb = false;
+        for (address, instr) in self.program.code.iter().enumerate() {
+            self.deadline_end(&mut out, address);
+            if instr.thumb != thumb {
+                thumb = instr.thumb;
+                writeln!(out, "{}", if thumb { ".thumb" } else { ".arm" }).unwrap();
+            }
+            if let Some(label) = self.labels.get(&address) {
+                writeln!(out, "{}:", label).unwrap();
+            }
+            for deadline in self.program.deadlines.iter().filter(|deadline| deadline.start == address) {
+                writeln!(out, ".deadline {}", deadline.budget).unwrap();
+            }
+            if let Some(bound) = self.program.loop_bounds.get(&address) {
+                writeln!(out, ".loop_bound {}", bound).unwrap();
+            }
+            writeln!(out, "    {}", self.instr(instr)).unwrap();
+        }
+        self.deadline_end(&mut out, self.program.code.len());
+
+        // labels pointing past the last instruction
+        for (_, label) in self.labels.range(self.program.code.len()..) {
+            writeln!(out, "{}:", label).unwrap();
+        }
+
+        out
+    }
+}
+
+impl Program {
+    /// Returns the program as assembly text that can be loaded again into an equivalent program.
+    pub fn disassemble(&self) -> String {
+        Disassembler::new(self).disassemble()
+    }
+}
@@ -2,11 +2,15 @@ use std::collections::HashMap;
 use std::fmt;
 use std::rc::Rc;
 use Operand::Memory;
-use crate::cpu::{CPSR, GENERAL_ARG_REG_CNT, SP};
+use crate::abi::abi::Abi;
+use crate::cpu::{CPSR, D_REG_BASE, FPSCR, GENERAL_ARG_REG_CNT, is_fp_register, is_single_register, is_vector_register, S_REG_BASE, SP, vector_lane};
 use crate::cpu::LR;
 use crate::cpu::PC;
 use crate::cpu::FP;
-use crate::instructions::instructions::Operand::{Code, Immediate, Register, Unused};
+use crate::instructions::custom::{CustomInstructions, CustomOpcode};
+use crate::instructions::instructions::Operand::{Code, Immediate, Register, RegisterList, Unused};
+use crate::instructions::symbol_table::SymbolTable;
+use crate::loader::loader::AssemblyWarning;
 
 #[derive(Debug, Clone, Copy)]
 pub struct SourceLocation {
@@ -35,9 +39,10 @@ pub enum Opcode {
     B,
     BX,
     BL,
+    BLX,
     CBZ,
     CBNZ,
-    // Acts like a poison pill. It isn't a public instruction.
+    // Ends the program with the exit code in r0; it is implicitly placed after the last instruction.
     EXIT,
     NEG,
     AND,
@@ -51,6 +56,140 @@ pub enum Opcode {
     BLT,
     BGE,
     BGT,
+    // enables interrupts
+    CPSIE,
+    // disables interrupts
+    CPSID,
+    // returns from an interrupt handler
+    ERET,
+    FADD,
+    FSUB,
+    FMUL,
+    FDIV,
+    FCMP,
+    FMOV,
+    // stores a list of registers on the stack
+    PUSH,
+    // loads a list of registers from the stack
+    POP,
+    // test bit and branch if zero
+    TBZ,
+    // test bit and branch if not zero
+    TBNZ,
+    // count leading zeros
+    CLZ,
+    // reverse bits
+    RBIT,
+    // unsigned bit field extract
+    UBFX,
+    // signed bit field extract
+    SBFX,
+    // bit field insert
+    BFI,
+    // moves a 16 bit immediate into the low half of a register and clears the other bits
+    MOVW,
+    // moves a 16 bit immediate into bits 16-31 of a register; the other bits are kept
+    MOVT,
+    // moves a 16 bit immediate into the given 16 bits of a register; the other bits are kept
+    MOVK,
+    // add with carry
+    ADC,
+    // subtract with carry; subtracts the inverted carry (the borrow)
+    SBC,
+    // ADD, SUB, ADC and SBC that also set the NZCV flags
+    ADDS,
+    SUBS,
+    ADCS,
+    SBCS,
+    // compare negative; sets the flags like an ADDS that discards its result
+    CMN,
+    // test; sets the N and Z flags like an AND that discards its result
+    TST,
+    // unsigned higher
+    BHI,
+    // unsigned lower or same
+    BLS,
+    // unsigned higher or same
+    BHS,
+    // unsigned lower
+    BLO,
+    // allocates a block of words on the heap; the address is 0 if the heap is exhausted
+    MALLOC,
+    // releases a block allocated using MALLOC
+    FREE,
+    // reads a performance counter (see COUNTERS); it only executes once all older instructions have retired
+    MRS,
+    // reads the next byte of the input of the program; -1 at the end of the input
+    READC,
+    // data memory barrier: the older stores become visible before the younger loads and stores execute
+    DMB,
+    // data synchronization barrier: like DMB, but no younger instruction executes before it has completed
+    DSB,
+    // add, subtract and multiply every lane of two vector registers
+    VADD,
+    VSUB,
+    VMUL,
+    // loads the lanes of a vector register from consecutive words
+    VLD1,
+    // stores the lanes of a vector register to consecutive words
+    VST1,
+    // loads a list of registers from consecutive words; the addressing mode determines where they start
+    LDM(BlockMode),
+    // stores a list of registers to consecutive words
+    STM(BlockMode),
+    // An instruction registered using a CustomInstructionHandler.
+    CUSTOM(CustomOpcode),
+}
+
+// All opcodes that can be used in a program; so without the custom instructions.
+pub(crate) const OPCODES: &[Opcode] = &[
+    Opcode::ADD, Opcode::SUB, Opcode::MUL, Opcode::SDIV, Opcode::ADR, Opcode::LDR, Opcode::STR, Opcode::NOP,
+    Opcode::PRINTR, Opcode::MOV, Opcode::B, Opcode::BX, Opcode::BL, Opcode::BLX, Opcode::CBZ, Opcode::CBNZ,
+    Opcode::NEG, Opcode::AND, Opcode::ORR, Opcode::EOR, Opcode::MVN, Opcode::CMP, Opcode::BEQ, Opcode::BNE,
+    Opcode::BLE, Opcode::BLT, Opcode::BGE, Opcode::BGT, Opcode::CPSIE, Opcode::CPSID, Opcode::ERET, Opcode::FADD,
+    Opcode::FSUB, Opcode::FMUL, Opcode::FDIV, Opcode::FCMP, Opcode::FMOV, Opcode::PUSH, Opcode::POP, Opcode::TBZ,
+    Opcode::TBNZ, Opcode::CLZ, Opcode::RBIT, Opcode::UBFX, Opcode::SBFX, Opcode::BFI, Opcode::MOVW, Opcode::MOVT,
+    Opcode::MOVK, Opcode::EXIT, Opcode::ADC, Opcode::SBC, Opcode::ADDS, Opcode::SUBS, Opcode::ADCS, Opcode::SBCS,
+    Opcode::CMN, Opcode::TST, Opcode::BHI, Opcode::BLS, Opcode::BHS, Opcode::BLO, Opcode::MALLOC, Opcode::FREE,
+    Opcode::MRS, Opcode::DMB, Opcode::DSB, Opcode::VADD, Opcode::VSUB, Opcode::VMUL, Opcode::VLD1, Opcode::VST1,
+    Opcode::READC, Opcode::LDM(BlockMode::IA), Opcode::LDM(BlockMode::IB), Opcode::LDM(BlockMode::DA),
+    Opcode::LDM(BlockMode::DB), Opcode::STM(BlockMode::IA), Opcode::STM(BlockMode::IB), Opcode::STM(BlockMode::DA),
+    Opcode::STM(BlockMode::DB),
+];
+
+/// The addressing mode of an LDM or STM: the base register points at the first word (increment after),
+/// the word before the first (increment before), the last word (decrement after) or the word after the
+/// last (decrement before). The lowest register is always transferred from or to the lowest address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockMode {
+    IA,
+    IB,
+    DA,
+    DB,
+}
+
+impl BlockMode {
+    // The offset from the base register of the lowest word of a block of `cnt` words.
+    pub(crate) fn first_offset(self, cnt: usize) -> WordType {
+        match self {
+            BlockMode::IA => 0,
+            BlockMode::IB => 1,
+            BlockMode::DA => 1 - cnt as WordType,
+            BlockMode::DB => -(cnt as WordType),
+        }
+    }
+
+    // If the writeback moves the base register up (past the block) rather than down.
+    pub(crate) fn increments(self) -> bool {
+        matches!(self, BlockMode::IA | BlockMode::IB)
+    }
+}
+
+// The performance counters that MRS can read; the operand is the index, e.g. MRS r0, CYCLES.
+pub(crate) const COUNTERS: &[&str] = &["CYCLES", "INSTRET", "L1_MISSES", "L2_MISSES"];
+
+pub(crate) fn counter_name(counter: WordType) -> Option<&'static str> {
+    usize::try_from(counter).ok().and_then(|index| COUNTERS.get(index).copied())
 }
 
 pub(crate) fn mnemonic(opcode: Opcode) -> &'static str {
@@ -65,10 +204,11 @@ pub(crate) fn mnemonic(opcode: Opcode) -> &'static str {
         Opcode::STR => "STR",
         Opcode::NOP => "NOP",
         Opcode::PRINTR => "PRINTR",
-        Opcode::MOV => "PRINTR",
+        Opcode::MOV => "MOV",
         Opcode::B => "B",
         Opcode::BX => "BX",
         Opcode::BL => "BL",
+        Opcode::BLX => "BLX",
         Opcode::CBZ => "CBZ",
         Opcode::CBNZ => "CBNZ",
         Opcode::AND => "AND",
@@ -83,6 +223,99 @@ pub(crate) fn mnemonic(opcode: Opcode) -> &'static str {
         Opcode::BLT => "BLT",
         Opcode::BGE => "BGE",
         Opcode::BGT => "BGT",
+        Opcode::CPSIE => "CPSIE",
+        Opcode::CPSID => "CPSID",
+        Opcode::ERET => "ERET",
+        Opcode::FADD => "FADD",
+        Opcode::FSUB => "FSUB",
+        Opcode::FMUL => "FMUL",
+        Opcode::FDIV => "FDIV",
+        Opcode::FCMP => "FCMP",
+        Opcode::FMOV => "FMOV",
+        Opcode::PUSH => "PUSH",
+        Opcode::POP => "POP",
+        Opcode::TBZ => "TBZ",
+        Opcode::TBNZ => "TBNZ",
+        Opcode::CLZ => "CLZ",
+        Opcode::RBIT => "RBIT",
+        Opcode::UBFX => "UBFX",
+        Opcode::SBFX => "SBFX",
+        Opcode::BFI => "BFI",
+        Opcode::MOVW => "MOVW",
+        Opcode::MOVT => "MOVT",
+        Opcode::MOVK => "MOVK",
+        Opcode::ADC => "ADC",
+        Opcode::SBC => "SBC",
+        Opcode::ADDS => "ADDS",
+        Opcode::SUBS => "SUBS",
+        Opcode::ADCS => "ADCS",
+        Opcode::SBCS => "SBCS",
+        Opcode::CMN => "CMN",
+        Opcode::TST => "TST",
+        Opcode::BHI => "BHI",
+        Opcode::BLS => "BLS",
+        Opcode::BHS => "BHS",
+        Opcode::BLO => "BLO",
+        Opcode::MALLOC => "MALLOC",
+        Opcode::FREE => "FREE",
+        Opcode::MRS => "MRS",
+        Opcode::READC => "READC",
+        Opcode::DMB => "DMB",
+        Opcode::DSB => "DSB",
+        Opcode::VADD => "VADD",
+        Opcode::VSUB => "VSUB",
+        Opcode::VMUL => "VMUL",
+        Opcode::VLD1 => "VLD1",
+        Opcode::VST1 => "VST1",
+        Opcode::LDM(mode) => match mode {
+            BlockMode::IA => "LDMIA",
+            BlockMode::IB => "LDMIB",
+            BlockMode::DA => "LDMDA",
+            BlockMode::DB => "LDMDB",
+        },
+        Opcode::STM(mode) => match mode {
+            BlockMode::IA => "STMIA",
+            BlockMode::IB => "STMIB",
+            BlockMode::DA => "STMDA",
+            BlockMode::DB => "STMDB",
+        },
+        Opcode::CUSTOM(custom) => custom.mnemonic,
+    }
+}
+
+/// The type of execution unit an instruction is dispatched to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) enum EUType {
+    ALU,
+    LoadStore,
+    Multiplier,
+    Divider,
+    FloatingPoint,
+}
+
+pub(crate) const EU_TYPES: [EUType; 5] = [EUType::ALU, EUType::LoadStore, EUType::Multiplier, EUType::Divider, EUType::FloatingPoint];
+
+impl fmt::Display for EUType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EUType::ALU => write!(f, "ALU"),
+            EUType::LoadStore => write!(f, "LoadStore"),
+            EUType::Multiplier => write!(f, "Multiplier"),
+            EUType::Divider => write!(f, "Divider"),
+            EUType::FloatingPoint => write!(f, "FloatingPoint"),
+        }
+    }
+}
+
+pub(crate) fn eu_type(opcode: Opcode) -> EUType {
+    match opcode {
+        Opcode::LDR | Opcode::STR | Opcode::PUSH | Opcode::POP | Opcode::DMB | Opcode::DSB |
+        Opcode::VLD1 | Opcode::VST1 | Opcode::LDM(_) | Opcode::STM(_) => EUType::LoadStore,
+        Opcode::MUL | Opcode::VMUL => EUType::Multiplier,
+        Opcode::SDIV => EUType::Divider,
+        Opcode::FADD | Opcode::FSUB | Opcode::FMUL | Opcode::FDIV | Opcode::FCMP | Opcode::FMOV => EUType::FloatingPoint,
+        _ => EUType::ALU,
     }
 }
 
@@ -111,6 +344,8 @@ pub(crate) fn get_opcode(mnemonic: &str) -> Option<Opcode> {
         "EOR" => Some(Opcode::EOR),
         "MVN" => Some(Opcode::MVN),
         "BL" => Some(Opcode::BL),
+        // BLR is the AArch64 name
+        "BLX" | "BLR" => Some(Opcode::BLX),
         "EXIT" => Some(Opcode::EXIT),
         "CMP" => Some(Opcode::CMP),
         "BEQ" => Some(Opcode::BEQ),
@@ -119,11 +354,66 @@ pub(crate) fn get_opcode(mnemonic: &str) -> Option<Opcode> {
         "BLT" => Some(Opcode::BLT),
         "BGE" => Some(Opcode::BGE),
         "BGT" => Some(Opcode::BGT),
+        "CPSIE" => Some(Opcode::CPSIE),
+        "CPSID" => Some(Opcode::CPSID),
+        "ERET" => Some(Opcode::ERET),
+        "FADD" => Some(Opcode::FADD),
+        "FSUB" => Some(Opcode::FSUB),
+        "FMUL" => Some(Opcode::FMUL),
+        "FDIV" => Some(Opcode::FDIV),
+        "FCMP" => Some(Opcode::FCMP),
+        "FMOV" => Some(Opcode::FMOV),
+        "PUSH" => Some(Opcode::PUSH),
+        "POP" => Some(Opcode::POP),
+        "TBZ" => Some(Opcode::TBZ),
+        "TBNZ" => Some(Opcode::TBNZ),
+        "CLZ" => Some(Opcode::CLZ),
+        "RBIT" => Some(Opcode::RBIT),
+        "UBFX" => Some(Opcode::UBFX),
+        "SBFX" => Some(Opcode::SBFX),
+        "BFI" => Some(Opcode::BFI),
+        "MOVW" => Some(Opcode::MOVW),
+        "MOVT" => Some(Opcode::MOVT),
+        "MOVK" => Some(Opcode::MOVK),
+        "ADC" => Some(Opcode::ADC),
+        "SBC" => Some(Opcode::SBC),
+        "ADDS" => Some(Opcode::ADDS),
+        "SUBS" => Some(Opcode::SUBS),
+        "ADCS" => Some(Opcode::ADCS),
+        "SBCS" => Some(Opcode::SBCS),
+        "CMN" => Some(Opcode::CMN),
+        "TST" => Some(Opcode::TST),
+        "BHI" => Some(Opcode::BHI),
+        "BLS" => Some(Opcode::BLS),
+        "BHS" => Some(Opcode::BHS),
+        "BLO" => Some(Opcode::BLO),
+        "MALLOC" => Some(Opcode::MALLOC),
+        "FREE" => Some(Opcode::FREE),
+        "MRS" => Some(Opcode::MRS),
+        "READC" => Some(Opcode::READC),
+        "DMB" => Some(Opcode::DMB),
+        "DSB" => Some(Opcode::DSB),
+        "VADD" => Some(Opcode::VADD),
+        "VSUB" => Some(Opcode::VSUB),
+        "VMUL" => Some(Opcode::VMUL),
+        "VLD1" => Some(Opcode::VLD1),
+        "VST1" => Some(Opcode::VST1),
+        // without a suffix LDM and STM increment after; the stack suffixes (full/empty, descending/ascending)
+        // are aliases, e.g. STMFD and LDMFD are a PUSH and a POP
+        "LDMIA" | "LDM" | "LDMFD" => Some(Opcode::LDM(BlockMode::IA)),
+        "LDMIB" | "LDMED" => Some(Opcode::LDM(BlockMode::IB)),
+        "LDMDA" | "LDMFA" => Some(Opcode::LDM(BlockMode::DA)),
+        "LDMDB" | "LDMEA" => Some(Opcode::LDM(BlockMode::DB)),
+        "STMIA" | "STM" | "STMEA" => Some(Opcode::STM(BlockMode::IA)),
+        "STMIB" | "STMFA" => Some(Opcode::STM(BlockMode::IB)),
+        "STMDA" | "STMED" => Some(Opcode::STM(BlockMode::DA)),
+        "STMDB" | "STMFD" => Some(Opcode::STM(BlockMode::DB)),
         _ => None,
     }
 }
 
-pub(crate) fn get_register(name: &str) -> Option<u16> {
+// The slot of the register with the name (e.g. r3, sp); None if the register file of the ABI doesn't have it.
+pub(crate) fn get_register(name: &str, abi: &Abi) -> Option<u16> {
     let name_uppercased = name.to_uppercase();
 
     match name_uppercased.as_str() {
@@ -135,10 +425,10 @@ pub(crate) fn get_register(name: &str) -> Option<u16> {
             let reg_name = &name_uppercased[1..];
             let reg: u16 = reg_name.parse().unwrap();
 
-            if reg >= GENERAL_ARG_REG_CNT {
+            if !abi.is_general_register(reg) {
                 return None;
             }
-            Some(reg)
+            Some(abi.slot(reg))
         }
     }
 }
@@ -150,14 +440,37 @@ pub(crate) fn create_instr(opcode: Opcode,
         cycles: 1,
         opcode,
         source_cnt: 0,
-        source: [Unused, Unused, Unused],
+        source: [Unused; MAX_SOURCE_COUNT as usize],
         sink_cnt: 0,
-        sink: [Unused, Unused],
+        sink: [Unused; MAX_SINK_COUNT as usize],
         loc: Some(loc),
         mem_stores: 0,
         is_control: false,
+        thumb: false,
+        indexing: Indexing::Offset,
     };
 
+    if !is_fp_opcode(opcode) && opcode != Opcode::PRINTR {
+        for operand in operands {
+            if let Register(reg) = operand {
+                if is_fp_register(*reg) || *reg == FPSCR {
+                    return Err(format!("{} doesn't accept the floating point register {}", mnemonic(opcode), operand));
+                }
+                if *reg == CPSR {
+                    return Err(format!("{} doesn't accept the status register {}", mnemonic(opcode), operand));
+                }
+            }
+        }
+    }
+
+    if !is_vector_opcode(opcode) {
+        for operand in operands {
+            if matches!(operand, Register(reg) if is_vector_register(*reg)) {
+                return Err(format!("{} doesn't accept the vector register {}", mnemonic(opcode), operand));
+            }
+        }
+    }
+
     match opcode {
         Opcode::SUB |
         Opcode::MUL |
@@ -166,7 +479,7 @@ pub(crate) fn create_instr(opcode: Opcode,
         Opcode::ORR |
         Opcode::EOR |
         Opcode::ADD => {
-            validate_operand_count(3, operands, opcode, loc)?;
+            validate_operand_count(3, operands, opcode)?;
 
             instr.sink_cnt = 1;
             instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -175,32 +488,56 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
             instr.source[1] = validate_operand(2, operands, opcode, &[Register(0), Immediate(0)])?;
         }
-        Opcode::ADR => { panic!() }
-        Opcode::LDR => {
-            validate_operand_count(2, operands, opcode, loc)?;
+        Opcode::ADR => {
+            validate_operand_count(2, operands, opcode)?;
 
             instr.sink_cnt = 1;
             instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
 
+            // the address of a label (Code) or of a variable (Memory)
             instr.source_cnt = 1;
-            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?
+            instr.source[0] = validate_operand(1, operands, opcode, &[Code(0), Memory(0)])?;
+        }
+        Opcode::LDR => {
+            // the address register can be followed by an immediate offset; e.g. LDR r0, [r1, #1]
+            if operands.len() != 3 {
+                validate_operand_count(2, operands, opcode)?;
+            }
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // An Immediate is a literal load (LDR r0, =label); the literal itself is loaded.
+            instr.source_cnt = operands.len() as u8 - 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0), Immediate(0)])?;
+            if operands.len() == 3 {
+                validate_operand(1, operands, opcode, &[Register(0)])?;
+                instr.source[1] = validate_operand(2, operands, opcode, &[Immediate(0)])?;
+            }
         }
         Opcode::STR => {
-            validate_operand_count(2, operands, opcode, loc)?;
+            if operands.len() != 3 {
+                validate_operand_count(2, operands, opcode)?;
+            }
 
             instr.mem_stores = 1;
 
-            instr.source_cnt = 1;
+            // the value and the register containing the address, optionally followed by an immediate
+            // offset. The store goes through the store buffer, so there is no sink.
+            instr.source_cnt = operands.len() as u8;
             instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
-
-            instr.sink_cnt = 1;
-            instr.sink[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            if operands.len() == 3 {
+                instr.source[2] = validate_operand(2, operands, opcode, &[Immediate(0)])?;
+            }
         }
-        Opcode::NOP => {
-            validate_operand_count(0, operands, opcode, loc)?;
+        Opcode::NOP |
+        Opcode::DMB |
+        Opcode::DSB => {
+            validate_operand_count(0, operands, opcode)?;
         }
         Opcode::PRINTR => {
-            validate_operand_count(1, operands, opcode, loc)?;
+            validate_operand_count(1, operands, opcode)?;
 
             instr.sink_cnt = 0;
 
@@ -208,7 +545,7 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
         }
         Opcode::MOV => {
-            validate_operand_count(2, operands, opcode, loc)?;
+            validate_operand_count(2, operands, opcode)?;
 
             instr.sink_cnt = 1;
             instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -217,7 +554,7 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.source[0] = validate_operand(1, operands, opcode, &[Immediate(0), Register(0)])?
         }
         Opcode::B => {
-            validate_operand_count(1, operands, opcode, loc)?;
+            validate_operand_count(1, operands, opcode)?;
 
             instr.source_cnt = 1;
             instr.source[0] = validate_operand(0, operands, opcode, &[Code(0)])?;
@@ -226,7 +563,7 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.sink[0] = Register(PC);
         }
         Opcode::BX => {
-            validate_operand_count(1, operands, opcode, loc)?;
+            validate_operand_count(1, operands, opcode)?;
 
             instr.source_cnt = 1;
             instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -235,7 +572,7 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.sink[0] = Register(PC);
         }
         Opcode::BL => {
-            validate_operand_count(1, operands, opcode, loc)?;
+            validate_operand_count(1, operands, opcode)?;
 
             instr.source_cnt = 2;
             instr.source[0] = validate_operand(0, operands, opcode, &[Code(0)])?;
@@ -245,9 +582,20 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.sink[0] = Register(LR);
             instr.sink[1] = Register(PC);
         }
+        Opcode::BLX => {
+            validate_operand_count(1, operands, opcode)?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.source[1] = Register(PC);
+
+            instr.sink_cnt = 2;
+            instr.sink[0] = Register(LR);
+            instr.sink[1] = Register(PC);
+        }
         Opcode::CBZ |
         Opcode::CBNZ => {
-            validate_operand_count(2, operands, opcode, loc)?;
+            validate_operand_count(2, operands, opcode)?;
 
             instr.source_cnt = 3;
             instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -258,12 +606,12 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.sink[0] = Register(PC);
         }
         Opcode::EXIT => {
-            validate_operand_count(0, operands, opcode, loc)?;
+            validate_operand_count(0, operands, opcode)?;
 
             instr.is_control = true;
         }
         Opcode::NEG => {
-            validate_operand_count(2, operands, opcode, loc)?;
+            validate_operand_count(2, operands, opcode)?;
 
             instr.sink_cnt = 1;
             instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -272,7 +620,7 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
         }
         Opcode::MVN => {
-            validate_operand_count(2, operands, opcode, loc)?;
+            validate_operand_count(2, operands, opcode)?;
 
             instr.sink_cnt = 1;
             instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -280,19 +628,51 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.source_cnt = 1;
             instr.source[0] = validate_operand(1, operands, opcode, &[Immediate(0), Register(0)])?;
         }
-        Opcode::CMP => {
-            validate_operand_count(2, operands, opcode, loc)?;
+        Opcode::ADC |
+        Opcode::SBC |
+        Opcode::ADDS |
+        Opcode::SUBS |
+        Opcode::ADCS |
+        Opcode::SBCS => {
+            validate_operand_count(3, operands, opcode)?;
 
-            instr.source_cnt = 3;
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0), Immediate(0)])?;
+            if !overwrites_flags(opcode) {
+                // the carry is read from the CPSR
+                instr.source_cnt = 3;
+                instr.source[2] = Register(CPSR);
+            }
+
+            if sets_flags(opcode) {
+                instr.sink_cnt = 2;
+                instr.sink[1] = Register(CPSR);
+            }
+        }
+        Opcode::CMP |
+        Opcode::CMN |
+        Opcode::TST => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.source_cnt = 2;
             instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
             instr.source[1] = validate_operand(1, operands, opcode, &[Immediate(0), Register(0)])?;
-            instr.source[2] = Register(CPSR);
+            if !overwrites_flags(opcode) {
+                // a TST keeps the C and V flags
+                instr.source_cnt = 3;
+                instr.source[2] = Register(CPSR);
+            }
 
             instr.sink_cnt = 1;
             instr.sink[0] = Register(CPSR);
         }
-        Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE => {
-            validate_operand_count(2, operands, opcode, loc)?;
+        Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+        Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => {
+            validate_operand_count(1, operands, opcode)?;
 
             instr.source_cnt = 3;
             instr.source[0] = validate_operand(0, operands, opcode, &[Code(0)])?;
@@ -302,21 +682,681 @@ pub(crate) fn create_instr(opcode: Opcode,
             instr.sink_cnt = 1;
             instr.sink[0] = Register(PC);
         }
+        Opcode::CPSIE |
+        Opcode::CPSID => {
+            validate_operand_count(0, operands, opcode)?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = Register(CPSR);
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = Register(CPSR);
+        }
+        Opcode::ERET => {
+            validate_operand_count(0, operands, opcode)?;
+
+            // The return address and the saved CPSR are banked; they are restored at retirement.
+            instr.is_control = true;
+            return Ok(instr);
+        }
+        Opcode::FADD |
+        Opcode::FSUB |
+        Opcode::FMUL |
+        Opcode::FDIV => {
+            validate_operand_count(3, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0)])?;
+
+            validate_fp_registers(opcode, operands)?;
+        }
+        Opcode::FCMP => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(1, operands, opcode, &[Register(0)])?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = Register(FPSCR);
+
+            validate_fp_registers(opcode, operands)?;
+        }
+        Opcode::FMOV => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // an Immediate is converted to a floating point value
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0), Immediate(0)])?;
+
+            validate_fp_registers(opcode, operands)?;
+        }
+        Opcode::PUSH => {
+            validate_operand_count(1, operands, opcode)?;
+            let registers = register_list(validate_operand(0, operands, opcode, &[RegisterList(0)])?);
+            if registers.contains(&SP) || registers.contains(&PC) {
+                return Err(format!("{} can't store SP or PC", mnemonic(opcode)));
+            }
+
+            // the registers are stored below the SP; the lowest register at the lowest address.
+            instr.mem_stores = registers.len() as u8;
+            instr.source_cnt = 1 + registers.len() as u8;
+            instr.source[0] = Register(SP);
+            for (k, reg) in registers.iter().enumerate() {
+                instr.source[1 + k] = Register(*reg);
+            }
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = Register(SP);
+        }
+        Opcode::POP => {
+            validate_operand_count(1, operands, opcode)?;
+            let registers = register_list(validate_operand(0, operands, opcode, &[RegisterList(0)])?);
+            if registers.contains(&SP) {
+                return Err(format!("{} can't load SP", mnemonic(opcode)));
+            }
+
+            instr.source_cnt = 1;
+            instr.source[0] = Register(SP);
+
+            // the loaded registers followed by the updated SP; a POP of the PC is a return.
+            instr.sink_cnt = registers.len() as u8 + 1;
+            for (k, reg) in registers.iter().enumerate() {
+                instr.sink[k] = Register(*reg);
+            }
+            instr.sink[registers.len()] = Register(SP);
+        }
+        Opcode::TBZ |
+        Opcode::TBNZ => {
+            validate_operand_count(3, operands, opcode)?;
+
+            // the register, the number of the tested bit and the branch target
+            instr.source_cnt = 4;
+            instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(1, operands, opcode, &[Immediate(0)])?;
+            instr.source[2] = validate_operand(2, operands, opcode, &[Code(0)])?;
+            instr.source[3] = Register(PC);
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = Register(PC);
+        }
+        Opcode::CLZ |
+        Opcode::RBIT => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+        }
+        Opcode::UBFX |
+        Opcode::SBFX => {
+            validate_operand_count(4, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // the register, the lsb and the width of the field
+            instr.source_cnt = 3;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Immediate(0)])?;
+            instr.source[2] = validate_operand(3, operands, opcode, &[Immediate(0)])?;
+        }
+        Opcode::BFI => {
+            validate_operand_count(4, operands, opcode)?;
+
+            let rd = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.sink_cnt = 1;
+            instr.sink[0] = rd;
+
+            // the bits outside of the field are kept, so the destination is also a source
+            instr.source_cnt = 4;
+            instr.source[0] = rd;
+            instr.source[1] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[2] = validate_operand(2, operands, opcode, &[Immediate(0)])?;
+            instr.source[3] = validate_operand(3, operands, opcode, &[Immediate(0)])?;
+        }
+        Opcode::MOVW => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = validate_half_word(validate_operand(1, operands, opcode, &[Immediate(0)])?, opcode)?;
+        }
+        Opcode::MOVT => {
+            validate_operand_count(2, operands, opcode)?;
+
+            let rd = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.sink_cnt = 1;
+            instr.sink[0] = rd;
+
+            // the low half is kept, so the destination is also a source
+            instr.source_cnt = 2;
+            instr.source[0] = rd;
+            instr.source[1] = validate_half_word(validate_operand(1, operands, opcode, &[Immediate(0)])?, opcode)?;
+        }
+        Opcode::MOVK => {
+            validate_operand_count(3, operands, opcode)?;
+
+            let rd = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.sink_cnt = 1;
+            instr.sink[0] = rd;
+
+            // the register, the half word and the number of bits it is shifted (0, 16, 32 or 48)
+            instr.source_cnt = 3;
+            instr.source[0] = rd;
+            instr.source[1] = validate_half_word(validate_operand(1, operands, opcode, &[Immediate(0)])?, opcode)?;
+            instr.source[2] = validate_operand(2, operands, opcode, &[Immediate(0)])?;
+            if !matches!(instr.source[2], Immediate(0 | 16 | 32 | 48)) {
+                return Err(format!("{} shift {} must be 0, 16, 32 or 48", mnemonic(opcode), instr.source[2]));
+            }
+        }
+        Opcode::MALLOC => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // the number of words to allocate
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0), Immediate(0)])?;
+        }
+        Opcode::FREE => {
+            validate_operand_count(1, operands, opcode)?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+        }
+        Opcode::MRS => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // the index of the counter in COUNTERS
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Immediate(0)])?;
+            if counter_name(instr.source[0].get_constant()).is_none() {
+                return Err(format!("{} counter {} must be one of {}", mnemonic(opcode), instr.source[0], COUNTERS.join(", ")));
+            }
+        }
+        Opcode::READC => {
+            validate_operand_count(1, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+        }
+        Opcode::VADD |
+        Opcode::VSUB |
+        Opcode::VMUL => {
+            validate_operand_count(3, operands, opcode)?;
+
+            // a single lane; see expand_vector_lanes
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_vector_register(0, operands, opcode)?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_vector_register(1, operands, opcode)?;
+            instr.source[1] = validate_vector_register(2, operands, opcode)?;
+        }
+        Opcode::VLD1 => {
+            validate_operand_count(2, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_vector_register(0, operands, opcode)?;
+
+            // the register containing the address of the first lane
+            instr.source_cnt = 1;
+            instr.source[0] = validate_address_register(1, operands, opcode)?;
+        }
+        Opcode::VST1 => {
+            validate_operand_count(2, operands, opcode)?;
+
+            // the register containing the address of the first lane, followed by the lanes
+            instr.mem_stores = 1;
+            instr.source_cnt = 2;
+            instr.source[0] = validate_address_register(1, operands, opcode)?;
+            instr.source[1] = validate_vector_register(0, operands, opcode)?;
+        }
+        Opcode::LDM(_) => {
+            validate_operand_count(2, operands, opcode)?;
+            let base = validate_operand(0, operands, opcode, &[Register(0)])?;
+            let registers = register_list(validate_operand(1, operands, opcode, &[RegisterList(0)])?);
+            if is_control_operand(&base) {
+                return Err(format!("{} can't use the PC as base register", mnemonic(opcode)));
+            }
+            if registers.contains(&SP) {
+                return Err(format!("{} can't load SP", mnemonic(opcode)));
+            }
+            // the micro-ops after its load would use the loaded value as address
+            if registers.contains(&base.get_register()) {
+                return Err(format!("{} can't load its base register {}", mnemonic(opcode), base));
+            }
+
+            instr.source_cnt = 1;
+            instr.source[0] = base;
+
+            // the loaded registers; the base register follows with writeback (see apply_writeback). A load of
+            // the PC is a branch.
+            instr.sink_cnt = registers.len() as u8;
+            for (k, reg) in registers.iter().enumerate() {
+                instr.sink[k] = Register(*reg);
+            }
+        }
+        Opcode::STM(_) => {
+            validate_operand_count(2, operands, opcode)?;
+            let base = validate_operand(0, operands, opcode, &[Register(0)])?;
+            let registers = register_list(validate_operand(1, operands, opcode, &[RegisterList(0)])?);
+            if is_control_operand(&base) {
+                return Err(format!("{} can't use the PC as base register", mnemonic(opcode)));
+            }
+            if registers.contains(&SP) || registers.contains(&PC) {
+                return Err(format!("{} can't store SP or PC", mnemonic(opcode)));
+            }
+
+            instr.mem_stores = registers.len() as u8;
+            instr.source_cnt = 1 + registers.len() as u8;
+            instr.source[0] = base;
+            for (k, reg) in registers.iter().enumerate() {
+                instr.source[1 + k] = Register(*reg);
+            }
+        }
+        Opcode::CUSTOM(_) => {
+            return Err(format!("Custom instruction {} should be created using its handler", mnemonic(opcode)));
+        }
     }
 
     instr.is_control = is_control(&instr);
     return Ok(instr);
 }
 
-fn validate_operand_count(expected: usize, operands: &Vec<Operand>, opcode: Opcode, loc: SourceLocation) -> Result<(), String> {
+/// Makes a load or store with an immediate offset pre-indexed (`LDR r0, [r1, #1]!`) or post-indexed
+/// (`LDR r0, [r1], #1`): the address register is written back with the address plus the offset, so
+/// it becomes a sink of the instruction. A pre-indexed access uses the updated address, a post-indexed
+/// access the original one.
+pub(crate) fn apply_indexing(instr: &mut Instr, indexing: Indexing) -> Result<(), String> {
+    if indexing == Indexing::Offset {
+        return Ok(());
+    }
+
+    let base_index = match instr.opcode {
+        Opcode::LDR => 0,
+        Opcode::STR => 1,
+        _ => return Err(format!("{} doesn't support pre- or post-indexed addressing", mnemonic(instr.opcode))),
+    };
+    if instr.source_cnt as usize != base_index + 2 {
+        return Err(format!("{} with writeback needs an address register and an immediate offset", mnemonic(instr.opcode)));
+    }
+
+    let base = instr.source[base_index];
+    if is_control_operand(&base) {
+        return Err(format!("{} can't write back to the PC", mnemonic(instr.opcode)));
+    }
+    let transferred = if instr.opcode == Opcode::LDR { instr.sink[0] } else { instr.source[0] };
+    if matches!((transferred, base), (Register(a), Register(b)) if a == b) {
+        return Err(format!("{} with writeback can't transfer its address register {}", mnemonic(instr.opcode), base));
+    }
+
+    instr.sink[instr.sink_cnt as usize] = base;
+    instr.sink_cnt += 1;
+    instr.indexing = indexing;
+    Ok(())
+}
+
+/// Makes an LDM or STM write back its base register (`LDMIA r0!, {r1, r2}`): the base register is moved
+/// past the block of words, so it becomes the last sink of the instruction. The indexing records when the
+/// ARM manual updates it: before the access (IB, DB) or after it (IA, DA).
+pub(crate) fn apply_writeback(instr: &mut Instr) -> Result<(), String> {
+    let mode = match instr.opcode {
+        Opcode::LDM(mode) | Opcode::STM(mode) => mode,
+        _ => return Err(format!("{} doesn't write back a base register; only LDM and STM accept 'r0!'", mnemonic(instr.opcode))),
+    };
+    let base = instr.source[0];
+    if instr.block_registers().iter().any(|reg| reg.get_register() == base.get_register()) {
+        return Err(format!("{} with writeback can't transfer its base register {}", mnemonic(instr.opcode), base));
+    }
+
+    instr.sink[instr.sink_cnt as usize] = base;
+    instr.sink_cnt += 1;
+    instr.indexing = if matches!(mode, BlockMode::IB | BlockMode::DB) { Indexing::PreIndexed } else { Indexing::PostIndexed };
+    Ok(())
+}
+
+// The memory operand of a load or store: the address register and the immediate offset, if any, in the
+// syntax of the indexing; e.g. `[r1, #1]!`.
+pub(crate) fn address_operand(indexing: Indexing, base: &str, offset: Option<&str>) -> String {
+    match (indexing, offset) {
+        (_, None) => format!("[{}]", base),
+        (Indexing::Offset, Some(offset)) => format!("[{}, {}]", base, offset),
+        (Indexing::PreIndexed, Some(offset)) => format!("[{}, {}]!", base, offset),
+        (Indexing::PostIndexed, Some(offset)) => format!("[{}], {}", base, offset),
+    }
+}
+
+// A vector instruction is created for a single lane; this repeats its vector operands for every lane.
+// VADD, VSUB and VMUL read the lanes of the first source, followed by the lanes of the second source.
+pub(crate) fn expand_vector_lanes(instr: &mut Instr, lanes: u8) {
+    let lane = |operand: Operand, k: u8| Register(operand.get_register() + k as RegisterType);
+    match instr.opcode {
+        Opcode::VADD | Opcode::VSUB | Opcode::VMUL => {
+            let (vd, vn, vm) = (instr.sink[0], instr.source[0], instr.source[1]);
+            for k in 0..lanes {
+                instr.sink[k as usize] = lane(vd, k);
+                instr.source[k as usize] = lane(vn, k);
+                instr.source[(lanes + k) as usize] = lane(vm, k);
+            }
+            instr.sink_cnt = lanes;
+            instr.source_cnt = 2 * lanes;
+        }
+        Opcode::VLD1 => {
+            let vd = instr.sink[0];
+            for k in 0..lanes {
+                instr.sink[k as usize] = lane(vd, k);
+            }
+            instr.sink_cnt = lanes;
+        }
+        Opcode::VST1 => {
+            let vd = instr.source[1];
+            for k in 0..lanes {
+                instr.source[1 + k as usize] = lane(vd, k);
+            }
+            instr.source_cnt = 1 + lanes;
+            instr.mem_stores = lanes;
+        }
+        _ => {}
+    }
+}
+
+fn validate_half_word(operand: Operand, opcode: Opcode) -> Result<Operand, String> {
+    match operand {
+        Immediate(value) if !(0..=0xFFFF).contains(&value) =>
+            Err(format!("{} immediate {} doesn't fit in 16 bits", mnemonic(opcode), operand)),
+        _ => Ok(operand),
+    }
+}
+
+// The bit number of a TBZ/TBNZ and the field of a UBFX, SBFX, BFI, MOVT or MOVK must be within a word.
+pub(crate) fn validate_bit_range(instr: &Instr, word_size: u8) -> Result<(), String> {
+    let (lsb, width) = match instr.opcode {
+        Opcode::TBZ | Opcode::TBNZ => (instr.source[1].get_constant(), 1),
+        Opcode::UBFX | Opcode::SBFX => (instr.source[1].get_constant(), instr.source[2].get_constant()),
+        Opcode::BFI => (instr.source[2].get_constant(), instr.source[3].get_constant()),
+        Opcode::MOVT => (16, 16),
+        Opcode::MOVK => (instr.source[2].get_constant(), 16),
+        _ => return Ok(()),
+    };
+
+    if lsb < 0 || lsb >= word_size as WordType {
+        return Err(format!("{} bit {} is outside of a word of {} bits", mnemonic(instr.opcode), lsb, word_size));
+    }
+    if width < 1 || lsb + width > word_size as WordType {
+        return Err(format!("{} field of {} bits from bit {} is outside of a word of {} bits",
+                           mnemonic(instr.opcode), width, lsb, word_size));
+    }
+    Ok(())
+}
+
+// An ARM modified immediate: an 8 bit value rotated right by an even number of bits.
+fn is_modified_immediate(value: u32) -> bool {
+    (0..16).any(|rotation| value.rotate_left(2 * rotation) <= 0xFF)
+}
+
+/// The values the immediate field of a 32-bit ARM encoding can hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ImmediateRange {
+    // an 8 bit value rotated right by an even number of bits
+    Modified,
+    // a value of the given number of bits
+    Unsigned(u8),
+    // a two's complement value of the given number of bits
+    Signed(u8),
+}
+
+impl ImmediateRange {
+    fn contains(self, value: WordType) -> bool {
+        match self {
+            ImmediateRange::Modified => value >= i32::MIN as WordType && value <= u32::MAX as WordType && is_modified_immediate(value as u32),
+            ImmediateRange::Unsigned(bits) => (0..1 << bits).contains(&value),
+            ImmediateRange::Signed(bits) => (-(1 << (bits - 1))..1 << (bits - 1)).contains(&value),
+        }
+    }
+}
+
+impl fmt::Display for ImmediateRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImmediateRange::Modified => write!(f, "modified immediate"),
+            ImmediateRange::Unsigned(bits) => write!(f, "{} bit unsigned value", bits),
+            ImmediateRange::Signed(bits) => write!(f, "{} bit signed value", bits),
+        }
+    }
+}
+
+/// An encoding of an immediate: how an assembler derives the field from the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ImmediateForm {
+    // the value itself
+    Value(ImmediateRange),
+    // the inverted value with the complementary instruction; e.g. MOV r0, #-1 becomes MVN r0, #0
+    Inverted(ImmediateRange),
+    // the negated value with the opposite instruction; e.g. ADD r0, r0, #-1 becomes SUB r0, r0, #1
+    Negated(ImmediateRange),
+}
+
+impl ImmediateForm {
+    fn accepts(self, value: WordType) -> bool {
+        match self {
+            ImmediateForm::Value(range) => range.contains(value),
+            ImmediateForm::Inverted(range) => range.contains(!value),
+            ImmediateForm::Negated(range) => range.contains(value.wrapping_neg()),
+        }
+    }
+}
+
+impl fmt::Display for ImmediateForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImmediateForm::Value(range) => write!(f, "{}", range),
+            ImmediateForm::Inverted(range) => write!(f, "inverted {}", range),
+            ImmediateForm::Negated(range) => write!(f, "negated {}", range),
+        }
+    }
+}
+
+/// The index of the immediate operand of the opcode in the sources and its encodings on a 32-bit ARM (the
+/// T32 encoding for CBZ and CBNZ, which A32 doesn't have, and the A64 encoding for TBZ and TBNZ). A branch
+/// target is encoded as the offset in instructions from the branch. An opcode without encodings has no
+/// immediate form at all; an opcode that isn't listed isn't restricted.
+pub(crate) fn immediate_forms(opcode: Opcode) -> Option<(usize, &'static [ImmediateForm])> {
+    use ImmediateForm::*;
+    use ImmediateRange::*;
+
+    let forms: (usize, &'static [ImmediateForm]) = match opcode {
+        Opcode::MOV => (0, &[Value(Unsigned(16)), Value(Modified), Inverted(Modified)]),
+        Opcode::MVN => (0, &[Value(Modified), Inverted(Modified)]),
+        // ADDW and SUBW take a 12 bit value
+        Opcode::ADD | Opcode::SUB => (1, &[Value(Modified), Negated(Modified), Value(Unsigned(12)), Negated(Unsigned(12))]),
+        Opcode::CMP | Opcode::ADDS | Opcode::SUBS | Opcode::CMN => (1, &[Value(Modified), Negated(Modified)]),
+        // an ADC of an immediate whose inverse is encodable becomes a SBC and the other way around
+        Opcode::ADC | Opcode::SBC | Opcode::ADCS | Opcode::SBCS => (1, &[Value(Modified), Inverted(Modified)]),
+        Opcode::AND | Opcode::ORR | Opcode::EOR | Opcode::TST => (1, &[Value(Modified)]),
+        Opcode::MUL | Opcode::SDIV => (1, &[]),
+        // the offset after the address register; e.g. LDR r0, [r1, #-4]
+        Opcode::LDR => (1, &[Value(Unsigned(12)), Negated(Unsigned(12))]),
+        Opcode::STR => (2, &[Value(Unsigned(12)), Negated(Unsigned(12))]),
+        Opcode::B | Opcode::BL | Opcode::BEQ | Opcode::BNE | Opcode::BLE | Opcode::BLT | Opcode::BGE | Opcode::BGT |
+        Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => (0, &[Value(Signed(24))]),
+        // CBZ and CBNZ only branch forward
+        Opcode::CBZ | Opcode::CBNZ => (1, &[Value(Unsigned(6))]),
+        Opcode::TBZ | Opcode::TBNZ => (2, &[Value(Signed(14))]),
+        _ => return None,
+    };
+    Some(forms)
+}
+
+/// With strict immediates, the immediate operand of an instruction must fit one of the encodings of its
+/// opcode on a 32-bit ARM (see immediate_forms); e.g. 0x12345678 needs a MOVW and a MOVT (or a literal
+/// load) and a CBZ can't branch backward. The `pc` is the code address of the instruction, for the offset
+/// of a branch. Without strict immediates (the relaxed mode) any value is accepted.
+pub(crate) fn validate_immediates(instr: &Instr, pc: usize) -> Result<(), String> {
+    let Some((index, forms)) = immediate_forms(instr.opcode) else {
+        return Ok(());
+    };
+    // e.g. the address register of LDR r0, [r1] has no offset and a literal load has no address register
+    if index >= instr.source_cnt as usize {
+        return Ok(());
+    }
+    let (value, what) = match instr.source[index] {
+        Immediate(value) if matches!(instr.opcode, Opcode::LDR | Opcode::STR) => (value, "offset"),
+        Immediate(value) => (value, "immediate"),
+        Code(target) => (target - pc as WordType, "branch offset"),
+        _ => return Ok(()),
+    };
+    if forms.iter().any(|form| form.accepts(value)) {
+        return Ok(());
+    }
+
+    let hint = match instr.opcode {
+        _ if forms.is_empty() => return Err(format!("{} has no immediate form; load the value {} into a register first",
+                                                     mnemonic(instr.opcode), value)),
+        Opcode::CBZ | Opcode::CBNZ => "; use a CMP with a BEQ or BNE instead",
+        Opcode::LDR | Opcode::STR => "; compute the address in a register first",
+        _ if what == "branch offset" => "; branch through a register with BX or BLX instead",
+        _ => "; use MOVW and MOVT or a literal load (LDR r0, =value)",
+    };
+    let forms: Vec<String> = forms.iter().map(|form| form.to_string()).collect();
+    Err(format!("{} {} {} fits none of its encodings ({}){}", mnemonic(instr.opcode), what, value, forms.join(", "), hint))
+}
+
+/// The size in bytes of the encoding of the instruction: 4 in ARM code. In Thumb code the common
+/// instructions on the low registers (r0-r7) with a small immediate have a 16-bit encoding, like
+/// Thumb-2; e.g. `ADD r0, r0, #1` takes 2 bytes, but `ADD r0, r8, #1` and `UBFX` take 4.
+pub(crate) fn encoded_size(instr: &Instr) -> u8 {
+    if !instr.thumb {
+        return 4;
+    }
+
+    let low = |operand: Operand| matches!(operand, Register(reg) if reg < 8);
+    let imm = |operand: Operand, max: WordType| matches!(operand, Immediate(value) if (0..=max).contains(&value));
+    let low_or_imm = |operand: Operand, max: WordType| low(operand) || imm(operand, max);
+    let same = |a: Operand, b: Operand| matches!((a, b), (Register(x), Register(y)) if x == y);
+    let narrow = match instr.opcode {
+        Opcode::NOP | Opcode::CPSIE | Opcode::CPSID | Opcode::ADR | Opcode::B |
+        Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+        Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => true,
+        // any register can be moved or branched to
+        Opcode::BX | Opcode::BLX => true,
+        Opcode::MOV => matches!(instr.source[0], Register(_)) || (low(instr.sink[0]) && imm(instr.source[0], 0xFF)),
+        Opcode::MVN | Opcode::NEG => low(instr.sink[0]) && low(instr.source[0]),
+        Opcode::ADD | Opcode::SUB | Opcode::ADDS | Opcode::SUBS => {
+            let max = if same(instr.sink[0], instr.source[0]) { 0xFF } else { 7 };
+            low(instr.sink[0]) && low(instr.source[0]) && low_or_imm(instr.source[1], max)
+        }
+        // only the two operand form: the destination is the first source
+        Opcode::AND | Opcode::ORR | Opcode::EOR | Opcode::MUL |
+        Opcode::ADC | Opcode::SBC | Opcode::ADCS | Opcode::SBCS =>
+            same(instr.sink[0], instr.source[0]) && low(instr.sink[0]) && low(instr.source[1]),
+        Opcode::CMP => low(instr.source[0]) && low_or_imm(instr.source[1], 0xFF),
+        Opcode::CMN | Opcode::TST => low(instr.source[0]) && low(instr.source[1]),
+        // a literal load is PC relative
+        // the narrow forms have an offset of up to 31 words and no writeback
+        Opcode::LDR => low(instr.sink[0]) && instr.indexing == Indexing::Offset &&
+            ((low(instr.source[0]) && (instr.source_cnt == 1 || imm(instr.source[1], 31))) || matches!(instr.source[0], Immediate(_))),
+        Opcode::STR => low(instr.source[0]) && low(instr.source[1]) && instr.indexing == Indexing::Offset &&
+            (instr.source_cnt == 2 || imm(instr.source[2], 31)),
+        Opcode::CBZ | Opcode::CBNZ => low(instr.source[0]),
+        // the low registers plus the LR (PUSH) or the PC (POP)
+        Opcode::PUSH => instr.source[1..instr.source_cnt as usize].iter().all(|reg| low(*reg) || matches!(reg, Register(LR))),
+        Opcode::POP => instr.sink[..instr.sink_cnt as usize - 1].iter().all(|reg| low(*reg) || matches!(reg, Register(PC))),
+        // the narrow forms only increment after, with writeback
+        Opcode::LDM(BlockMode::IA) | Opcode::STM(BlockMode::IA) =>
+            low(instr.source[0]) && instr.indexing != Indexing::Offset && instr.block_registers().iter().all(|reg| low(*reg)),
+        _ => false,
+    };
+    if narrow { 2 } else { 4 }
+}
+
+// Returns the registers in a register list in ascending order.
+pub(crate) fn register_list(operand: Operand) -> Vec<RegisterType> {
+    match operand {
+        RegisterList(mask) => (0..GENERAL_ARG_REG_CNT).filter(|reg| mask & (1 << reg) != 0).collect(),
+        _ => panic!("Operand is not a RegisterList but of type {:?}", operand),
+    }
+}
+
+// If the instruction writes the NZCV flags of the CPSR besides its result.
+pub(crate) fn sets_flags(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::ADDS | Opcode::SUBS | Opcode::ADCS | Opcode::SBCS)
+}
+
+// If the instruction writes all the NZCV flags without reading the CPSR. So it doesn't wait for an older flag
+// setter, just like a register write doesn't wait for the previous value of the register. Only the flags of a
+// flag setter are written to the CPSR when it retires; the interrupt mask is kept.
+pub(crate) fn overwrites_flags(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::ADDS | Opcode::SUBS | Opcode::CMP | Opcode::CMN)
+}
+
+pub(crate) fn is_vector_opcode(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::VADD | Opcode::VSUB | Opcode::VMUL | Opcode::VLD1 | Opcode::VST1)
+}
+
+fn validate_vector_register(op_index: usize, operands: &[Operand], opcode: Opcode) -> Result<Operand, String> {
+    let operand = validate_operand(op_index, operands, opcode, &[Register(0)])?;
+    if !is_vector_register(operand.get_register()) {
+        return Err(format!("{} expects a vector register as argument nr {}, but found {}", mnemonic(opcode), op_index + 1, operand));
+    }
+    Ok(operand)
+}
+
+// The general purpose register holding the address of a vector load or store.
+fn validate_address_register(op_index: usize, operands: &[Operand], opcode: Opcode) -> Result<Operand, String> {
+    let operand = validate_operand(op_index, operands, opcode, &[Register(0)])?;
+    if is_vector_register(operand.get_register()) {
+        return Err(format!("{} expects a general purpose register as argument nr {}, but found {}", mnemonic(opcode), op_index + 1, operand));
+    }
+    Ok(operand)
+}
+
+pub(crate) fn is_fp_opcode(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::FADD | Opcode::FSUB | Opcode::FMUL | Opcode::FDIV | Opcode::FCMP | Opcode::FMOV)
+}
+
+// All registers of a floating point instruction must be floating point registers of the same precision.
+fn validate_fp_registers(opcode: Opcode, operands: &Vec<Operand>) -> Result<(), String> {
+    let mut single = None;
+    for operand in operands {
+        if let Register(reg) = operand {
+            if !is_fp_register(*reg) {
+                return Err(format!("{} expects a floating point register, but found {}", mnemonic(opcode), operand));
+            }
+
+            if *single.get_or_insert(is_single_register(*reg)) != is_single_register(*reg) {
+                return Err(format!("{} can't mix single and double precision registers", mnemonic(opcode)));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_operand_count(expected: usize, operands: &[Operand], opcode: Opcode) -> Result<(), String> {
     if operands.len() != expected {
-        return Err(format!("Operand count mismatch. {:?} expects {} arguments, but {} are provided at {}:{}",
-                           opcode, expected, operands.len(), loc.line, loc.column));
+        return Err(format!("Operand count mismatch. {:?} expects {} arguments, but {} are provided",
+                           opcode, expected, operands.len()));
     }
     Ok(())
 }
 
-fn validate_operand(op_index: usize, operands: &Vec<Operand>, opcode: Opcode, acceptable_types: &[Operand]) -> Result<Operand, String> {
+pub(crate) fn validate_operand(op_index: usize, operands: &[Operand], opcode: Opcode, acceptable_types: &[Operand]) -> Result<Operand, String> {
     let operand = operands[op_index];
 
     for &typ in acceptable_types {
@@ -331,7 +1371,7 @@ fn validate_operand(op_index: usize, operands: &Vec<Operand>, opcode: Opcode, ac
                 opcode, acceptable_names_str, op_index + 1, operand.base_name()))
 }
 
-fn is_control(instr: &Instr) -> bool {
+pub(crate) fn is_control(instr: &Instr) -> bool {
     instr.source.iter().any(|op| is_control_operand(op)) ||
         instr.sink.iter().any(|op| is_control_operand(op))
 }
@@ -340,53 +1380,66 @@ fn is_control_operand(op: &Operand) -> bool {
     matches!(op, Register(register) if *register == PC)
 }
 
-pub(crate) const NOP: Instr = Instr {
-    cycles: 1,
-    opcode: Opcode::NOP,
-    source_cnt: 0,
-    source: [Operand::Unused, Operand::Unused, Operand::Unused],
-    sink_cnt: 0,
-    sink: [Operand::Unused, Operand::Unused],
-    loc: None,
-    mem_stores: 0,
-    is_control: false,
-};
-
 pub(crate) const EXIT: Instr = Instr {
     cycles: 1,
     opcode: Opcode::EXIT,
     source_cnt: 0,
-    source: [Operand::Unused, Operand::Unused, Operand::Unused],
+    source: [Operand::Unused; MAX_SOURCE_COUNT as usize],
     sink_cnt: 0,
-    sink: [Operand::Unused, Operand::Unused],
+    sink: [Operand::Unused; MAX_SINK_COUNT as usize],
     loc: None,
     mem_stores: 0,
     is_control: false,
+    thumb: false,
+    indexing: Indexing::Offset,
 };
 
-pub(crate) type RegisterType = u16;
-pub(crate) type WordType = i64;
+pub type RegisterType = u16;
+pub type WordType = i64;
+
+// The index of an instruction in the code of the Program. Instead of the instruction itself, the
+// index is passed through the pipeline; the index just past the last instruction is the implicit EXIT.
+pub(crate) type InstrIndex = u32;
+
+// The EXIT that is executed when a program runs past its last instruction.
+static IMPLICIT_EXIT: Instr = EXIT;
+
+/// A fetched instruction on its way to the backend.
+#[derive(Clone, Copy)]
+pub(crate) struct FetchedInstr {
+    pub(crate) index: InstrIndex,
+    // the code address of the instruction; needed to refetch instructions after a pipeline flush.
+    pub(crate) pc: usize,
+    // the code address the frontend continued fetching from after a control instruction that hit
+    // in the BTB. None if the frontend stopped fetching until the instruction retired.
+    pub(crate) predicted_pc: Option<usize>,
+    // if the predicted_pc of a return came from the return address stack instead of the BTB
+    pub(crate) ras_predicted: bool,
+    pub(crate) fetch_cycle: u64,
+    // unique for every fetched instruction; a refetched instruction gets a new one.
+    pub(crate) seq: u64,
+}
 
 // The InstrQueue sits between frontend and backend
 pub(crate) struct InstrQueue {
     capacity: u16,
     head: u64,
     tail: u64,
-    instructions: Vec<Rc<Instr>>,
+    entries: Vec<FetchedInstr>,
 }
 
 impl InstrQueue {
     pub fn new(capacity: u16) -> Self {
-        let mut instructions = Vec::with_capacity(capacity as usize);
+        let mut entries = Vec::with_capacity(capacity as usize);
         for _ in 0..capacity {
-            instructions.push(Rc::new(NOP));
+            entries.push(FetchedInstr { index: 0, pc: 0, predicted_pc: None, ras_predicted: false, fetch_cycle: 0, seq: 0 });
         }
 
         InstrQueue {
             capacity,
             head: 0,
             tail: 0,
-            instructions,
+            entries,
         }
     }
 
@@ -394,6 +1447,10 @@ impl InstrQueue {
         (self.tail - self.head) as u16
     }
 
+    pub fn capacity(&self) -> u16 {
+        self.capacity
+    }
+
     pub fn is_empty(&self) -> bool {
         self.head == self.tail
     }
@@ -402,11 +1459,11 @@ impl InstrQueue {
         self.size() == self.capacity
     }
 
-    pub fn enqueue(&mut self, instr: Rc<Instr>) {
+    pub fn enqueue(&mut self, fetched: FetchedInstr) {
         assert!(!self.is_full(), "Can't enqueue when InstrQueue is empty.");
 
         let index = (self.tail % self.capacity as u64) as usize;
-        self.instructions[index] = instr;
+        self.entries[index] = fetched;
         self.tail += 1;
     }
 
@@ -415,17 +1472,32 @@ impl InstrQueue {
         self.head += 1;
     }
 
-    pub fn peek(&self) -> Rc<Instr> {
+    pub fn peek(&self) -> FetchedInstr {
         assert!(!self.is_empty(), "Can't peek when InstrQueue is empty.");
 
         let index = (self.head % self.capacity as u64) as usize;
-        return Rc::clone(&self.instructions[index]);
+        self.entries[index]
+    }
+
+    // The queued instructions; oldest first.
+    pub fn entries(&self) -> Vec<FetchedInstr> {
+        (self.head..self.tail)
+            .map(|k| self.entries[(k % self.capacity as u64) as usize])
+            .collect()
+    }
+
+    pub fn flush(&mut self) {
+        self.head = self.tail;
     }
 }
 
-// The maximum number of source (input) operands for an instruction.
-pub(crate) const MAX_SOURCE_COUNT: u8 = 3;
-pub(crate) const MAX_SINK_COUNT: u8 = 2;
+// The maximum number of registers in the register list of PUSH, POP, LDM and STM.
+pub(crate) const MAX_REGISTER_LIST_LEN: u8 = 16;
+// The maximum number of source (input) operands for an instruction; a PUSH reads the SP and its registers.
+// The backend schedules micro-ops, which have far fewer operands; see microcode.
+pub(crate) const MAX_SOURCE_COUNT: u8 = MAX_REGISTER_LIST_LEN + 1;
+// The maximum number of sink (output) operands for an instruction; a POP writes its registers and the SP.
+pub(crate) const MAX_SINK_COUNT: u8 = MAX_REGISTER_LIST_LEN + 1;
 
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct Instr {
@@ -439,40 +1511,186 @@ pub(crate) struct Instr {
     pub(crate) mem_stores: u8,
     // True if the instruction is a control instruction; so a partly serializing instruction (no other instructions)
     pub(crate) is_control: bool,
+    // True if the instruction is in Thumb code (.thumb); it determines the size of its encoding
+    pub(crate) thumb: bool,
+    // How a load or store uses its immediate offset (see apply_indexing); or if an LDM or STM writes back its
+    // base register (see apply_writeback)
+    pub(crate) indexing: Indexing,
+}
+
+/// The addressing mode of a load or store with an immediate offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Indexing {
+    // [r1, #1]; the address register isn't changed
+    Offset,
+    // [r1, #1]!; the address register is updated before the access
+    PreIndexed,
+    // [r1], #1; the address register is updated after the access
+    PostIndexed,
+}
+
+impl Instr {
+    /// True for a call of a function: a BL or a BLX through a register. Both write the return address to LR.
+    pub(crate) fn is_call(&self) -> bool {
+        matches!(self.opcode, Opcode::BL | Opcode::BLX)
+    }
+
+    /// True for a return from a function: a BX LR or a POP (or its LDMFD SP! form) that loads the PC.
+    pub(crate) fn is_return(&self) -> bool {
+        match self.opcode {
+            Opcode::BX => matches!(self.source[0], Register(LR)),
+            Opcode::POP => self.is_control,
+            Opcode::LDM(BlockMode::IA) => self.is_control && matches!(self.source[0], Register(SP)) && self.indexing != Indexing::Offset,
+            _ => false,
+        }
+    }
+
+    /// True for an instruction that reads memory: an LDR through a register, a POP, an LDM or a VLD1. An
+    /// LDR of a literal (LDR r0, =label) doesn't access memory.
+    pub(crate) fn is_load(&self) -> bool {
+        match self.opcode {
+            Opcode::LDR => matches!(self.source[0], Register(_)),
+            Opcode::POP | Opcode::VLD1 | Opcode::LDM(_) => true,
+            _ => false,
+        }
+    }
+
+    /// The registers an LDM or STM transfers, in the order of their words; empty for other instructions.
+    pub(crate) fn block_registers(&self) -> &[Operand] {
+        match self.opcode {
+            // the base register is the last sink with writeback
+            Opcode::LDM(_) => &self.sink[..self.sink_cnt as usize - (self.indexing != Indexing::Offset) as usize],
+            Opcode::STM(_) => &self.source[1..self.source_cnt as usize],
+            _ => &[],
+        }
+    }
+
+    /// Returns a displayable version of this instruction where registers that have an alias
+    /// (declared using .req) are printed using their alias.
+    pub(crate) fn with_aliases<'a>(&'a self, aliases: &'a HashMap<RegisterType, String>) -> AliasedInstr<'a> {
+        AliasedInstr { instr: self, aliases: Some(aliases) }
+    }
 }
 
 impl fmt::Display for Instr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} ", mnemonic(self.opcode))?;
+        AliasedInstr { instr: self, aliases: None }.fmt(f)
+    }
+}
+
+pub(crate) struct AliasedInstr<'a> {
+    instr: &'a Instr,
+    aliases: Option<&'a HashMap<RegisterType, String>>,
+}
+
+impl fmt::Display for AliasedInstr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instr = self.instr;
+        let op = |operand: Operand| AliasedOperand { operand, aliases: self.aliases };
 
-        match self.opcode {
+        write!(f, "{} ", mnemonic(instr.opcode))?;
+
+        match instr.opcode {
             Opcode::ADD |
             Opcode::SUB |
             Opcode::MUL |
             Opcode::SDIV |
             Opcode::AND |
             Opcode::ORR |
-            Opcode::EOR => write!(f, "{}, {}, {}", self.sink[0], self.source[0], self.source[1])?,
-            Opcode::LDR => write!(f, "{}, {}", self.sink[0], self.source[0])?,
-            Opcode::STR => write!(f, "{}, {}", self.source[0], self.sink[0])?,
-            Opcode::MOV => write!(f, "{}, {}", self.sink[0], self.source[0])?,
-            Opcode::NOP => {}
-            Opcode::ADR => write!(f, "{}, {}", self.sink[0], self.source[0])?,
-            Opcode::PRINTR => write!(f, "{}", self.source[0])?,
+            Opcode::EOR |
+            Opcode::ADC |
+            Opcode::SBC |
+            Opcode::ADDS |
+            Opcode::SUBS |
+            Opcode::ADCS |
+            Opcode::SBCS |
+            Opcode::FADD |
+            Opcode::FSUB |
+            Opcode::FMUL |
+            Opcode::FDIV => write!(f, "{}, {}, {}", op(instr.sink[0]), op(instr.source[0]), op(instr.source[1]))?,
+            Opcode::LDR => match instr.source[0] {
+                Immediate(_) => write!(f, "{}, ={}", op(instr.sink[0]), op(instr.source[0]))?,
+                _ => {
+                    let offset = (instr.source_cnt > 1).then(|| format!("#{}", op(instr.source[1])));
+                    write!(f, "{}, {}", op(instr.sink[0]), address_operand(instr.indexing, &op(instr.source[0]).to_string(), offset.as_deref()))?
+                }
+            },
+            Opcode::STR => {
+                let offset = (instr.source_cnt > 2).then(|| format!("#{}", op(instr.source[2])));
+                write!(f, "{}, {}", op(instr.source[0]), address_operand(instr.indexing, &op(instr.source[1]).to_string(), offset.as_deref()))?
+            }
+            Opcode::MOV |
+            Opcode::FMOV => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::NOP |
+            Opcode::CPSIE |
+            Opcode::CPSID |
+            Opcode::ERET |
+            Opcode::DMB |
+            Opcode::DSB => {}
+            Opcode::ADR => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::PRINTR => write!(f, "{}", op(instr.source[0]))?,
             Opcode::B |
             Opcode::BX |
-            Opcode::BL => write!(f, "{}", self.source[0])?,
+            Opcode::BL |
+            Opcode::BLX => write!(f, "{}", op(instr.source[0]))?,
             Opcode::CBZ |
-            Opcode::CBNZ => write!(f, "{}, {}", self.source[0], self.source[1])?,
-            Opcode::NEG => write!(f, "{}, {}", self.sink[0], self.source[0])?,
-            Opcode::MVN => write!(f, "{}, {}", self.sink[0], self.source[0])?,
-            Opcode::CMP => write!(f, "{}, {}", self.source[0], self.source[1])?,
+            Opcode::CBNZ => write!(f, "{}, {}", op(instr.source[0]), op(instr.source[1]))?,
+            Opcode::TBZ |
+            Opcode::TBNZ => write!(f, "{}, {}, {}", op(instr.source[0]), op(instr.source[1]), op(instr.source[2]))?,
+            Opcode::UBFX |
+            Opcode::SBFX => write!(f, "{}, {}, {}, {}", op(instr.sink[0]), op(instr.source[0]), op(instr.source[1]), op(instr.source[2]))?,
+            Opcode::CLZ |
+            Opcode::RBIT => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::BFI => write!(f, "{}, {}, {}, {}", op(instr.sink[0]), op(instr.source[1]), op(instr.source[2]), op(instr.source[3]))?,
+            Opcode::MOVW => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::MOVT => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[1]))?,
+            Opcode::MOVK => write!(f, "{}, {}, {}", op(instr.sink[0]), op(instr.source[1]), op(instr.source[2]))?,
+            Opcode::NEG => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::MVN => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::CMP |
+            Opcode::CMN |
+            Opcode::TST |
+            Opcode::FCMP => write!(f, "{}, {}", op(instr.source[0]), op(instr.source[1]))?,
             Opcode::EXIT => {}
-            Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE =>
-                write!(f, "{}", self.source[0])?,
+            Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+            Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => write!(f, "{}", op(instr.source[0]))?,
+            Opcode::MALLOC => write!(f, "{}, {}", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::FREE => write!(f, "{}", op(instr.source[0]))?,
+            Opcode::MRS => write!(f, "{}, {}", op(instr.sink[0]), counter_name(instr.source[0].get_constant()).unwrap_or("?"))?,
+            Opcode::READC => write!(f, "{}", op(instr.sink[0]))?,
+            Opcode::PUSH | Opcode::POP => {
+                let registers = match instr.opcode {
+                    Opcode::PUSH => &instr.source[1..instr.source_cnt as usize],
+                    // the last sink is the SP
+                    _ => &instr.sink[..instr.sink_cnt as usize - 1],
+                };
+                let registers: Vec<String> = registers.iter().map(|reg| op(*reg).to_string()).collect();
+                write!(f, "{{{}}}", registers.join(", "))?
+            }
+            Opcode::LDM(_) | Opcode::STM(_) => {
+                let writeback = if instr.indexing != Indexing::Offset { "!" } else { "" };
+                let registers: Vec<String> = instr.block_registers().iter().map(|reg| op(*reg).to_string()).collect();
+                write!(f, "{}{}, {{{}}}", op(instr.source[0]), writeback, registers.join(", "))?
+            }
+            // the vector registers are their lane 0
+            Opcode::VADD |
+            Opcode::VSUB |
+            Opcode::VMUL => write!(f, "{}, {}, {}", op(instr.sink[0]), op(instr.source[0]), op(instr.source[instr.sink_cnt as usize]))?,
+            Opcode::VLD1 => write!(f, "{}, [{}]", op(instr.sink[0]), op(instr.source[0]))?,
+            Opcode::VST1 => write!(f, "{}, [{}]", op(instr.source[1]), op(instr.source[0]))?,
+            Opcode::CUSTOM(_) => {
+                let sinks = instr.sink.iter().take(instr.sink_cnt as usize);
+                let sources = instr.source.iter().take(instr.source_cnt as usize);
+                for (k, operand) in sinks.chain(sources).enumerate() {
+                    if k > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", op(*operand))?;
+                }
+            }
         }
 
-        if let Some(loc) = self.loc {
+        if let Some(loc) = instr.loc {
             write!(f, " ; {}:{}", loc.line, loc.column)?;
         }
 
@@ -481,7 +1699,7 @@ impl fmt::Display for Instr {
 }
 
 #[derive(Clone, Copy, Debug)]
-pub(crate) enum Operand {
+pub enum Operand {
     Register(RegisterType),
     // The operand is directly specified in the instruction itself.
     Immediate(WordType),
@@ -491,6 +1709,10 @@ pub(crate) enum Operand {
 
     Code(WordType),
 
+    // A bitmask of general purpose registers like {r0, r1, lr}; bit n is register n. Only used as
+    // an operand of PUSH, POP, LDM and STM until the instruction is created.
+    RegisterList(u32),
+
     Unused,
 }
 
@@ -502,6 +1724,7 @@ impl Operand {
             Immediate(_) => "Immediate",
             Memory(_) => "Memory",
             Code(_) => "Code",
+            RegisterList(_) => "RegisterList",
             Unused => "Unused",
         }
     }
@@ -518,17 +1741,46 @@ impl fmt::Display for Operand {
                     SP => write!(f, "SP"),
                     PC => write!(f, "PC"),
                     CPSR => write!(f, "CPSR"),
+                    FPSCR => write!(f, "FPSCR"),
+                    _ if is_single_register(*reg) => write!(f, "S{}", reg - S_REG_BASE),
+                    _ if is_fp_register(*reg) => write!(f, "D{}", reg - D_REG_BASE),
+                    _ if is_vector_register(*reg) => match vector_lane(*reg) {
+                        (vreg, 0) => write!(f, "V{}", vreg),
+                        (vreg, lane) => write!(f, "V{}[{}]", vreg, lane),
+                    },
                     _ => write!(f, "R{}", reg),
                 }
             }  // Add a comma here
             Immediate(val) => write!(f, "{}", val),
             Memory(addr) => write!(f, "[{}]", addr),
             Code(addr) => write!(f, "[{}]", addr),
+            RegisterList(_) => {
+                let registers: Vec<String> = register_list(*self).iter()
+                    .map(|reg| Register(*reg).to_string())
+                    .collect();
+                write!(f, "{{{}}}", registers.join(", "))
+            }
             Unused => write!(f, "Unused"),
         }
     }
 }
 
+pub(crate) struct AliasedOperand<'a> {
+    pub(crate) operand: Operand,
+    pub(crate) aliases: Option<&'a HashMap<RegisterType, String>>,
+}
+
+impl fmt::Display for AliasedOperand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Register(reg), Some(aliases)) = (self.operand, self.aliases) {
+            if let Some(alias) = aliases.get(&reg) {
+                return write!(f, "{}", alias);
+            }
+        }
+        write!(f, "{}", self.operand)
+    }
+}
+
 //Indexed(u8, i16),   // Indexed addressing mode (base register and offset).
 //Indirect(u8),
 
@@ -567,15 +1819,213 @@ pub(crate) struct Data {
     pub(crate) offset: u64,
 }
 
-pub(crate) struct Program {
+/// A code region with a cycle budget; declared using .deadline and .end_deadline.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Deadline {
+    // the code address of the first instruction in the region
+    pub(crate) start: usize,
+    // the code address of the first instruction after the region
+    pub(crate) end: usize,
+    pub(crate) budget: u64,
+    pub(crate) loc: SourceLocation,
+}
+
+/// The registers and memory words that are set before the first cycle (.reg and .mem); so a test
+/// can start from a prepared state without setup code.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InitialState {
+    pub(crate) registers: Vec<(RegisterType, WordType)>,
+    // (address, value)
+    pub(crate) memory: Vec<(WordType, WordType)>,
+}
+
+/// A check of the state at the end of the program; declared using .expect.
+#[derive(Clone, Debug)]
+pub(crate) struct InlineExpectation {
+    pub(crate) target: ExpectTarget,
+    pub(crate) value: WordType,
+    pub(crate) loc: SourceLocation,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum ExpectTarget {
+    Register(RegisterType),
+    // the variable (with the offset) as written in the directive, and its address
+    Memory(String, u64),
+}
+
+impl fmt::Display for InlineExpectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            ExpectTarget::Register(reg) => write!(f, ".expect {}, {}", Operand::Register(*reg), self.value),
+            ExpectTarget::Memory(location, _) => write!(f, ".expect mem[{}], {}", location, self.value),
+        }
+    }
+}
+
+pub struct Program {
     pub(crate) data_items: HashMap::<String, Rc<Data>>,
-    pub(crate) code: Vec<Rc<Instr>>,
+    pub(crate) code: Vec<Instr>,
     pub(crate) entry_point: usize,
+    // the memory address of the first instruction; code address N is memory address code_base + N
+    pub(crate) code_base: WordType,
+    // register -> alias (.req); used for printing only.
+    pub(crate) register_aliases: HashMap<RegisterType, String>,
+    // the numbering of the registers; the operands are the slots in the register file
+    pub(crate) abi: Abi,
+    pub(crate) symbols: SymbolTable,
+    pub(crate) custom_instructions: CustomInstructions,
+    pub(crate) deadlines: Vec<Deadline>,
+    // the code address of the interrupt handler; declared using .irq_handler
+    pub(crate) irq_handler: Option<usize>,
+    // code address of a backward branch -> the maximum number of iterations of its loop (.loop_bound)
+    pub(crate) loop_bounds: HashMap<usize, u64>,
+    // checked after the program has exited (.expect)
+    pub(crate) expectations: Vec<InlineExpectation>,
+    // applied by the CPU and the reference interpreter when the program is loaded
+    pub(crate) initial_state: InitialState,
+    // the dead code and unreferenced labels found by the loader
+    pub(crate) warnings: Vec<AssemblyWarning>,
+    // code address -> the micro-ops of an instruction that is decoded into micro-ops
+    pub(crate) micro_ops: HashMap<InstrIndex, Vec<Instr>>,
 }
 
 impl Program {
-    pub fn get_instr(&self, pos: usize) -> Rc<Instr> {
-        Rc::clone(&self.code[pos])
+    /// The labels, variables and source lines of the program.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Returns the code address of a label, e.g. `program.address_of("main")`.
+    pub fn address_of(&self, label: &str) -> Option<usize> {
+        self.symbols.address_of(label)
+    }
+
+    /// Returns the label that points to the given code address, if any.
+    pub fn label_at(&self, code_address: usize) -> Option<&str> {
+        self.symbols.label_at(code_address)
+    }
+
+    /// Returns the source line of the instruction at the given code address.
+    pub fn line_for(&self, pc: usize) -> Option<usize> {
+        self.symbols.line_for(pc)
+    }
+
+    /// The warnings of the loader, e.g. unreachable code, ordered on location.
+    pub fn warnings(&self) -> &[AssemblyWarning] {
+        &self.warnings
+    }
+
+    /// The memory address of the instruction at the given code address; e.g. the value of LDR r0, =label.
+    pub(crate) fn memory_address(&self, code_address: usize) -> WordType {
+        self.code_base + code_address as WordType
+    }
+
+    /// The code address of a memory address; e.g. the target of a BX. An address below the code wraps to a
+    /// code address past the end of the program.
+    pub(crate) fn code_address(&self, addr: WordType) -> usize {
+        addr.wrapping_sub(self.code_base) as usize
+    }
+
+    /// The instruction at the given index; the index just past the last instruction is the implicit EXIT.
+    pub(crate) fn instr(&self, index: InstrIndex) -> &Instr {
+        if index as usize == self.code.len() {
+            &IMPLICIT_EXIT
+        } else {
+            &self.code[index as usize]
+        }
+    }
+
+    /// The micro-ops of the instruction at the given index; an instruction that isn't decoded into
+    /// micro-ops is its own single micro-op.
+    pub(crate) fn uops(&self, index: InstrIndex) -> &[Instr] {
+        match self.micro_ops.get(&index) {
+            Some(uops) => uops,
+            None => std::slice::from_ref(self.instr(index)),
+        }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instr(opcode: Opcode, operands: &[Operand]) -> Instr {
+        create_instr(opcode, &operands.to_vec(), SourceLocation { line: 1, column: 1 }).unwrap()
+    }
+
+    fn reads_cpsr(instr: &Instr) -> bool {
+        instr.source[..instr.source_cnt as usize].iter().any(|operand| matches!(operand, Register(CPSR)))
+    }
+
+    fn writes_cpsr(instr: &Instr) -> bool {
+        instr.sink[..instr.sink_cnt as usize].iter().any(|operand| matches!(operand, Register(CPSR)))
+    }
+
+    #[test]
+    fn flag_overwriters_dont_read_the_cpsr() {
+        for opcode in [Opcode::ADDS, Opcode::SUBS] {
+            let instr = instr(opcode, &[Register(0), Register(1), Immediate(1)]);
+            assert!(overwrites_flags(opcode));
+            assert!(!reads_cpsr(&instr), "{} reads the CPSR", mnemonic(opcode));
+            assert!(writes_cpsr(&instr), "{} doesn't write the CPSR", mnemonic(opcode));
+        }
+        for opcode in [Opcode::CMP, Opcode::CMN] {
+            let instr = instr(opcode, &[Register(0), Immediate(1)]);
+            assert!(overwrites_flags(opcode));
+            assert!(!reads_cpsr(&instr), "{} reads the CPSR", mnemonic(opcode));
+            assert!(writes_cpsr(&instr), "{} doesn't write the CPSR", mnemonic(opcode));
+        }
+    }
+
+    #[test]
+    fn carry_readers_depend_on_the_cpsr() {
+        for opcode in [Opcode::ADCS, Opcode::SBCS] {
+            let instr = instr(opcode, &[Register(0), Register(1), Immediate(1)]);
+            assert!(!overwrites_flags(opcode));
+            assert!(reads_cpsr(&instr), "{} doesn't read the carry", mnemonic(opcode));
+            assert!(writes_cpsr(&instr), "{} doesn't write the CPSR", mnemonic(opcode));
+        }
+        for opcode in [Opcode::ADC, Opcode::SBC] {
+            let instr = instr(opcode, &[Register(0), Register(1), Immediate(1)]);
+            assert!(reads_cpsr(&instr), "{} doesn't read the carry", mnemonic(opcode));
+            assert!(!writes_cpsr(&instr), "{} writes the CPSR", mnemonic(opcode));
+        }
+        // a TST keeps the C and V flags
+        let tst = instr(Opcode::TST, &[Register(0), Immediate(1)]);
+        assert!(!overwrites_flags(Opcode::TST));
+        assert!(reads_cpsr(&tst));
+        assert!(writes_cpsr(&tst));
+    }
+
+    #[test]
+    fn non_flag_setters_leave_the_cpsr_alone() {
+        for opcode in [Opcode::ADD, Opcode::SUB] {
+            let instr = instr(opcode, &[Register(0), Register(1), Immediate(1)]);
+            assert!(!overwrites_flags(opcode));
+            assert!(!reads_cpsr(&instr), "{} reads the CPSR", mnemonic(opcode));
+            assert!(!writes_cpsr(&instr), "{} writes the CPSR", mnemonic(opcode));
+        }
+    }
+
+    #[test]
+    fn branch_offsets_are_range_checked() {
+        // every opcode that branches to a label, e.g. BHI label
+        let branches: Vec<Opcode> = OPCODES.iter().copied()
+            .filter(|opcode| create_instr(*opcode, &vec![Code(0)], SourceLocation { line: 1, column: 1 }).is_ok())
+            .collect();
+        assert!(branches.contains(&Opcode::BHI));
+        for opcode in branches {
+            assert!(validate_immediates(&instr(opcode, &[Code(100)]), 0).is_ok(), "{}", mnemonic(opcode));
+            assert!(validate_immediates(&instr(opcode, &[Code(1 << 24)]), 0).is_err(), "{} isn't range checked", mnemonic(opcode));
+        }
+    }
+
+    #[test]
+    fn conditional_branches_read_the_cpsr() {
+        let beq = instr(Opcode::BEQ, &[Code(0)]);
+        assert!(reads_cpsr(&beq));
+        assert!(!writes_cpsr(&beq));
+    }
+}
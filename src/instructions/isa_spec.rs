@@ -0,0 +1,142 @@
+use serde::Serialize;
+
+use crate::abi::abi::Abi;
+use crate::cpu::{CPSR, CPUConfig, D_REG_BASE, Endianness, FPSCR, S_REG_BASE, V_REG_BASE};
+use crate::instructions::instructions::{create_instr, eu_type, mnemonic, Opcode, OPCODES, Operand, SourceLocation};
+
+// The kinds of operands an instruction can be probed with, and how they are named in the specification.
+const OPERAND_KINDS: [(&str, Operand); 8] = [
+    ("register", Operand::Register(0)),
+    ("single", Operand::Register(S_REG_BASE)),
+    ("double", Operand::Register(D_REG_BASE)),
+    // a vector register (v0); the loader expands it into its lanes
+    ("vector", Operand::Register(V_REG_BASE)),
+    ("immediate", Operand::Immediate(0)),
+    ("label", Operand::Code(0)),
+    ("variable", Operand::Memory(0)),
+    // {r1}; an LDM can't load its base register, which is probed as r0
+    ("register_list", Operand::RegisterList(2)),
+];
+
+const MAX_OPERAND_CNT: u32 = 4;
+
+/// A machine readable description of the implemented instruction set.
+///
+/// The description isn't maintained by hand: the operand forms are found by creating every
+/// instruction with every combination of operand kinds, and the flag effects, execution unit and
+/// latency come from the created instruction and the CPU configuration.
+#[derive(Serialize, Debug)]
+pub struct IsaSpec {
+    // the number of bits of a word
+    pub word_size: u8,
+    pub endianness: Endianness,
+    // the general purpose registers and the registers with a special role
+    pub abi: Abi,
+    pub instructions: Vec<InstrSpec>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InstrSpec {
+    pub mnemonic: String,
+    // every accepted combination of operand kinds, e.g. ["register", "register", "immediate"]
+    pub operand_forms: Vec<Vec<String>>,
+    // the status registers (CPSR, FPSCR) that are read
+    pub reads_flags: Vec<String>,
+    // the status registers (CPSR, FPSCR) that are written
+    pub writes_flags: Vec<String>,
+    // if the frontend stops fetching until the instruction retires
+    pub control: bool,
+    pub execution_unit: String,
+    pub latency: u8,
+}
+
+impl IsaSpec {
+    pub fn new(cpu_config: &CPUConfig) -> IsaSpec {
+        let instructions = OPCODES.iter()
+            .map(|opcode| InstrSpec::new(*opcode, cpu_config))
+            .collect();
+        IsaSpec {
+            word_size: cpu_config.word_size,
+            endianness: cpu_config.endianness,
+            abi: cpu_config.abi(),
+            instructions,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+impl InstrSpec {
+    fn new(opcode: Opcode, cpu_config: &CPUConfig) -> InstrSpec {
+        let mut operand_forms = Vec::new();
+        let mut example = None;
+        for operand_cnt in 0..=MAX_OPERAND_CNT {
+            for form in operand_forms_of_len(operand_cnt) {
+                let operands: Vec<Operand> = form.iter().map(|kind| OPERAND_KINDS[*kind].1).collect();
+                if let Ok(instr) = create_instr(opcode, &operands, SourceLocation { line: 0, column: 0 }) {
+                    operand_forms.push(form.iter().map(|kind| String::from(OPERAND_KINDS[*kind].0)).collect());
+                    example.get_or_insert(instr);
+                }
+            }
+        }
+
+        let example = example.unwrap_or_else(|| panic!("{} doesn't accept any operand form", mnemonic(opcode)));
+        let eu_type = eu_type(opcode);
+        InstrSpec {
+            mnemonic: String::from(mnemonic(opcode)),
+            operand_forms,
+            reads_flags: status_registers(&example.source[..example.source_cnt as usize]),
+            writes_flags: status_registers(&example.sink[..example.sink_cnt as usize]),
+            control: example.is_control,
+            execution_unit: eu_type.to_string(),
+            latency: cpu_config.execution_units.get(eu_type).latency,
+        }
+    }
+}
+
+// All combinations of operand kinds (as index in OPERAND_KINDS) with the given length.
+fn operand_forms_of_len(len: u32) -> Vec<Vec<usize>> {
+    let mut forms = vec![vec![]];
+    for _ in 0..len {
+        forms = forms.into_iter()
+            .flat_map(|form| (0..OPERAND_KINDS.len()).map(move |kind| {
+                let mut form = form.clone();
+                form.push(kind);
+                form
+            }))
+            .collect();
+    }
+    forms
+}
+
+fn status_registers(operands: &[Operand]) -> Vec<String> {
+    let mut registers = Vec::new();
+    for operand in operands {
+        match operand {
+            Operand::Register(CPSR) => registers.push(String::from("CPSR")),
+            Operand::Register(FPSCR) => registers.push(String::from("FPSCR")),
+            _ => {}
+        }
+    }
+    registers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_is_described() {
+        let isa_spec = IsaSpec::new(&CPUConfig::default());
+        assert_eq!(isa_spec.instructions.len(), OPCODES.len());
+        for instr_spec in &isa_spec.instructions {
+            assert!(!instr_spec.operand_forms.is_empty(), "{} has no operand form", instr_spec.mnemonic);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use crate::cpu::{EUPoolConfig, SP};
+use crate::instructions::instructions::{eu_type, Indexing, Instr, InstrIndex, MAX_SINK_COUNT, MAX_SOURCE_COUNT, Opcode, Operand, WordType};
+use crate::instructions::instructions::Operand::{Immediate, Register, Unused};
+
+// The maximum number of sources of a micro-op; e.g. a BFI or a TBZ reads 4.
+pub(crate) const MAX_UOP_SOURCE_COUNT: u8 = 4;
+// The maximum number of sinks of a micro-op; e.g. a BL writes the LR and the PC.
+pub(crate) const MAX_UOP_SINK_COUNT: u8 = 2;
+
+/// True for an instruction that is decoded into micro-ops: an instruction with a register list or
+/// with a vector register reads or writes too many registers to be scheduled as a single operation,
+/// and a load or store with writeback also updates its address register.
+pub(crate) fn is_microcoded(instr: &Instr) -> bool {
+    matches!(instr.opcode, Opcode::PUSH | Opcode::POP | Opcode::VADD | Opcode::VSUB | Opcode::VMUL | Opcode::VLD1 | Opcode::VST1 |
+                           Opcode::LDM(_) | Opcode::STM(_))
+        || instr.indexing != Indexing::Offset
+}
+
+/// Decodes the instruction into the micro-ops the backend schedules individually. Every micro-op
+/// accesses at most a single word of memory. The results of the micro-ops, in order, are the
+/// results of the sinks of the instruction. E.g. `POP {r4, pc}` becomes `LDR r4, [sp, #0]`,
+/// `LDR pc, [sp, #1]` and `ADD sp, sp, #2`.
+pub(crate) fn micro_ops(instr: &Instr, execution_units: &EUPoolConfig) -> Vec<Instr> {
+    let sources = &instr.source[..instr.source_cnt as usize];
+    let sinks = &instr.sink[..instr.sink_cnt as usize];
+    let uop = |opcode, sources: &[Operand], sinks: &[Operand]| micro_op(instr, opcode, sources, sinks, execution_units);
+
+    match instr.opcode {
+        Opcode::PUSH => {
+            // the registers are stored below the SP before the SP moves down
+            let registers = &sources[1..];
+            let register_cnt = registers.len() as WordType;
+            let mut uops: Vec<Instr> = registers.iter().enumerate()
+                .map(|(k, reg)| uop(Opcode::STR, &[*reg, Register(SP), Immediate(k as WordType - register_cnt)], &[]))
+                .collect();
+            uops.push(uop(Opcode::SUB, &[Register(SP), Immediate(register_cnt)], &[Register(SP)]));
+            uops
+        }
+        Opcode::POP => {
+            // the last sink is the SP
+            let registers = &sinks[..sinks.len() - 1];
+            let register_cnt = registers.len() as WordType;
+            let mut uops: Vec<Instr> = registers.iter().enumerate()
+                .map(|(k, reg)| uop(Opcode::LDR, &[Register(SP), Immediate(k as WordType)], &[*reg]))
+                .collect();
+            uops.push(uop(Opcode::ADD, &[Register(SP), Immediate(register_cnt)], &[Register(SP)]));
+            uops
+        }
+        Opcode::VADD | Opcode::VSUB | Opcode::VMUL => {
+            let lanes = sinks.len();
+            (0..lanes)
+                .map(|k| uop(instr.opcode, &[sources[k], sources[lanes + k]], &[sinks[k]]))
+                .collect()
+        }
+        Opcode::VLD1 => sinks.iter().enumerate()
+            .map(|(k, lane)| uop(Opcode::VLD1, &[sources[0], Immediate(k as WordType)], &[*lane]))
+            .collect(),
+        Opcode::VST1 => sources[1..].iter().enumerate()
+            .map(|(k, lane)| uop(Opcode::STR, &[*lane, sources[0], Immediate(k as WordType)], &[]))
+            .collect(),
+        // a load or store per register, from the lowest address up, followed by the writeback; e.g.
+        // `STMDB r0!, {r1, r2}` becomes `STR r1, [r0, #-2]`, `STR r2, [r0, #-1]` and `SUB r0, r0, #2`
+        Opcode::LDM(mode) | Opcode::STM(mode) => {
+            let registers = instr.block_registers();
+            let (base, first) = (sources[0], mode.first_offset(registers.len()));
+            let mut uops: Vec<Instr> = registers.iter().enumerate()
+                .map(|(k, reg)| {
+                    let offset = Immediate(first + k as WordType);
+                    if instr.opcode == Opcode::LDM(mode) {
+                        uop(Opcode::LDR, &[base, offset], &[*reg])
+                    } else {
+                        uop(Opcode::STR, &[*reg, base, offset], &[])
+                    }
+                })
+                .collect();
+            if instr.indexing != Indexing::Offset {
+                let opcode = if mode.increments() { Opcode::ADD } else { Opcode::SUB };
+                uops.push(uop(opcode, &[base, Immediate(registers.len() as WordType)], &[base]));
+            }
+            uops
+        }
+        // the last source is the offset and the last sink the address register; e.g. `LDR r0, [r1], #1`
+        // becomes `LDR r0, [r1]` and `ADD r1, r1, #1`
+        Opcode::LDR | Opcode::STR if instr.indexing != Indexing::Offset => {
+            let (base, offset) = (sources[sources.len() - 2], sources[sources.len() - 1]);
+            let access_sources = if instr.indexing == Indexing::PreIndexed { sources } else { &sources[..sources.len() - 1] };
+            vec![
+                uop(instr.opcode, access_sources, &sinks[..sinks.len() - 1]),
+                uop(Opcode::ADD, &[base, offset], &[base]),
+            ]
+        }
+        _ => vec![*instr],
+    }
+}
+
+// A micro-op of the instruction; it keeps the location and the instruction set of the instruction.
+fn micro_op(instr: &Instr, opcode: Opcode, sources: &[Operand], sinks: &[Operand], execution_units: &EUPoolConfig) -> Instr {
+    let mut uop = *instr;
+    uop.opcode = opcode;
+    uop.cycles = execution_units.get(eu_type(opcode)).latency;
+    uop.source = [Unused; MAX_SOURCE_COUNT as usize];
+    uop.source[..sources.len()].copy_from_slice(sources);
+    uop.source_cnt = sources.len() as u8;
+    uop.sink = [Unused; MAX_SINK_COUNT as usize];
+    uop.sink[..sinks.len()].copy_from_slice(sinks);
+    uop.sink_cnt = sinks.len() as u8;
+    uop.mem_stores = if opcode == Opcode::STR { 1 } else { 0 };
+    uop.indexing = Indexing::Offset;
+    // the control flow is handled when the instruction retires as a whole
+    uop.is_control = false;
+    uop
+}
+
+/// The micro-ops of the instructions in the code that are decoded into micro-ops; indexed by the
+/// code address of the instruction.
+pub(crate) fn decode_micro_ops(code: &[Instr], execution_units: &EUPoolConfig) -> HashMap<InstrIndex, Vec<Instr>> {
+    code.iter().enumerate()
+        .filter(|(_, instr)| is_microcoded(instr))
+        .map(|(index, instr)| (index as InstrIndex, micro_ops(instr, execution_units)))
+        .collect()
+}
+
+/// The number of consecutive words accessed by a load or a store.
+pub(crate) fn transfer_cnt(instr: &Instr) -> u8 {
+    match instr.opcode {
+        Opcode::PUSH | Opcode::VST1 => instr.mem_stores,
+        // the last sink of a POP is the SP
+        Opcode::POP => instr.sink_cnt - 1,
+        Opcode::VLD1 => instr.sink_cnt,
+        Opcode::LDM(_) | Opcode::STM(_) => instr.block_registers().len() as u8,
+        _ => 1,
+    }
+}
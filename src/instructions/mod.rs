@@ -1 +1,6 @@
-pub mod instructions;
\ No newline at end of file
+pub mod instructions;
+pub mod custom;
+pub mod disassembler;
+pub mod isa_spec;
+pub mod microcode;
+pub mod symbol_table;
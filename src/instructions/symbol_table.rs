@@ -0,0 +1,83 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::instructions::instructions::{Data, Instr};
+
+/// The symbols of a program: the labels, the variables and the source line of every instruction.
+pub struct SymbolTable {
+    // label -> code address
+    labels: HashMap<String, usize>,
+    // code address -> the labels pointing to it, sorted by name
+    labels_at: BTreeMap<usize, Vec<String>>,
+    // variable -> memory offset
+    variables: HashMap<String, u64>,
+    // memory offset -> variable
+    variables_at: BTreeMap<u64, String>,
+    // code address -> source line; None for an instruction without a source, e.g. a generated one
+    lines: Vec<Option<usize>>,
+}
+
+impl SymbolTable {
+    pub(crate) fn new(labels: &HashMap<String, usize>,
+                      data_items: &HashMap<String, Rc<Data>>,
+                      code: &[Instr]) -> SymbolTable {
+        let mut labels_at: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for (name, address) in labels {
+            labels_at.entry(*address).or_default().push(name.clone());
+        }
+        for names in labels_at.values_mut() {
+            names.sort();
+        }
+
+        SymbolTable {
+            labels: labels.clone(),
+            labels_at,
+            variables: data_items.iter().map(|(name, data)| (name.clone(), data.offset)).collect(),
+            variables_at: data_items.iter().map(|(name, data)| (data.offset, name.clone())).collect(),
+            lines: code.iter().map(|instr| instr.loc.map(|loc| loc.line)).collect(),
+        }
+    }
+
+    /// The code address of a label.
+    pub fn address_of(&self, label: &str) -> Option<usize> {
+        self.labels.get(label).copied()
+    }
+
+    /// The label that points to the given code address. If there are multiple, the first by name.
+    pub fn label_at(&self, code_address: usize) -> Option<&str> {
+        self.labels_at.get(&code_address).map(|names| names[0].as_str())
+    }
+
+    /// All labels with their code address, ordered by address.
+    pub fn labels(&self) -> impl Iterator<Item=(&str, usize)> {
+        self.labels_at.iter()
+            .flat_map(|(address, names)| names.iter().map(move |name| (name.as_str(), *address)))
+    }
+
+    /// The memory offset of a variable.
+    pub fn offset_of(&self, variable: &str) -> Option<u64> {
+        self.variables.get(variable).copied()
+    }
+
+    /// The variable at the given memory offset.
+    pub fn variable_at(&self, offset: u64) -> Option<&str> {
+        self.variables_at.get(&offset).map(|name| name.as_str())
+    }
+
+    /// All variables with their memory offset, ordered by offset.
+    pub fn variables(&self) -> impl Iterator<Item=(&str, u64)> {
+        self.variables_at.iter().map(|(offset, name)| (name.as_str(), *offset))
+    }
+
+    /// The source line of the instruction at the given code address.
+    pub fn line_for(&self, pc: usize) -> Option<usize> {
+        self.lines.get(pc).copied().flatten()
+    }
+
+    /// The code addresses of the instructions on a source line; e.g. to set a breakpoint on a line.
+    pub fn addresses_for_line(&self, line: usize) -> Vec<usize> {
+        (0..self.lines.len())
+            .filter(|pc| self.lines[*pc] == Some(line))
+            .collect()
+    }
+}
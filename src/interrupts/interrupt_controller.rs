@@ -0,0 +1,45 @@
+use crate::instructions::instructions::WordType;
+
+/// The interrupt controller.
+///
+/// Devices raise an IRQ which stays pending till the CPU takes it. When the IRQ is taken, the
+/// return address and the CPSR are saved in banked registers, so the handler is free to use
+/// all normal registers including the LR. ERET restores both.
+pub(crate) struct InterruptController {
+    pub(crate) pending: bool,
+    // if the CPU is executing the interrupt handler
+    pub(crate) active: bool,
+    // the banked LR; the address of the instruction to return to
+    pub(crate) lr_irq: usize,
+    // the saved CPSR
+    pub(crate) spsr: WordType,
+}
+
+impl InterruptController {
+    pub(crate) fn new() -> InterruptController {
+        InterruptController {
+            pending: false,
+            active: false,
+            lr_irq: 0,
+            spsr: 0,
+        }
+    }
+
+    pub(crate) fn raise(&mut self) {
+        self.pending = true;
+    }
+
+    // Takes the pending IRQ; the return address and the CPSR are saved.
+    pub(crate) fn enter(&mut self, return_address: usize, cpsr: WordType) {
+        self.pending = false;
+        self.active = true;
+        self.lr_irq = return_address;
+        self.spsr = cpsr;
+    }
+
+    // Returns the address and the CPSR to return to.
+    pub(crate) fn exit(&mut self) -> (usize, WordType) {
+        self.active = false;
+        (self.lr_irq, self.spsr)
+    }
+}
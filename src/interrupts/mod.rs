@@ -0,0 +1,2 @@
+pub mod interrupt_controller;
+pub mod timer;
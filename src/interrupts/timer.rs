@@ -0,0 +1,37 @@
+use crate::interrupts::interrupt_controller::InterruptController;
+
+/// A timer device that raises an IRQ every 'period' cycles. A period of 0 disables the timer.
+pub(crate) struct Timer {
+    period: u64,
+    counter: u64,
+}
+
+impl Timer {
+    pub(crate) fn new(period: u64) -> Timer {
+        Timer { period, counter: 0 }
+    }
+
+    pub(crate) fn do_cycle(&mut self, interrupt_controller: &mut InterruptController) {
+        if self.period == 0 {
+            return;
+        }
+
+        self.counter += 1;
+        if self.counter == self.period {
+            self.counter = 0;
+            interrupt_controller.raise();
+        }
+    }
+
+    // The number of cycles till the cycle that raises the next IRQ; None when the timer is disabled.
+    pub(crate) fn cycles_till_irq(&self) -> Option<u64> {
+        (self.period > 0).then(|| self.period - self.counter)
+    }
+
+    // Advances the timer by the given number of cycles in which no IRQ is raised.
+    pub(crate) fn skip(&mut self, cycles: u64) {
+        if self.period > 0 {
+            self.counter += cycles;
+        }
+    }
+}
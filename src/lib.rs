@@ -0,0 +1,27 @@
+// every module lives in a file of the same name in its directory, e.g. backend/backend.rs
+#![allow(clippy::module_inception)]
+
+use lalrpop_util::lalrpop_mod;
+
+pub mod cpu;
+pub mod loader;
+pub mod instructions;
+pub mod snapshot;
+mod frontend;
+mod backend;
+mod memory_subsystem;
+pub mod abi;
+mod interrupts;
+pub mod guest_io;
+mod random;
+pub mod analysis;
+pub mod reference_interpreter;
+pub mod fuzz;
+pub mod testgen;
+pub mod conformance;
+pub mod plugin;
+pub mod report;
+#[cfg(feature = "scripting")]
+pub mod script;
+
+lalrpop_mod!(pub assembly, "/loader/assembly.rs");
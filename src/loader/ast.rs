@@ -11,19 +11,38 @@ use std::fmt::{Debug};
 /// Because it is decoupled, it will make it easier to switch to a different parser generator
 /// at some point.
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ASTRegister {
+    // rN; the number as written, which can be beyond the configured register file
+    Numbered(u64),
+    // fp, sp, s0, cpsr, ...; the index of the register
+    Named(u64),
+}
+
 #[derive(Debug)]
 pub enum ASTOperand {
     // register, position
-    Register(u64, usize),
+    Register(ASTRegister, usize),
     // value, position
-    Immediate(u64, usize),
+    Immediate(i64, usize),
     // label name, position
     Label(String, usize),
+    // label name, offset in instructions (or words for a variable), position; e.g. loop+2
+    LabelOffset(String, i64, usize),
+    // label number, forward (1f) or backward (1b), position
+    LocalLabel(u64, bool, usize),
     // the name of the variable
     AddressOf(String, usize),
     // register, offset, position
-    MemRegisterIndirect(u64, usize),
-    //MemRegIndirectWithOffset(u64, u64, usize),
+    MemRegisterIndirect(ASTRegister, usize),
+    // register alias, position
+    MemAliasIndirect(String, usize),
+    // registers, position
+    RegisterList(Vec<ASTRegister>, usize),
+    // register, position; the base register of LDM/STM with writeback, e.g. r0!
+    RegisterWriteback(ASTRegister, usize),
+    // register, offset, writeback (pre-indexed), position; e.g. [r1, #1]!
+    MemRegIndirectWithOffset(ASTRegister, i64, bool, usize),
     //MemRegIndirectWithRegOffset(u64, u64, usize),
     Unused(),
 }
@@ -31,7 +50,7 @@ pub enum ASTOperand {
 #[derive(Debug)]
 pub struct ASTData {
     pub name: String,
-    pub value: u64,
+    pub value: i64,
     pub pos: usize,
 }
 
@@ -41,6 +60,7 @@ pub struct ASTInstr {
     pub op1: ASTOperand,
     pub op2: ASTOperand,
     pub op3: ASTOperand,
+    pub op4: ASTOperand,
     pub pos: usize,
 }
 
@@ -48,6 +68,30 @@ pub struct ASTInstr {
 #[derive(Debug)]
 pub enum ASTDirective {
     Global(String, usize),
+    // the alignment of the next variable as a power of 2 words, position
+    Align(u64, usize),
+    // alias name, register, position
+    Req(String, ASTRegister, usize),
+    // cycle budget, position
+    Deadline(u64, usize),
+    // position
+    EndDeadline(usize),
+    // label of the interrupt handler, position
+    IrqHandler(String, usize),
+    // the maximum number of iterations of the loop closed by the next instruction, position
+    LoopBound(u64, usize),
+    // the next instructions are ARM code, position
+    Arm(usize),
+    // the next instructions are Thumb code, position
+    Thumb(usize),
+    // register, expected value at the end of the program, position
+    ExpectRegister(ASTRegister, i64, usize),
+    // the name in front of the brackets (mem), variable, offset, expected value at the end of the program, position
+    ExpectMemory(String, String, u64, i64, usize),
+    // register, value before the first cycle, position
+    InitRegister(ASTRegister, i64, usize),
+    // variable, offset, value before the first cycle, position
+    InitMemory(String, u64, i64, usize),
 }
 
 #[derive(Debug)]
@@ -85,6 +129,12 @@ pub struct ASTDataSection {
     pub lines: Vec<ASTDataLine>,
 }
 
+#[derive(Debug)]
+pub enum ASTSection {
+    Text(ASTTextSection),
+    Data(ASTDataSection),
+}
+
 #[derive(Debug)]
 pub struct ASTPreamble {
     pub directives: Vec<ASTDirective>,
@@ -93,9 +143,8 @@ pub struct ASTPreamble {
 #[derive(Debug)]
 pub struct ASTAssemblyFile {
     pub preamble: ASTPreamble,
-    pub ds_before: Vec<ASTDataSection>,
-    pub ts: ASTTextSection,
-    pub ds_after: Vec<ASTDataSection>,
+    // in the order of the file
+    pub sections: Vec<ASTSection>,
 }
 
 pub trait ASTVisitor {
@@ -132,6 +181,7 @@ impl ASTInstr {
         if !self.op1.accept(visitor) { return false; }
         if !self.op2.accept(visitor) { return false; }
         if !self.op3.accept(visitor) { return false; }
+        if !self.op4.accept(visitor) { return false; }
         visitor.visit_instr(self)
     }
 }
@@ -189,6 +239,15 @@ impl ASTDataSection {
     }
 }
 
+impl ASTSection {
+    pub fn accept(&self, visitor: &mut dyn ASTVisitor) -> bool {
+        match self {
+            ASTSection::Text(section) => section.accept(visitor),
+            ASTSection::Data(section) => section.accept(visitor),
+        }
+    }
+}
+
 impl ASTPreamble {
     pub fn accept(&self, visitor: &mut dyn ASTVisitor) -> bool {
         for directive in &self.directives {
@@ -202,13 +261,7 @@ impl ASTAssemblyFile {
     pub fn accept(&self, visitor: &mut dyn ASTVisitor) -> bool {
         if !self.preamble.accept(visitor) { return false; }
 
-        for section in &self.ds_before {
-            if !section.accept(visitor) { return false; }
-        }
-
-        if !self.ts.accept(visitor){ return false; }
-
-        for section in &self.ds_after {
+        for section in &self.sections {
             if !section.accept(visitor) { return false; }
         }
         visitor.visit_assembly_file(self)
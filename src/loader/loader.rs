@@ -1,5 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io::Read;
 use std::path::Path;
 use std::rc::Rc;
 use lalrpop_util::ParseError;
@@ -8,98 +10,410 @@ use regex::Regex;
 
 use crate::{assembly};
 
-use crate::cpu::{CPUConfig, GENERAL_ARG_REG_CNT};
-use crate::instructions::instructions::{create_instr, Data, get_opcode, Instr, Opcode, Operand, Program, RegisterType, SourceLocation, WordType};
+use crate::analysis::dead_code::unreachable_code;
+
+use crate::cpu::{CPUConfig, PC};
+use crate::instructions::custom::CustomInstructions;
+use crate::instructions::instructions::{apply_indexing, apply_writeback, COUNTERS, create_instr, Data, Deadline, eu_type, expand_vector_lanes, ExpectTarget, get_opcode, Indexing, InitialState, InlineExpectation, Instr, MAX_REGISTER_LIST_LEN, mnemonic, Opcode, Operand, Program, RegisterType, SourceLocation, validate_bit_range, validate_immediates, WordType};
 use crate::instructions::instructions::Operand::Register;
-use crate::loader::ast::{ASTAssemblyFile, ASTData, ASTDirective, ASTInstr, ASTLabel, ASTOperand, ASTVisitor};
+use crate::instructions::microcode::{decode_micro_ops, micro_ops};
+use crate::instructions::symbol_table::SymbolTable;
+use crate::loader::ast::{ASTAssemblyFile, ASTData, ASTDataLine, ASTDirective, ASTInstr, ASTLabel, ASTOperand, ASTRegister, ASTVisitor};
 use crate::loader::loader::LoadError::AnalysisError;
 
 
+/// The Loader is a two pass assembler.
+///
+/// The first pass (SymbolScan) collects all symbols: labels, variables and register aliases.
+/// The second pass (ProgramGeneration) generates the instructions. Because all symbols are known
+/// after the first pass, labels and variables can be referenced before they are declared; e.g. a
+/// .data section after the .text section. Unresolved symbols don't stop the second pass; they are
+/// collected and reported together at the end.
+///
+/// A program can be linked with library files. Every pass visits the files one after the other, so
+/// the symbols of all files are shared; e.g. a program can call a function declared in a library.
 struct Loader {
     cpu_config: CPUConfig,
+    // the files in the order they are placed in memory: the libraries followed by the program; the source
+    // name of a program that isn't loaded from a file
+    paths: Vec<String>,
+    // the text of every file, in the same order as the paths
+    inputs: Vec<String>,
+    // if the errors and warnings name their file: when there are multiple files or the source is named
+    show_path: bool,
+    // the file that is currently being visited
     path: String,
+    // the index of the file that is currently being visited
+    file_index: usize,
     heap_limit: u32,
+    // the alignment in words of the next variable (.align)
+    pending_alignment: Option<u32>,
+    // the largest alignment of a variable; the variables are placed at such a boundary
+    data_alignment: u32,
     code: Vec<Instr>,
     data_section: HashMap::<String, Rc<Data>>,
     labels: HashMap<String, usize>,
+    // the code addresses of the local labels (e.g. '1:') per file and number, in address order
+    local_labels: HashMap<(usize, u64), Vec<usize>>,
+    // the register aliases declared using .req
+    register_aliases: HashMap<String, RegisterType>,
+    // the first alias declared for every aliased register; traces print it instead of the register
+    register_alias_names: HashMap<RegisterType, String>,
+    // where every label, variable and register alias is declared; used to report duplicates
+    symbol_locations: HashMap<String, String>,
     instr_cnt: usize,
     entry_point: usize,
-    errors: Vec<String>,
+    custom_instructions: CustomInstructions,
+    errors: Vec<AssemblyError>,
+    deadlines: Vec<Deadline>,
+    // the .deadline that hasn't been closed by an .end_deadline yet
+    open_deadline: Option<Deadline>,
+    irq_handler: Option<usize>,
+    loop_bounds: HashMap<usize, u64>,
+    // the .loop_bound that applies to the next instruction
+    pending_loop_bound: Option<(u64, SourceLocation)>,
+    expectations: Vec<InlineExpectation>,
+    initial_state: InitialState,
     input_string: String,
+    // if the code being visited is Thumb code (.thumb); every file starts with ARM code
+    thumb: bool,
+    // per code address if the instruction is Thumb code; collected by the SymbolScan
+    thumb_code: Vec<bool>,
+    // the code address of the first instruction of the program file
+    program_start: usize,
+    // the labels declared in the program file; checked for references when the program has been generated
+    program_labels: Vec<(String, SourceLocation)>,
+    // the labels used by an instruction or a directive
+    referenced_labels: HashSet<String>,
+    // the code addresses loaded as a value (LDR r0, =label); they can be reached through an indirect branch
+    address_taken: Vec<usize>,
+    warnings: Vec<AssemblyWarning>,
+}
+
+/// An error in an assembly file, e.g. an unknown register or a duplicate label.
+#[derive(Clone, Debug)]
+pub struct AssemblyError {
+    /// The file or the name of the source; only set when a program is linked with libraries or its
+    /// source is named (see load_from_reader).
+    pub path: Option<String>,
+    /// None if the error isn't about a line; e.g. the program doesn't fit in memory.
+    pub loc: Option<SourceLocation>,
+    pub message: String,
+    /// Printed after the location; e.g. where a duplicate symbol was declared first.
+    pub note: Option<String>,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(loc) = self.loc {
+            write!(f, " at {}", loc)?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, "; {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// What an [AssemblyWarning] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarningKind {
+    // instructions that can't be reached from the entry point, the interrupt handler or an address taken label
+    UnreachableCode,
+    // a label that no instruction or directive refers to
+    UnreferencedLabel,
+}
+
+/// A problem in an assembly file that doesn't stop it from loading, e.g. dead code.
+#[derive(Clone, Debug)]
+pub struct AssemblyWarning {
+    pub kind: WarningKind,
+    /// The file or the name of the source; only set when a program is linked with libraries or its
+    /// source is named.
+    pub path: Option<String>,
+    pub loc: SourceLocation,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path)?;
+        }
+        write!(f, "{} at {}", self.message, self.loc)
+    }
 }
 
 pub enum LoadError {
     NotFoundError(String),
-    ParseError(String),
-    AnalysisError(Vec<String>),
+    // the first syntax error of every file that can't be parsed
+    ParseError(Vec<AssemblyError>),
+    // all other errors of all files, ordered on file and location
+    AnalysisError(Vec<AssemblyError>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFoundError(msg) => write!(f, "{}", msg),
+            LoadError::ParseError(errors) | LoadError::AnalysisError(errors) => {
+                let lines: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+        }
+    }
 }
 
 impl Loader {
     fn load(&mut self) -> Result<Program, LoadError> {
-        let mut input = match fs::read_to_string(&self.path) {
-            Ok(content) => content,
-            Err(err) => {
-                panic!("Error reading file: {}", err);
+        let mut files = Vec::with_capacity(self.paths.len());
+        for file_index in 0..self.paths.len() {
+            let mut input = self.inputs[file_index].clone();
+            if !input.ends_with('\n') {
+                input.push('\n');
             }
-        };
 
-        if !input.ends_with('\n') {
-            input.push('\n');
+            self.select_file(file_index, input.clone());
+
+            // the other files are still parsed, so all their syntax errors are reported at once
+            match self.parse() {
+                Ok(assembly) => files.push((input, assembly)),
+                Err(error) => self.errors.push(error),
+            }
         }
 
-        self.input_string = input;
+        if !self.errors.is_empty() {
+            return Err(LoadError::ParseError(self.errors.clone()));
+        }
 
-        let assembly = match self.parse() {
-            Ok(value) => value,
-            Err(error) => return error,
-        };
+        for (file_index, (input, assembly)) in files.iter().enumerate() {
+            self.select_file(file_index, input.clone());
+            if self.is_program_file() {
+                // without a .global the program starts at its first instruction
+                self.entry_point = self.instr_cnt;
+                self.program_start = self.instr_cnt;
+            }
+
+            let mut symbolic_scan = SymbolScan { loader: self };
+            assembly.accept(&mut symbolic_scan);
+        }
+
+        self.place_variables();
+
+        for (file_index, (input, assembly)) in files.iter().enumerate() {
+            self.select_file(file_index, input.clone());
 
-        let mut symbolic_scan = SymbolScan { loader: self };
-        assembly.accept(&mut symbolic_scan);
+            let mut program_generation = ProgramGeneration { loader: self, operand_stack: Vec::new() };
+            assembly.accept(&mut program_generation);
 
-        let mut program_generation = ProgramGeneration { loader: self, operand_stack: Vec::new() };
-        assembly.accept(&mut program_generation);
+            if let Some((_, loc)) = self.pending_loop_bound.take() {
+                self.error(loc, String::from(".loop_bound without a branch"));
+            }
 
-        let mut code = Vec::with_capacity(self.code.len());
-        for k in 0..self.code.len() {
-            code.push(Rc::new(*self.code.get_mut(k).unwrap()));
+            if let Some(deadline) = self.open_deadline.take() {
+                self.error(deadline.loc, String::from(".deadline without .end_deadline"));
+            }
         }
 
+        if self.errors.is_empty() {
+            self.find_dead_code();
+        }
+
+        // like an assembler, the errors are reported in the order of the files and the lines
+        let paths = &self.paths;
+        self.errors.sort_by_key(|error| (
+            error.path.as_ref().and_then(|path| paths.iter().position(|p| p == path)),
+            error.loc.map(|loc| (loc.line, loc.column)),
+        ));
+
+        let code = self.code.clone();
+
+        // The aliases are kept on the program so that traces can print the alias instead of the register;
+        // a register that isn't kept in its own slot is printed with its number.
+        let abi = self.cpu_config.abi();
+        let mut register_aliases = abi.register_names();
+        register_aliases.extend(self.register_alias_names.clone());
+
         return if self.errors.is_empty() {
-            Ok(Program { code, data_items: self.data_section.clone(), entry_point: self.entry_point })
+            let symbols = SymbolTable::new(&self.labels, &self.data_section, &code);
+            let micro_ops = decode_micro_ops(&code, &self.cpu_config.execution_units);
+            Ok(Program { code, data_items: self.data_section.clone(), entry_point: self.entry_point, code_base: self.cpu_config.code_base() as WordType, register_aliases, abi, symbols, custom_instructions: self.custom_instructions.clone(), deadlines: self.deadlines.clone(), irq_handler: self.irq_handler, loop_bounds: self.loop_bounds.clone(), expectations: self.expectations.clone(), initial_state: self.initial_state.clone(), warnings: self.warnings.clone(), micro_ops })
         } else {
             Err(AnalysisError(self.errors.clone()))
         };
     }
 
-    fn parse(&mut self) -> Result<ASTAssemblyFile, Result<Program, LoadError>> {
+    // The code occupies the memory from the code base. The variables were placed from address 0 by the
+    // SymbolScan; they are moved to the data_base of the memory layout or, without one, to just after the code.
+    // A randomized layout moves them up a bit further.
+    fn place_variables(&mut self) {
+        let layout = &self.cpu_config.memory_layout;
+        let code_size = self.instr_cnt as u32;
+        let code_base = self.cpu_config.code_base();
+        let code_end = code_base + code_size;
+        // the variables were aligned relative to address 0, so they stay aligned after a boundary
+        let data_start = if layout.data_base > 0 { layout.data_base } else { code_end };
+        let data_base = (data_start + layout.shifts().data).next_multiple_of(self.data_alignment);
+        // after the redzone of the last variable
+        let data_end = data_base + self.heap_limit + if self.data_section.is_empty() { 0 } else { self.cpu_config.redzone_size as u32 };
+        if data_base < code_end && data_end > code_base {
+            let message = format!("The variables at [{}, {}) overlap the {} instructions of the code at [{}, {})",
+                                  data_base, data_end, code_size, code_base, code_end);
+            self.errors.push(AssemblyError { path: None, loc: None, message, note: None });
+            return;
+        }
+        // the heap grows up to the stack
+        if layout.heap_base > 0 && layout.heap_base < code_end {
+            let message = format!("The heap at 'heap_base' {} overlaps the {} instructions of the code at [{}, {})",
+                                  layout.heap_base, code_size, code_base, code_end);
+            self.errors.push(AssemblyError { path: None, loc: None, message, note: None });
+            return;
+        }
+
+        // the code and the variables are placed below the heap and the stack
+        let initial_sp = self.cpu_config.initial_sp();
+        let stack_limit = initial_sp - self.cpu_config.stack_capacity();
+        if code_end > stack_limit {
+            let message = format!("Insufficient memory for {} instructions at {}; they must fit below the stack at [{}, {})",
+                                  code_size, code_base, stack_limit, initial_sp);
+            self.errors.push(AssemblyError { path: None, loc: None, message, note: None });
+            return;
+        }
+        if layout.heap_base > 0 && data_end > layout.heap_base {
+            let message = format!("Insufficient memory for {} words of variables at {}; they must fit below the heap at 'heap_base' {}",
+                                  data_end - data_base, data_base, layout.heap_base);
+            self.errors.push(AssemblyError { path: None, loc: None, message, note: None });
+            return;
+        }
+        if data_base + self.heap_limit > stack_limit {
+            let message = format!("Insufficient memory for {} instructions and {} words of variables at {}; they must fit below the stack at [{}, {})",
+                                  code_size, self.heap_limit, data_base, stack_limit, initial_sp);
+            self.errors.push(AssemblyError { path: None, loc: None, message, note: None });
+            return;
+        }
+
+        for data in self.data_section.values_mut() {
+            *data = Rc::new(Data { value: data.value, offset: data.offset + data_base as u64 });
+        }
+        self.heap_limit += data_base;
+    }
+
+    // Warns about the unreachable instructions and the unreferenced labels of the program file. A library
+    // can hold functions the program doesn't use, so it isn't checked.
+    fn find_dead_code(&mut self) {
+        let path = if self.show_path { self.paths.last().cloned() } else { None };
+
+        let mut roots = vec![self.entry_point];
+        roots.extend(self.irq_handler);
+        roots.extend(&self.address_taken);
+        for (start, end) in unreachable_code(&self.code, self.program_start, &roots) {
+            if let Some(loc) = self.code[start].loc {
+                let cnt = end - start;
+                let message = format!("{} unreachable instruction{}", cnt, if cnt == 1 { "" } else { "s" });
+                self.warnings.push(AssemblyWarning { kind: WarningKind::UnreachableCode, path: path.clone(), loc, message });
+            }
+        }
+
+        for (name, loc) in &self.program_labels {
+            if !self.referenced_labels.contains(name) {
+                let message = format!("Label '{}' is never referenced", name);
+                self.warnings.push(AssemblyWarning { kind: WarningKind::UnreferencedLabel, path: path.clone(), loc: *loc, message });
+            }
+        }
+        self.warnings.sort_by_key(|warning| (warning.loc.line, warning.loc.column));
+    }
+
+    fn select_file(&mut self, file_index: usize, input: String) {
+        self.file_index = file_index;
+        self.path = self.paths[file_index].clone();
+        self.input_string = input;
+        self.thumb = false;
+    }
+
+    // The index of the register; an error for a register that the configured register file doesn't have.
+    fn resolve_register(&self, register: ASTRegister) -> Result<RegisterType, String> {
+        match register {
+            ASTRegister::Named(index) => Ok(index as RegisterType),
+            ASTRegister::Numbered(number) => {
+                let general_reg_count = self.cpu_config.general_reg_count as u64;
+                if number < general_reg_count {
+                    Ok(self.cpu_config.abi().slot(number as RegisterType))
+                } else {
+                    Err(format!("Unknown register r{}; the CPU has {} general purpose registers (r0-r{})",
+                                number, general_reg_count, general_reg_count - 1))
+                }
+            }
+        }
+    }
+
+    // The program is placed after the libraries, so a program that runs past its last instruction exits
+    // instead of continuing in a library.
+    fn is_program_file(&self) -> bool {
+        self.file_index == self.paths.len() - 1
+    }
+
+    // An error at the location in the file being visited. The file is only part of the error when
+    // there are multiple files.
+    fn error_at(&self, loc: SourceLocation, message: String) -> AssemblyError {
+        let path = if self.show_path { Some(self.path.clone()) } else { None };
+        AssemblyError { path, loc: Some(loc), message, note: None }
+    }
+
+    fn error(&mut self, loc: SourceLocation, message: String) {
+        let error = self.error_at(loc, message);
+        self.errors.push(error);
+    }
+
+    // An error about a symbol that is declared twice; it notes where the symbol was declared first.
+    fn duplicate(&mut self, loc: SourceLocation, message: String, name: &str) {
+        let error = self.error_at(loc, message);
+        let note = self.first_declared(name);
+        self.errors.push(AssemblyError { note, ..error });
+    }
+
+    fn declare_symbol(&mut self, name: &str, loc: SourceLocation) {
+        let location = if self.show_path {
+            format!("{}:{}:{}", self.path, loc.line, loc.column)
+        } else {
+            format!("{}:{}", loc.line, loc.column)
+        };
+        self.symbol_locations.entry(name.to_string()).or_insert(location);
+    }
+
+    // e.g. 'first declared at lib.asm:3:1'
+    fn first_declared(&self, name: &str) -> Option<String> {
+        self.symbol_locations.get(name).map(|location| format!("first declared at {}", location))
+    }
+
+    fn parse(&mut self) -> Result<ASTAssemblyFile, AssemblyError> {
         let x = &self.input_string;
         let parse_result = assembly::AssemblyFileParser::new()
             .parse(x.as_str());
 
-        let assembly_file: ASTAssemblyFile = match parse_result {
-            Ok(a) => a,
-            Err(err) => {
-                let cause = match err {
-                    ParseError::InvalidToken { location } => {
-                        let loc = self.to_source_location(location);
-                        format!("Invalid token at {}:{}", loc.line, loc.column)
-                    }
-                    ParseError::UnrecognizedToken { token, expected } => {
-                        let loc = self.to_source_location(token.0);
-                        format!("Unrecognized token '{}' at {}:{}. Expected: {}", token.1, loc.line, loc.column, expected.join(" or "))
-                    }
-                    ParseError::ExtraToken { token } => {
-                        let loc = self.to_source_location(token.0);
-                        format!("Extra token '{}' at {}:{}", token.1, loc.line, loc.column)
-                    }
-                    _ => format!("{:?}", err),
-                };
-
-                return Err(Err(LoadError::ParseError(cause)));
+        parse_result.map_err(|err| match err {
+            ParseError::InvalidToken { location } => {
+                self.error_at(self.to_source_location(location), String::from("Invalid token"))
             }
-        };
-        Ok(assembly_file)
+            ParseError::UnrecognizedEof { location, expected } => {
+                let message = format!("Unexpected end of file. Expected: {}", expected.join(" or "));
+                self.error_at(self.to_source_location(location), message)
+            }
+            ParseError::UnrecognizedToken { token, expected } => {
+                let message = format!("Unrecognized token '{}'", token.1);
+                let error = self.error_at(self.to_source_location(token.0), message);
+                AssemblyError { note: Some(format!("expected: {}", expected.join(" or "))), ..error }
+            }
+            ParseError::ExtraToken { token } => {
+                self.error_at(self.to_source_location(token.0), format!("Extra token '{}'", token.1))
+            }
+            ParseError::User { error: (location, cause) } => {
+                self.error_at(self.to_source_location(location), cause)
+            }
+        })
     }
 
     fn to_source_location(&self, offset: usize) -> SourceLocation {
@@ -129,27 +443,43 @@ pub struct SymbolScan<'a> {
 
 impl ASTVisitor for SymbolScan<'_> {
     fn visit_data(&mut self, ast_data: &ASTData) -> bool {
-        if self.loader.heap_limit == self.loader.cpu_config.memory_size {
+        let redzone_size = self.loader.cpu_config.redzone_size as u32;
+        if self.loader.heap_limit + redzone_size >= self.loader.cpu_config.memory_size {
             let loc = self.loader.to_source_location(ast_data.pos);
-            self.loader.errors.push(format!("Insufficient heap to declare variable '{}' at {}:{}", ast_data.name, loc.line, loc.column));
-            return false;
+            self.loader.error(loc, format!("Insufficient heap to declare variable '{}'", ast_data.name));
+            return true;
         }
 
         if !is_valid_variable_name(&ast_data.name) {
             let loc = self.loader.to_source_location(ast_data.pos);
-            self.loader.errors.push(format!("Illegal variable name '{}' at {}:{}", ast_data.name, loc.line, loc.column));
+            self.loader.error(loc, format!("Illegal variable name '{}'", ast_data.name));
         }
 
         if self.loader.labels.contains_key(&ast_data.name) {
             let loc = self.loader.to_source_location(ast_data.pos);
-            self.loader.errors.push(format!("There already exists a label with name '{}' at {}:{}", ast_data.name, loc.line, loc.column));
+            self.loader.duplicate(loc, format!("There already exists a label with name '{}'", ast_data.name), &ast_data.name);
+        }
+
+        if self.loader.register_aliases.contains_key(&ast_data.name) {
+            let loc = self.loader.to_source_location(ast_data.pos);
+            self.loader.duplicate(loc, format!("There already exists a register alias with name '{}'", ast_data.name), &ast_data.name);
         }
 
         if self.loader.data_section.contains_key(&ast_data.name) {
             let loc = self.loader.to_source_location(ast_data.pos);
-            self.loader.errors.push(format!("Duplicate variable '{}' at {}:{}", ast_data.name, loc.line, loc.column));
+            self.loader.duplicate(loc, format!("Duplicate variable '{}'", ast_data.name), &ast_data.name);
+        }
+
+        let loc = self.loader.to_source_location(ast_data.pos);
+        self.loader.declare_symbol(&ast_data.name, loc);
+
+        // the padding goes in front of the redzone, so the variable itself is aligned
+        if let Some(alignment) = self.loader.pending_alignment.take() {
+            self.loader.heap_limit = (self.loader.heap_limit + redzone_size).next_multiple_of(alignment) - redzone_size;
         }
 
+        // every variable is preceded by a redzone
+        self.loader.heap_limit += redzone_size;
         self.loader.data_section.insert(ast_data.name.clone(),
                                         Rc::new(Data { value: ast_data.value as WordType, offset: self.loader.heap_limit as u64 }));
         self.loader.heap_limit += 1;
@@ -157,22 +487,107 @@ impl ASTVisitor for SymbolScan<'_> {
     }
 
     fn visit_instr(&mut self, _: &ASTInstr) -> bool {
+        self.loader.thumb_code.push(self.loader.thumb);
         self.loader.instr_cnt += 1;
         true
     }
 
     fn visit_label(&mut self, ast_label: &ASTLabel) -> bool {
+        if let Ok(number) = ast_label.name.parse::<u64>() {
+            // a local label isn't a symbol; a reference picks the nearest declaration in the file
+            self.loader.local_labels.entry((self.loader.file_index, number)).or_default().push(self.loader.instr_cnt);
+            return true;
+        }
+
         if self.loader.data_section.contains_key(&ast_label.name) {
             let loc = self.loader.to_source_location(ast_label.pos);
-            self.loader.errors.push(format!("There already exists a variable with name '{}' at {}:{}", ast_label.name, loc.line, loc.column));
+            self.loader.duplicate(loc, format!("There already exists a variable with name '{}'", ast_label.name), &ast_label.name);
+        }
+
+        if self.loader.register_aliases.contains_key(&ast_label.name) {
+            let loc = self.loader.to_source_location(ast_label.pos);
+            self.loader.duplicate(loc, format!("There already exists a register alias with name '{}'", ast_label.name), &ast_label.name);
         }
 
         if self.loader.labels.contains_key(&ast_label.name) {
             let loc = self.loader.to_source_location(ast_label.pos);
-            self.loader.errors.push(format!("Duplicate label '{}' at {}:{}", ast_label.name, loc.line, loc.column));
+            self.loader.duplicate(loc, format!("Duplicate label '{}'", ast_label.name), &ast_label.name);
         } else {
             self.loader.labels.insert(ast_label.name.clone(), self.loader.instr_cnt);
         }
+
+        let loc = self.loader.to_source_location(ast_label.pos);
+        if self.loader.is_program_file() {
+            self.loader.program_labels.push((ast_label.name.clone(), loc));
+        }
+        self.loader.declare_symbol(&ast_label.name, loc);
+        true
+    }
+
+    fn visit_data_line(&mut self, ast_data_line: &ASTDataLine) -> bool {
+        if let ASTDataLine::Directive(ASTDirective::Align(power, pos)) = ast_data_line {
+            // a power of 2 words that fits in the memory
+            match u32::try_from(*power).ok().and_then(|power| 1u32.checked_shl(power)).filter(|alignment| *alignment <= self.loader.cpu_config.memory_size) {
+                Some(alignment) => {
+                    self.loader.pending_alignment = Some(alignment);
+                    self.loader.data_alignment = self.loader.data_alignment.max(alignment);
+                }
+                None => {
+                    let loc = self.loader.to_source_location(*pos);
+                    self.loader.error(loc, format!("The alignment of 2^{} words exceeds the memory", power));
+                }
+            }
+        }
+        true
+    }
+
+    fn visit_directive(&mut self, ast_directive: &ASTDirective) -> bool {
+        match ast_directive {
+            ASTDirective::Global(_, _) => {}
+            // the code is aligned already: every instruction takes one address; see visit_data_line
+            ASTDirective::Align(_, _) => {}
+            ASTDirective::Req(alias, register, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+
+                if !is_valid_variable_name(alias) {
+                    self.loader.error(loc, format!("Illegal register alias '{}'", alias));
+                }
+
+                let register = match self.loader.resolve_register(*register) {
+                    Ok(register) => register,
+                    Err(msg) => {
+                        self.loader.error(loc, msg);
+                        return true;
+                    }
+                };
+
+                if self.loader.labels.contains_key(alias) {
+                    self.loader.duplicate(loc, format!("There already exists a label with name '{}'", alias), alias);
+                }
+
+                if self.loader.data_section.contains_key(alias) {
+                    self.loader.duplicate(loc, format!("There already exists a variable with name '{}'", alias), alias);
+                }
+
+                if self.loader.register_aliases.contains_key(alias) {
+                    self.loader.duplicate(loc, format!("Duplicate register alias '{}'", alias), alias);
+                } else {
+                    self.loader.register_aliases.insert(alias.clone(), register);
+                    self.loader.register_alias_names.entry(register).or_insert_with(|| alias.clone());
+                }
+                self.loader.declare_symbol(alias, loc);
+            }
+            ASTDirective::Arm(_) => self.loader.thumb = false,
+            ASTDirective::Thumb(_) => self.loader.thumb = true,
+            ASTDirective::Deadline(_, _) |
+            ASTDirective::EndDeadline(_) |
+            ASTDirective::IrqHandler(_, _) |
+            ASTDirective::LoopBound(_, _) |
+            ASTDirective::ExpectRegister(_, _, _) |
+            ASTDirective::ExpectMemory(_, _, _, _, _) |
+            ASTDirective::InitRegister(_, _, _) |
+            ASTDirective::InitMemory(_, _, _, _) => {}
+        }
         true
     }
 }
@@ -182,42 +597,146 @@ pub struct ProgramGeneration<'a> {
     operand_stack: Vec<Operand>,
 }
 
+impl ProgramGeneration<'_> {
+    // The index of a register operand; r0 as a placeholder for an unknown register, so that the rest of the
+    // instruction can still be validated.
+    fn resolve_register(&mut self, register: ASTRegister, pos: usize) -> RegisterType {
+        self.loader.resolve_register(register).unwrap_or_else(|msg| {
+            let loc = self.loader.to_source_location(pos);
+            self.loader.error(loc, msg);
+            0
+        })
+    }
+
+    // Pushes the address of the variable or the code label plus the offset; e.g. ADR r0, table+2 or B loop+1.
+    fn push_label(&mut self, label_name: &str, offset: i64, pos: usize) {
+        if let Some(data) = self.loader.data_section.get(label_name) {
+            // e.g. ADR r0, var
+            self.operand_stack.push(Operand::Memory(data.offset as WordType + offset as WordType));
+            return;
+        }
+
+        match self.loader.labels.get(label_name) {
+            Some(code_address) => {
+                self.loader.referenced_labels.insert(label_name.to_string());
+                let target = *code_address as i64 + offset;
+                if target < 0 || target > self.loader.instr_cnt as i64 {
+                    let loc = self.loader.to_source_location(pos);
+                    self.loader.error(loc, format!("The target '{}{:+}' is outside the code", label_name, offset));
+                }
+                self.operand_stack.push(Operand::Code(target.max(0) as WordType));
+            }
+            None => {
+                let loc = self.loader.to_source_location(pos);
+                self.loader.error(loc, format!("Unknown label '{}'", label_name));
+                self.operand_stack.push(Operand::Code(0));
+            }
+        }
+    }
+}
+
+impl ProgramGeneration<'_> {
+    // Only BL, BX, BLX and a POP or LDM of the PC can switch between ARM and Thumb code (interworking); a
+    // direct branch must stay in the same instruction set.
+    fn validate_interworking(&self, instr: &Instr) -> Option<String> {
+        let target = match instr.opcode {
+            Opcode::B | Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+            Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => instr.source[0],
+            Opcode::CBZ | Opcode::CBNZ => instr.source[1],
+            Opcode::TBZ | Opcode::TBNZ => instr.source[2],
+            _ => return None,
+        };
+        let Operand::Code(address) = target else {
+            return None;
+        };
+
+        // the implicit EXIT after the last instruction can be reached from both
+        let target_thumb = self.loader.thumb_code.get(address as usize).copied().unwrap_or(instr.thumb);
+        if target_thumb == instr.thumb {
+            return None;
+        }
+        let set = |thumb: bool| if thumb { "Thumb" } else { "ARM" };
+        Some(format!("{} can't branch from {} to {} code; use BL, BX or BLX to switch the instruction set",
+                     mnemonic(instr.opcode), set(instr.thumb), set(target_thumb)))
+    }
+}
+
 impl ASTVisitor for ProgramGeneration<'_> {
     fn visit_operand(&mut self, ast_operand: &ASTOperand) -> bool {
         match ast_operand {
-            ASTOperand::Register(reg, pos) => {
-                if *reg >= GENERAL_ARG_REG_CNT as u64 {
-                    let loc = self.loader.to_source_location(*pos);
-                    self.loader.errors.push(format!("Unknown register r'{}' at {}:{}", *reg, loc.line, loc.column));
-                    return false;
-                }
-
-                self.operand_stack.push(Register(*reg as RegisterType));
+            ASTOperand::Register(reg, pos) | ASTOperand::RegisterWriteback(reg, pos) => {
+                let register = self.resolve_register(*reg, *pos);
+                self.operand_stack.push(Register(register));
             }
             ASTOperand::Immediate(value, _) => {
                 self.operand_stack.push(Operand::Immediate(*value as WordType));
             }
             ASTOperand::Label(label_name, pos) => {
-                match self.loader.labels.get(label_name) {
+                if let Some(register) = self.loader.register_aliases.get(label_name) {
+                    self.operand_stack.push(Register(*register));
+                    return true;
+                }
+
+                // a performance counter of a MRS, e.g. MRS r0, CYCLES; a symbol with the same name takes precedence
+                let is_symbol = self.loader.labels.contains_key(label_name) || self.loader.data_section.contains_key(label_name);
+                if let Some(counter) = COUNTERS.iter().position(|name| name == label_name).filter(|_| !is_symbol) {
+                    self.operand_stack.push(Operand::Immediate(counter as WordType));
+                    return true;
+                }
+
+                self.push_label(label_name, 0, *pos);
+            }
+            ASTOperand::LabelOffset(label_name, offset, pos) => {
+                if self.loader.register_aliases.contains_key(label_name) {
+                    let loc = self.loader.to_source_location(*pos);
+                    self.loader.error(loc, format!("Register alias '{}' can't have an offset", label_name));
+                    self.operand_stack.push(Register(0));
+                    return true;
+                }
+
+                self.push_label(label_name, *offset, *pos);
+            }
+            ASTOperand::LocalLabel(number, forward, pos) => {
+                // the address of the instruction the operand belongs to
+                let address = self.loader.code.len();
+                let target = self.loader.local_labels.get(&(self.loader.file_index, *number))
+                    .and_then(|addresses| if *forward {
+                        addresses.iter().find(|target| **target > address)
+                    } else {
+                        addresses.iter().rev().find(|target| **target <= address)
+                    });
+
+                match target {
                     Some(code_address) => {
                         self.operand_stack.push(Operand::Code(*code_address as WordType));
                     }
                     None => {
                         let loc = self.loader.to_source_location(*pos);
-                        self.loader.errors.push(format!("Unknown label '{}' at {}:{}", label_name, loc.line, loc.column));
-                        return false;
+                        let (direction, suffix) = if *forward { ("after", "f") } else { ("before", "b") };
+                        let error = self.loader.error_at(loc, format!("Unknown local label '{}{}'", number, suffix));
+                        self.loader.errors.push(AssemblyError { note: Some(format!("there is no '{}:' {} it", number, direction)), ..error });
+                        self.operand_stack.push(Operand::Code(0));
                     }
                 }
             }
             ASTOperand::AddressOf(label_name, pos) => {
+                if let Some(code_address) = self.loader.labels.get(label_name) {
+                    // e.g. LDR r0, =label
+                    self.loader.referenced_labels.insert(label_name.clone());
+                    self.loader.address_taken.push(*code_address);
+                    let addr = self.loader.cpu_config.code_base() as WordType + *code_address as WordType;
+                    self.operand_stack.push(Operand::Immediate(addr));
+                    return true;
+                }
+
                 match self.loader.data_section.get(label_name) {
                     Some(data) => {
                         self.operand_stack.push(Operand::Immediate(data.offset as WordType));
                     }
                     None => {
                         let loc = self.loader.to_source_location(*pos);
-                        self.loader.errors.push(format!("Unknown variable '{}' at {}:{}", label_name, loc.line, loc.column));
-                        return false;
+                        self.loader.error(loc, format!("Unknown variable '{}'", label_name));
+                        self.operand_stack.push(Operand::Immediate(0));
                     }
                 }
             }
@@ -225,10 +744,58 @@ impl ASTVisitor for ProgramGeneration<'_> {
             ASTOperand::Unused() => {}
             ASTOperand::MemRegisterIndirect(register, pos) => {
                 // address
-                self.operand_stack.push(Operand::Register(*register as RegisterType));
+                let register = self.resolve_register(*register, *pos);
+                self.operand_stack.push(Operand::Register(register));
                 // offset
                 //self.operand_stack.push(Operand::Immediate(0));
             }
+            ASTOperand::MemRegIndirectWithOffset(register, offset, _, pos) => {
+                // address and offset; the indexing is determined by the instruction
+                let register = self.resolve_register(*register, *pos);
+                self.operand_stack.push(Operand::Register(register));
+                self.operand_stack.push(Operand::Immediate(*offset as WordType));
+            }
+            ASTOperand::MemAliasIndirect(alias, pos) => {
+                match self.loader.register_aliases.get(alias) {
+                    Some(register) => {
+                        self.operand_stack.push(Operand::Register(*register));
+                    }
+                    None => {
+                        let loc = self.loader.to_source_location(*pos);
+                        self.loader.error(loc, format!("Unknown register alias '{}'", alias));
+                        self.operand_stack.push(Operand::Register(0));
+                    }
+                }
+            }
+            ASTOperand::RegisterList(registers, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                let mut mask: u32 = 0;
+                for reg in registers {
+                    let reg = match self.loader.resolve_register(*reg) {
+                        Ok(reg) => reg,
+                        Err(msg) => {
+                            // once for a range like r0-r99
+                            self.loader.error(loc, msg);
+                            break;
+                        }
+                    };
+                    if reg >= self.loader.cpu_config.general_reg_count {
+                        self.loader.error(loc, format!("Register list can only contain general purpose registers (r0-r{})",
+                                                       self.loader.cpu_config.general_reg_count - 1));
+                        continue;
+                    }
+                    if mask & (1 << reg) != 0 {
+                        self.loader.error(loc, format!("Duplicate register {} in register list", Register(reg)));
+                    }
+                    mask |= 1 << reg;
+                }
+
+                if mask.count_ones() > MAX_REGISTER_LIST_LEN as u32 {
+                    self.loader.error(loc, format!("Register list has more than {} registers", MAX_REGISTER_LIST_LEN));
+                    mask = 1;
+                }
+                self.operand_stack.push(Operand::RegisterList(mask));
+            }
             //ASTOperand::MemoryAccessWithImmediate(_, _, _) => {}
         };
 
@@ -238,20 +805,63 @@ impl ASTVisitor for ProgramGeneration<'_> {
     fn visit_instr(&mut self, ast_instr: &ASTInstr) -> bool {
         // todo: this is very inefficient because for every instruction the whole file content is scanned.
         let loc = self.loader.to_source_location(ast_instr.pos);
-        let mut opcode_option = get_opcode(&ast_instr.mnemonic);
+        let opcode_option = get_opcode(&ast_instr.mnemonic)
+            .or_else(|| self.loader.custom_instructions.find(&ast_instr.mnemonic));
 
-        if opcode_option.is_none() || opcode_option.unwrap() == Opcode::EXIT {
-            self.loader.errors.push(format!("Unknown mnemonic '{}' at {}:{}", ast_instr.mnemonic, loc.line, loc.column));
-            return false;
+        if opcode_option.is_none() {
+            self.loader.error(loc, format!("Unknown mnemonic '{}'", ast_instr.mnemonic));
+            self.operand_stack.clear();
+            return true;
         }
 
         let opcode = opcode_option.unwrap();
-        match create_instr(opcode, &self.operand_stack, loc) {
-            Ok(instr) => {
+        let instr_result = match opcode {
+            Opcode::CUSTOM(custom) => self.loader.custom_instructions.create_instr(custom, &self.operand_stack, loc),
+            _ => create_instr(opcode, &self.operand_stack, loc),
+        };
+        match instr_result {
+            Ok(mut instr) => {
+                if !matches!(opcode, Opcode::CUSTOM(_)) {
+                    instr.cycles = self.loader.cpu_config.execution_units.get(eu_type(opcode)).latency;
+                }
+                if let Err(msg) = apply_indexing(&mut instr, indexing(ast_instr)) {
+                    self.loader.error(loc, msg);
+                }
+                if matches!(ast_instr.op1, ASTOperand::RegisterWriteback(..)) {
+                    if let Err(msg) = apply_writeback(&mut instr) {
+                        self.loader.error(loc, msg);
+                    }
+                }
+                expand_vector_lanes(&mut instr, self.loader.cpu_config.vector.lanes);
+                if let Err(msg) = validate_bit_range(&instr, self.loader.cpu_config.word_size) {
+                    self.loader.error(loc, msg);
+                }
+                if self.loader.cpu_config.strict_immediates {
+                    if let Err(msg) = validate_immediates(&instr, self.loader.code.len()) {
+                        self.loader.error(loc, msg);
+                    }
+                }
+                if instr.mem_stores as u16 > self.loader.cpu_config.sb_capacity {
+                    self.loader.error(loc, format!("{} stores {} words, but the store buffer has a capacity of {}",
+                                                   mnemonic(opcode), instr.mem_stores, self.loader.cpu_config.sb_capacity));
+                }
+                // the micro-ops of an instruction enter the ROB together
+                let uop_cnt = micro_ops(&instr, &self.loader.cpu_config.execution_units).len();
+                if uop_cnt > self.loader.cpu_config.rob_capacity as usize {
+                    self.loader.error(loc, format!("{} decodes into {} micro-ops, but the ROB has a capacity of {}",
+                                                   mnemonic(opcode), uop_cnt, self.loader.cpu_config.rob_capacity));
+                }
+                instr.thumb = self.loader.thumb;
+                if let Some(msg) = self.validate_interworking(&instr) {
+                    self.loader.error(loc, msg);
+                }
+                if let Some((bound, _)) = self.loader.pending_loop_bound.take() {
+                    self.loader.loop_bounds.insert(self.loader.code.len(), bound);
+                }
                 self.loader.code.push(instr);
             }
             Err(msg) => {
-                self.loader.errors.push(format!("{} at {}:{}", msg, loc.line, loc.column));
+                self.loader.error(loc, msg);
             }
         };
         self.operand_stack.clear();
@@ -263,27 +873,213 @@ impl ASTVisitor for ProgramGeneration<'_> {
             ASTDirective::Global(start_label, pos) => {
                 match self.loader.labels.get(start_label) {
                     Some(code_address) => {
-                        self.loader.entry_point = *code_address as usize;
-                        return true;
+                        self.loader.referenced_labels.insert(start_label.clone());
+                        // the .global of a library only exports the label
+                        if self.loader.is_program_file() {
+                            self.loader.entry_point = *code_address;
+                        }
+                    }
+                    None => {
+                        let loc = self.loader.to_source_location(*pos);
+                        self.loader.error(loc, format!("Unknown label '{}'", start_label));
+                    }
+                }
+                true
+            }
+            ASTDirective::Req(_, _, _) |
+            ASTDirective::Align(_, _) => true,
+            ASTDirective::Arm(_) => {
+                self.loader.thumb = false;
+                true
+            }
+            ASTDirective::Thumb(_) => {
+                self.loader.thumb = true;
+                true
+            }
+            ASTDirective::LoopBound(bound, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                if *bound == 0 {
+                    self.loader.error(loc, String::from("The bound of .loop_bound must be larger than 0"));
+                }
+                self.loader.pending_loop_bound = Some((*bound, loc));
+                true
+            }
+            ASTDirective::IrqHandler(handler_label, pos) => {
+                match self.loader.labels.get(handler_label) {
+                    Some(code_address) => {
+                        self.loader.referenced_labels.insert(handler_label.clone());
+                        self.loader.irq_handler = Some(*code_address);
                     }
                     None => {
                         let loc = self.loader.to_source_location(*pos);
-                        self.loader.errors.push(format!("Unknown label '{}' at {}:{}", start_label, loc.line, loc.column));
-                        return false;
+                        self.loader.error(loc, format!("Unknown label '{}'", handler_label));
                     }
                 }
+                true
+            }
+            ASTDirective::Deadline(budget, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                if let Some(open) = self.loader.open_deadline {
+                    let error = self.loader.error_at(loc, String::from("Nested .deadline"));
+                    self.loader.errors.push(AssemblyError { note: Some(format!("the .deadline at {} isn't closed", open.loc)), ..error });
+                    return true;
+                }
+
+                if *budget == 0 {
+                    self.loader.error(loc, String::from("The cycle budget of .deadline must be larger than 0"));
+                }
+
+                let start = self.loader.code.len();
+                self.loader.open_deadline = Some(Deadline { start, end: start, budget: *budget, loc });
+                true
+            }
+            ASTDirective::ExpectRegister(register, value, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                let register = match self.loader.resolve_register(*register) {
+                    Ok(register) => register,
+                    Err(error) => {
+                        self.loader.error(loc, error);
+                        return true;
+                    }
+                };
+                let target = ExpectTarget::Register(register);
+                self.loader.expectations.push(InlineExpectation { target, value: *value as WordType, loc });
+                true
+            }
+            ASTDirective::ExpectMemory(space, variable, offset, value, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                if space != "mem" {
+                    self.loader.error(loc, format!("Unknown .expect target '{}[...]'; use mem[variable]", space));
+                    return true;
+                }
+                let addr = match self.loader.data_section.get(variable) {
+                    Some(data) => data.offset + offset,
+                    None => {
+                        self.loader.error(loc, format!("Unknown variable '{}'", variable));
+                        return true;
+                    }
+                };
+                if addr >= self.loader.cpu_config.memory_size as u64 {
+                    self.loader.error(loc, format!("mem[{}+{}] is outside of the memory", variable, offset));
+                    return true;
+                }
+                let location = if *offset == 0 { variable.clone() } else { format!("{}+{}", variable, offset) };
+                let target = ExpectTarget::Memory(location, addr);
+                self.loader.expectations.push(InlineExpectation { target, value: *value as WordType, loc });
+                true
+            }
+            ASTDirective::InitRegister(register, value, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                let register = match self.loader.resolve_register(*register) {
+                    Ok(register) => register,
+                    Err(error) => {
+                        self.loader.error(loc, error);
+                        return true;
+                    }
+                };
+                if register == PC {
+                    self.loader.error(loc, String::from("The PC can't be set using .reg; the program starts at its entry point"));
+                    return true;
+                }
+                self.loader.initial_state.registers.push((register, *value as WordType));
+                true
+            }
+            ASTDirective::InitMemory(variable, offset, value, pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                let addr = match self.loader.data_section.get(variable) {
+                    Some(data) => data.offset + offset,
+                    None => {
+                        self.loader.error(loc, format!("Unknown variable '{}'", variable));
+                        return true;
+                    }
+                };
+                if addr >= self.loader.cpu_config.memory_size as u64 {
+                    self.loader.error(loc, format!("{}+{} is outside of the memory", variable, offset));
+                    return true;
+                }
+                self.loader.initial_state.memory.push((addr as WordType, *value as WordType));
+                true
+            }
+            ASTDirective::EndDeadline(pos) => {
+                let loc = self.loader.to_source_location(*pos);
+                match self.loader.open_deadline.take() {
+                    Some(mut deadline) => {
+                        deadline.end = self.loader.code.len();
+                        if deadline.start == deadline.end {
+                            self.loader.error(deadline.loc, String::from("Empty .deadline region"));
+                        } else {
+                            self.loader.deadlines.push(deadline);
+                        }
+                    }
+                    None => {
+                        self.loader.error(loc, String::from(".end_deadline without .deadline"));
+                    }
+                }
+                true
             }
         }
     }
 }
 
+/// Parses an immediate literal: a decimal (optionally negative), hexadecimal (0x1F), binary (0b1010)
+/// or character ('A') constant. A character can be one of the escapes \n, \r, \t, \0, \\, \' and \".
+pub(crate) fn parse_immediate(literal: &str) -> Result<WordType, String> {
+    if let Some(c) = literal.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')) {
+        return match c.strip_prefix('\\') {
+            Some("n") => Ok('\n' as WordType),
+            Some("r") => Ok('\r' as WordType),
+            Some("t") => Ok('\t' as WordType),
+            Some("0") => Ok(0),
+            Some(escaped @ ("\\" | "'" | "\"")) => Ok(escaped.chars().next().unwrap() as WordType),
+            Some(_) => Err(format!("Unknown escape in the character {}", literal)),
+            None => Ok(c.chars().next().unwrap() as WordType),
+        };
+    }
+
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, literal),
+    };
+
+    let magnitude = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        u64::from_str_radix(binary, 2)
+    } else {
+        digits.parse::<u64>()
+    }.map_err(|_| format!("Immediate '{}' doesn't fit in 64 bits", literal))?;
+
+    // the magnitude of a negative value can be one larger than the largest positive value.
+    if negative {
+        if magnitude > WordType::MAX as u64 + 1 {
+            return Err(format!("Immediate '{}' doesn't fit in 64 bits", literal));
+        }
+        Ok((magnitude as WordType).wrapping_neg())
+    } else {
+        if magnitude > WordType::MAX as u64 {
+            return Err(format!("Immediate '{}' doesn't fit in 64 bits", literal));
+        }
+        Ok(magnitude as WordType)
+    }
+}
+
+// The indexing of a load or store: `[r1, #1]!` is pre-indexed and an offset after the memory operand,
+// e.g. `[r1], #1`, is post-indexed.
+fn indexing(ast_instr: &ASTInstr) -> Indexing {
+    match (&ast_instr.op2, &ast_instr.op3) {
+        (ASTOperand::MemRegIndirectWithOffset(_, _, true, _), _) => Indexing::PreIndexed,
+        (ASTOperand::MemRegisterIndirect(..) | ASTOperand::MemAliasIndirect(..), ASTOperand::Immediate(..)) => Indexing::PostIndexed,
+        _ => Indexing::Offset,
+    }
+}
+
 fn is_valid_variable_name(name: &String) -> bool {
     if name.is_empty() {
         return false;
     }
 
     // todo: the other registers are ignored.
-    let re = Regex::new(r"^(?i)R\d+$").unwrap();
+    let re = Regex::new(r"^(?i)([RSDV]\d+|CPSR|FPSCR)$").unwrap();
     if re.is_match(name) {
         return false;
     }
@@ -298,23 +1094,114 @@ fn is_valid_variable_name(name: &String) -> bool {
 
 // for the time being we always return the same program
 pub fn load(cpu_config: CPUConfig, path_str: &str) -> Result<Program, LoadError> {
-    let path = Path::new(path_str);
+    load_with_custom_instructions(cpu_config, path_str, CustomInstructions::new())
+}
+
+/// Loads a program linked with library files; e.g. a library with a shared memcpy. The labels and
+/// variables of every file can be referenced from the other files. The first path is the program; its
+/// .global determines the entry point.
+pub fn load_files(cpu_config: CPUConfig, path_strs: &[&str]) -> Result<Program, LoadError> {
+    load_files_with_custom_instructions(cpu_config, path_strs, CustomInstructions::new())
+}
+
+/// Loads a program that can contain the given custom instructions besides the normal instructions.
+pub fn load_with_custom_instructions(cpu_config: CPUConfig,
+                                     path_str: &str,
+                                     custom_instructions: CustomInstructions) -> Result<Program, LoadError> {
+    load_files_with_custom_instructions(cpu_config, &[path_str], custom_instructions)
+}
 
-    if !path.exists() {
-        return Err(LoadError::NotFoundError(format!("File '{}' does not exist.", path_str)));
+/// Loads a program linked with library files that can contain the given custom instructions.
+pub fn load_files_with_custom_instructions(cpu_config: CPUConfig,
+                                           path_strs: &[&str],
+                                           custom_instructions: CustomInstructions) -> Result<Program, LoadError> {
+    if path_strs.is_empty() {
+        return Err(LoadError::NotFoundError(String::from("No file to load.")));
     }
 
+    for path_str in path_strs {
+        if !Path::new(path_str).exists() {
+            return Err(LoadError::NotFoundError(format!("File '{}' does not exist.", path_str)));
+        }
+    }
+
+    // the libraries are placed before the program
+    let mut paths: Vec<String> = path_strs[1..].iter().map(|path| String::from(*path)).collect();
+    paths.push(String::from(path_strs[0]));
+
+    let mut inputs = Vec::with_capacity(paths.len());
+    for path in &paths {
+        match fs::read_to_string(path) {
+            Ok(content) => inputs.push(content),
+            Err(err) => {
+                return Err(LoadError::NotFoundError(format!("Error reading file '{}': {}", path, err)));
+            }
+        }
+    }
+
+    let show_path = paths.len() > 1;
+    link(cpu_config, paths, inputs, show_path, custom_instructions)
+}
+
+/// Loads a program from its source text instead of a file; e.g. a program embedded in a test or generated
+/// by a tool. The errors and warnings have no file, like those of a single file.
+pub fn load_from_str(cpu_config: CPUConfig, source: &str) -> Result<Program, LoadError> {
+    link(cpu_config, vec![String::from("<string>")], vec![String::from(source)], false, CustomInstructions::new())
+}
+
+/// Loads a program from a reader; e.g. the stdin or an entry of an archive. If a name is given, the errors
+/// and warnings are reported with it as their file.
+pub fn load_from_reader(cpu_config: CPUConfig, name: Option<&str>, reader: &mut dyn Read) -> Result<Program, LoadError> {
+    let source_name = String::from(name.unwrap_or("<reader>"));
+    let mut source = String::new();
+    if let Err(err) = reader.read_to_string(&mut source) {
+        return Err(LoadError::NotFoundError(format!("Error reading '{}': {}", source_name, err)));
+    }
+    link(cpu_config, vec![source_name], vec![source], name.is_some(), CustomInstructions::new())
+}
+
+// Assembles the sources and links them into a program; the last source is the program.
+fn link(cpu_config: CPUConfig,
+        paths: Vec<String>,
+        inputs: Vec<String>,
+        show_path: bool,
+        custom_instructions: CustomInstructions) -> Result<Program, LoadError> {
     let mut loader = Loader {
         heap_limit: 0,
+        pending_alignment: None,
+        data_alignment: 1,
         cpu_config,
-        path: String::from(path_str),
+        paths,
+        inputs,
+        show_path,
+        path: String::new(),
+        file_index: 0,
         code: Vec::new(),
         data_section: HashMap::<String, Rc<Data>>::new(),
         labels: HashMap::<String, usize>::new(),
+        local_labels: HashMap::new(),
+        register_aliases: HashMap::<String, RegisterType>::new(),
+        register_alias_names: HashMap::new(),
+        symbol_locations: HashMap::new(),
+        custom_instructions,
         instr_cnt: 0,
         entry_point: 0,
         errors: Vec::new(),
+        deadlines: Vec::new(),
+        open_deadline: None,
+        irq_handler: None,
+        loop_bounds: HashMap::new(),
+        pending_loop_bound: None,
+        expectations: Vec::new(),
+        initial_state: InitialState::default(),
         input_string: String::new(),
+        thumb: false,
+        thumb_code: Vec::new(),
+        program_start: 0,
+        program_labels: Vec::new(),
+        referenced_labels: HashSet::new(),
+        address_taken: Vec::new(),
+        warnings: Vec::new(),
     };
 
     return loader.load();
@@ -1,73 +1,673 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
 use std::process::exit;
 use std::rc::Rc;
-use lalrpop_util::lalrpop_mod;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-use crate::cpu::{CPU, load_cpu_config};
-use crate::loader::loader::{load, LoadError};
-
-mod cpu;
-mod loader;
-mod frontend;
-mod backend;
-mod instructions;
-mod memory_subsystem;
-
-
-lalrpop_mod!(pub assembly, "/loader/assembly.rs");
+use rust_cpu_emulator::analysis::watchpoint::Watchpoint;
+use rust_cpu_emulator::conformance::junit::to_junit_xml;
+use rust_cpu_emulator::conformance::expectation::check_inline;
+use rust_cpu_emulator::conformance::runner::{Outcome, run_suite};
+use rust_cpu_emulator::cpu::{CPU, CPUConfig, load_cpu_config};
+use rust_cpu_emulator::fuzz::differential::{run_differential, run_stress_differential};
+use rust_cpu_emulator::instructions::instructions::{Program, WordType};
+use rust_cpu_emulator::instructions::isa_spec::IsaSpec;
+use rust_cpu_emulator::loader::loader::{load_files, LoadError};
+use rust_cpu_emulator::report::bench::run_bench;
+use rust_cpu_emulator::report::comparison::compare_configs;
+use rust_cpu_emulator::report::pipeline_report::PipelineRecorder;
+#[cfg(feature = "scripting")]
+use rust_cpu_emulator::script::script::run_script;
+use rust_cpu_emulator::snapshot::snapshot::Snapshot;
+use rust_cpu_emulator::testgen::generator::{TestGenerator, TestGenOptions};
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "ARM CPU Emulator")]
-struct Opt {
+struct ProgramOpt {
     /// Path of the file to load
-    #[structopt(short, long, parse(from_os_str))]
+    #[structopt(parse(from_os_str))]
     file: PathBuf,
 
+    /// Links a library file with the program, e.g. a file with shared functions; can be repeated
+    #[structopt(long = "lib", parse(from_os_str))]
+    libs: Vec<PathBuf>,
+
+    /// Passes an argument to the program; the first 4 in r0..r3, the rest on the stack. Can be repeated
+    #[structopt(long = "arg", allow_hyphen_values = true)]
+    args: Vec<WordType>,
+
+    /// Writes a memory image file to memory before the program starts; a .bin file is raw binary, any other file hex text
+    #[structopt(long, parse(from_os_str))]
+    memory_image: Option<PathBuf>,
+
+    /// The address the memory image is written to
+    #[structopt(long, default_value = "0")]
+    image_offset: u64,
+
     /// Sets a custom config file
     #[structopt(short, long, parse(from_os_str), default_value = "cpu.yaml")]
     config: PathBuf,
 }
 
+// parsed once, so the size of the Run variant doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt, Debug)]
+#[structopt(name = "ARM CPU Emulator")]
+enum Command {
+    /// Runs a program
+    Run {
+        #[structopt(flatten)]
+        program: ProgramOpt,
+
+        /// Writes the architectural state at the end of the program to a snapshot file
+        #[structopt(long, parse(from_os_str))]
+        dump_snapshot: Option<PathBuf>,
+
+        /// Compares the architectural state at the end of the program with a snapshot file
+        #[structopt(long, parse(from_os_str))]
+        expect_snapshot: Option<PathBuf>,
+
+        /// Writes an HTML report with a pipeline diagram, a source heat map and the statistics
+        #[structopt(long, parse(from_os_str))]
+        html_report: Option<PathBuf>,
+
+        /// The first cycle shown in the pipeline diagram of the HTML report
+        #[structopt(long, default_value = "1")]
+        report_from: u64,
+
+        /// The number of cycles shown in the pipeline diagram of the HTML report
+        #[structopt(long, default_value = "100")]
+        report_cycles: u64,
+
+        /// Exits the emulator with the exit code of the program
+        #[structopt(long)]
+        exit_code: bool,
+
+        /// Writes the memory at the end of the program to a file; a .bin file is raw binary, any other file hex text
+        #[structopt(long, parse(from_os_str))]
+        dump_memory: Option<PathBuf>,
+
+        /// Writes the call graph with the cycles per function in the callgrind format, e.g. for KCachegrind
+        #[structopt(long, parse(from_os_str))]
+        callgrind: Option<PathBuf>,
+
+        /// Samples the retiring instruction and its call stack every so many cycles (sampling_period)
+        #[structopt(long)]
+        sample_every: Option<u64>,
+
+        /// Writes the sampled call stacks as collapsed stacks, e.g. for flamegraph.pl; needs --sample-every
+        #[structopt(long, parse(from_os_str))]
+        collapsed_stacks: Option<PathBuf>,
+
+        /// Checks every retiring instruction against the reference interpreter (oracle)
+        #[structopt(long)]
+        oracle: bool,
+
+        /// Reports the instructions that trigger the watch expression, e.g. 'mem[count] > 100 && r3 == 0'
+        #[structopt(long)]
+        watch: Vec<String>,
+
+        /// Writes the performance counters in the OpenMetrics (Prometheus) text format, labeled with the program and config
+        #[structopt(long, parse(from_os_str))]
+        metrics: Option<PathBuf>,
+
+        /// Randomizes the memory layout with the seed: the variables, the heap and the stack are moved by a random number of words
+        #[structopt(long)]
+        randomize_layout: Option<u64>,
+    },
+    /// Assembles a program without running it
+    Check {
+        #[structopt(flatten)]
+        program: ProgramOpt,
+    },
+    /// Runs a program while tracing every pipeline stage
+    Trace {
+        #[structopt(flatten)]
+        program: ProgramOpt,
+    },
+    /// Runs a program without tracing and prints the performance counters
+    Stats {
+        #[structopt(flatten)]
+        program: ProgramOpt,
+
+        /// Prints the performance counters as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Runs a program under two configs and prints the difference of the performance counters
+    Compare {
+        /// The program and the baseline config
+        #[structopt(flatten)]
+        program: ProgramOpt,
+
+        /// The config that is compared with the baseline
+        #[structopt(long, parse(from_os_str))]
+        candidate: PathBuf,
+
+        /// Prints the comparison as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Prints a machine readable description of the instruction set
+    Isa {
+        /// Sets a custom config file; used for the latencies
+        #[structopt(short, long, parse(from_os_str), default_value = "cpu.yaml")]
+        config: PathBuf,
+
+        /// Prints the description as TOML instead of JSON
+        #[structopt(long)]
+        toml: bool,
+    },
+    /// Runs random programs on both the CPU and a reference interpreter and compares the results
+    Fuzz {
+        /// Sets a custom config file
+        #[structopt(short, long, parse(from_os_str), default_value = "cpu.yaml")]
+        config: PathBuf,
+
+        /// The seed of the first program; every next program uses the next seed
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+
+        /// The number of programs to run
+        #[structopt(long, default_value = "100")]
+        iterations: u64,
+
+        /// The number of random instructions per program
+        #[structopt(long, default_value = "50")]
+        length: usize,
+
+        /// Generates stress test programs with loops, PUSH/POP pairs and heap accesses
+        #[structopt(long)]
+        stress: bool,
+    },
+    /// Prints a random stress test program as assembly; the same seed gives the same program
+    Testgen {
+        /// Sets a custom config file
+        #[structopt(short, long, parse(from_os_str), default_value = "cpu.yaml")]
+        config: PathBuf,
+
+        /// The seed of the program
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+
+        /// The number of random steps (instructions, loops and PUSH/POP pairs)
+        #[structopt(long, default_value = "50")]
+        length: usize,
+    },
+    /// Runs every .asm test in a directory tree and checks it against its .expect.yaml sidecar file and its .expect directives
+    Conformance {
+        /// The root directory of the test corpus
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// A config to run the tests on; can be repeated to run the tests on multiple configs
+        #[structopt(short, long, parse(from_os_str))]
+        config: Vec<PathBuf>,
+
+        /// Writes a JUnit XML summary
+        #[structopt(long, parse(from_os_str))]
+        junit: Option<PathBuf>,
+    },
+    /// Runs every .asm file with .expect directives in a directory tree and reports every expectation
+    Test {
+        /// The directory with the tests
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Sets a custom config file
+        #[structopt(short, long, parse(from_os_str), default_value = "cpu.yaml")]
+        config: PathBuf,
+    },
+    /// Runs every .asm program in a directory tree and prints a table of the cycles, IPC and miss rates per program
+    Bench {
+        /// The directory with the programs
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+
+        /// Sets a custom config file
+        #[structopt(short, long, parse(from_os_str), default_value = "cpu.yaml")]
+        config: PathBuf,
+
+        /// Prints the table as CSV instead of Markdown
+        #[structopt(long)]
+        csv: bool,
+
+        /// Writes the table to a file instead of printing it
+        #[structopt(long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Runs a Rhai script that automates experiments, e.g. a parameter sweep
+    #[cfg(feature = "scripting")]
+    Script {
+        /// Path of the script
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
 fn main() {
-    let opt = Opt::from_args();
+    match Command::from_args() {
+        Command::Run { program, dump_snapshot, expect_snapshot, html_report, report_from, report_cycles, exit_code, dump_memory, callgrind, sample_every, collapsed_stacks, oracle, watch, metrics, randomize_layout } => {
+            let mut cpu_config = load_config(&program);
+            if callgrind.is_some() {
+                cpu_config.set("call_graph", "true").unwrap();
+            }
+            if let Some(period) = sample_every {
+                cpu_config.set("sampling_period", &period.to_string()).unwrap();
+            }
+            if oracle {
+                cpu_config.set("oracle", "true").unwrap();
+            }
+            if let Some(seed) = randomize_layout {
+                cpu_config.set("memory_layout.seed", &seed.to_string()).unwrap();
+                if let Err(error) = cpu_config.set("memory_layout.randomize", "true") {
+                    println!("{}", error);
+                    exit(1);
+                }
+            }
+            let loaded = load_program(&program, &cpu_config, false);
+            let mut cpu = CPU::new(&cpu_config);
+            for expression in &watch {
+                match Watchpoint::new(expression, &loaded, &cpu_config) {
+                    Ok(watchpoint) => cpu.add_watchpoint(watchpoint),
+                    Err(error) => {
+                        println!("{}", error);
+                        exit(1);
+                    }
+                }
+            }
+
+            let recorder = Rc::new(RefCell::new(PipelineRecorder::new(report_from, report_cycles)));
+            if html_report.is_some() {
+                cpu.add_plugin(Box::new(Rc::clone(&recorder)));
+            }
+
+            let program_exit_code = run_cpu(&mut cpu, &loaded, &program);
+
+            if let Some(report_path) = html_report {
+                let source = fs::read_to_string(&program.file).unwrap_or_default();
+                let title = program.file.display().to_string();
+                let html = recorder.borrow().to_html(&title, &source, &cpu.perf_counters());
+                if let Err(error) = fs::write(&report_path, html) {
+                    println!("Failed to write {}. Cause: {}", report_path.display(), error);
+                    exit(1);
+                }
+            }
+
+            if let Some(dump_path) = dump_memory {
+                if let Err(error) = cpu.dump_memory(dump_path.to_str().unwrap()) {
+                    println!("Failed to write {}. Cause: {}", dump_path.display(), error);
+                    exit(1);
+                }
+            }
+
+            if let Some(callgrind_path) = callgrind {
+                if let Err(error) = cpu.write_callgrind(callgrind_path.to_str().unwrap(), &program.file.display().to_string()) {
+                    println!("Failed to write {}. Cause: {}", callgrind_path.display(), error);
+                    exit(1);
+                }
+            }
+
+            if let Some(stacks_path) = collapsed_stacks {
+                if let Err(error) = cpu.write_collapsed_stacks(stacks_path.to_str().unwrap()) {
+                    println!("Failed to write {}. Cause: {}", stacks_path.display(), error);
+                    exit(1);
+                }
+            }
+
+            if let Some(metrics_path) = metrics {
+                let labels = [("program", program.file.display().to_string()), ("config", program.config.display().to_string())];
+                let labels: Vec<(&str, &str)> = labels.iter().map(|(name, value)| (*name, value.as_str())).collect();
+                if let Err(error) = fs::write(&metrics_path, cpu.perf_counters().to_openmetrics(&labels)) {
+                    println!("Failed to write {}. Cause: {}", metrics_path.display(), error);
+                    exit(1);
+                }
+            }
+
+            check_snapshot(&cpu, &loaded, dump_snapshot, expect_snapshot);
+            check_expectations(&cpu, &loaded);
+
+            if exit_code {
+                // a program that ends with a fault has no exit code
+                exit(program_exit_code.map_or(1, |code| code as i32));
+            }
+        }
+        Command::Check { program } => {
+            let cpu_config = load_config(&program);
+            load_program(&program, &cpu_config, false);
+            println!("No problems found.");
+        }
+        Command::Trace { program } => {
+            let mut cpu_config = load_config(&program);
+            cpu_config.set_trace(true);
+            let loaded = load_program(&program, &cpu_config, false);
+            let mut cpu = CPU::new(&cpu_config);
+            run_cpu(&mut cpu, &loaded, &program);
+        }
+        Command::Stats { program, json } => {
+            let mut cpu_config = load_config(&program);
+            cpu_config.set_trace(false);
+            let loaded = load_program(&program, &cpu_config, json);
+            let mut cpu = CPU::new(&cpu_config);
+            if json {
+                // only the JSON goes to stdout; the output of the program goes to stderr
+                cpu.set_quiet(true);
+                cpu.set_stdout(Box::new(io::stderr()));
+            }
+            run_cpu(&mut cpu, &loaded, &program);
+
+            let perf_counters = cpu.perf_counters();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&perf_counters).unwrap());
+            } else {
+                println!("{}", perf_counters);
+            }
+        }
+        Command::Compare { program, candidate, json } => {
+            let baseline_config = load_config(&program);
+            let candidate_config = load_config_file(&candidate);
+            let mut paths = vec![program.file.to_str().unwrap()];
+            for lib in &program.libs {
+                paths.push(lib.to_str().unwrap());
+            }
+
+            let baseline_name = program.config.display().to_string();
+            let candidate_name = candidate.display().to_string();
+            let memory_image = program.memory_image.as_ref().map(|path| (path.to_str().unwrap(), program.image_offset));
+            match compare_configs(&baseline_name, &baseline_config, &candidate_name, &candidate_config, &paths, &program.args, memory_image) {
+                Ok(comparison) if json => println!("{}", comparison.to_json()),
+                Ok(comparison) => print!("{}", comparison),
+                Err(error) => {
+                    println!("{}", error);
+                    exit(1);
+                }
+            }
+        }
+        Command::Isa { config, toml } => {
+            let isa_spec = IsaSpec::new(&load_config_file(&config));
+            if toml {
+                print!("{}", isa_spec.to_toml());
+            } else {
+                println!("{}", isa_spec.to_json());
+            }
+        }
+        Command::Fuzz { config, seed, iterations, length, stress } => {
+            let cpu_config = load_config_file(&config);
+            let options = TestGenOptions { length, ..TestGenOptions::default() };
+            let mut mismatch_cnt = 0;
+            for iteration in 0..iterations {
+                let result = if stress {
+                    run_stress_differential(&cpu_config, seed + iteration, &options)
+                } else {
+                    run_differential(&cpu_config, seed + iteration, length)
+                };
+                if let Err(mismatch) = result {
+                    mismatch_cnt += 1;
+                    println!("Mismatch for seed {}: {}", mismatch.seed, mismatch.reason);
+                    println!("{}", mismatch.program.disassemble());
+                }
+            }
+
+            println!("{} of {} programs mismatched", mismatch_cnt, iterations);
+            if mismatch_cnt > 0 {
+                exit(1);
+            }
+        }
+        Command::Testgen { config, seed, length } => {
+            let cpu_config = load_config_file(&config);
+            let options = TestGenOptions { length, ..TestGenOptions::default() };
+            print!("{}", TestGenerator::new(&cpu_config, seed, options).generate().disassemble());
+        }
+        Command::Conformance { dir, mut config, junit } => {
+            if config.is_empty() {
+                config.push(PathBuf::from("cpu.yaml"));
+            }
+
+            let mut suites = Vec::new();
+            for config_path in &config {
+                let cpu_config = load_config_file(config_path);
+                let config_name = config_path.display().to_string();
+                match run_suite(&dir, &config_name, &cpu_config) {
+                    Ok(suite) => suites.push(suite),
+                    Err(error) => {
+                        println!("Failed to read {}. Cause: {}", dir.display(), error);
+                        exit(1);
+                    }
+                }
+            }
+
+            let mut unsuccessful_cnt = 0;
+            for suite in &suites {
+                for result in &suite.results {
+                    match &result.outcome {
+                        Outcome::Passed | Outcome::Skipped => {}
+                        Outcome::Failed(failures) => {
+                            println!("FAIL [{}] {}", suite.config_name, result.name);
+                            for failure in failures {
+                                println!("  {}", failure);
+                            }
+                        }
+                        Outcome::Error(error) => {
+                            println!("ERROR [{}] {}", suite.config_name, result.name);
+                            println!("  {}", error);
+                        }
+                    }
+                }
+                unsuccessful_cnt += suite.failure_cnt() + suite.error_cnt();
+            }
 
-    let cpu_config_path = opt.config.to_str().unwrap();
-    let cpu_config = match load_cpu_config(cpu_config_path) {
+            if let Some(junit_path) = junit {
+                if let Err(error) = fs::write(&junit_path, to_junit_xml(&suites)) {
+                    println!("Failed to write {}. Cause: {}", junit_path.display(), error);
+                    exit(1);
+                }
+            }
+
+            let total: usize = suites.iter().map(|suite| suite.results.len()).sum();
+            println!("{} of {} test run(s) failed", unsuccessful_cnt, total);
+            if unsuccessful_cnt > 0 {
+                exit(1);
+            }
+        }
+        Command::Test { dir, config } => {
+            let cpu_config = load_config_file(&config);
+            let suite = match run_suite(&dir, &config.display().to_string(), &cpu_config) {
+                Ok(suite) => suite,
+                Err(error) => {
+                    println!("Failed to read {}. Cause: {}", dir.display(), error);
+                    exit(1);
+                }
+            };
+
+            let mut expectation_cnt = 0;
+            let mut failed_cnt = 0;
+            for result in &suite.results {
+                match &result.outcome {
+                    Outcome::Skipped => continue,
+                    Outcome::Passed => println!("PASS {}", result.name),
+                    Outcome::Failed(_) => println!("FAIL {}", result.name),
+                    Outcome::Error(error) => println!("ERROR {}\n  {}", result.name, error),
+                }
+                for outcome in &result.inline {
+                    match &outcome.failure {
+                        None => println!("  PASS line {}: {}", outcome.line, outcome.directive),
+                        Some(failure) => println!("  FAIL line {}: {}: {}", outcome.line, outcome.directive, failure),
+                    }
+                }
+                if let Outcome::Failed(failures) = &result.outcome {
+                    // the failures of the sidecar file; the failed directives are already listed
+                    for failure in failures.iter().skip(result.inline.iter().filter(|outcome| !outcome.passed()).count()) {
+                        println!("  {}", failure);
+                    }
+                }
+                expectation_cnt += result.inline.len();
+                failed_cnt += result.inline.iter().filter(|outcome| !outcome.passed()).count();
+            }
+
+            let unsuccessful_cnt = suite.failure_cnt() + suite.error_cnt();
+            println!("{} of {} expectation(s) failed; {} of {} test(s) failed",
+                     failed_cnt, expectation_cnt, unsuccessful_cnt, suite.results.len() - suite.skipped_cnt());
+            if unsuccessful_cnt > 0 {
+                exit(1);
+            }
+        }
+        Command::Bench { dir, config, csv, output } => {
+            let cpu_config = load_config_file(&config);
+            let result = match run_bench(&dir, &config.display().to_string(), &cpu_config) {
+                Ok(result) => result,
+                Err(error) => {
+                    println!("Failed to read {}. Cause: {}", dir.display(), error);
+                    exit(1);
+                }
+            };
+
+            let table = if csv { result.to_csv() } else { result.to_markdown() };
+            let summary = format!("{} of {} program(s) failed", result.error_cnt(), result.rows.len());
+            match output {
+                Some(output_path) => {
+                    if let Err(error) = fs::write(&output_path, table) {
+                        println!("Failed to write {}. Cause: {}", output_path.display(), error);
+                        exit(1);
+                    }
+                    println!("{}", summary);
+                }
+                // the stdout is the table, e.g. bench --csv > out.csv
+                None => {
+                    print!("{}", table);
+                    eprintln!("{}", summary);
+                }
+            }
+            if result.error_cnt() > 0 {
+                exit(1);
+            }
+        }
+        #[cfg(feature = "scripting")]
+        Command::Script { file } => {
+            if let Err(error) = run_script(file.to_str().unwrap()) {
+                println!("{}", error);
+                exit(1);
+            }
+        }
+    }
+}
+
+fn load_config(opt: &ProgramOpt) -> CPUConfig {
+    load_config_file(&opt.config)
+}
+
+fn load_config_file(path: &Path) -> CPUConfig {
+    let cpu_config_path = path.to_str().unwrap();
+    match load_cpu_config(cpu_config_path) {
         Ok(config) => config,
         Err(error) => {
             println!("Failed to load {}. Cause: {}", cpu_config_path, error);
-            exit(0);
+            exit(1);
         }
-    };
+    }
+}
 
+// Quiet, nothing but the errors is printed to stdout; the warnings go to stderr.
+fn load_program(opt: &ProgramOpt, cpu_config: &CPUConfig, quiet: bool) -> Rc<Program> {
     let path = opt.file.to_str().unwrap();
-    println!("Loading {}", path);
-    let load_result = load(cpu_config.clone(), path);
-    let program = match load_result {
-        Ok(p) => Rc::new(p),
+    if !quiet {
+        println!("Loading {}", path);
+    }
+    let mut paths = vec![path];
+    for lib in &opt.libs {
+        paths.push(lib.to_str().unwrap());
+    }
+    match load_files(cpu_config.clone(), &paths) {
+        Ok(p) => {
+            for warning in p.warnings() {
+                if quiet {
+                    eprintln!("Warning: {}", warning);
+                } else {
+                    println!("Warning: {}", warning);
+                }
+            }
+            Rc::new(p)
+        }
         Err(err) => {
             println!("Loading program '{}' failed.", path);
             match err {
-                LoadError::ParseError(msg) => {
-                    println!("{}", msg);
-                    exit(1);
-                }
-
-                LoadError::AnalysisError(msg_vec) => {
-                    for msg in msg_vec {
-                        println!("{}", msg);
+                LoadError::ParseError(errors) | LoadError::AnalysisError(errors) => {
+                    for error in errors {
+                        println!("{}", error);
                     }
-                    exit(1);
                 }
                 LoadError::NotFoundError(msg) => {
                     println!("{}", msg);
-                    exit(1);
                 }
             }
+            exit(1);
+        }
+    }
+}
+
+// Runs the program with the arguments and the memory image of the options and returns its exit
+// code; exits when the CPU hangs.
+fn run_cpu(cpu: &mut CPU, program: &Rc<Program>, opt: &ProgramOpt) -> Option<WordType> {
+    if let Err(error) = cpu.set_arguments(&opt.args) {
+        println!("{}", error);
+        exit(1);
+    }
+
+    if let Some(image_path) = &opt.memory_image {
+        if let Err(error) = cpu.load_memory_image(image_path.to_str().unwrap(), opt.image_offset) {
+            println!("Failed to load memory image {}. Cause: {}", image_path.display(), error);
+            exit(1);
+        }
+    }
+
+    match cpu.run_until_exit(program) {
+        Ok(exit_code) => exit_code,
+        Err(diagnosis) => {
+            print!("{}", diagnosis);
+            exit(1);
         }
-    };
+    }
+}
+
+// Reports the .expect directives of the program; a failed expectation fails the run.
+fn check_expectations(cpu: &CPU, program: &Program) {
+    let outcomes = check_inline(program, &cpu.snapshot(program), cpu.exit_code().is_some());
+    for outcome in &outcomes {
+        match &outcome.failure {
+            None => println!("PASS line {}: {}", outcome.line, outcome.directive),
+            Some(failure) => println!("FAIL line {}: {}: {}", outcome.line, outcome.directive, failure),
+        }
+    }
+    if outcomes.iter().any(|outcome| !outcome.passed()) {
+        exit(1);
+    }
+}
+
+fn check_snapshot(cpu: &CPU, program: &Program, dump_snapshot: Option<PathBuf>, expect_snapshot: Option<PathBuf>) {
+    let snapshot = cpu.snapshot(program);
 
-    let mut cpu = CPU::new(&cpu_config);
-    cpu.run(&program);
+    if let Some(dump_path) = dump_snapshot {
+        let dump_path = dump_path.to_str().unwrap();
+        if let Err(error) = snapshot.save(dump_path) {
+            println!("Failed to write snapshot {}. Cause: {}", dump_path, error);
+            exit(1);
+        }
+    }
+
+    if let Some(expect_path) = expect_snapshot {
+        let expect_path = expect_path.to_str().unwrap();
+        let expected = match Snapshot::load(expect_path) {
+            Ok(expected) => expected,
+            Err(error) => {
+                println!("Failed to load snapshot {}. Cause: {}", expect_path, error);
+                exit(1);
+            }
+        };
+
+        let diff = expected.diff(&snapshot);
+        println!("{}", diff);
+        if !diff.is_empty() {
+            exit(1);
+        }
+    }
 }
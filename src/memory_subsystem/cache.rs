@@ -0,0 +1,306 @@
+use std::fmt;
+
+use crate::cpu::{CacheConfig, CachesConfig, PrefetchPolicy, WritePolicy};
+use crate::instructions::instructions::WordType;
+use crate::memory_subsystem::prefetcher::Prefetcher;
+
+// A line in a set; only the tag is kept, the values are in the memory.
+struct Line {
+    tag: u64,
+    dirty: bool,
+    // brought in by the prefetcher and not accessed by a load yet
+    prefetched: bool,
+    // the cycle a prefetched line arrives; a load that accesses it earlier waits for it
+    ready_cycle: u64,
+}
+
+struct PrefetchStats {
+    issued: u64,
+    // the prefetched lines that were accessed by a load before they were evicted
+    useful: u64,
+    // the useful prefetches that hadn't arrived yet when the load accessed the line
+    late: u64,
+}
+
+struct CacheStats {
+    reads: u64,
+    read_misses: u64,
+    writes: u64,
+    write_misses: u64,
+    // the dirty lines written to the next level when they were evicted
+    writebacks: u64,
+    // the stores passed on to the next level by a write-through cache
+    write_throughs: u64,
+}
+
+// A set associative cache with LRU replacement.
+struct Cache {
+    line_size: u64,
+    set_cnt: u64,
+    associativity: usize,
+    latency: u8,
+    write_policy: WritePolicy,
+    // per set the valid lines, from the least to the most recently used
+    sets: Vec<Vec<Line>>,
+    stats: CacheStats,
+}
+
+impl Cache {
+    fn new(config: &CacheConfig) -> Cache {
+        let set_cnt = (config.size / (config.line_size * config.associativity)) as u64;
+        Cache {
+            line_size: config.line_size as u64,
+            set_cnt,
+            associativity: config.associativity as usize,
+            latency: config.latency,
+            write_policy: config.write_policy,
+            sets: (0..set_cnt).map(|_| Vec::with_capacity(config.associativity as usize)).collect(),
+            stats: CacheStats { reads: 0, read_misses: 0, writes: 0, write_misses: 0, writebacks: 0, write_throughs: 0 },
+        }
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        let tag = addr / self.line_size;
+        self.sets[(tag % self.set_cnt) as usize].iter().any(|line| line.tag == tag)
+    }
+
+    // Looks up the line of the address and makes it the most recently used. On a hit, returns if the
+    // line was prefetched (and not accessed before) and the cycle it arrives.
+    fn lookup(&mut self, addr: u64, write: bool) -> Option<(bool, u64)> {
+        let tag = addr / self.line_size;
+        let set = &mut self.sets[(tag % self.set_cnt) as usize];
+        let index = set.iter().position(|line| line.tag == tag)?;
+        let mut line = set.remove(index);
+        let hit = (line.prefetched, line.ready_cycle);
+        line.dirty |= write;
+        line.prefetched = false;
+        set.push(line);
+        Some(hit)
+    }
+
+    // Places the line of the address; a prefetched line arrives at the given cycle. Returns the address
+    // of the evicted line if it was dirty.
+    fn fill(&mut self, addr: u64, dirty: bool, prefetch_ready: Option<u64>) -> Option<u64> {
+        let tag = addr / self.line_size;
+        let associativity = self.associativity;
+        let set = &mut self.sets[(tag % self.set_cnt) as usize];
+        let evicted = if set.len() == associativity { Some(set.remove(0)) } else { None };
+        set.push(Line { tag, dirty, prefetched: prefetch_ready.is_some(), ready_cycle: prefetch_ready.unwrap_or(0) });
+        evicted.filter(|line| line.dirty).map(|line| line.tag * self.line_size)
+    }
+}
+
+/// The data cache hierarchy: an L1 followed by an optional unified L2 (and further levels).
+///
+/// The memory holds the values, so the caches only keep the tags. A load gets the latency of the
+/// levels it accesses; the stores update the caches when the store buffer writes them to memory.
+pub(crate) struct CacheHierarchy {
+    levels: Vec<Cache>,
+    memory_latency: u8,
+    // the number of words of the memory; nothing is prefetched beyond it
+    memory_size: u64,
+    configs: Vec<CacheConfig>,
+    memory_reads: u64,
+    memory_writes: u64,
+    prefetcher: Prefetcher,
+    prefetch_stats: PrefetchStats,
+}
+
+impl CacheHierarchy {
+    pub(crate) fn new(config: &CachesConfig, memory_size: u32) -> CacheHierarchy {
+        CacheHierarchy {
+            levels: config.levels.iter().map(Cache::new).collect(),
+            memory_latency: config.memory_latency,
+            memory_size: memory_size as u64,
+            configs: config.levels.clone(),
+            memory_reads: 0,
+            memory_writes: 0,
+            prefetcher: Prefetcher::new(&config.prefetcher),
+            prefetch_stats: PrefetchStats { issued: 0, useful: 0, late: 0 },
+        }
+    }
+
+    // Empties the caches and clears the statistics.
+    pub(crate) fn reset(&mut self) {
+        self.levels = self.configs.iter().map(Cache::new).collect();
+        self.memory_reads = 0;
+        self.memory_writes = 0;
+        self.prefetcher.reset();
+        self.prefetch_stats = PrefetchStats { issued: 0, useful: 0, late: 0 };
+    }
+
+    // Clears the statistics, but keeps the contents of the caches; e.g. after the caches were warmed up.
+    pub(crate) fn clear_stats(&mut self) {
+        for cache in &mut self.levels {
+            cache.stats = CacheStats { reads: 0, read_misses: 0, writes: 0, write_misses: 0, writebacks: 0, write_throughs: 0 };
+        }
+        self.memory_reads = 0;
+        self.memory_writes = 0;
+        self.prefetch_stats = PrefetchStats { issued: 0, useful: 0, late: 0 };
+    }
+
+    // The read and write misses of the level (0 is the L1) so far; 0 if there is no such level.
+    pub(crate) fn misses(&self, level: usize) -> u64 {
+        self.levels.get(level).map_or(0, |cache| cache.stats.read_misses + cache.stats.write_misses)
+    }
+
+    // The part of the accesses to the level (0 is the L1) that missed; None if there is no such level
+    // or it wasn't accessed.
+    pub(crate) fn miss_rate(&self, level: usize) -> Option<f64> {
+        let stats = &self.levels.get(level)?.stats;
+        let accesses = stats.reads + stats.writes;
+        if accesses == 0 {
+            return None;
+        }
+        Some((stats.read_misses + stats.write_misses) as f64 / accesses as f64)
+    }
+
+    // Returns the cycles added to the latency of a load (at the code address) of the consecutive words
+    // at the address.
+    pub(crate) fn load(&mut self, pc: usize, addr: WordType, cnt: u8, cycle: u64) -> u8 {
+        let line_size = self.levels[0].line_size;
+        let mut latency = 0;
+        let mut previous_line = None;
+        for k in 0..cnt as u64 {
+            let word = addr as u64 + k;
+            if previous_line == Some(word / line_size) {
+                continue;
+            }
+            previous_line = Some(word / line_size);
+            latency = latency.max(self.demand_read(pc, word, cycle));
+        }
+        latency.min(u8::MAX as u32) as u8
+    }
+
+    // A load reads the address from the L1; the access trains the prefetcher. Returns the latency.
+    fn demand_read(&mut self, pc: usize, addr: u64, cycle: u64) -> u32 {
+        let l1 = &mut self.levels[0];
+        l1.stats.reads += 1;
+        let mut latency = l1.latency as u32;
+        let line_size = l1.line_size;
+        let triggered = match l1.lookup(addr, false) {
+            Some((false, _)) => false,
+            Some((true, ready_cycle)) => {
+                self.prefetch_stats.useful += 1;
+                if ready_cycle > cycle {
+                    self.prefetch_stats.late += 1;
+                    latency += (ready_cycle - cycle) as u32;
+                }
+                true
+            }
+            None => {
+                l1.stats.read_misses += 1;
+                latency += self.read(1, addr);
+                self.allocate(0, addr, false, None);
+                true
+            }
+        };
+
+        for target in self.prefetcher.observe(pc, addr, line_size, triggered) {
+            self.prefetch(target, cycle);
+        }
+        latency
+    }
+
+    // Brings the line of the address into the L1 unless it is there already.
+    fn prefetch(&mut self, addr: i64, cycle: u64) {
+        if addr < 0 || addr as u64 >= self.memory_size || self.levels[0].contains(addr as u64) {
+            return;
+        }
+
+        self.prefetch_stats.issued += 1;
+        let latency = self.levels[0].latency as u32 + self.read(1, addr as u64);
+        self.allocate(0, addr as u64, false, Some(cycle + latency as u64));
+    }
+
+    // A store written to memory by the store buffer.
+    pub(crate) fn store(&mut self, addr: WordType) {
+        self.write(0, addr as u64, false);
+    }
+
+    // Reads the line of the address at the level; returns the latency.
+    fn read(&mut self, level: usize, addr: u64) -> u32 {
+        if level == self.levels.len() {
+            self.memory_reads += 1;
+            return self.memory_latency as u32;
+        }
+
+        let cache = &mut self.levels[level];
+        cache.stats.reads += 1;
+        let latency = cache.latency as u32;
+        if cache.lookup(addr, false).is_some() {
+            return latency;
+        }
+
+        cache.stats.read_misses += 1;
+        let latency = latency + self.read(level + 1, addr);
+        self.allocate(level, addr, false, None);
+        latency
+    }
+
+    // Writes the address at the level; a writeback writes a whole line, so it doesn't need to read the line.
+    fn write(&mut self, level: usize, addr: u64, writeback: bool) {
+        if level == self.levels.len() {
+            self.memory_writes += 1;
+            return;
+        }
+
+        let cache = &mut self.levels[level];
+        cache.stats.writes += 1;
+        match cache.write_policy {
+            WritePolicy::WriteBack => {
+                if cache.lookup(addr, true).is_none() {
+                    cache.stats.write_misses += 1;
+                    if !writeback {
+                        self.read(level + 1, addr);
+                    }
+                    self.allocate(level, addr, true, None);
+                }
+            }
+            WritePolicy::WriteThrough => {
+                if cache.lookup(addr, false).is_none() {
+                    cache.stats.write_misses += 1;
+                }
+                cache.stats.write_throughs += 1;
+                self.write(level + 1, addr, writeback);
+            }
+        }
+    }
+
+    fn allocate(&mut self, level: usize, addr: u64, dirty: bool, prefetch_ready: Option<u64>) {
+        if let Some(evicted) = self.levels[level].fill(addr, dirty, prefetch_ready) {
+            self.levels[level].stats.writebacks += 1;
+            self.write(level + 1, evicted, true);
+        }
+    }
+}
+
+impl fmt::Display for CacheHierarchy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Caches:")?;
+        for (index, cache) in self.levels.iter().enumerate() {
+            let stats = &cache.stats;
+            let accesses = stats.reads + stats.writes;
+            let misses = stats.read_misses + stats.write_misses;
+            let miss_rate = if accesses == 0 { 0.0 } else { 100.0 * misses as f64 / accesses as f64 };
+            writeln!(f, "  L{}: {} reads ({} misses), {} writes ({} misses); miss rate {:.1}%",
+                     index + 1, stats.reads, stats.read_misses, stats.writes, stats.write_misses, miss_rate)?;
+            match cache.write_policy {
+                WritePolicy::WriteBack => writeln!(f, "      write back: {} dirty lines written back ({} words)",
+                                                   stats.writebacks, stats.writebacks * cache.line_size)?,
+                WritePolicy::WriteThrough => writeln!(f, "      write through: {} words written through",
+                                                      stats.write_throughs)?,
+            }
+        }
+        if self.prefetcher.policy() != PrefetchPolicy::None {
+            let stats = &self.prefetch_stats;
+            let percentage = |count: u64, total: u64| if total == 0 { 0.0 } else { 100.0 * count as f64 / total as f64 };
+            // coverage: the part of the misses without prefetching that the prefetcher removed
+            let coverage = percentage(stats.useful, stats.useful + self.levels[0].stats.read_misses);
+            writeln!(f, "  Prefetcher ({:?}): {} prefetches, {} useful; accuracy {:.1}%, coverage {:.1}%, {} late",
+                     self.prefetcher.policy(), stats.issued, stats.useful, percentage(stats.useful, stats.issued),
+                     coverage, stats.late)?;
+        }
+        writeln!(f, "  Memory: {} line reads, {} writes", self.memory_reads, self.memory_writes)
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+
+use crate::cpu::CPUConfig;
+use crate::instructions::instructions::{Instr, Opcode, Program, SourceLocation, WordType};
+
+// A block of memory allocated using MALLOC.
+#[derive(Clone)]
+pub(crate) struct Allocation {
+    pub(crate) addr: WordType,
+    // the number of words requested
+    pub(crate) size: WordType,
+    // the MALLOC that allocated the block
+    pub(crate) allocated_at: Option<SourceLocation>,
+    // the calls (BL or BLX) that led to the MALLOC, the innermost first
+    pub(crate) allocated_from: Vec<Option<SourceLocation>>,
+    pub(crate) freed: bool,
+    // the FREE that released the block
+    pub(crate) freed_at: Option<SourceLocation>,
+    // the calls that led to the FREE, the innermost first
+    pub(crate) freed_from: Vec<Option<SourceLocation>>,
+}
+
+impl Allocation {
+    fn allocated(&self) -> String {
+        at(self.allocated_at, &self.allocated_from)
+    }
+
+    fn freed(&self) -> String {
+        at(self.freed_at, &self.freed_from)
+    }
+}
+
+// A call that hasn't returned yet.
+#[derive(Clone)]
+struct Call {
+    // the value of the LR: the code address the call returns to
+    return_addr: usize,
+    // the BL or BLX
+    loc: Option<SourceLocation>,
+}
+
+// The location followed by the call sites that led to it; e.g. 12:5 (called from 30:5, 5:5).
+fn at(loc: Option<SourceLocation>, calls: &[Option<SourceLocation>]) -> String {
+    let describe = |loc: Option<SourceLocation>| loc.map_or(String::from("an unknown location"), |loc| loc.to_string());
+    if calls.is_empty() {
+        return describe(loc);
+    }
+    let calls: Vec<String> = calls.iter().map(|call| describe(*call)).collect();
+    format!("{} (called from {})", describe(loc), calls.join(", "))
+}
+
+/// The allocator behind the MALLOC and FREE instructions.
+///
+/// The heap lies between the variables and the stack; it starts at the heap_base of the memory layout or
+/// just after the variables, moved up when the layout is randomized. Blocks are handed out in address order and
+/// are separated by redzones; a freed block is never reused, so every later access to it can be
+/// reported as a use after free. The heap only does the bookkeeping; the memory subsystem poisons
+/// the redzones and the freed blocks.
+///
+/// Like the sampling profiler, the heap keeps the chain of the calls that haven't returned yet up to date at
+/// retirement, so the reports show the call sites that led to the MALLOC and the FREE of a block.
+#[derive(Clone)]
+pub(crate) struct Heap {
+    // the configured first address of the heap; 0 for just after the variables
+    base: WordType,
+    // the number of words the heap is moved up by the randomization of the memory layout
+    shift: WordType,
+    // the first address of the heap
+    start: WordType,
+    // the first address after the heap; the lowest address the stack may grow to
+    end: WordType,
+    redzone_size: WordType,
+    // the first free address; every block and its redzones lie below it
+    top: WordType,
+    // in address order
+    allocations: Vec<Allocation>,
+    // the active calls, from the outermost to the innermost
+    calls: Vec<Call>,
+}
+
+impl Heap {
+    pub(crate) fn new(cpu_config: &CPUConfig) -> Heap {
+        let end = (cpu_config.initial_sp() - cpu_config.stack_capacity()) as WordType;
+        let layout = &cpu_config.memory_layout;
+        Heap {
+            base: layout.heap_base as WordType,
+            shift: layout.shifts().heap as WordType,
+            start: 0,
+            end,
+            redzone_size: cpu_config.redzone_size as WordType,
+            top: 0,
+            allocations: Vec::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    // Places the empty heap at the heap base or after the code and the variables of the program.
+    pub(crate) fn init(&mut self, program: &Program) {
+        // after the redzone of the last variable
+        let data_end = program.data_items.values()
+            .map(|data| data.offset as WordType + 1 + self.redzone_size)
+            .max()
+            .unwrap_or(0);
+        let after_data = data_end.max(program.memory_address(program.code.len()));
+        self.start = self.base.max(after_data) + self.shift;
+        self.top = self.start;
+        self.allocations.clear();
+        self.calls.clear();
+    }
+
+    // Keeps the active calls up to date; called for every retired instruction.
+    pub(crate) fn on_retire(&mut self, instr: &Instr, pc: usize, next_pc: usize) {
+        match instr.opcode {
+            _ if instr.is_call() => self.calls.push(Call { return_addr: pc + 1, loc: instr.loc }),
+            // a return to an outer caller (e.g. after a tail call) also ends the calls in between
+            _ if instr.is_return() => {
+                if let Some(index) = self.calls.iter().rposition(|call| call.return_addr == next_pc) {
+                    self.calls.truncate(index);
+                }
+            }
+            Opcode::EXIT => self.calls.clear(),
+            _ => {}
+        }
+    }
+
+    // The call sites of the active calls, the innermost first.
+    fn call_sites(&self) -> Vec<Option<SourceLocation>> {
+        self.calls.iter().rev().map(|call| call.loc).collect()
+    }
+
+    // The address the next allocation of the given number of words gets; 0 (NULL) if the heap is exhausted.
+    pub(crate) fn next_addr(&self, size: WordType) -> WordType {
+        let addr = self.top + self.redzone_size;
+        if size < 0 || addr + size + self.redzone_size > self.end {
+            0
+        } else {
+            addr
+        }
+    }
+
+    // Allocates a block of the given number of words; returns 0 (NULL) if the heap is exhausted.
+    pub(crate) fn allocate(&mut self, size: WordType, loc: Option<SourceLocation>) -> WordType {
+        let addr = self.next_addr(size);
+        if addr != 0 {
+            self.top = addr + size;
+            let allocated_from = self.call_sites();
+            self.allocations.push(Allocation { addr, size, allocated_at: loc, allocated_from, freed: false, freed_at: None, freed_from: Vec::new() });
+        }
+        addr
+    }
+
+    // Checks if the address can be freed; freeing 0 (NULL) does nothing.
+    pub(crate) fn check_free(&self, addr: WordType) -> Result<(), String> {
+        if addr == 0 {
+            return Ok(());
+        }
+
+        match self.allocations.iter().find(|allocation| allocation.addr == addr) {
+            None => Err(format!("Free of address {} that wasn't returned by MALLOC", addr)),
+            Some(allocation) if allocation.freed => Err(format!(
+                "Double free of the block at address {} allocated at {}; first freed at {}",
+                addr, allocation.allocated(), allocation.freed())),
+            Some(_) => Ok(()),
+        }
+    }
+
+    // Frees the block at the address; returns the block so its memory can be poisoned.
+    pub(crate) fn free(&mut self, addr: WordType, loc: Option<SourceLocation>) -> Option<&Allocation> {
+        let freed_from = self.call_sites();
+        let allocation = self.allocations.iter_mut().find(|allocation| allocation.addr == addr && !allocation.freed)?;
+        allocation.freed = true;
+        allocation.freed_at = loc;
+        allocation.freed_from = freed_from;
+        Some(allocation)
+    }
+
+    pub(crate) fn redzone_size(&self) -> WordType {
+        self.redzone_size
+    }
+
+    // Describes a faulting access to a poisoned heap address; None if the address isn't in the heap.
+    pub(crate) fn describe(&self, addr: WordType) -> Option<String> {
+        if addr < self.start || addr >= self.top + self.redzone_size {
+            return None;
+        }
+
+        if let Some(allocation) = self.allocations.iter()
+            .find(|allocation| addr >= allocation.addr && addr < allocation.addr + allocation.size) {
+            return Some(format!("Heap use after free at address {}: word {} of the block of {} word(s) allocated at {} and freed at {}",
+                                addr, addr - allocation.addr, allocation.size, allocation.allocated(), allocation.freed()));
+        }
+
+        // report the access relative to the nearest block
+        let allocation = self.allocations.iter()
+            .min_by_key(|allocation| (addr - allocation.addr).abs().min((addr - (allocation.addr + allocation.size - 1)).abs()))?;
+        if addr >= allocation.addr + allocation.size {
+            Some(format!("Heap buffer overflow at address {}: {} word(s) past the block of {} word(s) allocated at {}",
+                         addr, addr - (allocation.addr + allocation.size - 1), allocation.size, allocation.allocated()))
+        } else {
+            Some(format!("Heap buffer underflow at address {}: {} word(s) before the block of {} word(s) allocated at {}",
+                         addr, allocation.addr - addr, allocation.size, allocation.allocated()))
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    // All blocks in address order; including the freed blocks.
+    pub(crate) fn allocations(&self) -> impl Iterator<Item=&Allocation> {
+        self.allocations.iter()
+    }
+
+    // The blocks that were never freed.
+    pub(crate) fn leaks(&self) -> impl Iterator<Item=&Allocation> {
+        self.allocations.iter().filter(|allocation| !allocation.freed)
+    }
+}
+
+impl fmt::Display for Heap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let allocated: WordType = self.allocations.iter().map(|allocation| allocation.size).sum();
+        writeln!(f, "Heap: {} allocation(s) of {} word(s) in total; {} of {} words used",
+                 self.allocations.len(), allocated, self.top - self.start, self.end - self.start)?;
+        for allocation in self.leaks() {
+            writeln!(f, "  Leak: {} word(s) at address {} allocated at {}",
+                     allocation.size, allocation.addr, allocation.allocated())?;
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,34 @@
+use std::error::Error;
 use std::rc::Rc;
 
-use crate::cpu::CPUConfig;
-use crate::instructions::instructions::{Program, WordType};
+use crate::cpu::{AlignmentPolicy, CPUConfig, Endianness};
+use crate::instructions::instructions::{Program, SourceLocation, WordType};
+use crate::memory_subsystem::cache::CacheHierarchy;
+use crate::memory_subsystem::heap::Heap;
 use crate::memory_subsystem::store_buffer::StoreBuffer;
+use crate::plugin::plugin::AccessKind;
+use crate::snapshot::memory_image::{ImageFormat, MemoryImage};
 
 pub(crate) struct MemorySubsystem {
     pub(crate) memory: Vec<WordType>,
     pub(crate) sb: StoreBuffer,
+    // the number of poisoned words around every variable
+    redzone_size: u8,
+    // true for every word that is part of a redzone
+    poisoned: Vec<bool>,
+    // the name and offset of every variable; used for reporting redzone violations.
+    objects: Vec<(String, u64)>,
+    // the loaded program; its code occupies the memory from the code base
+    program_option: Option<Rc<Program>>,
+    // the blocks allocated using MALLOC
+    pub(crate) heap: Heap,
+    // only set when the caches are enabled
+    pub(crate) caches: Option<CacheHierarchy>,
+    // the layout of a word in a binary memory image
+    word_size: u8,
+    endianness: Endianness,
+    alignment_policy: AlignmentPolicy,
+    unaligned_penalty: u8,
 }
 
 impl MemorySubsystem {
@@ -20,8 +42,22 @@ impl MemorySubsystem {
         let sb = StoreBuffer::new(cpu_config);
 
         MemorySubsystem {
+            poisoned: vec![false; memory.len()],
             memory,
             sb,
+            redzone_size: cpu_config.redzone_size,
+            objects: Vec::new(),
+            program_option: None,
+            heap: Heap::new(cpu_config),
+            caches: if cpu_config.caches.enabled {
+                Some(CacheHierarchy::new(&cpu_config.caches, cpu_config.memory_size))
+            } else {
+                None
+            },
+            word_size: cpu_config.word_size,
+            endianness: cpu_config.endianness,
+            alignment_policy: cpu_config.alignment_policy,
+            unaligned_penalty: cpu_config.unaligned_penalty,
         }
     }
 
@@ -33,12 +69,233 @@ impl MemorySubsystem {
         for data in program.data_items.values() {
             self.memory[data.offset as usize] = data.value;
         }
+        self.program_option = Some(Rc::clone(program));
+
+        self.objects.clear();
+        for k in 0..self.poisoned.len() {
+            self.poisoned[k] = false;
+        }
+
+        if self.redzone_size > 0 {
+            for (name, data) in &program.data_items {
+                let offset = data.offset as usize;
+                let redzone_size = self.redzone_size as usize;
+                let start = offset.saturating_sub(redzone_size);
+                let end = (offset + redzone_size).min(self.poisoned.len() - 1);
+                for k in start..=end {
+                    self.poisoned[k] = true;
+                }
+                self.objects.push((name.clone(), data.offset));
+            }
+
+            // The redzone of one variable can overlap with the next variable.
+            for data in program.data_items.values() {
+                self.poisoned[data.offset as usize] = false;
+            }
+        }
+
+        self.heap.init(program);
+        if let Some(caches) = &mut self.caches {
+            caches.reset();
+        }
+    }
+
+    // Allocates a block of the given number of words and poisons its redzones; returns 0 (NULL) if
+    // the heap is exhausted.
+    pub(crate) fn malloc(&mut self, size: WordType, loc: Option<SourceLocation>) -> WordType {
+        let addr = self.heap.allocate(size, loc);
+        if addr != 0 {
+            let redzone_size = self.heap.redzone_size();
+            for k in addr - redzone_size..addr + size + redzone_size {
+                self.poisoned[k as usize] = k < addr || k >= addr + size;
+            }
+        }
+        addr
+    }
+
+    // Frees the block at the address; the whole block is poisoned, so later accesses fault.
+    pub(crate) fn free(&mut self, addr: WordType, loc: Option<SourceLocation>) {
+        if let Some(allocation) = self.heap.free(addr, loc) {
+            for k in allocation.addr..allocation.addr + allocation.size {
+                self.poisoned[k as usize] = true;
+            }
+        }
+    }
+
+    // Takes over the heap of the reference interpreter after fast forwarding; the redzones and the freed
+    // blocks are poisoned like they would have been by malloc and free.
+    pub(crate) fn restore_heap(&mut self, heap: Heap) {
+        let redzone_size = heap.redzone_size();
+        for allocation in heap.allocations() {
+            for k in allocation.addr - redzone_size..allocation.addr + allocation.size + redzone_size {
+                self.poisoned[k as usize] = allocation.freed || k < allocation.addr || k >= allocation.addr + allocation.size;
+            }
+        }
+        self.heap = heap;
     }
 
-    pub fn do_cycle(&mut self) {
-        self.sb.do_cycle(&mut self.memory);
+    // An access of an instruction (at the code address) executed by the reference interpreter while
+    // fast forwarding; it only brings the line into the caches.
+    pub(crate) fn warm_up(&mut self, pc: usize, addr: WordType, kind: AccessKind) {
+        if let Some(caches) = &mut self.caches {
+            match kind {
+                AccessKind::Load => {
+                    caches.load(pc, addr, 1, 0);
+                }
+                AccessKind::Store => caches.store(addr),
+            }
+        }
+    }
+
+    // Writes the words of the image to memory; the image was checked to fit in memory when it was added.
+    pub(crate) fn write_image(&mut self, image: &MemoryImage) {
+        let offset = image.offset as usize;
+        self.memory[offset..offset + image.words.len()].copy_from_slice(&image.words);
+    }
+
+    pub(crate) fn load_image(&self, path: &str, offset: u64) -> Result<MemoryImage, Box<dyn Error>> {
+        MemoryImage::load(path, ImageFormat::from_path(path), offset, self.word_size, self.endianness)
+    }
+
+    // Writes the architectural memory to a file; the format follows from the extension of the path.
+    pub(crate) fn dump(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let image = MemoryImage { offset: 0, words: self.architectural_memory() };
+        image.save(path, ImageFormat::from_path(path), self.word_size, self.endianness)
+    }
+
+    // The memory as seen by the program; so including the committed stores that are still in the store buffer.
+    pub(crate) fn architectural_memory(&self) -> Vec<WordType> {
+        let mut memory = self.memory.clone();
+        for (addr, value) in self.sb.committed_stores() {
+            memory[addr as usize] = value;
+        }
+        memory
+    }
+
+    // The word as seen by the program: the value of the youngest committed store to it, or memory.
+    pub(crate) fn architectural_word(&self, addr: WordType) -> WordType {
+        self.sb.committed_stores().iter().rev()
+            .find(|(store_addr, _)| *store_addr == addr)
+            .map_or(self.memory[addr as usize], |(_, value)| *value)
+    }
+
+    // Checks if the address can be accessed. An error describes the faulting access.
+    pub(crate) fn check_access(&self, addr: WordType, kind: AccessKind) -> Result<(), String> {
+        if addr < 0 || addr as usize >= self.memory.len() {
+            return Err(format!("Access to address {} outside of memory of {} words", addr, self.memory.len()));
+        }
+
+        if let Some(program) = &self.program_option {
+            check_code_access(program, addr, kind)?;
+        }
+
+        if !self.poisoned[addr as usize] {
+            return Ok(());
+        }
+
+        if let Some(fault) = self.heap.describe(addr) {
+            return Err(fault);
+        }
+
+        // report the access relative to the nearest variable
+        let (name, offset) = self.objects.iter()
+            .min_by_key(|(_, offset)| (addr - *offset as WordType).abs())
+            .unwrap();
+        let distance = addr - *offset as WordType;
+        if distance > 0 {
+            Err(format!("Redzone access at address {}: {} word(s) past variable '{}'", addr, distance, name))
+        } else {
+            Err(format!("Redzone access at address {}: {} word(s) before variable '{}'", addr, -distance, name))
+        }
+    }
+
+    // Checks the alignment of an access of the consecutive words at the address; only faults with the
+    // fault alignment policy.
+    pub(crate) fn check_alignment(&self, addr: WordType, cnt: u8) -> Result<(), String> {
+        check_alignment(addr, cnt, self.alignment_policy)
+    }
+
+    // The cycles added to an access of the consecutive words at the address by the alignment policy.
+    pub(crate) fn alignment_penalty(&self, addr: WordType, cnt: u8) -> u8 {
+        if self.alignment_policy == AlignmentPolicy::Penalty && !is_aligned(addr, cnt) {
+            self.unaligned_penalty
+        } else {
+            0
+        }
+    }
+
+    // Checks the consecutive words of an access against the current state of the heap.
+    pub(crate) fn check_heap_access(&self, addr: WordType, cnt: u8) -> Result<(), String> {
+        for k in addr..addr + cnt as WordType {
+            if k >= 0 && self.poisoned.get(k as usize) == Some(&true) {
+                if let Some(fault) = self.heap.describe(k) {
+                    return Err(fault);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the number of stores that were coalesced in the store buffer and the number that were
+    // written before an older store.
+    pub fn do_cycle(&mut self) -> (u64, u64) {
+        let counts = self.sb.do_cycle(&mut self.memory);
+        self.update_caches();
+        counts
+    }
+
+    // With the retire drain policy, the stores are written to memory when they retire.
+    pub(crate) fn write_committed_stores(&mut self) {
+        self.sb.write_committed(&mut self.memory);
+        self.update_caches();
+    }
+
+    // Returns the cycles the caches add to the latency of a load (at the code address) of the
+    // consecutive words at the address; 0 without caches or when the load faults.
+    pub(crate) fn load_latency(&mut self, pc: usize, addr: WordType, cnt: u8, cycle: u64) -> u8 {
+        match &mut self.caches {
+            Some(caches) if addr >= 0 && addr as usize + cnt as usize <= self.memory.len() => caches.load(pc, addr, cnt, cycle),
+            _ => 0,
+        }
+    }
+
+    // The stores written to memory pass through the caches.
+    fn update_caches(&mut self) {
+        let written = self.sb.take_written();
+        if let Some(caches) = &mut self.caches {
+            for addr in written {
+                caches.store(addr);
+            }
+        }
     }
 }
 
+// The code occupies the memory from the code base, so an instruction also has a memory address. The code
+// can't be accessed as data: there is no instruction encoding to read and a store would modify an
+// instruction that may already be decoded (self-modifying code).
+pub(crate) fn check_code_access(program: &Program, addr: WordType, kind: AccessKind) -> Result<(), String> {
+    match program.code.get(program.code_address(addr)) {
+        Some(instr) if kind == AccessKind::Store =>
+            Err(format!("Store to code address {} [{}]; self-modifying code isn't supported", addr, instr)),
+        Some(instr) =>
+            Err(format!("Load from code address {} [{}]; code can't be read as data", addr, instr)),
+        None => Ok(()),
+    }
+}
 
+// the alignment in words of a multi-word access; a doubleword like the stack alignment of the AAPCS
+const ACCESS_ALIGNMENT: WordType = 2;
 
+// Memory is addressed in words, so a single word access is always aligned; a multi-word access (PUSH,
+// POP, LDM, STM, VLD1, VST1) needs to start at a doubleword boundary.
+pub(crate) fn is_aligned(addr: WordType, cnt: u8) -> bool {
+    cnt <= 1 || addr.rem_euclid(ACCESS_ALIGNMENT) == 0
+}
+
+pub(crate) fn check_alignment(addr: WordType, cnt: u8, policy: AlignmentPolicy) -> Result<(), String> {
+    if policy != AlignmentPolicy::Fault || is_aligned(addr, cnt) {
+        return Ok(());
+    }
+    Err(format!("Unaligned access of {} words at address {}; a multi-word access must start at a multiple of {} words",
+                cnt, addr, ACCESS_ALIGNMENT))
+}
@@ -1,2 +1,5 @@
 pub mod memory_subsystem;
+pub(crate) mod heap;
+pub(crate) mod cache;
+pub(crate) mod prefetcher;
 mod store_buffer;
\ No newline at end of file
@@ -0,0 +1,83 @@
+use crate::cpu::{PrefetchPolicy, PrefetcherConfig};
+
+// The number of times a stride has to repeat before the stride prefetcher trusts it.
+const STRIDE_CONFIDENCE: u8 = 2;
+
+// A load tracked by the stride prefetcher.
+struct StrideEntry {
+    // the code address of the load
+    pc: usize,
+    last_addr: u64,
+    stride: i64,
+    // the number of times in a row the stride repeated
+    confidence: u8,
+}
+
+/// Observes the demand accesses of the loads to the L1 and predicts the addresses that will be
+/// accessed next.
+pub(crate) struct Prefetcher {
+    policy: PrefetchPolicy,
+    degree: u8,
+    // indexed on the code address of the load
+    table: Vec<Option<StrideEntry>>,
+}
+
+impl Prefetcher {
+    pub(crate) fn new(config: &PrefetcherConfig) -> Prefetcher {
+        Prefetcher {
+            policy: config.policy,
+            degree: config.degree,
+            table: (0..config.table_size).map(|_| None).collect(),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> PrefetchPolicy {
+        self.policy
+    }
+
+    pub(crate) fn reset(&mut self) {
+        for entry in self.table.iter_mut() {
+            *entry = None;
+        }
+    }
+
+    // Observes a demand access of the load at the code address; triggered is set when the access missed
+    // or hit a prefetched line for the first time. Returns the addresses to prefetch.
+    pub(crate) fn observe(&mut self, pc: usize, addr: u64, line_size: u64, triggered: bool) -> Vec<i64> {
+        match self.policy {
+            PrefetchPolicy::None => Vec::new(),
+            PrefetchPolicy::NextLine if triggered => {
+                let line = (addr / line_size) as i64;
+                (1..=self.degree as i64).map(|k| (line + k) * line_size as i64).collect()
+            }
+            PrefetchPolicy::NextLine => Vec::new(),
+            PrefetchPolicy::Stride => self.observe_stride(pc, addr),
+        }
+    }
+
+    fn observe_stride(&mut self, pc: usize, addr: u64) -> Vec<i64> {
+        let index = pc % self.table.len();
+        let entry = match &mut self.table[index] {
+            Some(entry) if entry.pc == pc => entry,
+            slot => {
+                *slot = Some(StrideEntry { pc, last_addr: addr, stride: 0, confidence: 0 });
+                return Vec::new();
+            }
+        };
+
+        let stride = addr as i64 - entry.last_addr as i64;
+        entry.last_addr = addr;
+        if stride != 0 && stride == entry.stride {
+            entry.confidence = (entry.confidence + 1).min(STRIDE_CONFIDENCE);
+        } else {
+            entry.stride = stride;
+            entry.confidence = 0;
+            return Vec::new();
+        }
+
+        if entry.confidence < STRIDE_CONFIDENCE {
+            return Vec::new();
+        }
+        (1..=self.degree as i64).map(|k| addr as i64 + k * stride).collect()
+    }
+}
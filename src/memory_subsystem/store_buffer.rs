@@ -1,18 +1,54 @@
-use crate::cpu::CPUConfig;
+use crate::cpu::{CPUConfig, MemoryModel, SBDrainPolicy};
 use crate::instructions::instructions::{WordType};
+use crate::random::random::Jitter;
+use crate::report::pipeline_state::StoreBufferSlot;
 
 struct StoreBufferEntry {
     value: WordType,
     addr: WordType,
     completed: bool,
+    // written to memory before an older store (relaxed memory model); it is removed once the older stores are written
+    visible: bool,
+    // the speculation epoch of the store; see squash
+    epoch: u64,
 }
 
+/// The store buffer.
+///
+/// Slots are allocated in program order at issue. A store completes (address and value are known)
+/// when it executes and it commits when it retires. Only committed stores are written to memory, so
+/// stores that are flushed from the pipeline never become visible.
+///
+/// With the TSO memory model, the committed stores are written to memory in program order. With the
+/// relaxed memory model, a younger committed store can be written before an older store to another
+/// address; the slot is freed once all older stores have been written.
+///
+/// Every slot has a sequence number (the position in the buffer since the start); an instruction that
+/// records the tail at issue, can determine which stores are older than itself.
+///
+/// Every store is also tagged with its speculation epoch: the number of control instructions issued
+/// before it. When a branch turns out to be mispredicted, the stores of the later epochs were issued from
+/// the wrong path and are squashed before they can drain, while the older stores stay.
 pub(crate) struct StoreBuffer {
     head: u64,
+    // everything before this point is committed
+    committed: u64,
     tail: u64,
     entries: Vec<StoreBufferEntry>,
     capacity: u16,
+    // the number of stores that can be written to memory per cycle
     lfb_count: u8,
+    // the number of stores written to memory in the current cycle
+    drained: u8,
+    drain_policy: SBDrainPolicy,
+    coalescing: bool,
+    memory_model: MemoryModel,
+    // only set when the latency jitter is enabled
+    jitter: Option<Jitter>,
+    // the number of cycles the oldest committed store still waits before it is written to memory
+    head_delay: Option<u8>,
+    // the addresses written to memory since the last take_written; so the caches can be updated
+    written: Vec<WordType>,
 }
 
 impl StoreBuffer {
@@ -23,15 +59,30 @@ impl StoreBuffer {
                 value: 0,
                 addr: 0,
                 completed: false,
+                visible: false,
+                epoch: 0,
             })
         }
 
         StoreBuffer {
             capacity: cpu_config.sb_capacity,
             head: 0,
+            committed: 0,
             tail: 0,
             entries,
             lfb_count: cpu_config.lfb_count,
+            drained: 0,
+            drain_policy: cpu_config.sb_drain_policy,
+            coalescing: cpu_config.sb_coalescing,
+            memory_model: cpu_config.memory_model,
+            // a different seed than the execution units, so the delays are independent
+            jitter: if cpu_config.jitter.enabled {
+                Some(Jitter::new(cpu_config.jitter.seed.wrapping_add(1), cpu_config.jitter.max_store_delay))
+            } else {
+                None
+            },
+            head_delay: None,
+            written: Vec::new(),
         }
     }
 
@@ -39,18 +90,40 @@ impl StoreBuffer {
         return (self.tail - self.head) as u16;
     }
 
+    pub fn capacity(&self) -> u16 {
+        self.capacity
+    }
+
     pub fn has_space(&self) -> bool {
         return self.size() < self.capacity;
     }
 
-    pub fn allocate(&mut self) -> u16 {
+    pub fn has_space_for(&self, cnt: u16) -> bool {
+        self.capacity - self.size() >= cnt
+    }
+
+    pub fn allocate(&mut self, epoch: u64) -> u16 {
         assert!(self.has_space(), "StoreBuffer: can't allocate because there is no space");
 
         let index = (self.tail % self.capacity as u64) as usize;
+        self.entries[index].epoch = epoch;
         self.tail += 1;
         return index as u16;
     }
 
+    // The sequence number of the next store to be allocated.
+    pub fn tail(&self) -> u64 {
+        self.tail
+    }
+
+    // Returns the sequence number of the store at the given index.
+    pub fn seq(&self, index: u16) -> u64 {
+        let capacity = self.capacity as u64;
+        let head_index = self.head % capacity;
+        let distance = (index as u64 + capacity - head_index) % capacity;
+        self.head + distance
+    }
+
     pub fn store(&mut self, index: u16, addr: WordType, value: WordType) {
         let sb_entry = &mut self.entries[index as usize];
         sb_entry.addr = addr;
@@ -58,29 +131,210 @@ impl StoreBuffer {
         sb_entry.completed = true;
     }
 
-    pub fn do_cycle(&mut self, memory: &mut Vec<WordType>) {
-        for _ in 0..self.lfb_count {
-            if self.tail == self.head {
-                // store buffer is empty
-                break;
+    // Commits the oldest uncommitted store; stores retire in program order.
+    pub fn commit(&mut self) {
+        assert!(self.committed < self.tail, "StoreBuffer: can't commit because there are no stores");
+        self.committed += 1;
+    }
+
+    // Checks if there is a store older than 'barrier' (a tail) for which the address isn't known yet.
+    pub fn has_unresolved_before(&self, barrier: u64) -> bool {
+        for seq in self.head..barrier.min(self.tail) {
+            let index = (seq % self.capacity as u64) as usize;
+            if !self.entries[index].completed {
+                return true;
             }
+        }
+        false
+    }
 
-            let index = (self.head % self.capacity as u64) as usize;
-            let mut sb_entry = &mut self.entries[index];
-            if !sb_entry.completed {
-                // the store buffer isn't empty, but there is a slot that didn't receive a store yet
-                // We stop, so that we ensure that all stores in the store buffer, will be written
-                // to memory in program order.
-                return;
+    // Finds the youngest store older than 'barrier' (a tail) to the given address. Returns the
+    // sequence number of the store and its value.
+    pub fn lookup(&self, addr: WordType, barrier: u64) -> Option<(u64, WordType)> {
+        for seq in (self.head..barrier.min(self.tail)).rev() {
+            let index = (seq % self.capacity as u64) as usize;
+            let sb_entry = &self.entries[index];
+            if sb_entry.completed && sb_entry.addr == addr {
+                return Some((seq, sb_entry.value));
             }
+        }
+        None
+    }
 
-            memory[sb_entry.addr as usize] = sb_entry.value;
+    // Returns the (address, value) of the committed stores that haven't been written to memory yet,
+    // in program order.
+    pub fn committed_stores(&self) -> Vec<(WordType, WordType)> {
+        (self.head..self.committed)
+            .map(|seq| {
+                let sb_entry = &self.entries[(seq % self.capacity as u64) as usize];
+                (sb_entry.addr, sb_entry.value)
+            })
+            .collect()
+    }
+
+    // The occupied slots in program order; the stores that are visible in memory are left out.
+    pub(crate) fn slots(&self) -> Vec<StoreBufferSlot> {
+        (self.head..self.tail)
+            .map(|seq| (seq, &self.entries[(seq % self.capacity as u64) as usize]))
+            .filter(|(_, sb_entry)| !sb_entry.visible)
+            .map(|(seq, sb_entry)| StoreBufferSlot {
+                seq,
+                addr: sb_entry.completed.then_some(sb_entry.addr),
+                value: sb_entry.completed.then_some(sb_entry.value),
+                committed: seq < self.committed,
+            })
+            .collect()
+    }
 
+    // If there are committed stores that haven't been written to memory yet; a barrier waits for them.
+    pub fn has_committed(&self) -> bool {
+        self.committed > self.head
+    }
+
+    // Removes all stores that have not been committed.
+    pub fn flush(&mut self) {
+        for seq in self.committed..self.tail {
+            let index = (seq % self.capacity as u64) as usize;
+            let sb_entry = &mut self.entries[index];
             sb_entry.completed = false;
             sb_entry.value = 0;
             sb_entry.addr = 0;
+        }
+        self.tail = self.committed;
+    }
 
-            self.head += 1;
+    // Removes the stores of the given speculation epoch and later, e.g. the stores issued after a
+    // mispredicted branch. The epochs increase in program order, so these are the youngest stores; a
+    // store from the wrong path can't have been committed. Returns the number of removed stores.
+    pub fn squash(&mut self, epoch: u64) -> u64 {
+        let mut squashed_cnt = 0;
+        while self.tail > self.head {
+            let index = ((self.tail - 1) % self.capacity as u64) as usize;
+            let sb_entry = &mut self.entries[index];
+            if sb_entry.epoch < epoch {
+                break;
+            }
+            assert!(self.tail > self.committed, "StoreBuffer: can't squash a committed store");
+            sb_entry.completed = false;
+            sb_entry.value = 0;
+            sb_entry.addr = 0;
+            self.tail -= 1;
+            squashed_cnt += 1;
         }
+        squashed_cnt
+    }
+
+    // With the retire drain policy, checks if a store with the given number of transfers can be written
+    // to memory in this cycle. A store with more transfers than the drain bandwidth (e.g. a PUSH) is
+    // written when no other store was written in the cycle.
+    pub fn can_drain(&self, cnt: u8) -> bool {
+        self.drained == 0 || self.drained as u16 + cnt as u16 <= self.lfb_count as u16
+    }
+
+    // With the retire drain policy, writes the committed stores to memory as they retire.
+    pub fn write_committed(&mut self, memory: &mut [WordType]) {
+        while self.head < self.committed {
+            self.write_head(memory);
+            self.drained += 1;
+        }
+    }
+
+    // Writes committed stores to memory. Returns the number of stores that were coalesced and the number
+    // of stores that were written before an older store.
+    pub fn do_cycle(&mut self, memory: &mut [WordType]) -> (u64, u64) {
+        self.drained = 0;
+        if self.drain_policy == SBDrainPolicy::Retire {
+            // the stores have been written when they retired
+            return (0, 0);
+        }
+
+        let mut coalesced_cnt = 0;
+        let mut reordered_cnt = 0;
+        while self.drained < self.lfb_count {
+            if self.committed == self.head {
+                // there are no committed stores
+                break;
+            }
+
+            if let Some(jitter) = &mut self.jitter {
+                let delay = self.head_delay.get_or_insert_with(|| jitter.delay());
+                if *delay > 0 {
+                    *delay -= 1;
+                    break;
+                }
+                self.head_delay = None;
+            }
+
+            if self.coalescing && self.is_overwritten(self.head) {
+                // a younger committed store writes the same address; the store is merged into it
+                // without using the drain bandwidth.
+                self.discard_head();
+                coalesced_cnt += 1;
+                continue;
+            }
+
+            if self.memory_model == MemoryModel::Relaxed {
+                let seq = self.relaxed_next();
+                if seq != self.head {
+                    reordered_cnt += 1;
+                }
+                self.write(seq, memory);
+            } else {
+                self.write_head(memory);
+            }
+            self.drained += 1;
+        }
+        (coalesced_cnt, reordered_cnt)
+    }
+
+    // The youngest committed store that hasn't been written and that has no older unwritten store to the
+    // same address; so the stores to an address stay in order. The oldest unwritten store always qualifies.
+    fn relaxed_next(&self) -> u64 {
+        let entry = |seq: u64| &self.entries[(seq % self.capacity as u64) as usize];
+        (self.head..self.committed).rev()
+            .filter(|seq| !entry(*seq).visible)
+            .find(|seq| (self.head..*seq).all(|older| entry(older).visible || entry(older).addr != entry(*seq).addr))
+            .unwrap()
+    }
+
+    // Writes the committed store to memory; the written stores at the head are removed.
+    fn write(&mut self, seq: u64, memory: &mut [WordType]) {
+        let sb_entry = &mut self.entries[(seq % self.capacity as u64) as usize];
+        memory[sb_entry.addr as usize] = sb_entry.value;
+        sb_entry.visible = true;
+        self.written.push(sb_entry.addr);
+        while self.head < self.committed && self.entries[(self.head % self.capacity as u64) as usize].visible {
+            self.discard_head();
+        }
+    }
+
+    // If a younger committed store writes the same address as the store with the given sequence number.
+    fn is_overwritten(&self, seq: u64) -> bool {
+        let addr = self.entries[(seq % self.capacity as u64) as usize].addr;
+        (seq + 1..self.committed).any(|younger| self.entries[(younger % self.capacity as u64) as usize].addr == addr)
+    }
+
+    // Committed stores are written to memory in program order.
+    fn write_head(&mut self, memory: &mut [WordType]) {
+        let sb_entry = &self.entries[(self.head % self.capacity as u64) as usize];
+        memory[sb_entry.addr as usize] = sb_entry.value;
+        self.written.push(sb_entry.addr);
+        self.discard_head();
+    }
+
+    // Returns the addresses written to memory since the previous call, in program order.
+    pub(crate) fn take_written(&mut self) -> Vec<WordType> {
+        std::mem::take(&mut self.written)
+    }
+
+    fn discard_head(&mut self) {
+        let index = (self.head % self.capacity as u64) as usize;
+        let sb_entry = &mut self.entries[index];
+        sb_entry.completed = false;
+        sb_entry.visible = false;
+        sb_entry.value = 0;
+        sb_entry.addr = 0;
+
+        self.head += 1;
     }
 }
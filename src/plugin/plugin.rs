@@ -0,0 +1,307 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::instructions::instructions::{eu_type, mnemonic, Instr, Operand, Program, RegisterType, SourceLocation, WordType};
+
+/// An instruction passing a stage of the pipeline.
+///
+/// Decode, issue and execute also happen for instructions on a path that is flushed later on;
+/// only retired instructions are part of the architectural execution.
+pub struct InstrEvent<'a> {
+    pub(crate) instr: &'a Instr,
+    pub(crate) seq: u64,
+    pub(crate) pc: usize,
+    pub(crate) cycle: u64,
+    // the values written to the sinks; empty before the instruction executed
+    pub(crate) results: &'a [WordType],
+}
+
+impl InstrEvent<'_> {
+    pub fn mnemonic(&self) -> &'static str {
+        mnemonic(self.instr.opcode)
+    }
+
+    /// The instruction as written in assembly, e.g. 'ADD r0, r1, #1'.
+    pub fn text(&self) -> String {
+        self.instr.to_string()
+    }
+
+    /// Identifies this execution of the instruction; it is the same on all its events. Every fetch
+    /// gets a new one, so an instruction that is refetched after a flush gets a new one too.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// The code address of the instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn loc(&self) -> Option<SourceLocation> {
+        self.instr.loc
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// The type of execution unit the instruction runs on, e.g. 'ALU'.
+    pub fn eu_type(&self) -> String {
+        eu_type(self.instr.opcode).to_string()
+    }
+
+    /// The number of cycles the instruction occupies its execution unit.
+    pub fn latency(&self) -> u8 {
+        self.instr.cycles
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.instr.is_control
+    }
+
+    /// The registers read by the instruction.
+    pub fn source_registers(&self) -> Vec<RegisterType> {
+        registers(&self.instr.source[..self.instr.source_cnt as usize])
+    }
+
+    /// The registers written by the instruction; in the same order as the results.
+    pub fn sink_registers(&self) -> Vec<RegisterType> {
+        registers(&self.instr.sink[..self.instr.sink_cnt as usize])
+    }
+
+    /// The values written by the instruction; empty on decode and issue.
+    pub fn results(&self) -> &[WordType] {
+        self.results
+    }
+}
+
+fn registers(operands: &[Operand]) -> Vec<RegisterType> {
+    operands.iter()
+        .filter_map(|operand| match operand {
+            Operand::Register(reg) => Some(*reg),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    Load,
+    Store,
+}
+
+/// A single word read or written by an instruction when it executes.
+///
+/// A load reads from the store buffer or memory; a store writes into the store buffer and reaches
+/// memory after it retired. Like execution, the access can be on a path that is flushed later on.
+pub struct MemoryAccess<'a> {
+    pub(crate) instr: &'a Instr,
+    pub(crate) pc: usize,
+    pub(crate) kind: AccessKind,
+    pub(crate) addr: WordType,
+    pub(crate) value: WordType,
+    pub(crate) cycle: u64,
+}
+
+impl MemoryAccess<'_> {
+    pub fn mnemonic(&self) -> &'static str {
+        mnemonic(self.instr.opcode)
+    }
+
+    /// The code address of the accessing instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn loc(&self) -> Option<SourceLocation> {
+        self.instr.loc
+    }
+
+    pub fn kind(&self) -> AccessKind {
+        self.kind
+    }
+
+    pub fn addr(&self) -> WordType {
+        self.addr
+    }
+
+    /// The value read or written.
+    pub fn value(&self) -> WordType {
+        self.value
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+}
+
+/// Why the pipeline was flushed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlushCause {
+    /// A load has read a stale value; the load and all younger instructions are fetched again.
+    Replay,
+    /// A control instruction went to another target than the BTB predicted. Only the younger instructions
+    /// are discarded; it happens when the control instruction executes.
+    Mispredict,
+    /// An interrupt is taken before the oldest instruction retires.
+    Interrupt,
+    /// An ERET returned from the interrupt handler.
+    InterruptReturn,
+    /// A debugger stepped back; the frontend continues at the oldest instruction that was undone.
+    StepBack,
+}
+
+/// The pipeline discarded every instruction that hasn't retired, or after a misprediction the instructions
+/// younger than the control instruction; the frontend continues fetching at the target.
+pub struct FlushEvent {
+    pub(crate) cause: FlushCause,
+    // the code address of the instruction that caused the flush; for an interrupt the oldest
+    // instruction that hasn't retired, which is fetched again on return.
+    pub(crate) pc: usize,
+    pub(crate) target: usize,
+    pub(crate) squashed_cnt: u16,
+    pub(crate) cycle: u64,
+}
+
+impl FlushEvent {
+    pub fn cause(&self) -> FlushCause {
+        self.cause
+    }
+
+    /// The code address of the instruction that caused the flush.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The code address the frontend continues fetching from.
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    /// The number of instructions in the reorder buffer that were discarded.
+    pub fn squashed_cnt(&self) -> u16 {
+        self.squashed_cnt
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+}
+
+/// A control instruction retired with another target than the one the frontend continued fetching
+/// from after a BTB hit. The flush with FlushCause::Mispredict happened when it executed.
+pub struct MispredictEvent<'a> {
+    pub(crate) instr: &'a Instr,
+    pub(crate) pc: usize,
+    pub(crate) predicted_pc: usize,
+    pub(crate) target: usize,
+    pub(crate) cycle: u64,
+}
+
+impl MispredictEvent<'_> {
+    pub fn mnemonic(&self) -> &'static str {
+        mnemonic(self.instr.opcode)
+    }
+
+    /// The code address of the control instruction.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The code address the frontend continued fetching from.
+    pub fn predicted_pc(&self) -> usize {
+        self.predicted_pc
+    }
+
+    /// The code address the instruction actually went to.
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+}
+
+/// Gets notified of the micro-architectural events of every instruction and of the pipeline; e.g.
+/// to build coverage tools, cache simulators, visualizers or metric collectors. The tracing is
+/// such an observer as well. Every callback does nothing by default.
+pub trait Plugin {
+    /// The program was loaded; called before the first cycle.
+    fn on_load(&mut self, _program: &Rc<Program>) {}
+
+    /// The frontend fetched the instruction.
+    fn on_fetch(&mut self, _event: &InstrEvent) {}
+
+    /// The frontend moved the instruction into the instruction queue.
+    fn on_decode(&mut self, _event: &InstrEvent) {}
+
+    /// The instruction entered the reorder buffer; it gets a reservation station once one is free.
+    fn on_issue(&mut self, _event: &InstrEvent) {}
+
+    /// The operands of the instruction are ready and it was sent to an execution unit. An instruction that
+    /// is decoded into micro-ops is dispatched once per micro-op.
+    fn on_dispatch(&mut self, _event: &InstrEvent) {}
+
+    /// The instruction finished on its execution unit. For an instruction that is decoded into micro-ops
+    /// this is called per micro-op, with the results of that micro-op.
+    fn on_execute(&mut self, _event: &InstrEvent) {}
+
+    /// A word was accessed by an executing load or store; after on_execute of the instruction.
+    fn on_memory_access(&mut self, _access: &MemoryAccess) {}
+
+    /// The instruction retired; the architectural state contains its results.
+    fn on_retire(&mut self, _event: &InstrEvent) {}
+
+    /// A control instruction retired with another target than predicted.
+    fn on_mispredict(&mut self, _event: &MispredictEvent) {}
+
+    /// The instructions that haven't retired were discarded.
+    fn on_flush(&mut self, _event: &FlushEvent) {}
+}
+
+// Lets the caller keep a handle to a registered plugin; e.g. to read what it collected after the run.
+impl<P: Plugin> Plugin for Rc<RefCell<P>> {
+    fn on_load(&mut self, program: &Rc<Program>) {
+        self.borrow_mut().on_load(program)
+    }
+
+    fn on_fetch(&mut self, event: &InstrEvent) {
+        self.borrow_mut().on_fetch(event)
+    }
+
+    fn on_decode(&mut self, event: &InstrEvent) {
+        self.borrow_mut().on_decode(event)
+    }
+
+    fn on_issue(&mut self, event: &InstrEvent) {
+        self.borrow_mut().on_issue(event)
+    }
+
+    fn on_dispatch(&mut self, event: &InstrEvent) {
+        self.borrow_mut().on_dispatch(event)
+    }
+
+    fn on_execute(&mut self, event: &InstrEvent) {
+        self.borrow_mut().on_execute(event)
+    }
+
+    fn on_memory_access(&mut self, access: &MemoryAccess) {
+        self.borrow_mut().on_memory_access(access)
+    }
+
+    fn on_retire(&mut self, event: &InstrEvent) {
+        self.borrow_mut().on_retire(event)
+    }
+
+    fn on_mispredict(&mut self, event: &MispredictEvent) {
+        self.borrow_mut().on_mispredict(event)
+    }
+
+    fn on_flush(&mut self, event: &FlushEvent) {
+        self.borrow_mut().on_flush(event)
+    }
+}
+
+/// The registered plugins; shared by the frontend and the backend.
+pub(crate) type Plugins = Rc<RefCell<Vec<Box<dyn Plugin>>>>;
@@ -0,0 +1,99 @@
+use std::rc::Rc;
+
+use crate::cpu::{CPSR, Flags, Trace};
+use crate::instructions::instructions::{Instr, InstrIndex, Operand, Program};
+use crate::plugin::plugin::{FlushCause, FlushEvent, InstrEvent, MispredictEvent, Plugin};
+
+/// Prints the instructions passing the stages of the pipeline that are enabled in the trace config.
+///
+/// Registered by the CPU when any stage is traced; the registers are printed using their alias.
+pub(crate) struct Tracer {
+    trace: Trace,
+    program_option: Option<Rc<Program>>,
+}
+
+impl Tracer {
+    pub(crate) fn new(trace: &Trace) -> Self {
+        Self { trace: trace.clone(), program_option: None }
+    }
+
+    // The instruction as written in assembly, using the register aliases of the program.
+    fn text(&self, instr: &Instr) -> String {
+        match &self.program_option {
+            Some(program) => instr.with_aliases(&program.register_aliases).to_string(),
+            None => instr.to_string(),
+        }
+    }
+}
+
+impl Plugin for Tracer {
+    fn on_load(&mut self, program: &Rc<Program>) {
+        self.program_option = Some(Rc::clone(program));
+    }
+
+    fn on_fetch(&mut self, event: &InstrEvent) {
+        if !self.trace.decode {
+            return;
+        }
+
+        let line = self.program_option.as_ref().and_then(|program| program.line_for(event.pc));
+        match line {
+            Some(line) => println!("Frontend: ip_next_fetch: {} (line {}) decoded {}", event.pc, line, self.text(event.instr)),
+            None => println!("Frontend: ip_next_fetch: {} decoded {}", event.pc, self.text(event.instr)),
+        }
+    }
+
+    fn on_issue(&mut self, event: &InstrEvent) {
+        if self.trace.issue {
+            println!("issue: Issued [{}]", self.text(event.instr));
+        }
+    }
+
+    fn on_dispatch(&mut self, event: &InstrEvent) {
+        if self.trace.dispatch {
+            println!("Dispatched [{}]", self.text(event.instr));
+        }
+    }
+
+    fn on_execute(&mut self, event: &InstrEvent) {
+        if self.trace.execute {
+            println!("Executing {}", self.text(event.instr));
+        }
+    }
+
+    fn on_retire(&mut self, event: &InstrEvent) {
+        if !self.trace.retire {
+            return;
+        }
+
+        let instr = event.instr;
+        let cpsr_sink = (0..instr.sink_cnt as usize)
+            .find(|sink_index| matches!(instr.sink[*sink_index], Operand::Register(CPSR)));
+        match cpsr_sink {
+            Some(sink_index) => println!("Retiring {} [{}]", self.text(instr), Flags::from_cpsr(event.results[sink_index])),
+            None => println!("Retiring {}", self.text(instr)),
+        }
+    }
+
+    fn on_mispredict(&mut self, event: &MispredictEvent) {
+        if self.trace.retire {
+            println!("Mispredicted {}: predicted {}, actual {}", self.text(event.instr), event.predicted_pc, event.target);
+        }
+    }
+
+    fn on_flush(&mut self, event: &FlushEvent) {
+        if !self.trace.retire {
+            return;
+        }
+
+        match event.cause {
+            FlushCause::Replay => {
+                if let Some(program) = &self.program_option {
+                    println!("Replaying {}", self.text(program.instr(event.pc as InstrIndex)));
+                }
+            }
+            FlushCause::Interrupt => println!("Interrupt: vectoring to {}, returning to {}", event.target, event.pc),
+            FlushCause::Mispredict | FlushCause::InterruptReturn | FlushCause::StepBack => {}
+        }
+    }
+}
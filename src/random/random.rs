@@ -0,0 +1,41 @@
+/// A xorshift pseudo random generator; the same seed always gives the same sequence.
+pub(crate) struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub(crate) fn new(seed: u64) -> Random {
+        // xorshift gets stuck on a state of 0
+        Random { state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // a random number in the range [0, bound)
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// Randomly varies a latency within a bound. Used to shake out ordering bugs in the out of order
+/// machinery; the same seed always gives the same delays, so a run can be replayed.
+pub(crate) struct Jitter {
+    random: Random,
+    max_delay: u8,
+}
+
+impl Jitter {
+    pub(crate) fn new(seed: u64, max_delay: u8) -> Jitter {
+        Jitter { random: Random::new(seed), max_delay }
+    }
+
+    // a random number of extra cycles in the range [0, max_delay]
+    pub(crate) fn delay(&mut self) -> u8 {
+        self.random.below(self.max_delay as u64 + 1) as u8
+    }
+}
@@ -0,0 +1 @@
+pub mod reference_interpreter;
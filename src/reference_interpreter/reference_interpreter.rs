@@ -0,0 +1,393 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+
+use crate::cpu::{add_sub, ARCH_REG_CNT, AlignmentPolicy, CARRY_FLAG_BIT_POSITION, condition_holds, CPSR, CPUConfig, Flags, fp_result, extract_bits, fp_value, insert_bits, IRQ_MASK_BIT_POSITION, is_single_register, leading_zeros, NEGATIVE_FLAG_BIT_POSITION, OVERFLOW_FLAG_BIT_POSITION, PC, reverse_bits, SP, test_flags, vector_op, wrap_word, ZERO_FLAG_BIT_POSITION};
+use crate::instructions::instructions::{Indexing, Instr, Opcode, Operand, Program, RegisterType, sets_flags, WordType};
+use crate::memory_subsystem::heap::Heap;
+use crate::memory_subsystem::memory_subsystem::{check_alignment, check_code_access};
+use crate::plugin::plugin::AccessKind;
+use crate::snapshot::snapshot::Snapshot;
+
+/// A trivially simple in-order interpreter that executes a program one instruction at a time.
+///
+/// There is no pipeline, no renaming and no store buffer; every instruction reads the architectural
+/// registers and memory and writes its results before the next instruction starts. It serves as the
+/// reference for the pipelined CPU: for the same program both should end in the same architectural state.
+///
+/// Interrupts and redzones are not modeled; MALLOC and FREE hand out the same addresses as on the
+/// CPU, but accesses to freed blocks are not detected. MRS is rejected; the counters depend on the timing. So is
+/// READC; the input of the program belongs to the CPU.
+///
+/// The CPU also uses it to fast forward through the start of a program before the cycle accurate
+/// simulation takes over.
+pub struct ReferenceInterpreter<'a> {
+    program: &'a Program,
+    registers: Vec<WordType>,
+    memory: Vec<WordType>,
+    // the maximum number of instructions to execute; protects against programs that never end.
+    max_steps: u64,
+    // the number of bits of a word; integer arithmetic wraps at this width
+    word_size: u8,
+    // the number of bits of a vector lane; lane arithmetic wraps at this width
+    lane_width: u8,
+    heap: Heap,
+    // only the fault policy matters; there is no timing
+    alignment_policy: AlignmentPolicy,
+    // the memory accesses of the instruction that is executing, in program order
+    accesses: RefCell<Vec<(WordType, AccessKind)>>,
+}
+
+// An instruction executed by step; the CPU warms up its caches and BTB with it when fast forwarding.
+pub(crate) struct Step {
+    pub(crate) pc: usize,
+    // the code address of the next instruction
+    pub(crate) next_pc: usize,
+    // the addresses read and written, in program order
+    pub(crate) accesses: Vec<(WordType, AccessKind)>,
+}
+
+impl<'a> ReferenceInterpreter<'a> {
+    pub fn new(cpu_config: &CPUConfig, program: &'a Program, max_steps: u64) -> ReferenceInterpreter<'a> {
+        let mut registers = vec![0; ARCH_REG_CNT as usize];
+        // the same reset state as the CPU
+        registers[SP as usize] = cpu_config.initial_sp() as WordType;
+        registers[CPSR as usize] = 1 << IRQ_MASK_BIT_POSITION;
+        registers[PC as usize] = program.entry_point as WordType;
+
+        let mut memory = vec![0; cpu_config.memory_size as usize];
+        for data in program.data_items.values() {
+            memory[data.offset as usize] = data.value;
+        }
+
+        for (reg, value) in &program.initial_state.registers {
+            registers[*reg as usize] = *value;
+        }
+        for (addr, value) in &program.initial_state.memory {
+            memory[*addr as usize] = *value;
+        }
+
+        let mut heap = Heap::new(cpu_config);
+        heap.init(program);
+
+        ReferenceInterpreter {
+            program,
+            registers,
+            memory,
+            max_steps,
+            word_size: cpu_config.word_size,
+            lane_width: cpu_config.vector.lane_width,
+            heap,
+            alignment_policy: cpu_config.alignment_policy,
+            accesses: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Continues with the registers, the memory and the heap of an earlier interpreter (see into_state) or of
+    // the CPU; e.g. to execute the retiring instructions one at a time next to the pipeline.
+    pub(crate) fn with_state(cpu_config: &CPUConfig, program: &'a Program, registers: Vec<WordType>, memory: Vec<WordType>, heap: Heap) -> ReferenceInterpreter<'a> {
+        ReferenceInterpreter {
+            program,
+            registers,
+            memory,
+            max_steps: u64::MAX,
+            word_size: cpu_config.word_size,
+            lane_width: cpu_config.vector.lane_width,
+            heap,
+            alignment_policy: cpu_config.alignment_policy,
+            accesses: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Runs the program to the end and returns the final architectural state. An error describes
+    /// why the program couldn't be completed, e.g. a fault.
+    pub fn run(&mut self) -> Result<Snapshot, String> {
+        for _ in 0..self.max_steps {
+            if self.step()?.is_none() {
+                return Ok(self.snapshot());
+            }
+        }
+
+        Err(format!("The program didn't complete within {} instructions", self.max_steps))
+    }
+
+    // Replaces the registers and the memory; e.g. by the state of the CPU after the program is loaded
+    // with arguments and memory images. Nothing may have been executed yet, so the heap is still empty.
+    pub(crate) fn set_state(&mut self, registers: Vec<WordType>, memory: Vec<WordType>) {
+        self.registers = registers;
+        self.memory = memory;
+    }
+
+    // The architectural state to continue with: the registers, the memory and the heap.
+    pub(crate) fn into_state(self) -> (Vec<WordType>, Vec<WordType>, Heap) {
+        (self.registers, self.memory, self.heap)
+    }
+
+    pub(crate) fn register(&self, reg: RegisterType) -> WordType {
+        self.registers[reg as usize]
+    }
+
+    // The instruction that executes next; None at the end of the program.
+    pub(crate) fn next_instr(&self) -> Option<&'a Instr> {
+        self.program.code.get(self.registers[PC as usize] as usize)
+    }
+
+    // Executes the next instruction; returns None when the program has ended.
+    pub(crate) fn step(&mut self) -> Result<Option<Step>, String> {
+        let pc = self.registers[PC as usize] as usize;
+        // like the frontend, the PC points to the next instruction while an instruction executes.
+        self.registers[PC as usize] = (pc + 1) as WordType;
+
+        if pc == self.program.code.len() {
+            return Ok(None);
+        }
+
+        let instr = match self.program.code.get(pc) {
+            Some(instr) => instr,
+            None => return Err(format!("Jump to code address {} outside of the program", pc)),
+        };
+        if instr.opcode == Opcode::EXIT {
+            return Ok(None);
+        }
+
+        self.accesses.borrow_mut().clear();
+        let results = self.execute(instr)
+            .map_err(|fault| format!("Fault: {} [{}] at code address {}", fault, instr, pc))?;
+
+        for (sink, result) in instr.sink.iter().take(instr.sink_cnt as usize).zip(results) {
+            // the PC register holds the code address; the result is a memory address (see execute)
+            let result = if sink.get_register() == PC { self.program.code_address(result) as WordType } else { result };
+            self.registers[sink.get_register() as usize] = result;
+        }
+
+        let next_pc = self.registers[PC as usize] as usize;
+        self.heap.on_retire(instr, pc, next_pc);
+        Ok(Some(Step { pc, next_pc, accesses: self.accesses.take() }))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers.clone(),
+            memory: self.memory.clone(),
+            variables: self.program.symbols.variables()
+                .map(|(name, offset)| (String::from(name), offset))
+                .collect(),
+        }
+    }
+
+    fn check_access(&self, addr: WordType, kind: AccessKind) -> Result<usize, String> {
+        if addr < 0 || addr as usize >= self.memory.len() {
+            return Err(format!("Access to address {} outside of memory of {} words", addr, self.memory.len()));
+        }
+        check_code_access(self.program, addr, kind)?;
+        self.accesses.borrow_mut().push((addr, kind));
+        Ok(addr as usize)
+    }
+
+    // Executes a single instruction and returns the values of its sinks.
+    fn execute(&mut self, instr: &Instr) -> Result<Vec<WordType>, String> {
+        let registers = &self.registers;
+        let program = self.program;
+        // the PC and the branch targets are read as memory addresses, like a program sees them
+        let source = |index: usize| match instr.source[index] {
+            Operand::Register(PC) => program.memory_address(registers[PC as usize] as usize),
+            Operand::Register(reg) => registers[reg as usize],
+            Operand::Code(code_address) => program.memory_address(code_address as usize),
+            Operand::Immediate(value) | Operand::Memory(value) => value,
+            Operand::RegisterList(_) | Operand::Unused => panic!("Illegal source {:?}", instr.source[index]),
+        };
+
+        let cpsr = registers[CPSR as usize];
+        let word_size = self.word_size;
+        let wrap = |value: WordType| wrap_word(value, word_size);
+        // the address accessed by a load or store and, with writeback, the updated address register
+        let address = |base_index: usize| {
+            let base = source(base_index);
+            let offset = if instr.source_cnt as usize > base_index + 1 { source(base_index + 1) } else { 0 };
+            match instr.indexing {
+                Indexing::Offset => (base + offset, None),
+                Indexing::PreIndexed => (base + offset, Some(wrap(base + offset))),
+                Indexing::PostIndexed => (base, Some(wrap(base + offset))),
+            }
+        };
+
+        let results = match instr.opcode {
+            // there is no store buffer, so the barriers have nothing to wait for
+            Opcode::NOP | Opcode::EXIT | Opcode::PRINTR | Opcode::DMB | Opcode::DSB => vec![],
+            Opcode::ADD => vec![wrap(source(0).wrapping_add(source(1)))],
+            Opcode::SUB => vec![wrap(source(0).wrapping_sub(source(1)))],
+            Opcode::MUL => vec![wrap(source(0).wrapping_mul(source(1)))],
+            Opcode::SDIV => {
+                if source(1) == 0 {
+                    return Err(String::from("Division by zero"));
+                }
+                vec![wrap(source(0).wrapping_div(source(1)))]
+            }
+            Opcode::NEG => vec![wrap(source(0).wrapping_neg())],
+            Opcode::AND => vec![source(0) & source(1)],
+            Opcode::ORR => vec![source(0) | source(1)],
+            Opcode::EOR => vec![source(0) ^ source(1)],
+            Opcode::MVN => vec![!source(0)],
+            Opcode::CLZ => vec![leading_zeros(source(0), word_size)],
+            Opcode::RBIT => vec![reverse_bits(source(0), word_size)],
+            Opcode::UBFX => vec![extract_bits(source(0), source(1) as u32, source(2) as u32, false, word_size)],
+            Opcode::SBFX => vec![extract_bits(source(0), source(1) as u32, source(2) as u32, true, word_size)],
+            Opcode::MOVW => vec![source(0)],
+            Opcode::MOVT => vec![insert_bits(source(0), source(1), 16, 16, word_size)],
+            Opcode::MOVK => vec![insert_bits(source(0), source(1), source(2) as u32, 16, word_size)],
+            Opcode::BFI => vec![insert_bits(source(0), source(1), source(2) as u32, source(3) as u32, word_size)],
+            Opcode::MOV => vec![wrap(source(0))],
+            Opcode::ADR => vec![source(0)],
+            Opcode::LDR => match instr.source[0] {
+                // LDR r0, =label; the literal is loaded instead of a memory location.
+                Operand::Immediate(literal) => vec![wrap(literal)],
+                _ => {
+                    let (addr, writeback) = address(0);
+                    let mut results = vec![self.memory[self.check_access(addr, AccessKind::Load)?]];
+                    results.extend(writeback);
+                    results
+                }
+            },
+            Opcode::STR => {
+                let (addr, writeback) = address(1);
+                let addr = self.check_access(addr, AccessKind::Store)?;
+                self.memory[addr] = source(0);
+                writeback.into_iter().collect()
+            }
+            Opcode::PUSH => {
+                let register_cnt = instr.source_cnt as usize - 1;
+                let base = source(0) - register_cnt as WordType;
+                check_alignment(base, register_cnt as u8, self.alignment_policy)?;
+                for k in 0..register_cnt {
+                    let addr = self.check_access(base + k as WordType, AccessKind::Store)?;
+                    self.memory[addr] = source(1 + k);
+                }
+                vec![base]
+            }
+            Opcode::POP => {
+                let register_cnt = instr.sink_cnt as usize - 1;
+                let sp = source(0);
+                let mut results = Vec::with_capacity(register_cnt + 1);
+                check_alignment(sp, register_cnt as u8, self.alignment_policy)?;
+                for k in 0..register_cnt {
+                    results.push(self.memory[self.check_access(sp + k as WordType, AccessKind::Load)?]);
+                }
+                results.push(sp + register_cnt as WordType);
+                results
+            }
+            Opcode::LDM(mode) | Opcode::STM(mode) => {
+                let registers = instr.block_registers();
+                let register_cnt = registers.len() as WordType;
+                let first = source(0) + mode.first_offset(registers.len());
+                check_alignment(first, register_cnt as u8, self.alignment_policy)?;
+                let mut results = Vec::with_capacity(registers.len() + 1);
+                for k in 0..register_cnt {
+                    if instr.opcode == Opcode::LDM(mode) {
+                        results.push(self.memory[self.check_access(first + k, AccessKind::Load)?]);
+                    } else {
+                        let addr = self.check_access(first + k, AccessKind::Store)?;
+                        self.memory[addr] = source(1 + k as usize);
+                    }
+                }
+                if instr.indexing != Indexing::Offset {
+                    results.push(if mode.increments() { source(0) + register_cnt } else { source(0) - register_cnt });
+                }
+                results
+            }
+            Opcode::VADD | Opcode::VSUB | Opcode::VMUL => {
+                let lanes = instr.sink_cnt as usize;
+                (0..lanes)
+                    .map(|k| wrap_word(vector_op(instr.opcode, source(k), source(lanes + k)), self.lane_width))
+                    .collect()
+            }
+            Opcode::VLD1 => {
+                let lanes = instr.sink_cnt as usize;
+                check_alignment(source(0), lanes as u8, self.alignment_policy)?;
+                let mut results = Vec::with_capacity(lanes);
+                for k in 0..lanes {
+                    let addr = self.check_access(source(0) + k as WordType, AccessKind::Load)?;
+                    results.push(wrap_word(self.memory[addr], self.lane_width));
+                }
+                results
+            }
+            Opcode::VST1 => {
+                let lanes = instr.source_cnt as usize - 1;
+                check_alignment(source(0), lanes as u8, self.alignment_policy)?;
+                for k in 0..lanes {
+                    let addr = self.check_access(source(0) + k as WordType, AccessKind::Store)?;
+                    self.memory[addr] = source(1 + k);
+                }
+                vec![]
+            }
+            // an ADDS, SUBS, CMP or CMN doesn't have the CPSR as source; the other bits of the CPSR are kept
+            Opcode::ADC | Opcode::SBC | Opcode::ADDS | Opcode::SUBS | Opcode::ADCS | Opcode::SBCS => {
+                let (result, flags) = add_sub(instr.opcode, source(0), source(1), cpsr, word_size);
+                if sets_flags(instr.opcode) {
+                    vec![result, flags.apply_to(cpsr)]
+                } else {
+                    vec![result]
+                }
+            }
+            Opcode::CMP | Opcode::CMN => {
+                let (_, flags) = add_sub(instr.opcode, source(0), source(1), cpsr, word_size);
+                vec![flags.apply_to(cpsr)]
+            }
+            Opcode::TST => vec![test_flags(source(0), source(1), cpsr, word_size).apply_to(cpsr)],
+            Opcode::B => vec![source(0)],
+            Opcode::BX => vec![source(0)],
+            Opcode::BL | Opcode::BLX => vec![source(1), source(0)],
+            Opcode::CBZ => vec![if source(0) == 0 { source(1) } else { source(2) }],
+            Opcode::CBNZ => vec![if source(0) != 0 { source(1) } else { source(2) }],
+            Opcode::TBZ => vec![if (source(0) >> source(1)) & 1 == 0 { source(2) } else { source(3) }],
+            Opcode::TBNZ => vec![if (source(0) >> source(1)) & 1 == 1 { source(2) } else { source(3) }],
+            Opcode::BEQ | Opcode::BNE | Opcode::BLT | Opcode::BLE | Opcode::BGT | Opcode::BGE |
+            Opcode::BHI | Opcode::BLS | Opcode::BHS | Opcode::BLO => {
+                let taken = condition_holds(instr.opcode, Flags::from_cpsr(source(1)));
+                vec![if taken { source(0) } else { source(2) }]
+            }
+            Opcode::MALLOC => vec![self.heap.allocate(source(0), instr.loc)],
+            Opcode::FREE => {
+                self.heap.check_free(source(0))?;
+                self.heap.free(source(0), instr.loc);
+                vec![]
+            }
+            Opcode::CPSIE => vec![source(0) & !(1 << IRQ_MASK_BIT_POSITION)],
+            Opcode::CPSID => vec![source(0) | (1 << IRQ_MASK_BIT_POSITION)],
+            Opcode::ERET => return Err(String::from("Interrupts are not supported by the reference interpreter")),
+            Opcode::MRS => return Err(String::from("Performance counters are not supported by the reference interpreter")),
+            Opcode::READC => return Err(String::from("Input is not supported by the reference interpreter")),
+            Opcode::FADD | Opcode::FSUB | Opcode::FMUL | Opcode::FDIV => {
+                let a = fp_value(source(0));
+                let b = fp_value(source(1));
+                let result = match instr.opcode {
+                    Opcode::FADD => a + b,
+                    Opcode::FSUB => a - b,
+                    Opcode::FMUL => a * b,
+                    _ => a / b,
+                };
+                vec![fp_result(result, is_single_register(instr.sink[0].get_register()))]
+            }
+            Opcode::FMOV => {
+                let value = match instr.source[0] {
+                    Operand::Immediate(value) => value as f64,
+                    _ => fp_value(source(0)),
+                };
+                vec![fp_result(value, is_single_register(instr.sink[0].get_register()))]
+            }
+            Opcode::FCMP => {
+                let flags = match fp_value(source(0)).partial_cmp(&fp_value(source(1))) {
+                    Some(Ordering::Equal) => (1 << ZERO_FLAG_BIT_POSITION) | (1 << CARRY_FLAG_BIT_POSITION),
+                    Some(Ordering::Less) => 1 << NEGATIVE_FLAG_BIT_POSITION,
+                    Some(Ordering::Greater) => 1 << CARRY_FLAG_BIT_POSITION,
+                    None => (1 << CARRY_FLAG_BIT_POSITION) | (1 << OVERFLOW_FLAG_BIT_POSITION),
+                };
+                vec![flags]
+            }
+            Opcode::CUSTOM(custom) => {
+                let sources: Vec<WordType> = (0..instr.source_cnt as usize).map(source).collect();
+                self.program.custom_instructions.get(custom).execute(&sources)
+            }
+        };
+
+        Ok(results)
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use serde::Serialize;
+
+use crate::conformance::runner::find_tests;
+use crate::cpu::{CPU, CPUConfig};
+use crate::loader::loader::load;
+
+/// The statistics of a single benchmark run.
+#[derive(Clone, Serialize, Debug)]
+pub struct BenchStats {
+    pub cycles: u64,
+    pub retired: u64,
+    pub ipc: f64,
+    /// None when no control instruction was looked up in the BTB.
+    pub btb_miss_rate: Option<f64>,
+    /// None when the caches are disabled or the level wasn't accessed.
+    pub l1_miss_rate: Option<f64>,
+    pub l2_miss_rate: Option<f64>,
+}
+
+/// The result of a single program; an error when it doesn't assemble or hangs.
+#[derive(Clone, Serialize, Debug)]
+pub struct BenchRow {
+    // the path of the program relative to the benchmark directory
+    pub name: String,
+    pub result: Result<BenchStats, String>,
+}
+
+/// The results of all programs in a benchmark directory for one config.
+#[derive(Clone, Serialize, Debug)]
+pub struct BenchResult {
+    pub config_name: String,
+    pub rows: Vec<BenchRow>,
+}
+
+const COLUMNS: [&str; 8] = ["program", "cycles", "retired", "ipc", "btb_miss_rate", "l1_miss_rate", "l2_miss_rate", "error"];
+
+impl BenchResult {
+    pub fn error_cnt(&self) -> usize {
+        self.rows.iter().filter(|row| row.result.is_err()).count()
+    }
+
+    /// The table as CSV with a header line; an unknown rate is an empty field.
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("{}\n", COLUMNS.join(","));
+        for row in &self.rows {
+            let fields: Vec<String> = row_fields(row).into_iter().map(|field| csv_field(&field)).collect();
+            writeln!(csv, "{}", fields.join(",")).unwrap();
+        }
+        csv
+    }
+
+    /// The table as Markdown; an unknown rate is a '-'.
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("| {} |\n", COLUMNS.join(" | "));
+        writeln!(markdown, "|{}", "---|".repeat(COLUMNS.len())).unwrap();
+        for row in &self.rows {
+            let fields: Vec<String> = row_fields(row).into_iter()
+                .map(|field| if field.is_empty() { String::from("-") } else { field.replace('|', "\\|").replace('\n', " ") })
+                .collect();
+            writeln!(markdown, "| {} |", fields.join(" | ")).unwrap();
+        }
+        markdown
+    }
+}
+
+// The values of the columns of the row; empty when unknown.
+fn row_fields(row: &BenchRow) -> Vec<String> {
+    let rate = |rate: Option<f64>| rate.map_or(String::new(), |rate| format!("{:.4}", rate));
+    match &row.result {
+        Ok(stats) => vec![
+            row.name.clone(),
+            stats.cycles.to_string(),
+            stats.retired.to_string(),
+            format!("{:.2}", stats.ipc),
+            rate(stats.btb_miss_rate),
+            rate(stats.l1_miss_rate),
+            rate(stats.l2_miss_rate),
+            String::new(),
+        ],
+        Err(error) => {
+            let mut fields = vec![row.name.clone()];
+            fields.extend((0..COLUMNS.len() - 2).map(|_| String::new()));
+            fields.push(error.trim().to_string());
+            fields
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/// Runs every '.asm' program in the directory tree on the given config and collects the statistics
+/// per program, e.g. to track the performance of the microarchitecture across changes. Tracing, the
+/// frequency throttle and the output of the programs are disabled.
+pub fn run_bench(dir: &Path, config_name: &str, cpu_config: &CPUConfig) -> io::Result<BenchResult> {
+    let mut cpu_config = cpu_config.clone();
+    cpu_config.set_trace(false);
+
+    let rows = find_tests(dir)?.into_iter()
+        .map(|path| BenchRow {
+            name: path.strip_prefix(dir).unwrap_or(&path).display().to_string(),
+            result: run_program(&path, &cpu_config),
+        })
+        .collect();
+    Ok(BenchResult { config_name: config_name.to_string(), rows })
+}
+
+fn run_program(path: &Path, cpu_config: &CPUConfig) -> Result<BenchStats, String> {
+    let program = load(cpu_config.clone(), path.to_str().unwrap())
+        .map(Rc::new)
+        .map_err(|error| format!("Loading program failed. {}", error))?;
+
+    // only the table is printed
+    let mut cpu = CPU::new(cpu_config);
+    cpu.set_quiet(true);
+    cpu.set_throttled(false);
+    cpu.set_stdout(Box::new(io::sink()));
+    cpu.run(&program).map_err(|diagnosis| diagnosis.to_string())?;
+
+    let perf_counters = cpu.perf_counters();
+    Ok(BenchStats {
+        cycles: perf_counters.cycle_cnt,
+        retired: perf_counters.retire_cnt,
+        ipc: perf_counters.ipc() as f64,
+        btb_miss_rate: perf_counters.btb_hit_rate().map(|rate| 1.0 - rate as f64),
+        l1_miss_rate: cpu.cache_miss_rate(0),
+        l2_miss_rate: cpu.cache_miss_rate(1),
+    })
+}
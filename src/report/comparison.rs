@@ -0,0 +1,159 @@
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+
+use serde::Serialize;
+
+use crate::cpu::{CPU, CPUConfig, PerfCounters};
+use crate::instructions::instructions::{EU_TYPES, WordType};
+use crate::loader::loader::load_files;
+
+/// A statistic of the baseline and the candidate run.
+#[derive(Clone, Serialize, Debug)]
+pub struct StatDelta {
+    pub name: String,
+    /// None when the statistic is a rate without events, e.g. the BTB miss rate without a lookup.
+    pub baseline: Option<f64>,
+    pub candidate: Option<f64>,
+    /// candidate - baseline; None when either is unknown
+    pub delta: Option<f64>,
+    /// The delta relative to the baseline; None when the baseline is 0 or either is unknown.
+    pub percentage: Option<f64>,
+}
+
+/// The difference between the statistics of the same program run under two configurations.
+#[derive(Clone, Serialize, Debug)]
+pub struct Comparison {
+    pub baseline_name: String,
+    pub candidate_name: String,
+    pub stats: Vec<StatDelta>,
+}
+
+impl Comparison {
+    pub fn new(baseline_name: &str, baseline: &PerfCounters, candidate_name: &str, candidate: &PerfCounters) -> Comparison {
+        let stats = statistics(baseline).into_iter()
+            .zip(statistics(candidate))
+            .map(|((name, baseline), (_, candidate))| {
+                let delta = baseline.zip(candidate).map(|(baseline, candidate)| candidate - baseline);
+                let percentage = baseline.zip(delta)
+                    .filter(|(baseline, _)| *baseline != 0.0)
+                    .map(|(baseline, delta)| 100.0 * delta / baseline);
+                StatDelta { name, baseline, candidate, delta, percentage }
+            })
+            .collect();
+
+        Comparison {
+            baseline_name: String::from(baseline_name),
+            candidate_name: String::from(candidate_name),
+            stats,
+        }
+    }
+
+    /// Returns the delta of the statistic with the given name, e.g. "cycles" or "ipc".
+    pub fn get(&self, name: &str) -> Option<&StatDelta> {
+        self.stats.iter().find(|stat| stat.name == name)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+// The compared statistics in the order they are reported; None for a rate without events.
+fn statistics(perf_counters: &PerfCounters) -> Vec<(String, Option<f64>)> {
+    let mut stats = vec![
+        ("cycles", Some(perf_counters.cycle_cnt as f64)),
+        ("retired", Some(perf_counters.retire_cnt as f64)),
+        ("ipc", (perf_counters.cycle_cnt > 0).then(|| perf_counters.ipc() as f64)),
+        ("decoded", Some(perf_counters.decode_cnt as f64)),
+        ("issued", Some(perf_counters.issue_cnt as f64)),
+        ("dispatched", Some(perf_counters.dispatch_cnt as f64)),
+        ("executed", Some(perf_counters.execute_cnt as f64)),
+        ("load_replays", Some(perf_counters.load_replay_cnt as f64)),
+        ("interrupts", Some(perf_counters.interrupt_cnt as f64)),
+        ("squashed_loads", Some(perf_counters.squashed_load_cnt as f64)),
+        ("squashed_stores", Some(perf_counters.squashed_store_cnt as f64)),
+        ("btb_lookups", Some(perf_counters.btb_lookup_cnt as f64)),
+        ("btb_miss_rate", perf_counters.btb_hit_rate().map(|rate| 1.0 - rate as f64)),
+        ("btb_mispredicts", Some(perf_counters.btb_mispredict_cnt as f64)),
+        ("ras_lookups", Some(perf_counters.ras_lookup_cnt as f64)),
+        ("ras_miss_rate", perf_counters.ras_hit_rate().map(|rate| 1.0 - rate as f64)),
+        ("ras_mispredicts", Some(perf_counters.ras_mispredict_cnt as f64)),
+        ("redirects", Some(perf_counters.redirect_cnt as f64)),
+        ("redirect_latency", perf_counters.redirect_latency().map(f64::from)),
+        ("mispredict_penalty", perf_counters.mispredict_penalty().map(f64::from)),
+        ("forwarded_operands", Some(perf_counters.forwarded_operand_cnt as f64)),
+        ("sb_full_stalls", Some(perf_counters.sb_full_stall_cnt as f64)),
+        ("sb_drain_stalls", Some(perf_counters.sb_drain_stall_cnt as f64)),
+        ("sb_coalesced", Some(perf_counters.sb_coalesced_cnt as f64)),
+    ].into_iter()
+        .map(|(name, value)| (String::from(name), value))
+        .collect::<Vec<_>>();
+
+    for eu_type in EU_TYPES {
+        stats.push((format!("structural_stalls.{}", eu_type), Some(perf_counters.structural_stall_cnt[eu_type as usize] as f64)));
+    }
+    stats
+}
+
+// An unknown value is a '-'.
+fn format_value(value: Option<f64>) -> String {
+    match value {
+        None => String::from("-"),
+        Some(value) if value.fract() == 0.0 => format!("{}", value),
+        Some(value) => format!("{:.2}", value),
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name_width = self.stats.iter().map(|stat| stat.name.len()).fold("Statistic".len(), usize::max);
+        writeln!(f, "{:<name_width$} {:>14} {:>14} {:>14} {:>9}", "Statistic", self.baseline_name, self.candidate_name, "Delta", "%")?;
+        for stat in &self.stats {
+            let percentage = stat.percentage.map_or(String::from("-"), |percentage| format!("{:+.1}%", percentage));
+            writeln!(f, "{:<name_width$} {:>14} {:>14} {:>14} {:>9}",
+                     stat.name, format_value(stat.baseline), format_value(stat.candidate),
+                     format_value(stat.delta), percentage)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the program (and its libraries) with the same arguments and memory image (a path and an
+/// offset; see `CPU::load_memory_image`) under both configurations and compares the statistics. The program is loaded per configuration, since the configuration
+/// determines the instruction latencies. Tracing, the frequency throttle and the output of the runs are disabled.
+pub fn compare_configs(baseline_name: &str,
+                       baseline: &CPUConfig,
+                       candidate_name: &str,
+                       candidate: &CPUConfig,
+                       paths: &[&str],
+                       args: &[WordType],
+                       memory_image: Option<(&str, u64)>) -> Result<Comparison, String> {
+    let baseline_counters = run_with(baseline, paths, args, memory_image)
+        .map_err(|error| format!("The run with {} failed. {}", baseline_name, error))?;
+    let candidate_counters = run_with(candidate, paths, args, memory_image)
+        .map_err(|error| format!("The run with {} failed. {}", candidate_name, error))?;
+    Ok(Comparison::new(baseline_name, &baseline_counters, candidate_name, &candidate_counters))
+}
+
+fn run_with(cpu_config: &CPUConfig, paths: &[&str], args: &[WordType], memory_image: Option<(&str, u64)>) -> Result<PerfCounters, String> {
+    let mut cpu_config = cpu_config.clone();
+    cpu_config.set_trace(false);
+
+    let program = load_files(cpu_config.clone(), paths)
+        .map(Rc::new)
+        .map_err(|error| format!("Loading program '{}' failed. {}", paths[0], error))?;
+
+    // only the comparison is printed
+    let mut cpu = CPU::new(&cpu_config);
+    cpu.set_quiet(true);
+    cpu.set_throttled(false);
+    cpu.set_stdout(Box::new(io::sink()));
+    cpu.set_arguments(args)?;
+    if let Some((path, offset)) = memory_image {
+        cpu.load_memory_image(path, offset)
+            .map_err(|error| format!("Failed to load memory image {}. Cause: {}", path, error))?;
+    }
+    cpu.run(&program).map_err(|diagnosis| diagnosis.to_string())?;
+    Ok(cpu.perf_counters())
+}
@@ -0,0 +1,5 @@
+pub mod pipeline_report;
+pub mod comparison;
+pub mod pipeline_state;
+pub mod bench;
+pub mod openmetrics;
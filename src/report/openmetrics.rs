@@ -0,0 +1,127 @@
+use std::fmt::Write;
+
+use crate::cpu::PerfCounters;
+use crate::instructions::instructions::EU_TYPES;
+
+// The prefix of every metric name.
+const PREFIX: &str = "cpu_emulator";
+
+impl PerfCounters {
+    /// Returns the performance counters in the OpenMetrics text format, which Prometheus and most other
+    /// monitoring tools scrape; e.g. `cpu_emulator_cycles_total{program="asm/sum.asm"} 36`. The counters
+    /// are counters with a `_total` sample, the derived rates and averages (IPC, hit rates, latencies) are
+    /// gauges; a rate without events (e.g. the BTB hit rate without a lookup) has no sample. Every sample gets the labels, e.g. the program and the config,
+    /// and the counters per type of execution unit also an `eu` label.
+    pub fn to_openmetrics(&self, labels: &[(&str, &str)]) -> String {
+        let mut metrics = OpenMetrics { text: String::new(), labels: format_labels(labels) };
+
+        metrics.counter("cycles", "The simulated clock cycles", self.cycle_cnt);
+        metrics.counter("decoded", "The decoded instructions", self.decode_cnt);
+        metrics.counter("issued", "The issued micro-ops", self.issue_cnt);
+        metrics.counter("dispatched", "The micro-ops dispatched to an execution unit", self.dispatch_cnt);
+        metrics.counter("executed", "The executed micro-ops", self.execute_cnt);
+        metrics.counter("retired", "The retired instructions", self.retire_cnt);
+        metrics.gauge("ipc", "The retired instructions per cycle", (self.cycle_cnt > 0).then(|| self.ipc()));
+        metrics.counter("load_replays", "The loads replayed after a conflict with an older store", self.load_replay_cnt);
+        metrics.counter("interrupts", "The interrupts taken", self.interrupt_cnt);
+        metrics.counter("squashed_loads", "The executed loads flushed before they retired", self.squashed_load_cnt);
+        metrics.counter("squashed_stores", "The stores removed from the store buffer after a misprediction", self.squashed_store_cnt);
+        metrics.counter("btb_lookups", "The fetched control instructions looked up in the BTB", self.btb_lookup_cnt);
+        metrics.counter("btb_hits", "The BTB lookups that hit", self.btb_hit_cnt);
+        metrics.counter("btb_mispredicts", "The BTB hits with the wrong target", self.btb_mispredict_cnt);
+        metrics.gauge("btb_hit_rate", "The fraction of the BTB lookups that hit", self.btb_hit_rate());
+        metrics.counter("ras_lookups", "The fetched returns looked up in the return address stack", self.ras_lookup_cnt);
+        metrics.counter("ras_hits", "The return address stack lookups that hit", self.ras_hit_cnt);
+        metrics.counter("ras_mispredicts", "The return address stack hits with the wrong target", self.ras_mispredict_cnt);
+        metrics.gauge("ras_hit_rate", "The fraction of the return address stack lookups that hit", self.ras_hit_rate());
+        metrics.counter("redirects", "The redirects of the frontend after a control instruction", self.redirect_cnt);
+        metrics.gauge("redirect_latency_cycles", "The average cycles from fetching a control instruction to the redirect", self.redirect_latency());
+        metrics.counter("mispredicts", "The mispredictions after which the correct path was issued", self.mispredict_refill_cnt);
+        metrics.gauge("mispredict_penalty_cycles", "The average cycles from the flush to the issue of the correct path", self.mispredict_penalty());
+        metrics.counter("renames", "The physical registers allocated for the sinks of issued micro-ops", self.rename_cnt);
+        metrics.counter("forwarded_operands", "The source operands received through the bypass network", self.forwarded_operand_cnt);
+        metrics.counter("forwarding_saved_cycles", "The cycles saved by forwarding the last operand", self.forwarding_saved_cycle_cnt);
+        metrics.counter("sb_full_stalls", "The cycles issue stalled on a full store buffer", self.sb_full_stall_cnt);
+        metrics.counter("sb_drain_stalls", "The cycles a store waited for the drain bandwidth", self.sb_drain_stall_cnt);
+        metrics.counter("sb_coalesced", "The committed stores merged into a younger store", self.sb_coalesced_cnt);
+        metrics.counter("sb_reordered", "The stores written to memory before an older store", self.sb_reordered_cnt);
+        metrics.counter("mode_switches", "The retired branches between ARM and Thumb code", self.mode_switch_cnt);
+        metrics.counter("unaligned_accesses", "The multi-word accesses that paid the unaligned penalty", self.unaligned_access_cnt);
+        metrics.counter("fast_forwarded", "The instructions executed by the reference interpreter before the simulation", self.fast_forward_cnt);
+        metrics.counter("skipped_cycles", "The idle cycles skipped at once", self.skipped_cycle_cnt);
+        metrics.eu_counter("structural_stalls", "The times a ready micro-op found no free execution unit", &self.structural_stall_cnt);
+        metrics.eu_counter("eu_dispatches", "The micro-ops dispatched per type of execution unit", &self.eu_dispatch_cnt);
+
+        metrics.text.push_str("# EOF\n");
+        metrics.text
+    }
+}
+
+struct OpenMetrics {
+    text: String,
+    // the labels of every sample, without braces; e.g. program="asm/sum.asm"
+    labels: String,
+}
+
+impl OpenMetrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        writeln!(self.text, "# TYPE {}_{} {}", PREFIX, name, kind).unwrap();
+        writeln!(self.text, "# HELP {}_{} {}.", PREFIX, name, help).unwrap();
+    }
+
+    fn sample(&mut self, name: &str, extra_label: Option<String>, value: &str) {
+        let labels: Vec<&str> = [Some(self.labels.as_str()), extra_label.as_deref()].into_iter()
+            .flatten()
+            .filter(|labels| !labels.is_empty())
+            .collect();
+        if labels.is_empty() {
+            writeln!(self.text, "{}_{} {}", PREFIX, name, value).unwrap();
+        } else {
+            writeln!(self.text, "{}_{}{{{}}} {}", PREFIX, name, labels.join(","), value).unwrap();
+        }
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.family(name, "counter", help);
+        self.sample(&format!("{}_total", name), None, &value.to_string());
+    }
+
+    // A gauge without a value, a rate of nothing, has no sample.
+    fn gauge(&mut self, name: &str, help: &str, value: Option<f32>) {
+        self.family(name, "gauge", help);
+        if let Some(value) = value {
+            self.sample(name, None, &format_float(value));
+        }
+    }
+
+    // A counter per type of execution unit.
+    fn eu_counter(&mut self, name: &str, help: &str, values: &[u64]) {
+        self.family(name, "counter", help);
+        for eu_type in EU_TYPES {
+            self.sample(&format!("{}_total", name), Some(format!("eu=\"{}\"", eu_type)), &values[eu_type as usize].to_string());
+        }
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    labels.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// A label value is quoted; a backslash, a double quote and a line feed are escaped.
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// The special values are spelled NaN, +Inf and -Inf.
+fn format_float(value: f32) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        value.to_string()
+    }
+}
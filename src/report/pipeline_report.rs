@@ -0,0 +1,321 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::cpu::PerfCounters;
+use crate::plugin::plugin::{InstrEvent, Plugin};
+
+/// The cycles in which one execution of an instruction passed the stages of the pipeline; None for
+/// a stage it didn't reach (yet).
+struct InstrRecord {
+    seq: u64,
+    pc: usize,
+    text: String,
+    line: Option<usize>,
+    decode: Option<u64>,
+    issue: Option<u64>,
+    execute: Option<u64>,
+    retire: Option<u64>,
+}
+
+impl InstrRecord {
+    // the reached stages with their cycle, in pipeline order
+    fn stages(&self) -> Vec<(u64, Stage)> {
+        [(self.decode, Stage::Decode), (self.issue, Stage::Issue), (self.execute, Stage::Execute), (self.retire, Stage::Retire)]
+            .iter()
+            .filter_map(|(cycle, stage)| cycle.map(|cycle| (cycle, *stage)))
+            .collect()
+    }
+
+    // the stages reached in the given cycle
+    fn stages_at(&self, cycle: u64) -> Vec<Stage> {
+        self.stages().iter()
+            .filter(|(stage_cycle, _)| *stage_cycle == cycle)
+            .map(|(_, stage)| *stage)
+            .collect()
+    }
+
+    // Between two reached stages, the instruction waits after the first one. After its last stage
+    // it is either retired or flushed; so it doesn't wait.
+    fn waiting_at(&self, cycle: u64) -> Option<Stage> {
+        self.stages().windows(2)
+            .find(|pair| pair[0].0 < cycle && cycle < pair[1].0)
+            .map(|pair| pair[0].1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Decode,
+    Issue,
+    Execute,
+    Retire,
+}
+
+impl Stage {
+    fn letter(&self) -> &'static str {
+        match self {
+            Stage::Decode => "D",
+            Stage::Issue => "I",
+            Stage::Execute => "E",
+            Stage::Retire => "R",
+        }
+    }
+
+    fn class(&self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Issue => "issue",
+            Stage::Execute => "execute",
+            Stage::Retire => "retire",
+        }
+    }
+}
+
+/// The totals of a single source line over the whole run.
+#[derive(Default)]
+struct LineStats {
+    retire_cnt: u64,
+    flush_cnt: u64,
+    // the cycles between decode and retire of all retired instructions of the line
+    cycles: u64,
+}
+
+/// Records the pipeline stages of the instructions decoded within a window of cycles and writes them
+/// as a standalone HTML report: a pipeline diagram of the window, a heat map of the source lines
+/// over the whole run and the performance counters.
+pub struct PipelineRecorder {
+    first_cycle: u64,
+    last_cycle: u64,
+    // the instructions decoded within the window; ordered by seq
+    records: Vec<InstrRecord>,
+    // seq -> index in records
+    record_index: HashMap<u64, usize>,
+    // the in flight instructions: seq -> (decode cycle, source line)
+    in_flight: BTreeMap<u64, (u64, Option<usize>)>,
+    lines: BTreeMap<usize, LineStats>,
+    // the last cycle an event was seen in
+    end_cycle: u64,
+}
+
+impl PipelineRecorder {
+    /// Records the instructions decoded from first_cycle till first_cycle + cycle_cnt.
+    pub fn new(first_cycle: u64, cycle_cnt: u64) -> PipelineRecorder {
+        PipelineRecorder {
+            first_cycle,
+            last_cycle: first_cycle + cycle_cnt.max(1) - 1,
+            records: Vec::new(),
+            record_index: HashMap::new(),
+            in_flight: BTreeMap::new(),
+            lines: BTreeMap::new(),
+            end_cycle: 0,
+        }
+    }
+
+    fn record(&mut self, seq: u64) -> Option<&mut InstrRecord> {
+        let index = *self.record_index.get(&seq)?;
+        Some(&mut self.records[index])
+    }
+
+    fn observe(&mut self, cycle: u64) {
+        self.end_cycle = self.end_cycle.max(cycle);
+    }
+
+    /// Writes the report as a standalone HTML page. The source is the assembly of the program; it is
+    /// used for the heat map.
+    pub fn to_html(&self, title: &str, source: &str, perf_counters: &PerfCounters) -> String {
+        let mut html = String::new();
+        writeln!(html, "<!DOCTYPE html>").unwrap();
+        writeln!(html, "<html><head><meta charset=\"utf-8\"><title>{}</title>", escape(title)).unwrap();
+        writeln!(html, "<style>{}</style></head><body>", STYLE).unwrap();
+        writeln!(html, "<h1>{}</h1>", escape(title)).unwrap();
+
+        self.write_pipeline(&mut html);
+        self.write_heat_map(&mut html, source);
+        write_stats(&mut html, perf_counters);
+
+        writeln!(html, "<script>{}</script>", SCRIPT).unwrap();
+        writeln!(html, "</body></html>").unwrap();
+        html
+    }
+
+    fn write_pipeline(&self, html: &mut String) {
+        let last_cycle = self.last_cycle.min(self.end_cycle);
+        writeln!(html, "<h2>Pipeline (cycles {} to {})</h2>", self.first_cycle, last_cycle).unwrap();
+        writeln!(html, "<p><label><input type=\"checkbox\" id=\"hide-flushed\"> hide flushed instructions</label> \
+                        <span class=\"decode\">D</span> decode <span class=\"issue\">I</span> issue \
+                        <span class=\"execute\">E</span> execute <span class=\"retire\">R</span> retire</p>").unwrap();
+        if self.records.is_empty() {
+            writeln!(html, "<p>No instructions were decoded in this window.</p>").unwrap();
+            return;
+        }
+
+        // the diagram continues after the window till the recorded instructions are gone
+        let end_cycle = self.records.iter()
+            .filter_map(|record| record.retire.or(record.execute).or(record.issue).or(record.decode))
+            .max()
+            .unwrap_or(last_cycle);
+
+        writeln!(html, "<div class=\"scroll\"><table class=\"pipeline\">").unwrap();
+        write!(html, "<tr><th>seq</th><th>pc</th><th>instruction</th>").unwrap();
+        for cycle in self.first_cycle..=end_cycle {
+            write!(html, "<th>{}</th>", cycle).unwrap();
+        }
+        writeln!(html, "</tr>").unwrap();
+
+        for record in &self.records {
+            let flushed = record.retire.is_none();
+            let line_attr = record.line.map_or(String::new(), |line| format!(" data-line=\"{}\"", line));
+            write!(html, "<tr class=\"{}\"{}><td>{}</td><td>{}</td><td class=\"text\" title=\"{}\">{}</td>",
+                   if flushed { "instr flushed" } else { "instr" }, line_attr,
+                   record.seq, record.pc, escape(&tooltip(record)), escape(&record.text)).unwrap();
+            for cycle in self.first_cycle..=end_cycle {
+                let stages = record.stages_at(cycle);
+                match stages.last() {
+                    Some(stage) => {
+                        let letters: Vec<&str> = stages.iter().map(|stage| stage.letter()).collect();
+                        write!(html, "<td class=\"{}\">{}</td>", stage.class(), letters.join("")).unwrap();
+                    }
+                    None => match record.waiting_at(cycle) {
+                        Some(stage) => write!(html, "<td class=\"wait-{}\"></td>", stage.class()).unwrap(),
+                        None => write!(html, "<td></td>").unwrap(),
+                    },
+                }
+            }
+            writeln!(html, "</tr>").unwrap();
+        }
+        writeln!(html, "</table></div>").unwrap();
+    }
+
+    fn write_heat_map(&self, html: &mut String, source: &str) {
+        writeln!(html, "<h2>Source heat map</h2>").unwrap();
+        writeln!(html, "<p>The cycles between decode and retire of the retired instructions of every line, over the whole run.</p>").unwrap();
+        let max_cycles = self.lines.values().map(|stats| stats.cycles).max().unwrap_or(0).max(1);
+
+        writeln!(html, "<table class=\"heat\"><tr><th>line</th><th>retired</th><th>flushed</th><th>cycles</th><th>source</th></tr>").unwrap();
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let stats = self.lines.get(&line);
+            let (retired, flushed, cycles) = stats.map_or((0, 0, 0), |stats| (stats.retire_cnt, stats.flush_cnt, stats.cycles));
+            let heat = cycles as f64 / max_cycles as f64;
+            writeln!(html, "<tr class=\"line\" id=\"line-{}\" data-line=\"{}\" style=\"background: rgba(255, 80, 0, {:.2})\">\
+                            <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"text\">{}</td></tr>",
+                     line, line, heat, line, retired, flushed, cycles, escape(text)).unwrap();
+        }
+        writeln!(html, "</table>").unwrap();
+    }
+}
+
+impl Plugin for PipelineRecorder {
+    fn on_decode(&mut self, event: &InstrEvent) {
+        self.observe(event.cycle());
+        let line = event.loc().map(|loc| loc.line);
+        self.in_flight.insert(event.seq(), (event.cycle(), line));
+
+        if self.first_cycle <= event.cycle() && event.cycle() <= self.last_cycle {
+            self.record_index.insert(event.seq(), self.records.len());
+            self.records.push(InstrRecord {
+                seq: event.seq(),
+                pc: event.pc(),
+                text: event.text(),
+                line,
+                decode: Some(event.cycle()),
+                issue: None,
+                execute: None,
+                retire: None,
+            });
+        }
+    }
+
+    fn on_issue(&mut self, event: &InstrEvent) {
+        self.observe(event.cycle());
+        if let Some(record) = self.record(event.seq()) {
+            record.issue = Some(event.cycle());
+        }
+    }
+
+    fn on_execute(&mut self, event: &InstrEvent) {
+        self.observe(event.cycle());
+        if let Some(record) = self.record(event.seq()) {
+            record.execute = Some(event.cycle());
+        }
+    }
+
+    fn on_retire(&mut self, event: &InstrEvent) {
+        self.observe(event.cycle());
+        if let Some(record) = self.record(event.seq()) {
+            record.retire = Some(event.cycle());
+        }
+
+        // Instructions retire in fetch order; so the older instructions still in flight were flushed.
+        let younger = self.in_flight.split_off(&event.seq());
+        let flushed = std::mem::replace(&mut self.in_flight, younger);
+        for (_, (_, line)) in flushed {
+            if let Some(line) = line {
+                self.lines.entry(line).or_default().flush_cnt += 1;
+            }
+        }
+
+        if let Some((decode_cycle, Some(line))) = self.in_flight.remove(&event.seq()) {
+            let stats = self.lines.entry(line).or_default();
+            stats.retire_cnt += 1;
+            stats.cycles += event.cycle() - decode_cycle;
+        }
+    }
+}
+
+fn tooltip(record: &InstrRecord) -> String {
+    let cycle = |cycle: Option<u64>| cycle.map_or(String::from("-"), |cycle| cycle.to_string());
+    let line = record.line.map_or(String::from("-"), |line| line.to_string());
+    format!("{} (line {}) decode {}, issue {}, execute {}, retire {}",
+            record.text, line, cycle(record.decode), cycle(record.issue), cycle(record.execute), cycle(record.retire))
+}
+
+fn write_stats(html: &mut String, perf_counters: &PerfCounters) {
+    writeln!(html, "<h2>Statistics</h2>").unwrap();
+    writeln!(html, "<table class=\"stats\">").unwrap();
+    // the same counters as printed by the stats command; one per line like 'Cycles:       12'
+    for line in perf_counters.to_string().lines() {
+        let (name, value) = line.split_once(':').unwrap_or((line, ""));
+        writeln!(html, "<tr><td>{}</td><td>{}</td></tr>", escape(name.trim()), escape(value.trim())).unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 1em; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ddd; padding: 1px 4px; font-size: 12px; }
+.scroll { overflow-x: auto; }
+.pipeline td { text-align: center; min-width: 14px; }
+.text { font-family: monospace; text-align: left !important; white-space: pre; }
+.decode { background: #8ecae6; }
+.issue { background: #ffd166; }
+.execute { background: #06d6a0; }
+.retire { background: #ef476f; color: white; }
+.wait-decode { background: #dbeef7; }
+.wait-issue { background: #fff0c9; }
+.wait-execute { background: #c4f5e6; }
+.flushed { color: #999; text-decoration: line-through; }
+.selected td { outline: 2px solid #333; }
+.hide-flushed .flushed { display: none; }
+";
+
+// Clicking an instruction highlights its source line and the other way around.
+const SCRIPT: &str = "
+function select(line) {
+  document.querySelectorAll('.selected').forEach(row => row.classList.remove('selected'));
+  document.querySelectorAll('[data-line=\"' + line + '\"]').forEach(row => row.classList.add('selected'));
+}
+document.querySelectorAll('tr[data-line]').forEach(row =>
+  row.addEventListener('click', () => select(row.dataset.line)));
+document.getElementById('hide-flushed').addEventListener('change', event =>
+  document.body.classList.toggle('hide-flushed', event.target.checked));
+";
@@ -0,0 +1,89 @@
+use serde::Serialize;
+
+use crate::instructions::instructions::{InstrIndex, Program, WordType};
+
+/// An instruction in the pipeline.
+#[derive(Clone, Serialize, Debug)]
+pub struct PipelineInstr {
+    /// The fetch sequence number; unique for every fetched instruction, so a GUI can follow an
+    /// instruction from cycle to cycle.
+    pub seq: u64,
+    pub pc: usize,
+    pub text: String,
+    pub line: Option<usize>,
+}
+
+impl PipelineInstr {
+    pub(crate) fn new(program: &Program, instr_index: InstrIndex, pc: usize, seq: u64) -> PipelineInstr {
+        let instr = program.instr(instr_index);
+        PipelineInstr {
+            seq,
+            pc,
+            text: instr.with_aliases(&program.register_aliases).to_string(),
+            line: instr.loc.map(|loc| loc.line),
+        }
+    }
+}
+
+/// An instruction in an execution unit.
+#[derive(Clone, Serialize, Debug)]
+pub struct ExecutingInstr {
+    pub instr: PipelineInstr,
+    pub cycles_remaining: u8,
+}
+
+/// An execution unit with the instructions it is executing; more than one for a pipelined unit.
+#[derive(Clone, Serialize, Debug)]
+pub struct ExecutionUnitState {
+    pub index: u8,
+    pub eu_type: String,
+    pub pipelined: bool,
+    pub executing: Vec<ExecutingInstr>,
+}
+
+/// An occupied slot of the store buffer.
+#[derive(Clone, Serialize, Debug)]
+pub struct StoreBufferSlot {
+    pub seq: u64,
+    /// None until the store has executed.
+    pub addr: Option<WordType>,
+    pub value: Option<WordType>,
+    /// The store has retired and waits to be written to memory.
+    pub committed: bool,
+}
+
+/// An instruction in the reorder buffer.
+#[derive(Clone, Serialize, Debug)]
+pub struct ROBEntry {
+    pub instr: PipelineInstr,
+    pub state: String,
+    /// Why the instruction wasn't dispatched in the last cycle even though it was ready.
+    pub stall: Option<String>,
+    pub fetch_cycle: u64,
+}
+
+/// What is in every part of the pipeline in the current cycle; oldest first. It is serializable,
+/// so a separate process (e.g. a GUI) can poll it without being compiled into the emulator.
+#[derive(Clone, Serialize, Debug)]
+pub struct PipelineSnapshot {
+    pub cycle: u64,
+    /// The code address the frontend fetches from next.
+    pub pc: usize,
+    /// Fetching waits till a control instruction without a BTB prediction is executed.
+    pub frontend_halted: bool,
+    /// Fetched, waiting to be decoded.
+    pub fetch: Vec<PipelineInstr>,
+    /// Decoded, waiting in the instruction queue to be issued.
+    pub decode: Vec<PipelineInstr>,
+    /// Issued, waiting in a reservation station to be dispatched.
+    pub issue_queue: Vec<PipelineInstr>,
+    pub execution_units: Vec<ExecutionUnitState>,
+    pub store_buffer: Vec<StoreBufferSlot>,
+    pub rob: Vec<ROBEntry>,
+}
+
+impl PipelineSnapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FLOAT, INT};
+
+use crate::abi::abi::Abi;
+use crate::cpu::{CPU, CPUConfig, PerfCounters};
+use crate::instructions::instructions::RegisterType;
+use crate::loader::loader::load;
+use crate::snapshot::snapshot::Snapshot;
+
+/// The outcome of a program run from a script.
+#[derive(Clone)]
+pub struct ScriptRun {
+    perf_counters: PerfCounters,
+    snapshot: Snapshot,
+    // the numbering of the registers of the snapshot
+    abi: Abi,
+    output: Vec<String>,
+    // the hang diagnosis when the program didn't exit within max_cycles
+    hang: Option<String>,
+}
+
+/// Runs a Rhai script that automates experiments; e.g. a sweep that runs a program with ROB sizes
+/// 16..256. The script can load a config, change settings and run programs:
+///
+/// ```text
+/// let config = load_config("cpu.yaml");
+/// for rob_capacity in [16, 32, 64, 128, 256] {
+///     config.set("rob_capacity", rob_capacity);
+///     let run = run(config, "asm/conformance/sum.asm");
+///     print(`rob_capacity=${rob_capacity} cycles=${run.cycles} ipc=${run.ipc}`);
+/// }
+/// ```
+pub fn run_script(path: &str) -> Result<(), Box<dyn Error>> {
+    create_engine().run_file(PathBuf::from(path))
+        .map_err(|error| format!("Script '{}' failed: {}", path, error).into())
+}
+
+fn create_engine() -> Engine {
+    let mut engine = Engine::new();
+
+    engine.register_type_with_name::<CPUConfig>("Config")
+        .register_fn("load_config", |path: &str| -> Result<CPUConfig, Box<EvalAltResult>> {
+            CPUConfig::from_file(path).map_err(|error| error.to_string().into())
+        })
+        // the value is written like in the config file; nested settings are separated by a dot
+        .register_fn("set", |config: &mut CPUConfig, key: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            config.set(key, &value.to_string()).map_err(|error| error.to_string().into())
+        });
+
+    engine.register_type_with_name::<ScriptRun>("Run")
+        // the program is loaded using the config, since the config determines the instruction latencies
+        .register_fn("run", |config: CPUConfig, path: &str| -> Result<ScriptRun, Box<EvalAltResult>> {
+            run_program(config, path).map_err(|error| error.into())
+        })
+        .register_get("cycles", |run: &mut ScriptRun| run.perf_counters.cycle_cnt as INT)
+        .register_get("retired", |run: &mut ScriptRun| run.perf_counters.retire_cnt as INT)
+        .register_get("ipc", |run: &mut ScriptRun| run.perf_counters.ipc() as FLOAT)
+        .register_get("mispredicts", |run: &mut ScriptRun| (run.perf_counters.btb_mispredict_cnt + run.perf_counters.ras_mispredict_cnt) as INT)
+        .register_get("stats", |run: &mut ScriptRun| run.perf_counters.to_string())
+        .register_get("output", |run: &mut ScriptRun| {
+            run.output.iter().map(|line| Dynamic::from(line.clone())).collect::<rhai::Array>()
+        })
+        // the hang diagnosis; () when the program exited
+        .register_get("hang", |run: &mut ScriptRun| {
+            run.hang.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+        })
+        // the value of rN
+        .register_fn("register", |run: &mut ScriptRun, reg: INT| -> Result<INT, Box<EvalAltResult>> {
+            Some(reg).filter(|reg| (0..run.abi.general_reg_count as INT).contains(reg))
+                .and_then(|reg| run.snapshot.registers.get(run.abi.slot(reg as RegisterType) as usize))
+                .copied()
+                .ok_or_else(|| format!("Unknown register {}", reg).into())
+        })
+        .register_fn("variable", |run: &mut ScriptRun, name: &str| -> Result<INT, Box<EvalAltResult>> {
+            run.snapshot.variables.get(name)
+                .map(|offset| run.snapshot.memory[*offset as usize])
+                .ok_or_else(|| format!("Unknown variable '{}'", name).into())
+        });
+
+    engine
+}
+
+fn run_program(mut config: CPUConfig, path: &str) -> Result<ScriptRun, String> {
+    config.set_trace(false);
+
+    let program = load(config.clone(), path)
+        .map(Rc::new)
+        .map_err(|error| format!("Loading program '{}' failed. {}", path, error))?;
+
+    let mut cpu = CPU::new(&config);
+    // don't throttle the CPU to the configured frequency
+    cpu.set_throttled(false);
+    let hang = cpu.run(&program).err().map(|diagnosis| diagnosis.to_string());
+
+    Ok(ScriptRun {
+        perf_counters: cpu.perf_counters(),
+        snapshot: cpu.snapshot(&program),
+        abi: config.abi(),
+        output: cpu.output().to_vec(),
+        hang,
+    })
+}
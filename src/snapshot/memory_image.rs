@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::cpu::{Endianness, unsigned_word, wrap_word};
+use crate::instructions::instructions::WordType;
+
+// The number of words per line of a hex image.
+const HEX_WORDS_PER_LINE: usize = 8;
+
+/// The file format of a memory image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// Raw binary: every word is stored in word_size / 8 bytes in the configured endianness.
+    Binary,
+    /// Hexadecimal words (with or without `0x`) separated by whitespace; `//` and `#` start a comment.
+    Hex,
+}
+
+impl ImageFormat {
+    /// The format that belongs to the extension of the path: `.bin` is binary, anything else hex.
+    pub fn from_path(path: &str) -> ImageFormat {
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("bin") => ImageFormat::Binary,
+            _ => ImageFormat::Hex,
+        }
+    }
+}
+
+/// A block of words that is written to memory at an offset before the program starts; e.g. a matrix
+/// or an image that is too large to encode using `.word`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryImage {
+    /// The address of the first word.
+    pub offset: u64,
+    pub words: Vec<WordType>,
+}
+
+impl MemoryImage {
+    pub fn load(path: &str, format: ImageFormat, offset: u64, word_size: u8, endianness: Endianness) -> Result<MemoryImage, Box<dyn Error>> {
+        let words = match format {
+            ImageFormat::Binary => from_binary(&fs::read(path)?, word_size, endianness)?,
+            ImageFormat::Hex => from_hex(&fs::read_to_string(path)?, word_size)?,
+        };
+        Ok(MemoryImage { offset, words })
+    }
+
+    /// Writes the words; the offset isn't part of the file.
+    pub fn save(&self, path: &str, format: ImageFormat, word_size: u8, endianness: Endianness) -> Result<(), Box<dyn Error>> {
+        match format {
+            ImageFormat::Binary => fs::write(path, to_binary(&self.words, word_size, endianness))?,
+            ImageFormat::Hex => fs::write(path, to_hex(&self.words, self.offset, word_size))?,
+        }
+        Ok(())
+    }
+}
+
+fn from_binary(bytes: &[u8], word_size: u8, endianness: Endianness) -> Result<Vec<WordType>, String> {
+    let word_bytes = word_size as usize / 8;
+    if !bytes.len().is_multiple_of(word_bytes) {
+        return Err(format!("The image of {} bytes isn't a whole number of {} byte words", bytes.len(), word_bytes));
+    }
+
+    let words = bytes.chunks(word_bytes)
+        .map(|chunk| {
+            let value = match endianness {
+                Endianness::Little => chunk.iter().rev().fold(0u64, |value, byte| value << 8 | *byte as u64),
+                Endianness::Big => chunk.iter().fold(0u64, |value, byte| value << 8 | *byte as u64),
+            };
+            wrap_word(value as WordType, word_size)
+        })
+        .collect();
+    Ok(words)
+}
+
+fn to_binary(words: &[WordType], word_size: u8, endianness: Endianness) -> Vec<u8> {
+    let word_bytes = word_size as usize / 8;
+    let mut bytes = Vec::with_capacity(words.len() * word_bytes);
+    for word in words {
+        let value = unsigned_word(*word, word_size);
+        // least significant byte first
+        let mut word_le: Vec<u8> = (0..word_bytes).map(|k| (value >> (8 * k)) as u8).collect();
+        if endianness == Endianness::Big {
+            word_le.reverse();
+        }
+        bytes.extend(word_le);
+    }
+    bytes
+}
+
+fn from_hex(text: &str, word_size: u8) -> Result<Vec<WordType>, String> {
+    let mut words = Vec::new();
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.split("//").next().unwrap().split('#').next().unwrap();
+        for token in line.split_whitespace() {
+            let digits = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).unwrap_or(token);
+            let value = u64::from_str_radix(digits, 16)
+                .map_err(|_| format!("Invalid hex word '{}' at line {}", token, line_index + 1))?;
+            if word_size < 64 && value >> word_size != 0 {
+                return Err(format!("Hex word '{}' at line {} doesn't fit in {} bits", token, line_index + 1, word_size));
+            }
+            words.push(wrap_word(value as WordType, word_size));
+        }
+    }
+    Ok(words)
+}
+
+fn to_hex(words: &[WordType], offset: u64, word_size: u8) -> String {
+    let digits = word_size as usize / 4;
+    let mut text = String::new();
+    for (line_index, line) in words.chunks(HEX_WORDS_PER_LINE).enumerate() {
+        let line: Vec<String> = line.iter()
+            .map(|word| format!("{:0width$x}", unsigned_word(*word, word_size), width = digits))
+            .collect();
+        writeln!(text, "{}  // {}", line.join(" "), offset + (line_index * HEX_WORDS_PER_LINE) as u64).unwrap();
+    }
+    text
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::{CPSR, Flags};
+use crate::instructions::instructions::{Operand, RegisterType, WordType};
+
+/// The architectural state of the machine at some point in time.
+///
+/// The variables record the memory layout of the program so that differences in memory
+/// can be reported relative to a variable, e.g. `[result+3]`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub registers: Vec<WordType>,
+    pub memory: Vec<WordType>,
+    pub variables: BTreeMap<String, u64>,
+}
+
+/// A single difference between an expected and an actual snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    Register { register: RegisterType, expected: WordType, actual: WordType },
+    Memory { addr: u64, expected: WordType, actual: WordType },
+    MemorySize { expected: usize, actual: usize },
+}
+
+/// The structured result of comparing two snapshots.
+pub struct SnapshotDiff {
+    pub differences: Vec<Difference>,
+    // sorted by offset; used to describe memory addresses.
+    variables: Vec<(String, u64)>,
+}
+
+impl Snapshot {
+    /// The condition flags of the CPSR.
+    pub fn flags(&self) -> Flags {
+        Flags::from_cpsr(self.registers[CPSR as usize])
+    }
+
+    pub fn load(path: &str) -> Result<Snapshot, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let snapshot = serde_yaml::from_reader(file)?;
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        serde_yaml::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Compares this (expected) snapshot with the actual snapshot.
+    pub fn diff(&self, actual: &Snapshot) -> SnapshotDiff {
+        let mut differences = Vec::new();
+
+        for (reg, (expected, actual)) in self.registers.iter().zip(actual.registers.iter()).enumerate() {
+            if expected != actual {
+                differences.push(Difference::Register { register: reg as RegisterType, expected: *expected, actual: *actual });
+            }
+        }
+
+        if self.memory.len() != actual.memory.len() {
+            differences.push(Difference::MemorySize { expected: self.memory.len(), actual: actual.memory.len() });
+        }
+
+        for (addr, (expected, actual)) in self.memory.iter().zip(actual.memory.iter()).enumerate() {
+            if expected != actual {
+                differences.push(Difference::Memory { addr: addr as u64, expected: *expected, actual: *actual });
+            }
+        }
+
+        let mut variables: Vec<(String, u64)> = self.variables.iter()
+            .map(|(name, offset)| (name.clone(), *offset))
+            .collect();
+        variables.sort_by_key(|(_, offset)| *offset);
+
+        SnapshotDiff { differences, variables }
+    }
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    // Describes the address relative to the nearest variable at or below it.
+    fn describe_addr(&self, addr: u64) -> String {
+        match self.variables.iter().rev().find(|(_, offset)| *offset <= addr) {
+            Some((name, offset)) if *offset == addr => format!("[{}]", name),
+            Some((name, offset)) => format!("[{}+{}]", name, addr - offset),
+            None => format!("[{}]", addr),
+        }
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.differences.is_empty() {
+            return write!(f, "Snapshots are identical");
+        }
+
+        writeln!(f, "Snapshots differ at {} location(s):", self.differences.len())?;
+        for difference in &self.differences {
+            match difference {
+                Difference::Register { register, expected, actual } =>
+                    writeln!(f, "  register {}: expected {}, actual {}", Operand::Register(*register), expected, actual)?,
+                Difference::Memory { addr, expected, actual } =>
+                    writeln!(f, "  memory {} (address {}): expected {}, actual {}", self.describe_addr(*addr), addr, expected, actual)?,
+                Difference::MemorySize { expected, actual } =>
+                    writeln!(f, "  memory size: expected {} words, actual {} words", expected, actual)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::cpu::CPUConfig;
+use crate::instructions::custom::CustomInstructions;
+use crate::instructions::instructions::{create_instr, Data, eu_type, InitialState, Instr, Opcode, Operand, Program, RegisterType, SourceLocation, WordType};
+use crate::instructions::microcode::decode_micro_ops;
+use crate::instructions::symbol_table::SymbolTable;
+use crate::random::random::Random;
+
+// the registers the generated instructions operate on
+const WORK_REG_CNT: u64 = 6;
+// contains the address of the word that is loaded or stored
+const ADDR_REG: RegisterType = 6;
+// contains MASK; every arithmetic result is masked so that values can't overflow
+const MASK_REG: RegisterType = 7;
+const MASK: WordType = 0xFFFF;
+// the index into the heap buffer
+const INDEX_REG: RegisterType = 8;
+// the counters of the loops; one per nesting level
+const LOOP_REGS: [RegisterType; 2] = [9, 10];
+// contains the address of the heap buffer
+const BUFFER_REG: RegisterType = 12;
+// the number of words of the heap buffer; a power of 2 so an index can be masked
+const BUFFER_LEN: WordType = 8;
+const VARIABLE_CNT: usize = 4;
+// the maximum number of instructions a forward branch skips
+const MAX_BRANCH_SKIP: u64 = 4;
+// the maximum number of instructions emitted per random step; e.g. the masking, addressing and access of a buffer
+const MAX_INSTRS_PER_STEP: usize = 3;
+// the instructions besides the random steps: the setup and the FREE of the buffer
+const FIXED_INSTR_CNT: usize = 3 + WORK_REG_CNT as usize;
+
+/// The shape of the programs generated by a TestGenerator.
+#[derive(Clone, Debug)]
+pub struct TestGenOptions {
+    /// The number of random steps; a step is an instruction or the start of a loop or a PUSH/POP pair.
+    pub length: usize,
+    /// The maximum nesting of loops; at most 2.
+    pub max_loop_depth: u8,
+    /// The maximum number of iterations of a loop.
+    pub max_iterations: u64,
+    /// The maximum nesting of PUSH/POP pairs.
+    pub max_push_depth: u8,
+}
+
+impl Default for TestGenOptions {
+    fn default() -> Self {
+        TestGenOptions { length: 50, max_loop_depth: 2, max_iterations: 8, max_push_depth: 2 }
+    }
+}
+
+/// Generates random, but well-formed, programs for stress testing the pipeline.
+///
+/// Besides the arithmetic, compares and forward branches of the fuzz programs, the programs contain
+/// counted loops (possibly nested), balanced PUSH/POP pairs and indexed accesses to a heap buffer.
+/// Every program terminates: a loop counter is only written by its loop and a branch never leaves
+/// the loop body or PUSH/POP pair it is in. Every memory access is within a variable, the buffer or
+/// the stack. The same seed and options always give the same program.
+pub struct TestGenerator<'a> {
+    cpu_config: &'a CPUConfig,
+    options: TestGenOptions,
+    random: Random,
+    code: Vec<Instr>,
+    labels: HashMap<String, usize>,
+    // the random steps that still can be generated
+    budget: usize,
+}
+
+impl<'a> TestGenerator<'a> {
+    pub fn new(cpu_config: &'a CPUConfig, seed: u64, options: TestGenOptions) -> TestGenerator<'a> {
+        let budget = options.length;
+        TestGenerator {
+            cpu_config,
+            options,
+            random: Random::new(seed),
+            code: Vec::new(),
+            labels: HashMap::new(),
+            budget,
+        }
+    }
+
+    /// An upper bound of the number of instructions a program executes; useful as the step limit of
+    /// the reference interpreter.
+    pub fn max_steps(&self) -> u64 {
+        let depth = self.options.max_loop_depth.min(LOOP_REGS.len() as u8) as u32;
+        let instr_cnt = (FIXED_INSTR_CNT + MAX_INSTRS_PER_STEP * self.options.length) as u64;
+        instr_cnt.saturating_mul(self.options.max_iterations.max(1).saturating_pow(depth))
+    }
+
+    pub fn generate(mut self) -> Program {
+        // the variables are laid out like the loader does, so the redzones are respected. The code
+        // occupies the memory from the code base, so the variables are placed after the largest possible code.
+        let redzone_size = self.cpu_config.redzone_size as u64;
+        let mut data_items = HashMap::new();
+        let mut variables = Vec::new();
+        let mut heap_limit = self.cpu_config.code_base() as u64 + (FIXED_INSTR_CNT + MAX_INSTRS_PER_STEP * self.options.length) as u64;
+        for k in 0..VARIABLE_CNT {
+            heap_limit += redzone_size;
+            let data = Data { value: self.random.below(100) as WordType, offset: heap_limit };
+            variables.push(data.offset as WordType);
+            data_items.insert(format!("v{}", k), Rc::new(data));
+            heap_limit += 1;
+        }
+
+        self.emit(Opcode::MOV, vec![Operand::Register(MASK_REG), Operand::Immediate(MASK)]);
+        for reg in 0..WORK_REG_CNT {
+            let value = self.random.below(100) as WordType;
+            self.emit(Opcode::MOV, vec![Operand::Register(reg as RegisterType), Operand::Immediate(value)]);
+        }
+        self.emit(Opcode::MALLOC, vec![Operand::Register(BUFFER_REG), Operand::Immediate(BUFFER_LEN)]);
+
+        while self.budget > 0 {
+            self.generate_block(&variables, 0, 0);
+        }
+
+        self.emit(Opcode::FREE, vec![Operand::Register(BUFFER_REG)]);
+
+        let symbols = SymbolTable::new(&self.labels, &data_items, &self.code);
+        let micro_ops = decode_micro_ops(&self.code, &self.cpu_config.execution_units);
+        Program {
+            data_items,
+            code: self.code,
+            entry_point: 0,
+            register_aliases: self.cpu_config.abi().register_names(),
+            code_base: self.cpu_config.code_base() as WordType,
+            abi: self.cpu_config.abi(),
+            symbols,
+            custom_instructions: CustomInstructions::new(),
+            deadlines: Vec::new(),
+            irq_handler: None,
+            loop_bounds: HashMap::new(),
+            expectations: Vec::new(),
+            initial_state: InitialState::default(),
+            warnings: Vec::new(),
+            micro_ops,
+        }
+    }
+
+    // Generates steps till the budget runs out or the block randomly ends. The forward branches of
+    // the block don't go past its end.
+    fn generate_block(&mut self, variables: &[WordType], loop_depth: u8, push_depth: u8) {
+        let mut branches = Vec::new();
+        loop {
+            if self.budget == 0 {
+                break;
+            }
+            self.budget -= 1;
+
+            let can_loop = loop_depth < self.options.max_loop_depth.min(LOOP_REGS.len() as u8);
+            // a PUSH of two registers needs two store buffer entries; the loader rejects it otherwise
+            let can_push = push_depth < self.options.max_push_depth && self.cpu_config.sb_capacity >= 2;
+            let nested_start = self.code.len();
+            match self.random.below(12) {
+                0 if can_loop => self.generate_loop(variables, loop_depth, push_depth),
+                1 if can_push => self.generate_push_pop(variables, loop_depth, push_depth),
+                2 if loop_depth + push_depth > 0 => break,
+                _ => {
+                    if let Some(branch) = self.generate_instr(variables) {
+                        branches.push(branch);
+                    }
+                }
+            }
+
+            // a branch must not skip the MOV of a loop counter or a PUSH; so a branch into a loop or
+            // PUSH/POP pair goes to its start instead.
+            let nested_end = self.code.len();
+            for (_, target) in branches.iter_mut() {
+                if *target > nested_start && *target < nested_end {
+                    *target = nested_start;
+                }
+            }
+        }
+
+        let end = self.code.len();
+        for (branch, target) in branches {
+            self.patch_branch(branch, target.min(end));
+        }
+    }
+
+    // MOV rc, #n; loop: <block>; SUB rc, rc, #1; CBNZ rc, loop
+    fn generate_loop(&mut self, variables: &[WordType], loop_depth: u8, push_depth: u8) {
+        let counter = Operand::Register(LOOP_REGS[loop_depth as usize]);
+        let iterations = 1 + self.random.below(self.options.max_iterations.max(1)) as WordType;
+        self.emit(Opcode::MOV, vec![counter, Operand::Immediate(iterations)]);
+
+        let start = self.code.len();
+        self.labels.insert(format!("loop{}", start), start);
+        self.generate_block(variables, loop_depth + 1, push_depth);
+
+        self.emit(Opcode::SUB, vec![counter, counter, Operand::Immediate(1)]);
+        self.emit(Opcode::CBNZ, vec![counter, Operand::Code(start as WordType)]);
+    }
+
+    // PUSH {...}; <block>; POP {...}; the pushed work registers are restored. Two registers are pushed,
+    // so the SP stays doubleword aligned and the accesses don't fault with the fault alignment policy.
+    fn generate_push_pop(&mut self, variables: &[WordType], loop_depth: u8, push_depth: u8) {
+        let first = self.random.below(WORK_REG_CNT);
+        let second = (first + 1 + self.random.below(WORK_REG_CNT - 1)) % WORK_REG_CNT;
+        let mask = (1u32 << first) | (1u32 << second);
+
+        self.emit(Opcode::PUSH, vec![Operand::RegisterList(mask)]);
+        self.generate_block(variables, loop_depth, push_depth + 1);
+        self.emit(Opcode::POP, vec![Operand::RegisterList(mask)]);
+    }
+
+    // Returns the code address of a generated forward branch and its unclamped target.
+    fn generate_instr(&mut self, variables: &[WordType]) -> Option<(usize, usize)> {
+        let rd = self.work_reg();
+        let rn = self.work_reg();
+        match self.random.below(10) {
+            0 | 1 => {
+                let opcode = [Opcode::ADD, Opcode::SUB, Opcode::MUL, Opcode::AND, Opcode::ORR, Opcode::EOR][self.random.below(6) as usize];
+                let operand2 = if opcode != Opcode::MUL && self.random.below(2) == 0 {
+                    Operand::Immediate(self.random.below(100) as WordType)
+                } else {
+                    self.work_reg()
+                };
+                self.emit(opcode, vec![rd, rn, operand2]);
+                self.emit(Opcode::AND, vec![rd, rd, Operand::Register(MASK_REG)]);
+            }
+            2 => {
+                let opcode = if self.random.below(2) == 0 { Opcode::NEG } else { Opcode::MVN };
+                self.emit(opcode, vec![rd, rn]);
+                self.emit(Opcode::AND, vec![rd, rd, Operand::Register(MASK_REG)]);
+            }
+            3 => {
+                let source = if self.random.below(2) == 0 {
+                    Operand::Immediate(self.random.below(100) as WordType)
+                } else {
+                    rn
+                };
+                self.emit(Opcode::MOV, vec![rd, source]);
+            }
+            4 | 5 => {
+                let variable = variables[self.random.below(variables.len() as u64) as usize];
+                self.emit(Opcode::LDR, vec![Operand::Register(ADDR_REG), Operand::Immediate(variable)]);
+                self.emit_access(rd, rn);
+            }
+            6 | 7 => {
+                // the index is masked, so the access stays within the buffer
+                let index = Operand::Register(INDEX_REG);
+                self.emit(Opcode::AND, vec![index, rn, Operand::Immediate(BUFFER_LEN - 1)]);
+                self.emit(Opcode::ADD, vec![Operand::Register(ADDR_REG), Operand::Register(BUFFER_REG), index]);
+                self.emit_access(rd, rn);
+            }
+            8 => {
+                self.emit(Opcode::CMP, vec![rd, rn]);
+            }
+            _ => {
+                let branch = self.code.len();
+                match self.random.below(5) {
+                    0 => self.emit(Opcode::B, vec![Operand::Code(0)]),
+                    1 => self.emit(Opcode::CBZ, vec![rn, Operand::Code(0)]),
+                    2 => self.emit(Opcode::CBNZ, vec![rn, Operand::Code(0)]),
+                    _ => {
+                        let opcode = [Opcode::BEQ, Opcode::BNE, Opcode::BLT, Opcode::BGE][self.random.below(4) as usize];
+                        self.emit(opcode, vec![Operand::Code(0)]);
+                    }
+                }
+                let skip = self.random.below(MAX_BRANCH_SKIP + 1) as usize;
+                return Some((branch, branch + 1 + skip));
+            }
+        }
+        None
+    }
+
+    // A load into rd or a store of rn through the ADDR_REG.
+    fn emit_access(&mut self, rd: Operand, rn: Operand) {
+        if self.random.below(2) == 0 {
+            self.emit(Opcode::LDR, vec![rd, Operand::Register(ADDR_REG)]);
+        } else {
+            self.emit(Opcode::STR, vec![rn, Operand::Register(ADDR_REG)]);
+        }
+    }
+
+    fn work_reg(&mut self) -> Operand {
+        Operand::Register(self.random.below(WORK_REG_CNT) as RegisterType)
+    }
+
+    fn emit(&mut self, opcode: Opcode, operands: Vec<Operand>) {
+        let loc = SourceLocation { line: self.code.len() + 1, column: 1 };
+        let mut instr = create_instr(opcode, &operands, loc)
+            .unwrap_or_else(|error| panic!("Generated an invalid instruction: {}", error));
+        instr.cycles = self.cpu_config.execution_units.get(eu_type(opcode)).latency;
+        self.code.push(instr);
+    }
+
+    fn patch_branch(&mut self, branch: usize, target: usize) {
+        let instr = &mut self.code[branch];
+        for source in instr.source.iter_mut() {
+            if let Operand::Code(_) = source {
+                *source = Operand::Code(target as WordType);
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+mod common;
+
+// With sp: 29, r29 is the stack pointer and r13 an ordinary register.
+const PROGRAM: &str = "
+.text
+_start:
+    MOV r13, #7;
+    PUSH {r13};
+    POP {r1};
+    SUB r2, r29, sp;
+    MOV r3, r13;
+";
+
+#[test]
+fn a_configured_stack_pointer_frees_r13() {
+    let mut config = common::config();
+    config.set("abi.sp", "29").unwrap();
+    let (cpu, program) = common::run(&config, PROGRAM);
+
+    let registers = cpu.snapshot(&program).registers;
+    assert_eq!(&registers[1..4], &[7, 0, 7]);
+    // the disassembly numbers the registers like the program
+    let disassembly = program.disassemble();
+    assert!(disassembly.contains("MOV r13, #7"), "{}", disassembly);
+}
+
+#[test]
+fn special_registers_are_distinct_general_registers() {
+    let mut config = common::config();
+    assert!(config.set("abi.lr", "13").is_err());
+    config.set("general_reg_count", "16").unwrap();
+    assert!(config.set("abi.sp", "20").is_err());
+}
@@ -0,0 +1,58 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// The cpu.yaml with a clock that doesn't slow down the test; a file per test, since they run in parallel.
+fn fast_config(test: &str) -> PathBuf {
+    let config = fs::read_to_string("cpu.yaml").unwrap()
+        .replace("frequency_hz: 10\n", "frequency_hz: 1000000000\n");
+    let path = env::temp_dir().join(format!("{}_{}.yaml", test, std::process::id()));
+    fs::write(&path, config).unwrap();
+    path
+}
+
+fn stdout_of(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust_cpu_emulator")).args(args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn stats_json_is_the_only_output() {
+    let config = fast_config("stats_json");
+    let stdout = stdout_of(&["stats", "--json", "-c", config.to_str().unwrap(), "asm/conformance/countdown.asm"]);
+    fs::remove_file(&config).unwrap();
+    let stats: serde_json::Value = serde_json::from_str(&stdout).unwrap_or_else(|error| panic!("{}: {}", error, stdout));
+    assert!(stats["cycle_cnt"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn compare_json_is_the_only_output() {
+    let config = fast_config("compare_json");
+    let path = config.to_str().unwrap();
+    let stdout = stdout_of(&["compare", "--json", "-c", path, "--candidate", path, "asm/conformance/countdown.asm"]);
+    fs::remove_file(&config).unwrap();
+    let comparison: serde_json::Value = serde_json::from_str(&stdout).unwrap_or_else(|error| panic!("{}: {}", error, stdout));
+    assert!(comparison["stats"].as_array().unwrap().len() > 1);
+}
+
+#[test]
+fn bench_csv_is_the_only_output() {
+    let config = fast_config("bench_csv");
+    let stdout = stdout_of(&["bench", "asm/conformance", "-c", config.to_str().unwrap(), "--csv"]);
+    fs::remove_file(&config).unwrap();
+    let mut lines = stdout.lines();
+    let columns = lines.next().unwrap().split(',').count();
+    for line in lines {
+        assert_eq!(line.split(',').count(), columns, "{}", line);
+    }
+}
+
+#[test]
+fn a_leak_names_the_calls_that_led_to_the_malloc() {
+    let config = fast_config("heap_leak");
+    let stdout = stdout_of(&["run", "-c", config.to_str().unwrap(), "asm/heap.asm"]);
+    fs::remove_file(&config).unwrap();
+    assert!(stdout.contains("allocated at 31:5 (called from 26:5)"), "{}", stdout);
+}
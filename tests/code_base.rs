@@ -0,0 +1,45 @@
+mod common;
+
+use rust_cpu_emulator::loader::loader::load_from_str;
+
+// The code starts at 100: double is at 105, the BLX returns to 104 and the variable follows the code.
+const PROGRAM: &str = "
+.data
+    value: .word 5
+.text
+_start:
+    LDR r0, =double;
+    MOV r1, =value;
+    LDR r2, [r1];
+    BLX r0;
+    B done;
+double:
+    ADD r2, r2, r2;
+    MOV r4, lr;
+    BX lr;
+done:
+    MOV r5, #1;
+";
+
+#[test]
+fn code_addresses_start_at_the_code_base() {
+    for pipeline_mode in ["out_of_order", "in_order"] {
+        let mut config = common::config();
+        config.set("pipeline_mode", pipeline_mode).unwrap();
+        config.set("memory_layout.code_base", "100").unwrap();
+        let (cpu, program) = common::run(&config, PROGRAM);
+
+        let registers = cpu.snapshot(&program).registers;
+        let value = program.symbols().offset_of("value").unwrap() as i64;
+        assert!(value >= 109, "the variable at {} overlaps the code", value);
+        assert_eq!(&registers[..6], &[105, value, 10, 0, 104, 1]);
+    }
+}
+
+#[test]
+fn variables_cant_overlap_the_code() {
+    let mut config = common::config();
+    config.set("memory_layout.code_base", "100").unwrap();
+    config.set("memory_layout.data_base", "104").unwrap();
+    assert!(load_from_str(config, PROGRAM).is_err());
+}
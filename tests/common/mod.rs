@@ -0,0 +1,50 @@
+// The fixtures shared by the integration tests; not every test uses all of them.
+#![allow(dead_code)]
+
+use std::io;
+use std::rc::Rc;
+
+use rust_cpu_emulator::cpu::{CPU, CPUConfig, load_cpu_config, RunOutcome};
+use rust_cpu_emulator::instructions::instructions::Program;
+use rust_cpu_emulator::loader::loader::load_from_str;
+
+// The default config without the trace.
+pub fn config() -> CPUConfig {
+    let mut config = CPUConfig::default();
+    config.set_trace(false);
+    config
+}
+
+// The cpu.yaml of the repository without the trace.
+pub fn yaml_config() -> CPUConfig {
+    let mut config = load_cpu_config("cpu.yaml").unwrap_or_else(|error| panic!("{}", error));
+    config.set_trace(false);
+    config
+}
+
+pub fn load_source(config: &CPUConfig, source: &str) -> Rc<Program> {
+    Rc::new(load_from_str(config.clone(), source).unwrap_or_else(|error| panic!("{}", error)))
+}
+
+// A CPU that runs as fast as the host allows and discards the output of the program.
+pub fn cpu(config: &CPUConfig) -> CPU {
+    let mut cpu = CPU::new(config);
+    cpu.set_quiet(true);
+    cpu.set_throttled(false);
+    cpu.set_stdout(Box::new(io::sink()));
+    cpu
+}
+
+// Runs the program on the CPU until it exits.
+pub fn run_to_exit(cpu: &mut CPU, program: &Rc<Program>) {
+    cpu.load(program);
+    assert!(matches!(cpu.run_for(100_000), RunOutcome::Exited(_)));
+}
+
+// Loads the source and runs it until it exits.
+pub fn run(config: &CPUConfig, source: &str) -> (CPU, Rc<Program>) {
+    let program = load_source(config, source);
+    let mut cpu = cpu(config);
+    run_to_exit(&mut cpu, &program);
+    (cpu, program)
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use rust_cpu_emulator::cpu::CPUConfig;
+use rust_cpu_emulator::fuzz::differential::{Mismatch, run_differential, run_stress_differential};
+use rust_cpu_emulator::testgen::generator::TestGenOptions;
+
+// The seeds of the fixed regression set; `cargo run -- fuzz` explores beyond them.
+const SEEDS: std::ops::Range<u64> = 0..32;
+
+fn config(pipeline_mode: &str) -> CPUConfig {
+    let mut config = common::yaml_config();
+    config.set("pipeline_mode", pipeline_mode).unwrap();
+    config
+}
+
+fn check(result: Result<(), Mismatch>) {
+    if let Err(mismatch) = result {
+        panic!("Mismatch for seed {}: {}\n{}", mismatch.seed, mismatch.reason, mismatch.program.disassemble());
+    }
+}
+
+#[test]
+fn out_of_order_matches_the_reference_interpreter() {
+    let config = config("out_of_order");
+    for seed in SEEDS {
+        check(run_differential(&config, seed, 50));
+    }
+}
+
+#[test]
+fn in_order_matches_the_reference_interpreter() {
+    let config = config("in_order");
+    for seed in SEEDS {
+        check(run_differential(&config, seed, 50));
+    }
+}
+
+#[test]
+fn stress_programs_match_the_reference_interpreter() {
+    let options = TestGenOptions::default();
+    for pipeline_mode in ["out_of_order", "in_order"] {
+        let config = config(pipeline_mode);
+        for seed in SEEDS {
+            check(run_stress_differential(&config, seed, &options));
+        }
+    }
+}
+
+#[test]
+fn stress_programs_fit_a_store_buffer_of_one_entry() {
+    let mut config = config("out_of_order");
+    config.set("sb_capacity", "1").unwrap();
+    for seed in SEEDS {
+        check(run_stress_differential(&config, seed, &TestGenOptions::default()));
+    }
+}
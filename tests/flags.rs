@@ -0,0 +1,97 @@
+mod common;
+
+use rust_cpu_emulator::instructions::instructions::WordType;
+
+// Runs the program in both pipeline modes and returns the registers of both runs.
+fn run(source: &str) -> Vec<Vec<WordType>> {
+    ["out_of_order", "in_order"].into_iter()
+        .map(|pipeline_mode| {
+            let mut config = common::config();
+            config.set("pipeline_mode", pipeline_mode).unwrap();
+            let (cpu, program) = common::run(&config, source);
+            cpu.snapshot(&program).registers
+        })
+        .collect()
+}
+
+// Checks the registers from r0 on.
+fn assert_registers(source: &str, expected: &[WordType]) {
+    for registers in run(source) {
+        assert_eq!(&registers[..expected.len()], expected);
+    }
+}
+
+#[test]
+fn branch_follows_the_youngest_flag_overwriter() {
+    // the SUBS overwrites the flags of the CMP; so the BEQ is taken
+    assert_registers("
+.text
+_start:
+    MOV r0, #5;
+    MOV r1, #7;
+    MOV r2, #0;
+    CMP r0, r1;
+    SUBS r3, r0, #5;
+    BEQ done;
+    MOV r2, #1;
+done:
+    MOV r4, #9;
+", &[5, 7, 0, 0, 9]);
+}
+
+#[test]
+fn branch_skips_a_non_flag_setter() {
+    // the SUB doesn't touch the flags; so the BNE sees the CMP
+    assert_registers("
+.text
+_start:
+    MOV r0, #5;
+    MOV r1, #7;
+    MOV r2, #0;
+    CMP r0, r1;
+    SUB r3, r0, #5;
+    BNE done;
+    MOV r2, #1;
+done:
+    MOV r4, #9;
+", &[5, 7, 0, 0, 9]);
+}
+
+#[test]
+fn overwriter_after_a_slow_flag_setter() {
+    // the CMP of the slow quotient is overwritten by the CMP that doesn't wait for it
+    assert_registers("
+.text
+_start:
+    MOV r0, #100;
+    MOV r1, #3;
+    MOV r2, #0;
+    SDIV r3, r0, r1;
+    CMP r3, #33;
+    CMP r1, #4;
+    BGE done;
+    MOV r2, #1;
+done:
+    MOV r4, #9;
+", &[100, 3, 1, 33, 9]);
+}
+
+#[test]
+fn carry_reader_and_tst_keep_the_older_flags() {
+    // the ADCS adds the carry of the SUBS; the TST keeps it for the BHS
+    assert_registers("
+.text
+_start:
+    MOV r0, #5;
+    MOV r1, #7;
+    MOV r2, #0;
+    SUBS r3, r1, r0;
+    ADCS r4, r0, r0;
+    SUBS r5, r1, r0;
+    TST r5, #1;
+    BHS done;
+    MOV r2, #1;
+done:
+    MOV r6, #9;
+", &[5, 7, 0, 2, 11, 2, 9]);
+}
@@ -0,0 +1,18 @@
+mod common;
+
+#[test]
+fn the_first_declared_alias_is_printed() {
+    // with a hash map order the shown alias would differ between runs
+    let mut source = String::from(".text\n");
+    for k in 0..16 {
+        source.push_str(&format!(".req alias{}, r2\n", k));
+    }
+    source.push_str("_start:\n    MOV r2, #7;\n    PRINTR r2;\n");
+
+    let config = common::config();
+    let program = common::load_source(&config, &source);
+    let mut cpu = common::cpu(&config);
+    cpu.run(&program).unwrap_or_else(|diagnosis| panic!("{}", diagnosis));
+    assert_eq!(cpu.output().len(), 1);
+    assert!(cpu.output()[0].contains("alias0"), "{}", cpu.output()[0]);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::rc::Rc;
+
+use rust_cpu_emulator::cpu::{CPU, CPUConfig, PC, RunOutcome};
+use rust_cpu_emulator::instructions::instructions::Program;
+use rust_cpu_emulator::loader::loader::load;
+use rust_cpu_emulator::snapshot::snapshot::Snapshot;
+
+fn config(journal_depth: u32) -> CPUConfig {
+    let mut config = common::config();
+    config.set("journal_depth", &journal_depth.to_string()).unwrap();
+    config
+}
+
+fn start(config: &CPUConfig, path: &str) -> (CPU, Rc<Program>) {
+    let program = Rc::new(load(config.clone(), path).unwrap_or_else(|error| panic!("{}", error)));
+    let mut cpu = common::cpu(config);
+    cpu.load(&program);
+    (cpu, program)
+}
+
+// The PC is the fetch pointer of the frontend, which runs ahead of retirement.
+fn without_pc(mut snapshot: Snapshot) -> Snapshot {
+    snapshot.registers[PC as usize] = 0;
+    snapshot
+}
+
+#[test]
+fn step_back_restores_the_state() {
+    let config = config(16);
+    let (mut cpu, program) = start(&config, "asm/conformance/sum.asm");
+    for _ in 0..5 {
+        assert!(cpu.step_instruction().unwrap());
+    }
+    let before = without_pc(cpu.snapshot(&program));
+
+    for _ in 0..7 {
+        assert!(cpu.step_instruction().unwrap());
+    }
+    assert_ne!(without_pc(cpu.snapshot(&program)), before);
+
+    assert_eq!(cpu.step_back(7), 7);
+    assert_eq!(without_pc(cpu.snapshot(&program)), before);
+
+    // the program continues from there
+    assert!(matches!(cpu.run_for(100_000), RunOutcome::Exited(Some(55))));
+}
+
+#[test]
+fn step_back_is_bounded_by_the_journal_depth() {
+    let config = config(4);
+    let (mut cpu, program) = start(&config, "asm/conformance/sum.asm");
+    assert!(matches!(cpu.run_for(100_000), RunOutcome::Exited(Some(55))));
+    assert_eq!(cpu.journal_len(), 4);
+
+    // stepping back past the exit lets the program run again
+    assert_eq!(cpu.step_back(10), 4);
+    assert!(!cpu.is_exited());
+    assert!(matches!(cpu.run_for(100_000), RunOutcome::Exited(Some(55))));
+    assert_eq!(cpu.snapshot(&program).registers[0], 55);
+}
+
+#[test]
+fn without_a_journal_nothing_is_stepped_back() {
+    let config = config(0);
+    let (mut cpu, _) = start(&config, "asm/conformance/sum.asm");
+    assert!(cpu.step_instruction().unwrap());
+    assert_eq!(cpu.step_back(1), 0);
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use rust_cpu_emulator::loader::loader::{LoadError, load_from_str};
+
+// The messages of the errors of a program that can be parsed.
+fn errors(source: &str, general_reg_count: &str) -> Vec<String> {
+    let mut config = common::config();
+    config.set("general_reg_count", general_reg_count).unwrap();
+    match load_from_str(config, source) {
+        Err(LoadError::AnalysisError(errors)) => errors.into_iter().map(|error| error.message).collect(),
+        Err(error) => panic!("{}", error),
+        Ok(_) => panic!("the program was loaded"),
+    }
+}
+
+#[test]
+fn a_register_beyond_the_file_gives_a_single_error() {
+    for register in ["r31", "r32", "r99"] {
+        let errors = errors(&format!(".text\n_start:\n    MOV {}, #1;\n", register), "31");
+        assert_eq!(errors, vec![format!("Unknown register {}; the CPU has 31 general purpose registers (r0-r30)", register)]);
+    }
+}
+
+#[test]
+fn the_limit_is_the_configured_register_count() {
+    let errors = errors(".text\n_start:\n    LDR r0, [r16];\n    PUSH {r4, r16-r20};\n", "16");
+    let expected = [
+        "Unknown register r16; the CPU has 16 general purpose registers (r0-r15)",
+        "Unknown register r16; the CPU has 16 general purpose registers (r0-r15)",
+    ];
+    assert_eq!(errors, expected);
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rust_cpu_emulator::cpu::CPU;
+use rust_cpu_emulator::instructions::instructions::Program;
+use rust_cpu_emulator::plugin::plugin::{FlushCause, FlushEvent, InstrEvent, Plugin};
+
+// The BTB learns that the CBNZ is taken, so after the last iteration the frontend fetches the loop again and
+// issues a store of 0 to last. The store after the loop is committed in the store buffer after it.
+const PROGRAM: &str = "
+.data
+    last: .word 100
+    after: .word 0
+.text
+_start:
+    MOV r6, =last;
+    MOV r7, =after;
+    MOV r3, #8;
+loop:
+    STR r3, [r6];
+    SUB r3, r3, #1;
+    CBNZ r3, loop;
+    MOV r4, #5;
+    STR r4, [r7];
+";
+
+// The BNE only waits for the SUBS, so it executes while the STR of its iteration still waits for the SDIV.
+// After the last iteration the wrong path stores 14 to result; the older store of 15 isn't committed yet
+// when the misprediction is detected.
+const SLOW_STORE_PROGRAM: &str = "
+.data
+    result: .word 0
+.text
+_start:
+    MOV r6, =result;
+    MOV r0, #3;
+    MOV r1, #100;
+    MOV r2, #7;
+loop:
+    SDIV r4, r1, r2;
+    ADD r4, r4, r0;
+    STR r4, [r6];
+    SUBS r0, r0, #1;
+    BNE loop;
+";
+
+// The cycles of the mispredict flushes and of the retired stores.
+#[derive(Default)]
+struct FlushOrder {
+    mispredict_cycles: Vec<u64>,
+    store_retire_cycles: Vec<u64>,
+}
+
+impl Plugin for FlushOrder {
+    fn on_retire(&mut self, event: &InstrEvent) {
+        if event.mnemonic() == "STR" {
+            self.store_retire_cycles.push(event.cycle());
+        }
+    }
+
+    fn on_flush(&mut self, event: &FlushEvent) {
+        if event.cause() == FlushCause::Mispredict {
+            self.mispredict_cycles.push(event.cycle());
+        }
+    }
+}
+
+fn run(source: &str, plugin: Option<Box<dyn Plugin>>) -> (CPU, Rc<Program>) {
+    let config = common::yaml_config();
+    let program = common::load_source(&config, source);
+    let mut cpu = common::cpu(&config);
+    if let Some(plugin) = plugin {
+        cpu.add_plugin(plugin);
+    }
+    common::run_to_exit(&mut cpu, &program);
+    (cpu, program)
+}
+
+#[test]
+fn stores_after_a_mispredict_never_reach_memory() {
+    let (cpu, program) = run(PROGRAM, None);
+
+    let perf_counters = cpu.perf_counters();
+    assert!(perf_counters.squashed_store_cnt > 0, "no wrong-path store was issued");
+    let snapshot = cpu.snapshot(&program);
+    assert_eq!(snapshot.memory[program.symbols().offset_of("last").unwrap() as usize], 1);
+    assert_eq!(snapshot.memory[program.symbols().offset_of("after").unwrap() as usize], 5);
+}
+
+#[test]
+fn older_uncommitted_store_survives_the_squash() {
+    let flush_order = Rc::new(RefCell::new(FlushOrder::default()));
+    let (cpu, program) = run(SLOW_STORE_PROGRAM, Some(Box::new(Rc::clone(&flush_order))));
+
+    assert!(cpu.perf_counters().squashed_store_cnt > 0, "no wrong-path store was issued");
+    let flush_order = flush_order.borrow();
+    let last_mispredict = *flush_order.mispredict_cycles.last().expect("the loop exit wasn't mispredicted");
+    let last_store = *flush_order.store_retire_cycles.last().unwrap();
+    assert!(last_mispredict < last_store, "the store of the last iteration was committed before the squash");
+    let snapshot = cpu.snapshot(&program);
+    assert_eq!(snapshot.memory[program.symbols().offset_of("result").unwrap() as usize], 15);
+}